@@ -0,0 +1,371 @@
+use crate::intervals::percentile;
+use crate::merge;
+use crate::table::Table;
+use common::exclusion::{EXCLUSIONS_START_KEY, ExclusionWindows};
+use common::schemas::latency::LatencyRow;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CLIENT_SUFFIX: &str = "_client_latency.csv";
+
+/*
+ * Above these |t| the difference of the means of two runs is unlikely to
+ * be noise. Consecutive samples of a run are correlated, a slow encoder
+ * stays slow for a while, so t overstates the evidence and the hints are
+ * only a first filter before repeating the runs.
+ */
+const LIKELY_T: f64 = 2.;
+const SIGNIFICANT_T: f64 = 3.;
+
+/* A run given on the command line, by its directory and name. */
+struct Run {
+    dir: PathBuf,
+    name: String,
+}
+
+impl Run {
+    /*
+     * `<run_dir>/<name>`, or a run directory holding a single run, the way
+     * `merge` takes them.
+     */
+    fn find(path: &Path) -> io::Result<Self> {
+        if !path.is_dir() {
+            let dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if !dir.join(format!("{}{}", name, CLIENT_SUFFIX)).exists() {
+                return Err(io::Error::other(format!(
+                    "no {}{} in {}",
+                    name,
+                    CLIENT_SUFFIX,
+                    dir.display()
+                )));
+            }
+            return Ok(Run { dir, name });
+        }
+        let mut names = vec![];
+        for entry in std::fs::read_dir(path)? {
+            let file_name = entry?.file_name().to_string_lossy().into_owned();
+            if let Some(name) = file_name.strip_suffix(CLIENT_SUFFIX) {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        match names.len() {
+            1 => Ok(Run {
+                dir: path.to_path_buf(),
+                name: names.remove(0),
+            }),
+            0 => Err(io::Error::other(format!(
+                "no client latency files in {}",
+                path.display()
+            ))),
+            _ => Err(io::Error::other(format!(
+                "{} holds the runs {}, pass <run_dir>/<name>",
+                path.display(),
+                names.join(", ")
+            ))),
+        }
+    }
+
+    fn label(&self) -> String {
+        format!("{}/{}", self.dir.display(), self.name)
+    }
+}
+
+/* The samples of a run the comparison is made on, after the warm-up. */
+struct Samples {
+    latency: Vec<f64>,
+    client_cpu_usage: Vec<f64>,
+    publisher_cpu_usage: Vec<f64>,
+    /* Received share of the target bitrate, %, empty when it is unknown. */
+    receive_adherence: Vec<f64>,
+    freezes: Vec<f64>,
+    /* The `bitrate_adherence` the publisher recorded, see `common::adherence`. */
+    publisher_adherence: Option<f64>,
+}
+
+fn samples(run: &Run, keep_warmup: bool) -> io::Result<Samples> {
+    let client = Table::read(&run.dir.join(format!("{}{}", run.name, CLIENT_SUFFIX)))?;
+    let rows: Vec<LatencyRow> = client.records()?;
+    let target_kbps = merge::target_kbps(&run.dir, &run.name);
+    let cut = if keep_warmup {
+        0
+    } else {
+        merge::warmup_cut(&client, target_kbps)
+    };
+    /* Samples the client marked as excluded don't count, see `common::exclusion`. */
+    let rows: Vec<&LatencyRow> = rows[cut.min(rows.len())..]
+        .iter()
+        .filter(|row| row.excluded.is_empty())
+        .collect();
+    let finite = |values: Vec<f64>| -> Vec<f64> {
+        values
+            .into_iter()
+            .filter(|value| value.is_finite())
+            .collect()
+    };
+
+    /* Files without the per-sample delta have the cumulative count. */
+    let freezes = if rows.iter().any(|row| row.freeze_count_delta.is_finite()) {
+        rows.iter().map(|row| row.freeze_count_delta).collect()
+    } else {
+        rows.windows(2)
+            .map(|pair| pair[1].freeze_count - pair[0].freeze_count)
+            .collect()
+    };
+
+    Ok(Samples {
+        latency: finite(rows.iter().map(|row| row.latency as f64).collect()),
+        client_cpu_usage: finite(rows.iter().map(|row| row.cpu_usage).collect()),
+        publisher_cpu_usage: finite(publisher_cpu_usage(run)?),
+        receive_adherence: match target_kbps {
+            Some(target) if target > 0. => finite(
+                rows.iter()
+                    .map(|row| row.receive_kbps / target * 100.)
+                    .collect(),
+            ),
+            _ => vec![],
+        },
+        freezes: finite(freezes),
+        publisher_adherence: merge::metadata_value(
+            &run.dir,
+            &run.name,
+            "publisher",
+            "bitrate_adherence",
+        )
+        .and_then(|value| value.parse().ok()),
+    })
+}
+
+/*
+ * The publisher CPU usage of its per-second stats, the same sampling in
+ * every run. Samples taken inside an exclusion window are left out: the
+ * windows count from the start of the client's measurement, placed on the
+ * publisher clock with the clock offset the client estimated. A run with
+ * windows that can't be placed fails rather than counting them.
+ */
+fn publisher_cpu_usage(run: &Run) -> io::Result<Vec<f64>> {
+    let file = run.dir.join(format!("{}_publisher_stats.csv", run.name));
+    let client_entry = |key: &str| merge::metadata_value(&run.dir, &run.name, "client", key);
+    let windows: ExclusionWindows = match client_entry("exclusions") {
+        Some(windows) => windows.parse().map_err(io::Error::other)?,
+        None => ExclusionWindows::default(),
+    };
+    let table = match Table::read(&file) {
+        Ok(table) => table,
+        Err(_) if windows.is_empty() => return Ok(vec![]),
+        Err(e) => {
+            return Err(io::Error::other(format!(
+                "{}: {}, needed to leave the exclusion windows out",
+                file.display(),
+                e
+            )));
+        }
+    };
+    let column = table.column("cpu_usage")?;
+    if windows.is_empty() {
+        return Ok(table
+            .rows()
+            .iter()
+            .map(|row| row[column].parse().unwrap_or(f64::NAN))
+            .collect());
+    }
+    let start_ms: f64 = client_entry(EXCLUSIONS_START_KEY)
+        .and_then(|start| start.parse().ok())
+        .ok_or_else(|| {
+            io::Error::other(format!(
+                "{}: the client metadata has exclusion windows without {}, they can't be placed",
+                run.label(),
+                EXCLUSIONS_START_KEY
+            ))
+        })?;
+    /* Publisher minus client, 0 when the clocks weren't compared. */
+    let offset_ms: f64 = client_entry("clock_offset_ms")
+        .and_then(|offset| offset.parse().ok())
+        .unwrap_or(0.);
+    let timestamp = table.column("timestamp")?;
+    let mut usage = vec![];
+    for row in table.rows() {
+        let Ok(publisher_ms) = row[timestamp].parse::<f64>() else {
+            return Err(io::Error::other(format!(
+                "{}: invalid timestamp {}",
+                file.display(),
+                row[timestamp]
+            )));
+        };
+        let elapsed_ms = publisher_ms - offset_ms - start_ms;
+        let excluded = elapsed_ms >= 0.
+            && windows
+                .label_at(Duration::from_secs_f64(elapsed_ms / 1000.))
+                .is_some();
+        if !excluded {
+            usage.push(row[column].parse().unwrap_or(f64::NAN));
+        }
+    }
+    Ok(usage)
+}
+
+/* Mean and unbiased variance, NaN for the variance of a single value. */
+fn moments(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / (n - 1.);
+    (mean, variance)
+}
+
+/* Welch's t of the difference of the means of `b` and `a`. */
+fn welch_t(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+    let ((mean_a, var_a), (mean_b, var_b)) = (moments(a), moments(b));
+    let error = (var_a / a.len() as f64 + var_b / b.len() as f64).sqrt();
+    if error == 0. {
+        return (mean_a == mean_b).then_some(0.);
+    }
+    Some((mean_b - mean_a) / error)
+}
+
+fn hint(t: Option<f64>) -> String {
+    match t {
+        None => "-".to_string(),
+        Some(t) if t.abs() >= SIGNIFICANT_T => format!("significant (t {:.1})", t),
+        Some(t) if t.abs() >= LIKELY_T => format!("likely (t {:.1})", t),
+        Some(t) => format!("noise (t {:.1})", t),
+    }
+}
+
+/* One line of the diff, the values of run A and B. */
+struct Line {
+    metric: &'static str,
+    a: f64,
+    b: f64,
+    hint: String,
+}
+
+impl Line {
+    fn delta(&self) -> String {
+        if !self.a.is_finite() || !self.b.is_finite() {
+            return "-".to_string();
+        }
+        if self.a == 0. {
+            return if self.b == 0. { "0.0%" } else { "-" }.to_string();
+        }
+        format!("{:+.1}%", (self.b - self.a) / self.a.abs() * 100.)
+    }
+}
+
+fn value(value: f64) -> String {
+    if value.is_finite() {
+        format!("{:.2}", value)
+    } else {
+        "-".to_string()
+    }
+}
+
+fn sorted(values: &[f64]) -> Vec<f64> {
+    let mut values = values.to_vec();
+    values.sort_by(f64::total_cmp);
+    values
+}
+
+/*
+ * Compares run B with run A: the latency percentiles and mean, the CPU
+ * usage of both sides, the received share of the target bitrate and the
+ * bitrate adherence the publisher recorded, and the freezes. Deltas are
+ * relative to A. The means come with a hint whether the difference stands
+ * out of the spread of the samples, see `welch_t`; the percentiles share
+ * the hint of the latency mean, the publisher adherence is a single value
+ * without one. Warm-up samples are left out as in `merge`, unless
+ * `keep_warmup` is set.
+ */
+pub fn compare(run_a: &Path, run_b: &Path, keep_warmup: bool) -> io::Result<()> {
+    let (run_a, run_b) = (Run::find(run_a)?, Run::find(run_b)?);
+    let (a, b) = (samples(&run_a, keep_warmup)?, samples(&run_b, keep_warmup)?);
+    if a.latency.is_empty() || b.latency.is_empty() {
+        return Err(io::Error::other(
+            "no latency samples after the warm-up, --keep-warmup compares them all",
+        ));
+    }
+
+    let mean = |values: &[f64]| {
+        if values.is_empty() {
+            f64::NAN
+        } else {
+            moments(values).0
+        }
+    };
+    let (latency_a, latency_b) = (sorted(&a.latency), sorted(&b.latency));
+    let latency_hint = hint(welch_t(&a.latency, &b.latency));
+    let mut lines: Vec<Line> = [50., 95., 99.]
+        .into_iter()
+        .zip(["latency p50 (ms)", "latency p95 (ms)", "latency p99 (ms)"])
+        .map(|(p, metric)| Line {
+            metric,
+            a: percentile(&latency_a, p),
+            b: percentile(&latency_b, p),
+            hint: latency_hint.clone(),
+        })
+        .collect();
+    let means: [(&'static str, &[f64], &[f64]); 5] = [
+        ("latency mean (ms)", &a.latency, &b.latency),
+        (
+            "publisher cpu (%)",
+            &a.publisher_cpu_usage,
+            &b.publisher_cpu_usage,
+        ),
+        ("client cpu (%)", &a.client_cpu_usage, &b.client_cpu_usage),
+        (
+            "received of target (%)",
+            &a.receive_adherence,
+            &b.receive_adherence,
+        ),
+        ("freezes per sample", &a.freezes, &b.freezes),
+    ];
+    for (metric, values_a, values_b) in means {
+        lines.push(Line {
+            metric,
+            a: mean(values_a),
+            b: mean(values_b),
+            hint: hint(welch_t(values_a, values_b)),
+        });
+    }
+    lines.push(Line {
+        metric: "freezes",
+        a: a.freezes.iter().sum(),
+        b: b.freezes.iter().sum(),
+        hint: "-".to_string(),
+    });
+    lines.push(Line {
+        metric: "bitrate adherence (%)",
+        a: a.publisher_adherence.unwrap_or(f64::NAN),
+        b: b.publisher_adherence.unwrap_or(f64::NAN),
+        hint: "-".to_string(),
+    });
+
+    println!("A: {} ({} samples)", run_a.label(), a.latency.len());
+    println!("B: {} ({} samples)", run_b.label(), b.latency.len());
+    println!(
+        "{:<24}{:>12}{:>12}{:>10}  hint",
+        "metric", "A", "B", "delta"
+    );
+    for line in &lines {
+        println!(
+            "{:<24}{:>12}{:>12}{:>10}  {}",
+            line.metric,
+            value(line.a),
+            value(line.b),
+            line.delta(),
+            line.hint
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use crate::table::Table;
+use common::schemas::frames::FrameRow;
+use common::schemas::{SCHEMA_VERSION, SCHEMA_VERSION_KEY};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const FRAMES_SUFFIX: &str = "_client_frames.csv";
+
+/* An interval closer to two frame times than to one counts as a stutter, a frame is missing. */
+const STUTTER_FACTOR: f64 = 1.5;
+
+/*
+ * Inter-frame interval histograms of the frame logs the client writes with
+ * `--frame-log`. The average frame rate hides micro-stutter: a run at
+ * 30 fps with a 200 ms hitch every few seconds averages close to 30 fps but
+ * is what users notice. Every `<name>_client_frames.csv` in `run_dir` gets a
+ * `<name>_intervals.csv` with the count of intervals per `bucket_ms` wide
+ * bucket, and the percentiles and stutters are printed.
+ */
+pub fn intervals(run_dir: &Path, bucket_ms: f64) -> io::Result<()> {
+    if bucket_ms.is_nan() || bucket_ms <= 0. {
+        return Err(io::Error::other("the bucket width must be positive"));
+    }
+    let files = find_frame_files(run_dir)?;
+    if files.is_empty() {
+        return Err(io::Error::other(format!(
+            "no frame logs found in {}, run the client with --frame-log",
+            run_dir.display()
+        )));
+    }
+    for file in files {
+        let file_name = file.file_name().unwrap().to_string_lossy();
+        let name = file_name.strip_suffix(FRAMES_SUFFIX).unwrap();
+        let frames: Vec<FrameRow> = Table::read(&file)?.records()?;
+        let mut intervals: Vec<f64> = frames
+            .iter()
+            .map(|frame| frame.interval_ms)
+            .filter(|interval| interval.is_finite())
+            .collect();
+        if intervals.is_empty() {
+            eprintln!("Skipping {}, no intervals", file.display());
+            continue;
+        }
+        intervals.sort_by(f64::total_cmp);
+
+        let output_file = run_dir.join(format!("{}_intervals.csv", name));
+        let mut output = File::create(&output_file)?;
+        writeln!(output, "interval_ms,count,{}", SCHEMA_VERSION_KEY)?;
+        for (bucket, count) in histogram(&intervals, bucket_ms) {
+            writeln!(
+                output,
+                "{},{},{}",
+                bucket as f64 * bucket_ms,
+                count,
+                SCHEMA_VERSION
+            )?;
+        }
+
+        let median = percentile(&intervals, 50.);
+        let stutter = median * STUTTER_FACTOR;
+        let stutters = intervals
+            .iter()
+            .filter(|interval| **interval > stutter)
+            .count();
+        let mean = intervals.iter().sum::<f64>() / intervals.len() as f64;
+        println!(
+            "{}: {} intervals, mean {:.1} ms, p50 {:.1} ms, p95 {:.1} ms, p99 {:.1} ms, max {:.1} ms",
+            name,
+            intervals.len(),
+            mean,
+            median,
+            percentile(&intervals, 95.),
+            percentile(&intervals, 99.),
+            intervals[intervals.len() - 1]
+        );
+        println!(
+            "{}: {} stutters (> {:.1} ms, {:.2}% of the intervals), histogram in {}",
+            name,
+            stutters,
+            stutter,
+            stutters as f64 / intervals.len() as f64 * 100.,
+            output_file.display()
+        );
+    }
+    Ok(())
+}
+
+fn find_frame_files(run_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(run_dir)? {
+        let path = entry?.path();
+        if path.to_string_lossy().ends_with(FRAMES_SUFFIX) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/* Nearest rank percentile of sorted values. */
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100. * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/* Bucket index and count of every bucket up to the longest interval, empty ones included. */
+fn histogram(sorted: &[f64], bucket_ms: f64) -> Vec<(usize, usize)> {
+    let buckets = (sorted[sorted.len() - 1] / bucket_ms) as usize + 1;
+    let mut counts = vec![0; buckets];
+    for interval in sorted {
+        counts[((interval / bucket_ms) as usize).min(buckets - 1)] += 1;
+    }
+    counts.into_iter().enumerate().collect()
+}
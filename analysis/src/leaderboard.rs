@@ -0,0 +1,131 @@
+use crate::table::Table;
+use common::schemas::{SCHEMA_VERSION, SCHEMA_VERSION_KEY};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const LEADERBOARD_FILE: &str = "leaderboard.csv";
+
+const LEADERBOARD_HEADER: [&str; 16] = [
+    "run",
+    "timestamp",
+    "codec",
+    "resolution",
+    "bitrate",
+    "name",
+    "samples",
+    "latency_p50",
+    "latency_p95",
+    "publisher_cpu_usage",
+    "client_cpu_usage",
+    "receive_kbps",
+    "freezes",
+    "system_event_samples",
+    "time_to_first_frame_ms",
+    SCHEMA_VERSION_KEY,
+];
+
+/*
+ * One merged run, after the warm-up. Latencies are in ms, the CPU usages
+ * and the receive rate are means over the samples outside of system events,
+ * which `system_event_samples` counts instead, `freezes` counts those
+ * after the warm-up. The time to first frame is empty for clients that
+ * didn't record it.
+ */
+pub struct RunSummary {
+    pub run: String,
+    pub samples: usize,
+    pub latency_p50: f64,
+    pub latency_p95: f64,
+    pub publisher_cpu_usage: f64,
+    pub client_cpu_usage: f64,
+    pub receive_kbps: f64,
+    pub freezes: f64,
+    pub system_event_samples: usize,
+    pub time_to_first_frame_ms: Option<String>,
+}
+
+/*
+ * The leaderboard of a run directory, at the root of the results layout
+ * `<root>/<timestamp>/<codec>/<resolution>/<bitrate>`, so all the runs
+ * written there end up in the same file.
+ */
+pub fn default_file(run_dir: &Path) -> io::Result<PathBuf> {
+    let run_dir = run_dir.canonicalize()?;
+    let root = run_dir.ancestors().nth(4).unwrap_or(&run_dir);
+    Ok(root.join(LEADERBOARD_FILE))
+}
+
+/*
+ * Adds the summary of a run to `file` as one line, creating the file when
+ * needed. Merging a run again replaces its line instead of counting it twice.
+ */
+pub fn record(file: &Path, run_dir: &Path, name: &str, summary: &RunSummary) -> io::Result<()> {
+    let mut lines = vec![LEADERBOARD_HEADER.join(",")];
+    if file.exists() {
+        let leaderboard = Table::read(file)?;
+        let (run, name_column) = (leaderboard.column("run")?, leaderboard.column("name")?);
+        /* Rewritten in the current column order, columns added since are left empty. */
+        let columns: Vec<Option<usize>> = LEADERBOARD_HEADER
+            .iter()
+            .map(|column| leaderboard.column(column).ok())
+            .collect();
+        lines.extend(
+            leaderboard
+                .rows()
+                .iter()
+                .filter(|row| row[run] != summary.run || row[name_column] != name)
+                .map(|row| {
+                    let row: Vec<&str> = columns
+                        .iter()
+                        .map(|column| column.map_or("", |column| row[column].as_str()))
+                        .collect();
+                    row.join(",")
+                }),
+        );
+    }
+
+    /* The layout directories name the configuration, see `default_file`. */
+    let run_dir = run_dir.canonicalize()?;
+    let mut layout: Vec<String> = run_dir
+        .iter()
+        .rev()
+        .take(4)
+        .map(|part| part.to_string_lossy().into_owned())
+        .collect();
+    layout.resize(4, String::new());
+    let [bitrate, resolution, codec, timestamp] = [0, 1, 2, 3].map(|i| layout[i].as_str());
+
+    lines.push(format!(
+        "{},{},{},{},{},{},{},{:.1},{:.1},{:.2},{:.2},{:.1},{},{},{},{}",
+        summary.run,
+        timestamp,
+        codec,
+        resolution,
+        bitrate,
+        name,
+        summary.samples,
+        summary.latency_p50,
+        summary.latency_p95,
+        summary.publisher_cpu_usage,
+        summary.client_cpu_usage,
+        summary.receive_kbps,
+        summary.freezes,
+        summary.system_event_samples,
+        summary
+            .time_to_first_frame_ms
+            .as_deref()
+            .unwrap_or_default(),
+        SCHEMA_VERSION
+    ));
+    fs::write(file, lines.join("\n") + "\n")?;
+    println!(
+        "{}: {} p50 {:.1} ms, p95 {:.1} ms, {} runs",
+        file.display(),
+        name,
+        summary.latency_p50,
+        summary.latency_p95,
+        lines.len() - 1
+    );
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+mod compare;
+mod intervals;
+mod leaderboard;
+mod merge;
+mod table;
+
+#[derive(Parser)]
+#[command(name = "analysis")]
+#[command(about = "Post-processing of the benchmark results")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Join the publisher and client samples of a run into one file per tick
+    Merge {
+        /// Run directory, <output-dir>/<timestamp>/<codec>/<resolution>/<bitrate>
+        run_dir: PathBuf,
+
+        /// Keep the encoder warm-up samples, marked in the warmup column
+        #[arg(long)]
+        keep_warmup: bool,
+
+        /// Merge runs marked invalid too, and add them to the leaderboard
+        #[arg(long)]
+        include_invalid: bool,
+
+        /// Leaderboard the run summaries are added to, default leaderboard.csv at the root of the results
+        #[arg(long)]
+        leaderboard: Option<PathBuf>,
+
+        /// Don't add the run summaries to the leaderboard
+        #[arg(long, conflicts_with = "leaderboard")]
+        no_leaderboard: bool,
+    },
+    /// Histogram the inter-frame intervals of the client frame logs
+    Intervals {
+        /// Run directory, <output-dir>/<timestamp>/<codec>/<resolution>/<bitrate>
+        run_dir: PathBuf,
+
+        /// Width of the histogram buckets in ms
+        #[arg(long, default_value_t = 5.)]
+        bucket_ms: f64,
+    },
+    /// Diff two runs: latency percentiles, CPU, bitrate adherence and freezes
+    Compare {
+        /// Run A, <run_dir>/<name> or a run directory holding a single run
+        run_a: PathBuf,
+
+        /// Run B, compared with A
+        run_b: PathBuf,
+
+        /// Compare the encoder warm-up samples too
+        #[arg(long)]
+        keep_warmup: bool,
+    },
+}
+
+fn main() {
+    let args = Args::parse();
+    let result = match args.command {
+        Command::Merge {
+            run_dir,
+            keep_warmup,
+            include_invalid,
+            leaderboard,
+            no_leaderboard,
+        } => {
+            let leaderboard = match (leaderboard, no_leaderboard) {
+                (_, true) => Ok(None),
+                (Some(file), false) => Ok(Some(file)),
+                (None, false) => leaderboard::default_file(&run_dir).map(Some),
+            };
+            leaderboard.and_then(|leaderboard| {
+                merge::merge(
+                    &run_dir,
+                    keep_warmup,
+                    include_invalid,
+                    leaderboard.as_deref(),
+                )
+            })
+        }
+        Command::Intervals { run_dir, bucket_ms } => intervals::intervals(&run_dir, bucket_ms),
+        Command::Compare {
+            run_a,
+            run_b,
+            keep_warmup,
+        } => compare::compare(&run_a, &run_b, keep_warmup),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
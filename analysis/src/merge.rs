@@ -0,0 +1,440 @@
+use crate::intervals::percentile;
+use crate::leaderboard::{self, RunSummary};
+use crate::table::Table;
+use common::clock::ClockOffset;
+use common::schemas::{
+    self, SCHEMA_VERSION, SCHEMA_VERSION_KEY, latency::LatencyRow, ticks::TickRow,
+};
+use common::system_events::ANNOTATION_SEPARATOR;
+use common::validity;
+use common::warmup;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const CLIENT_SUFFIX: &str = "_client_latency.csv";
+
+/*
+ * Joins the client latency samples with the publisher tick samples of every
+ * run in `run_dir` on (run uuid, tick id), writing one combined record per
+ * tick to `<name>_merged.csv`. Ticks missing on either side are dropped
+ * instead of shifting the following rows. Encoder warm-up samples are
+ * dropped too, unless `keep_warmup` is set, see `warmup`. Samples the
+ * client marked as excluded are merged with their label but left out of
+ * the breakdown and the summary, see `common::exclusion`. The summary of
+ * every run goes to the `leaderboard` file, when there is one. Runs marked
+ * invalid are skipped, unless `include_invalid` is set, see
+ * `common::validity`.
+ */
+pub fn merge(
+    run_dir: &Path,
+    keep_warmup: bool,
+    include_invalid: bool,
+    leaderboard: Option<&Path>,
+) -> io::Result<()> {
+    let mut merged_any = false;
+    let mut skipped_invalid = 0;
+    for client_file in find_client_files(run_dir)? {
+        let file_name = client_file.file_name().unwrap().to_string_lossy();
+        let name = file_name.strip_suffix(CLIENT_SUFFIX).unwrap();
+        let publisher_file = run_dir.join(format!("{}_publisher_ticks.csv", name));
+        if !publisher_file.exists() {
+            eprintln!(
+                "Skipping {}, {} is missing",
+                client_file.display(),
+                publisher_file.display()
+            );
+            continue;
+        }
+        if let Some(reasons) = validity::invalid_reasons(run_dir, name) {
+            if !include_invalid {
+                eprintln!("Skipping {}, marked invalid: {}", name, reasons.join(", "));
+                skipped_invalid += 1;
+                continue;
+            }
+            eprintln!(
+                "{}: marked invalid, merged anyway: {}",
+                name,
+                reasons.join(", ")
+            );
+        }
+
+        schema_version(run_dir, name)?;
+        let output_file = run_dir.join(format!("{}_merged.csv", name));
+        let target_kbps = target_kbps(run_dir, name);
+        let time_to_first_frame = metadata_value(run_dir, name, "client", "time_to_first_frame_ms")
+            .filter(|time| !time.is_empty());
+        let summary = merge_files(
+            &publisher_file,
+            &client_file,
+            &output_file,
+            target_kbps,
+            clock_offset(run_dir, name),
+            keep_warmup,
+        )?;
+        merged_any = true;
+        match (leaderboard, summary) {
+            (Some(file), Some(mut summary)) => {
+                summary.time_to_first_frame_ms = time_to_first_frame;
+                leaderboard::record(file, run_dir, name, &summary)?
+            }
+            (Some(_), None) => eprintln!(
+                "{}: no samples after the warm-up outside of system events, not in the leaderboard",
+                name
+            ),
+            (None, _) => {}
+        }
+    }
+
+    if !merged_any && skipped_invalid > 0 {
+        return Err(io::Error::other(format!(
+            "all runs in {} are marked invalid, --include-invalid merges them",
+            run_dir.display()
+        )));
+    }
+    if !merged_any {
+        return Err(io::Error::other(format!(
+            "no publisher/client file pairs found in {}",
+            run_dir.display()
+        )));
+    }
+    Ok(())
+}
+
+/* The offset the client estimated before measuring, see `common::clock`. */
+fn clock_offset(run_dir: &Path, name: &str) -> Option<ClockOffset> {
+    let value = |key: &str| metadata_value(run_dir, name, "client", key);
+    Some(ClockOffset {
+        offset_ms: value("clock_offset_ms")?.parse().ok()?,
+        rtt_ms: value("clock_sync_rtt_ms")
+            .and_then(|rtt| rtt.parse().ok())
+            .unwrap_or(f64::NAN),
+        samples: value("clock_sync_samples")
+            .and_then(|samples| samples.parse().ok())
+            .unwrap_or(0),
+    })
+}
+
+/*
+ * From the capture of the first marked frame, when the publisher took its
+ * tick sample, to its decode on the client. Unlike `latency` it leaves out
+ * the tick's way to the publisher, but it compares the two clocks, so it
+ * is NaN without a clock offset or either timestamp.
+ */
+fn capture_latency(offset: Option<&ClockOffset>, tick: &TickRow, row: &LatencyRow) -> f64 {
+    match (offset, tick.timestamp, row.receive_timestamp) {
+        (Some(offset), Some(captured), Some(received)) => {
+            (received as i64 - offset.to_client_ms(captured as i64)) as f64
+        }
+        _ => f64::NAN,
+    }
+}
+
+fn find_client_files(run_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(run_dir)? {
+        let path = entry?.path();
+        if path.to_string_lossy().ends_with(CLIENT_SUFFIX) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/*
+ * Configured bitrate of the run, from the publisher metadata or else the
+ * `<bitrate>` directory of the results layout.
+ */
+pub fn target_kbps(run_dir: &Path, name: &str) -> Option<f64> {
+    let from_metadata = metadata_value(run_dir, name, "publisher", "bitrate")
+        .and_then(|bitrate| bitrate.parse().ok());
+    from_metadata.or_else(|| run_dir.file_name()?.to_str()?.parse().ok())
+}
+
+/*
+ * Checks both sides of a run can be read, see `common::schemas`. Files of
+ * version 1 lack the columns added over time, those are NaN or empty.
+ */
+fn schema_version(run_dir: &Path, name: &str) -> io::Result<()> {
+    for side in ["publisher", "client"] {
+        let entry = metadata_value(run_dir, name, side, SCHEMA_VERSION_KEY);
+        let version = schemas::version(entry.as_deref())
+            .map_err(|e| io::Error::other(format!("{} {}: {}", name, side, e)))?;
+        if version == schemas::OLDEST_SCHEMA_VERSION {
+            eprintln!(
+                "{}: {} files of schema version {}, columns added since are left empty",
+                name, side, version
+            );
+        }
+    }
+    Ok(())
+}
+
+/* An entry of the `<name>_<side>_metadata.csv` of a run, if it is there. */
+pub fn metadata_value(run_dir: &Path, name: &str, side: &str, entry: &str) -> Option<String> {
+    let metadata_file = run_dir.join(format!("{}_{}_metadata.csv", name, side));
+    let metadata = Table::read(&metadata_file).ok()?;
+    let (key, value) = (metadata.column("key").ok()?, metadata.column("value").ok()?);
+    metadata
+        .rows()
+        .iter()
+        .find(|row| row[key] == entry)
+        .map(|row| row[value].clone())
+}
+
+/*
+ * Index of the first client sample after the encoder warm-up, 0 when it
+ * can't be told (no target bitrate, files without rate columns) or the run
+ * never settles, so nothing is dropped on a guess.
+ */
+pub fn warmup_cut(client: &Table, target_kbps: Option<f64>) -> usize {
+    let Some(target_kbps) = target_kbps else {
+        eprintln!("Target bitrate unknown, keeping the warm-up samples");
+        return 0;
+    };
+    let (Ok(c_kbps), Ok(c_fps)) = (
+        client.column("receive_kbps"),
+        client.column("frames_per_second"),
+    ) else {
+        eprintln!("No receive_kbps column, keeping the warm-up samples");
+        return 0;
+    };
+    let values = |column: usize| -> Vec<f64> {
+        client
+            .rows()
+            .iter()
+            .map(|row| row[column].parse().unwrap_or(f64::NAN))
+            .collect()
+    };
+    match warmup::detect(&values(c_kbps), &values(c_fps), target_kbps) {
+        Some(cut) => cut,
+        None => {
+            eprintln!(
+                "The run never reached {} kbps or a level bitrate at a stable frame rate, keeping the warm-up samples",
+                target_kbps
+            );
+            0
+        }
+    }
+}
+
+/*
+ * Where the end-to-end latency of a tick goes. Capture-to-submit and encode
+ * come from the publisher tick (average per frame since the previous tick),
+ * the rest from the client stats: the network as half the round trip, the
+ * jitter buffer and decode out of the processing delay, which counts from
+ * the first packet of a frame to its decode and so includes the jitter
+ * buffer. The client delays are averages since the start of the stream.
+ * `other` is what's left: render, the watermark read back, clock effects.
+ * Stages of files written before a column existed are NaN.
+ */
+const STAGES: [&str; 6] = [
+    "capture_pipeline",
+    "encode",
+    "network",
+    "jitter_buffer",
+    "decode",
+    "other",
+];
+
+fn stages(latency: f64, tick: &TickRow, row: &LatencyRow) -> [f64; 6] {
+    /* `max` would turn a missing processing delay into 0. */
+    let decode = row.processing_delay - row.jitter_buffer_delay;
+    let mut stages = [
+        tick.pipeline_ms,
+        tick.encode_ms,
+        row.current_round_trip_time / 2.,
+        row.jitter_buffer_delay,
+        if decode < 0. { 0. } else { decode },
+        f64::NAN,
+    ];
+    let known: f64 = stages[..5].iter().filter(|stage| stage.is_finite()).sum();
+    stages[5] = latency - known;
+    stages
+}
+
+/*
+ * The system events of both sides a sample fell into, each kind prefixed
+ * with its side, e.g. `publisher:cpu_spike;client:network_changed`.
+ */
+fn system_events(sides: &[(&str, &str)]) -> String {
+    let mut kinds = vec![];
+    for (side, annotation) in sides {
+        for kind in annotation.split(ANNOTATION_SEPARATOR) {
+            if !kind.is_empty() {
+                kinds.push(format!("{}:{}", side, kind));
+            }
+        }
+    }
+    kinds.join(&ANNOTATION_SEPARATOR.to_string())
+}
+
+fn merge_files(
+    publisher_file: &Path,
+    client_file: &Path,
+    output_file: &Path,
+    target_kbps: Option<f64>,
+    clock_offset: Option<ClockOffset>,
+    keep_warmup: bool,
+) -> io::Result<Option<RunSummary>> {
+    let publisher = Table::read(publisher_file)?;
+    let client = Table::read(client_file)?;
+    let tick_rows: Vec<TickRow> = publisher.records()?;
+    let ticks: HashMap<(&str, u64), &TickRow> = tick_rows
+        .iter()
+        .map(|tick| ((tick.run.as_str(), tick.id), tick))
+        .collect();
+    let client_rows: Vec<LatencyRow> = client.records()?;
+    let cut = warmup_cut(&client, target_kbps);
+
+    let mut file = File::create(output_file)?;
+    writeln!(
+        file,
+        "run,id,latency,publisher_cpu_usage,client_cpu_usage,bytes_sent,bytes_received,warmup,{},system_events,excluded,capture_latency,{}",
+        STAGES.map(|stage| format!("{}_ms", stage)).join(","),
+        SCHEMA_VERSION_KEY
+    )?;
+    let mut merged = 0;
+    let mut disturbed = 0;
+    let mut excluded = 0;
+    let mut totals = [(0., 0); 6];
+    /* The samples after the warm-up, for the leaderboard. */
+    let (mut latencies, mut cpu_usages, mut receive_kbps) = (vec![], vec![], vec![]);
+    let mut run = None;
+    for (i, row) in client_rows.iter().enumerate() {
+        let in_warmup = i < cut;
+        if in_warmup && !keep_warmup {
+            continue;
+        }
+        let Some(tick) = ticks.get(&(row.run.as_str(), row.id)) else {
+            continue;
+        };
+        let latency = row.latency as f64;
+        let stages = stages(latency, tick, row);
+        let system_events = system_events(&[
+            ("publisher", tick.system_events.as_str()),
+            ("client", row.system_events.as_str()),
+        ]);
+        /*
+         * Whether the sample counts, and whether it is left out of the
+         * breakdown and the summary for a system event on either side.
+         */
+        let counted = !in_warmup && row.excluded.is_empty();
+        let quiet = system_events.is_empty();
+        if !in_warmup && !row.excluded.is_empty() {
+            excluded += 1;
+        }
+        if counted && !quiet {
+            disturbed += 1;
+        }
+        if counted && quiet {
+            latencies.push(latency);
+            cpu_usages.push((tick.cpu_usage, row.cpu_usage));
+            receive_kbps.push(row.receive_kbps);
+            run.get_or_insert_with(|| row.run.clone());
+        }
+        for ((sum, count), stage) in totals.iter_mut().zip(stages) {
+            if stage.is_finite() && counted && quiet {
+                *sum += stage;
+                *count += 1;
+            }
+        }
+        writeln!(
+            file,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            row.run,
+            row.id,
+            row.latency,
+            tick.cpu_usage,
+            row.cpu_usage,
+            tick.bytes_sent,
+            row.total_bytes,
+            in_warmup,
+            stages.map(|stage| format!("{:.2}", stage)).join(","),
+            system_events,
+            row.excluded,
+            capture_latency(clock_offset.as_ref(), tick, row),
+            SCHEMA_VERSION
+        )?;
+        merged += 1;
+    }
+
+    println!(
+        "{}: merged {} of {} client samples",
+        output_file.display(),
+        merged,
+        client_rows.len()
+    );
+    if let Some(first) = client_rows.get(cut).filter(|_| cut > 0) {
+        println!(
+            "Warm-up: {} samples before tick {} {}",
+            cut,
+            first.id,
+            if keep_warmup { "marked" } else { "excluded" }
+        );
+    }
+    if excluded > 0 {
+        println!(
+            "Exclusion windows: {} samples marked, left out of the breakdown and the summary",
+            excluded
+        );
+    }
+    if disturbed > 0 {
+        println!(
+            "System events: {} samples during other load, network changes or overheating, left out of the breakdown",
+            disturbed
+        );
+    }
+    let means: Vec<String> = STAGES
+        .iter()
+        .zip(totals)
+        .filter(|(_, (_, count))| *count > 0)
+        .map(|(stage, (sum, count))| format!("{} {:.1}", stage, sum / count as f64))
+        .collect();
+    if !means.is_empty() {
+        println!("Latency breakdown (mean ms): {}", means.join(", "));
+    }
+
+    let Some(run) = run else {
+        return Ok(None);
+    };
+    latencies.retain(|latency| latency.is_finite());
+    latencies.sort_by(f64::total_cmp);
+    if latencies.is_empty() {
+        return Ok(None);
+    }
+    /* Cumulative on the client, counted from the last warm-up sample. */
+    let freeze_count = |row: Option<&LatencyRow>| row.map_or(0., |row| row.freeze_count);
+    /* Freezes within the exclusion windows don't count either. */
+    let excluded_freezes: f64 = client_rows
+        .iter()
+        .skip(cut)
+        .filter(|row| !row.excluded.is_empty() && row.freeze_count_delta.is_finite())
+        .map(|row| row.freeze_count_delta)
+        .sum();
+    let freezes = freeze_count(client_rows.last())
+        - freeze_count(cut.checked_sub(1).and_then(|i| client_rows.get(i)))
+        - excluded_freezes;
+    Ok(Some(RunSummary {
+        run,
+        samples: latencies.len(),
+        latency_p50: percentile(&latencies, 50.),
+        latency_p95: percentile(&latencies, 95.),
+        publisher_cpu_usage: mean(cpu_usages.iter().map(|(publisher, _)| *publisher)),
+        client_cpu_usage: mean(cpu_usages.iter().map(|(_, client)| *client)),
+        receive_kbps: mean(receive_kbps.into_iter()),
+        freezes,
+        system_event_samples: disturbed,
+        time_to_first_frame_ms: None,
+    }))
+}
+
+/* Mean of the finite values, NaN without any. */
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values
+        .filter(|value| value.is_finite())
+        .fold((0., 0), |(sum, count), value| (sum + value, count + 1));
+    sum / count as f64
+}
@@ -0,0 +1,224 @@
+use serde::de::value::{Error, MapDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/*
+ * A CSV file as written by the screen_sharer and the client. Those files
+ * never quote numeric columns, so a plain split is enough.
+ */
+pub struct Table {
+    path: PathBuf,
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let mut lines = content.lines().filter(|line| !line.is_empty());
+        let header: Vec<String> = lines
+            .next()
+            .ok_or_else(|| io::Error::other(format!("{} is empty", path.display())))?
+            .split(',')
+            .map(str::to_string)
+            .collect();
+        let mut rows = vec![];
+        for line in lines {
+            let row: Vec<String> = line.split(',').map(str::to_string).collect();
+            if row.len() != header.len() {
+                eprintln!("{}: skipping malformed row {}", path.display(), line);
+                continue;
+            }
+            rows.push(row);
+        }
+        Ok(Table {
+            path: path.to_path_buf(),
+            header,
+            rows,
+        })
+    }
+
+    pub fn rows(&self) -> &[Vec<String>] {
+        &self.rows
+    }
+
+    /// The rows as `T`, see `common::schemas`. Columns `T` doesn't know are
+    /// ignored, those it lacks take their default.
+    pub fn records<T: DeserializeOwned>(&self) -> io::Result<Vec<T>> {
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                let cells = self
+                    .header
+                    .iter()
+                    .map(String::as_str)
+                    .zip(row.iter().map(|cell| Cell(cell)));
+                T::deserialize(MapDeserializer::<_, Error>::new(cells)).map_err(|e| {
+                    io::Error::other(format!("{}: row {}: {}", self.path.display(), i + 1, e))
+                })
+            })
+            .collect()
+    }
+
+    /// Index of a column, failing with the file's columns in the message.
+    pub fn column(&self, name: &str) -> io::Result<usize> {
+        self.header
+            .iter()
+            .position(|column| column == name)
+            .ok_or_else(|| {
+                io::Error::other(format!(
+                    "missing column {} (have {})",
+                    name,
+                    self.header.join(",")
+                ))
+            })
+    }
+}
+
+/*
+ * A cell for serde. Numbers are parsed as the field asks for them, an empty
+ * cell is a missing value: `None`, or NaN for a float.
+ */
+struct Cell<'a>(&'a str);
+
+macro_rules! parse_cell {
+    ($($method:ident => $visit:ident),*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            let value = self
+                .0
+                .parse()
+                .map_err(|_| de::Error::custom(format!("invalid number {}", self.0)))?;
+            visitor.$visit(value)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for Cell<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.0.is_empty() {
+            return visitor.visit_f64(f64::NAN);
+        }
+        let value = self
+            .0
+            .parse()
+            .map_err(|_| de::Error::custom(format!("invalid number {}", self.0)))?;
+        visitor.visit_f64(value)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    parse_cell!(
+        deserialize_bool => visit_bool,
+        deserialize_i64 => visit_i64,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32
+    );
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i128 u8 u16 u128 char str string bytes byte_buf unit
+        unit_struct newtype_struct seq tuple tuple_struct map struct enum
+        identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Cell<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Row {
+        id: u64,
+        delta: i64,
+        rate: f64,
+        share: f32,
+        ok: bool,
+        label: String,
+        note: Option<String>,
+        count: Option<u32>,
+        #[serde(default)]
+        added: String,
+    }
+
+    /* `content` written to a file of its own and read back. */
+    fn table(name: &str, content: &str) -> Table {
+        let path = std::env::temp_dir().join(format!(
+            "analysis_table_{}_{}.csv",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, content).unwrap();
+        let table = Table::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        table
+    }
+
+    const HEADER: &str = "id,delta,rate,share,ok,label,note,count,unknown";
+
+    #[test]
+    fn reads_cells_as_the_fields_ask() {
+        let table = table(
+            "typed",
+            &format!(
+                "{}\n7,-3,1.5,0.25,true,vp9,,12,x\n8,0,,1,false,av1,slow,,\n",
+                HEADER
+            ),
+        );
+        let rows: Vec<Row> = table.records().unwrap();
+        let row = &rows[0];
+        assert_eq!((row.id, row.delta, row.rate, row.share), (7, -3, 1.5, 0.25));
+        assert!(row.ok);
+        assert_eq!(row.label, "vp9");
+        assert_eq!((row.note.as_deref(), row.count), (None, Some(12)));
+        assert_eq!(row.added, "");
+        let row = &rows[1];
+        assert!(row.rate.is_nan());
+        assert!(!row.ok);
+        assert_eq!((row.note.as_deref(), row.count), (Some("slow"), None));
+    }
+
+    #[test]
+    fn invalid_numbers_name_the_row() {
+        let table = table(
+            "invalid",
+            &format!("{}\n1,0,1,1,true,a,,,\nabc,0,1,1,true,a,,,\n", HEADER),
+        );
+        let e = table.records::<Row>().unwrap_err().to_string();
+        assert!(e.contains("row 2"), "{}", e);
+        assert!(e.contains("invalid number abc"), "{}", e);
+    }
+
+    #[test]
+    fn skips_malformed_rows() {
+        let table = table("malformed", "a,b\n1,2\n3\n\n4,5\n");
+        assert_eq!(table.rows(), [["1", "2"], ["4", "5"]]);
+        assert_eq!(table.column("b").unwrap(), 1);
+        let e = table.column("c").unwrap_err().to_string();
+        assert_eq!(e, "missing column c (have a,b)");
+    }
+}
@@ -0,0 +1,4 @@
+fn main() {
+    /* SDK versions and commit for the run metadata. */
+    common::build_info::emit();
+}
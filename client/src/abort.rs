@@ -0,0 +1,243 @@
+/*
+ * Early abort of a run that is obviously broken on the machine under test,
+ * e.g. an encoder that can't keep up and piles seconds of latency, so a
+ * matrix doesn't spend the whole duration on it. Every condition is
+ * checked on each latency sample over the samples of its window, once the
+ * encoder warm-up is over and the steady part of the measurement has lasted
+ * that long. The warm-up ends as in the analysis, see `common::warmup`.
+ */
+use common::delta::Counter;
+use common::warmup;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/* Window of a condition given without one. */
+const DEFAULT_WINDOW: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AbortMetric {
+    /// Latency percentile above the threshold, ms.
+    LatencyAbove { percentile: f64, threshold_ms: f64 },
+    /// Mean receive rate below this share of the target bitrate, %.
+    BitrateBelow { percent: f64 },
+}
+
+/// A condition parsed from `latency_p<n>><ms>[:<seconds>]` or
+/// `bitrate<<percent>%[:<seconds>]`, e.g. `latency_p95>2000:30` or
+/// `bitrate<30%:30`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AbortCondition {
+    pub metric: AbortMetric,
+    pub window: Duration,
+}
+
+impl std::str::FromStr for AbortCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid abort condition: {}. Use latency_p<n>><ms>[:<seconds>] or bitrate<<percent>%[:<seconds>]",
+                s
+            )
+        };
+        let (condition, window) = match s.split_once(':') {
+            Some((condition, seconds)) => {
+                let seconds = seconds
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid abort condition window: {}", seconds))?;
+                (condition, Duration::from_secs(seconds))
+            }
+            None => (s, DEFAULT_WINDOW),
+        };
+        let number = |value: &str| value.trim().parse::<f64>().map_err(|_| invalid());
+        let metric = if let Some((metric, threshold)) = condition.split_once('>') {
+            let percentile = metric
+                .trim()
+                .strip_prefix("latency_p")
+                .ok_or_else(invalid)
+                .and_then(number)?;
+            if !(0. ..=100.).contains(&percentile) {
+                return Err(format!("Invalid latency percentile: {}", percentile));
+            }
+            AbortMetric::LatencyAbove {
+                percentile,
+                threshold_ms: number(threshold)?,
+            }
+        } else if let Some((metric, percent)) = condition.split_once('<') {
+            if metric.trim() != "bitrate" {
+                return Err(invalid());
+            }
+            AbortMetric::BitrateBelow {
+                percent: number(percent.trim().trim_end_matches('%'))?,
+            }
+        } else {
+            return Err(invalid());
+        };
+        Ok(AbortCondition { metric, window })
+    }
+}
+
+impl std::fmt::Display for AbortCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.metric {
+            AbortMetric::LatencyAbove {
+                percentile,
+                threshold_ms,
+            } => write!(f, "latency_p{}>{}", percentile, threshold_ms)?,
+            AbortMetric::BitrateBelow { percent } => write!(f, "bitrate<{}%", percent)?,
+        }
+        write!(f, ":{}", self.window.as_secs())
+    }
+}
+
+/* A latency sample: unix ms, latency in ms and receive rate in kbps. */
+struct Sample {
+    timestamp: u128,
+    latency: f64,
+    receive_kbps: f64,
+}
+
+/// Checks the conditions on every latency sample.
+pub struct AbortMonitor {
+    conditions: Vec<AbortCondition>,
+    samples: VecDeque<Sample>,
+    received_bytes: Counter,
+    /* Time, receive rate and frame rate of the samples while warming up. */
+    warmup: Vec<(u128, f64, f64)>,
+    /* Time of the first sample after the warm-up. */
+    first: Option<u128>,
+    /* Whether missing the target bitrate was already reported. */
+    warned_target: bool,
+}
+
+impl AbortMonitor {
+    pub fn new(conditions: Vec<AbortCondition>) -> Self {
+        AbortMonitor {
+            conditions,
+            samples: VecDeque::new(),
+            received_bytes: Counter::new(),
+            warmup: vec![],
+            first: None,
+            warned_target: false,
+        }
+    }
+
+    /// Records a sample taken at `timestamp` (unix ms), the reason to abort
+    /// if a condition held over its whole window. Bitrate conditions are
+    /// skipped while the publisher config and so the target is unknown,
+    /// without it the warm-up can't be told and no sample is skipped.
+    pub fn update(
+        &mut self,
+        timestamp: u128,
+        latency_ms: f64,
+        total_bytes: f64,
+        frames_per_second: f64,
+        target_kbps: Option<f64>,
+    ) -> Option<String> {
+        if self.conditions.is_empty() {
+            return None;
+        }
+        let receive_kbps = self.received_bytes.update(total_bytes, timestamp).kbps();
+        self.samples.push_back(Sample {
+            timestamp,
+            latency: latency_ms,
+            receive_kbps,
+        });
+        let longest = self.conditions.iter().map(|c| c.window).max()?;
+        while self
+            .samples
+            .front()
+            .is_some_and(|sample| timestamp.saturating_sub(sample.timestamp) > longest.as_millis())
+        {
+            self.samples.pop_front();
+        }
+        let first = match self.first {
+            Some(first) => first,
+            None => {
+                self.warmup
+                    .push((timestamp, receive_kbps, frames_per_second));
+                let steady = match target_kbps {
+                    Some(target_kbps) => {
+                        let (kbps, fps): (Vec<f64>, Vec<f64>) = self
+                            .warmup
+                            .iter()
+                            .map(|(_, kbps, fps)| (*kbps, *fps))
+                            .unzip();
+                        warmup::detect(&kbps, &fps, target_kbps)
+                    }
+                    None => Some(0),
+                };
+                let first = self.warmup[steady?].0;
+                self.warmup.clear();
+                self.samples.retain(|sample| sample.timestamp >= first);
+                *self.first.insert(first)
+            }
+        };
+
+        for condition in &self.conditions {
+            let window = condition.window.as_millis();
+            if timestamp.saturating_sub(first) < window {
+                continue;
+            }
+            let samples = self
+                .samples
+                .iter()
+                .filter(|sample| timestamp.saturating_sub(sample.timestamp) <= window);
+            match condition.metric {
+                AbortMetric::LatencyAbove {
+                    percentile,
+                    threshold_ms,
+                } => {
+                    let mut latencies: Vec<f64> = samples.map(|sample| sample.latency).collect();
+                    if latencies.is_empty() {
+                        continue;
+                    }
+                    latencies.sort_by(f64::total_cmp);
+                    let rank = (percentile / 100. * latencies.len() as f64).ceil() as usize;
+                    let value = latencies[rank.clamp(1, latencies.len()) - 1];
+                    if value > threshold_ms {
+                        return Some(format!(
+                            "{}: p{} latency {:.0} ms over the last {} s, above {} ms",
+                            condition,
+                            percentile,
+                            value,
+                            condition.window.as_secs(),
+                            threshold_ms
+                        ));
+                    }
+                }
+                AbortMetric::BitrateBelow { percent } => {
+                    let Some(target_kbps) = target_kbps.filter(|target| *target > 0.) else {
+                        if !self.warned_target {
+                            log::warn!("Target bitrate unknown, {} is not checked", condition);
+                            self.warned_target = true;
+                        }
+                        continue;
+                    };
+                    let rates: Vec<f64> = samples
+                        .map(|sample| sample.receive_kbps)
+                        .filter(|kbps| kbps.is_finite())
+                        .collect();
+                    if rates.is_empty() {
+                        continue;
+                    }
+                    let mean = rates.iter().sum::<f64>() / rates.len() as f64;
+                    let share = mean / target_kbps * 100.;
+                    if share < percent {
+                        return Some(format!(
+                            "{}: {:.0} kbps over the last {} s, {:.1}% of the {} kbps target",
+                            condition,
+                            mean,
+                            condition.window.as_secs(),
+                            share,
+                            target_kbps
+                        ));
+                    }
+                }
+            }
+        }
+        None
+    }
+}
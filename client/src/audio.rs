@@ -0,0 +1,203 @@
+/*
+ * Receive side stats of the remote audio tracks, when the room has any.
+ * The benchmark only publishes video, but with a microphone track next to
+ * the screen share the two compete for the same bandwidth estimate, and an
+ * encoder that overshoots shows up here as audio loss and concealment.
+ */
+use common::delta::Counter;
+use common::sink::Value;
+use livekit::prelude::*;
+use livekit::webrtc::stats::RtcStats;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often the audio tracks are sampled.
+pub const AUDIO_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct AudioSample {
+    /// Unix ms when the stats were read.
+    pub timestamp: u128,
+    pub track: String,
+    pub packets_received: u64,
+    pub packets_lost: i64,
+    /// Interarrival jitter, ms.
+    pub jitter: f64,
+    /// Average jitter buffer delay of the emitted samples, ms.
+    pub jitter_buffer_delay: f64,
+    pub total_samples_received: u64,
+    pub concealed_samples: u64,
+    pub concealment_events: u64,
+    /// Average playout delay, ms, NaN when no playout stats are reported.
+    pub playout_delay: f64,
+    pub synthesized_samples_events: u32,
+}
+
+async fn sample_track(track: &RemoteAudioTrack) -> Option<AudioSample> {
+    let stats = match track.get_stats().await {
+        Ok(stats) => stats,
+        Err(e) => {
+            log::warn!("Failed to get stats of audio track {}: {:?}", track.sid(), e);
+            return None;
+        }
+    };
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let (playout_delay, synthesized_samples_events) = stats
+        .iter()
+        .find_map(|stat| match stat {
+            RtcStats::MediaPlayout(playout) => {
+                let playout = &playout.audio_playout;
+                let delay = if playout.total_samples_count > 0 {
+                    playout.total_playout_delay / playout.total_samples_count as f64 * 1000.
+                } else {
+                    f64::NAN
+                };
+                Some((delay, playout.synthesized_samples_events))
+            }
+            _ => None,
+        })
+        .unwrap_or((f64::NAN, 0));
+    stats.iter().find_map(|stat| match stat {
+        RtcStats::InboundRtp(stats) => {
+            let emitted = stats.inbound.jitter_buffer_emitted_count as f64;
+            Some(AudioSample {
+                timestamp,
+                track: track.sid().to_string(),
+                packets_received: stats.received.packets_received,
+                packets_lost: stats.received.packets_lost,
+                jitter: stats.received.jitter * 1000.,
+                jitter_buffer_delay: if emitted > 0. {
+                    stats.inbound.jitter_buffer_delay / emitted * 1000.
+                } else {
+                    0.
+                },
+                total_samples_received: stats.inbound.total_samples_received,
+                concealed_samples: stats.inbound.concealed_samples,
+                concealment_events: stats.inbound.concealment_events,
+                playout_delay,
+                synthesized_samples_events,
+            })
+        }
+        _ => None,
+    })
+}
+
+/// Samples every subscribed remote audio track into `samples`, following
+/// the tracks subscribed and unsubscribed during the run.
+pub fn record_audio_stats(
+    room: &Room,
+    samples: Arc<Mutex<Vec<AudioSample>>>,
+) -> tokio::task::JoinHandle<()> {
+    let mut room_events = room.subscribe();
+    let mut tracks: Vec<RemoteAudioTrack> = Vec::new();
+    for participant in room.remote_participants().values() {
+        for publication in participant.track_publications().values() {
+            if publication.kind() != TrackKind::Audio {
+                continue;
+            }
+            match publication.track() {
+                Some(RemoteTrack::Audio(track)) => tracks.push(track),
+                /* Without auto subscribe the track arrives with `TrackSubscribed`. */
+                _ => publication.set_subscribed(true),
+            }
+        }
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(AUDIO_SAMPLE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    for track in &tracks {
+                        if let Some(sample) = sample_track(track).await {
+                            samples.lock().unwrap().push(sample);
+                        }
+                    }
+                }
+                event = room_events.recv() => match event {
+                    Some(RoomEvent::TrackPublished { publication, .. })
+                        if publication.kind() == TrackKind::Audio =>
+                    {
+                        publication.set_subscribed(true);
+                    }
+                    Some(RoomEvent::TrackSubscribed {
+                        track: RemoteTrack::Audio(track),
+                        ..
+                    }) => {
+                        log::info!("Recording stats of audio track {}", track.sid());
+                        tracks.push(track);
+                    }
+                    Some(RoomEvent::TrackUnsubscribed {
+                        track: RemoteTrack::Audio(track),
+                        ..
+                    }) => tracks.retain(|t| t.sid() != track.sid()),
+                    Some(_) => {}
+                    None => break,
+                },
+            }
+        }
+    })
+}
+
+/* Cumulative counters are followed by their per-sample deltas at the end. */
+pub const AUDIO_HEADER: [&str; 16] = [
+    "timestamp",
+    "run",
+    "track",
+    "packets_received",
+    "packets_lost",
+    "jitter",
+    "jitter_buffer_delay",
+    "total_samples_received",
+    "concealed_samples",
+    "concealment_events",
+    "playout_delay",
+    "synthesized_samples_events",
+    "packets_received_delta",
+    "packets_lost_delta",
+    "concealed_samples_delta",
+    "concealment_events_delta",
+];
+
+/// The samples as rows for a stats sink, deltas are taken per track.
+pub fn audio_rows(samples: &[AudioSample], run: &str) -> Vec<Vec<Value>> {
+    let mut counters: HashMap<&str, [Counter; 4]> = HashMap::new();
+    samples
+        .iter()
+        .map(|sample| {
+            let [received, lost, concealed, events] =
+                counters.entry(sample.track.as_str()).or_default();
+            let timestamp = sample.timestamp;
+            vec![
+                sample.timestamp.into(),
+                run.into(),
+                sample.track.as_str().into(),
+                sample.packets_received.into(),
+                sample.packets_lost.into(),
+                Value::float(sample.jitter, 2),
+                Value::float(sample.jitter_buffer_delay, 2),
+                sample.total_samples_received.into(),
+                sample.concealed_samples.into(),
+                sample.concealment_events.into(),
+                Value::float(sample.playout_delay, 2),
+                sample.synthesized_samples_events.into(),
+                received
+                    .update(sample.packets_received as f64, timestamp)
+                    .delta
+                    .into(),
+                lost.update(sample.packets_lost as f64, timestamp).delta.into(),
+                concealed
+                    .update(sample.concealed_samples as f64, timestamp)
+                    .delta
+                    .into(),
+                events
+                    .update(sample.concealment_events as f64, timestamp)
+                    .delta
+                    .into(),
+            ]
+        })
+        .collect()
+}
@@ -0,0 +1,263 @@
+/*
+ * Client side of the control protocol: drives the publisher and syncs
+ * clocks with it before the measurement, and watches its heartbeat during
+ * it, so a stalled publisher shows up in the events instead of as a
+ * latency gap.
+ */
+use common::clock::{ClockOffset, ClockSample, now_us};
+use common::control::{
+    CONTROL_TOPIC, ControlMessage, FINISH_PROTOCOL, LEGACY_WATERMARK, PublisherConfig,
+    WATERMARK_FRAMES,
+};
+use common::events::Event;
+use livekit::prelude::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Ping exchanges for the clock offset estimate.
+const CLOCK_SYNC_PINGS: u32 = 9;
+
+/// Heartbeats are sent every second, a few missed ones mean the publisher is gone.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Default)]
+pub struct ControlOptions {
+    /// Start a publisher waiting with `--wait-for-start`.
+    pub start: bool,
+    /// Bitrate in kbps to switch the publisher to.
+    pub bitrate: Option<u64>,
+    pub fps: Option<u32>,
+}
+
+impl ControlOptions {
+    pub fn changes_parameters(&self) -> bool {
+        self.bitrate.is_some() || self.fps.is_some()
+    }
+}
+
+async fn send(room: &Room, message: &ControlMessage) -> bool {
+    let res = room
+        .local_participant()
+        .publish_data(DataPacket {
+            payload: message.encode(),
+            topic: Some(CONTROL_TOPIC.to_string()),
+            reliable: true,
+            ..Default::default()
+        })
+        .await;
+    if let Err(e) = res {
+        log::warn!("Failed to send control message: {:?}", e);
+        return false;
+    }
+    true
+}
+
+/// Asks the publisher to mark the next frames for tick `id`, with the
+/// legacy request when its `protocol` version predates the control message.
+pub async fn request_watermark(room: &Room, id: u64, protocol: u32) -> bool {
+    if protocol > 0 {
+        let message = ControlMessage::Watermark {
+            id,
+            frames: WATERMARK_FRAMES,
+        };
+        return send(room, &message).await;
+    }
+    let res = room
+        .local_participant()
+        .publish_data(DataPacket {
+            payload: format!("{}:{}", LEGACY_WATERMARK, id).into_bytes(),
+            reliable: true,
+            ..Default::default()
+        })
+        .await;
+    if let Err(e) = res {
+        log::warn!("Failed to send watermark request: {:?}", e);
+        return false;
+    }
+    true
+}
+
+/// Tells the publisher the run is over, it stops and writes its results
+/// as when its duration runs out. False for a publisher whose `protocol`
+/// version predates `Finish`, which would ignore it.
+pub async fn finish_publisher(room: &Room, protocol: u32) -> bool {
+    protocol >= FINISH_PROTOCOL && send(room, &ControlMessage::Finish).await
+}
+
+/// Applies `options` on the publisher and returns the configuration it reports.
+pub async fn control_publisher(room: &Room, options: &ControlOptions) -> Option<PublisherConfig> {
+    let mut events = room.subscribe();
+    if options.changes_parameters() {
+        let message = ControlMessage::SetParameters {
+            bitrate: options.bitrate,
+            fps: options.fps,
+        };
+        send(room, &message).await;
+    }
+    if options.start {
+        send(room, &ControlMessage::Start).await;
+    }
+    /* A parameter change is answered with the config once the tracks are republished. */
+    if !options.changes_parameters() && !send(room, &ControlMessage::GetConfig).await {
+        return None;
+    }
+
+    let response = async {
+        while let Some(event) = events.recv().await {
+            if let RoomEvent::DataReceived { payload, topic, .. } = event {
+                if topic.as_deref() != Some(CONTROL_TOPIC) {
+                    continue;
+                }
+                if let Some(ControlMessage::Config(config)) = ControlMessage::decode(&payload) {
+                    return Some(config);
+                }
+            }
+        }
+        None
+    };
+    let config = tokio::time::timeout(Duration::from_secs(10), response)
+        .await
+        .ok()
+        .flatten();
+    match &config {
+        Some(config) => log::info!("Publisher config: {:?}", config),
+        None => log::warn!("Publisher didn't send its config"),
+    }
+    config
+}
+
+/*
+ * Estimates how far the publisher clock is ahead of ours from a few ping
+ * exchanges. Pings are sent one at a time so they don't queue behind each
+ * other, and a lost one is simply left out of the median.
+ */
+pub async fn estimate_clock_offset(room: &Room) -> Option<ClockOffset> {
+    let mut events = room.subscribe();
+    let mut samples = Vec::new();
+    for id in 0..CLOCK_SYNC_PINGS {
+        let sent = now_us();
+        if !send(room, &ControlMessage::Ping { id, sent }).await {
+            continue;
+        }
+        let pong = async {
+            while let Some(event) = events.recv().await {
+                if let RoomEvent::DataReceived { payload, topic, .. } = event {
+                    let returned = now_us();
+                    if topic.as_deref() != Some(CONTROL_TOPIC) {
+                        continue;
+                    }
+                    if let Some(ControlMessage::Pong {
+                        id: pong_id,
+                        sent,
+                        received,
+                        replied,
+                    }) = ControlMessage::decode(&payload)
+                    {
+                        if pong_id == id {
+                            return Some(ClockSample {
+                                sent,
+                                received,
+                                replied,
+                                returned,
+                            });
+                        }
+                    }
+                }
+            }
+            None
+        };
+        if let Ok(Some(sample)) = tokio::time::timeout(Duration::from_secs(1), pong).await {
+            samples.push(sample);
+        }
+    }
+
+    let offset = ClockOffset::estimate(&samples);
+    match &offset {
+        Some(offset) => log::info!(
+            "Publisher clock offset {:.1}ms (rtt {:.1}ms, {} samples)",
+            offset.offset_ms,
+            offset.rtt_ms,
+            offset.samples
+        ),
+        None => log::warn!("Publisher didn't answer the clock sync pings"),
+    }
+    offset
+}
+
+/// Keeps the last configuration the publisher broadcast in `config`, it
+/// sends one when its tracks are published and on every change.
+pub fn follow_publisher_config(
+    room: &Room,
+    config: Arc<Mutex<Option<PublisherConfig>>>,
+) -> tokio::task::JoinHandle<()> {
+    let mut room_events = room.subscribe();
+    tokio::spawn(async move {
+        while let Some(event) = room_events.recv().await {
+            let RoomEvent::DataReceived { payload, topic, .. } = event else {
+                continue;
+            };
+            if topic.as_deref() != Some(CONTROL_TOPIC) {
+                continue;
+            }
+            if let Some(ControlMessage::Config(update)) = ControlMessage::decode(&payload) {
+                let mut config = config.lock().unwrap();
+                if config.as_ref() != Some(&update) {
+                    log::info!("Publisher config: {:?}", update);
+                    *config = Some(update);
+                }
+            }
+        }
+    })
+}
+
+/// Records when the publisher heartbeat stops and comes back in `events`.
+pub fn monitor_heartbeat(
+    room: &Room,
+    events: Arc<Mutex<Vec<Event>>>,
+) -> tokio::task::JoinHandle<()> {
+    let mut room_events = room.subscribe();
+    tokio::spawn(async move {
+        let mut lost = false;
+        loop {
+            let heartbeat = async {
+                while let Some(event) = room_events.recv().await {
+                    if let RoomEvent::DataReceived { payload, topic, .. } = event {
+                        if topic.as_deref() != Some(CONTROL_TOPIC) {
+                            continue;
+                        }
+                        if let Some(ControlMessage::Heartbeat { capturing, .. }) =
+                            ControlMessage::decode(&payload)
+                        {
+                            return Some(capturing);
+                        }
+                    }
+                }
+                None
+            };
+            match tokio::time::timeout(HEARTBEAT_TIMEOUT, heartbeat).await {
+                Ok(Some(capturing)) => {
+                    if lost {
+                        lost = false;
+                        let detail = format!("capturing {}", capturing);
+                        log::info!("Publisher heartbeat restored, {}", detail);
+                        events
+                            .lock()
+                            .unwrap()
+                            .push(Event::new("publisher_heartbeat_restored", detail));
+                    }
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    if !lost {
+                        lost = true;
+                        log::warn!("Publisher heartbeat lost");
+                        events.lock().unwrap().push(Event::new(
+                            "publisher_heartbeat_lost",
+                            format!("no heartbeat for {}s", HEARTBEAT_TIMEOUT.as_secs()),
+                        ));
+                    }
+                }
+            }
+        }
+    })
+}
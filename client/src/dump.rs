@@ -0,0 +1,337 @@
+/*
+ * Raw dump of every RTC stats report the client reads, one JSON line per
+ * read, for the fields the latency table doesn't pick. The SDK only derives
+ * `Debug` for `RtcStats`, not `Serialize`, so the reports are converted from
+ * their `Debug` output: structs become objects keyed by field, `Some(x)`
+ * becomes `x` and `None` null, other tuple variants `{"Variant": value}` and
+ * unit variants strings. Fields added by a new SDK version show up without
+ * a code change. A report that can't be converted is kept as its `Debug`
+ * string.
+ */
+use common::schemas::{SCHEMA_VERSION, SCHEMA_VERSION_KEY};
+use livekit::webrtc::stats::RtcStats;
+use serde_json::{Map, Number, Value, json};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+use std::sync::Mutex;
+
+pub struct RtcStatsDump {
+    file: Mutex<BufWriter<File>>,
+    run: String,
+}
+
+impl RtcStatsDump {
+    pub fn create(path: &Path, run: &str) -> io::Result<Self> {
+        Ok(RtcStatsDump {
+            file: Mutex::new(BufWriter::new(File::create(path)?)),
+            run: run.to_string(),
+        })
+    }
+
+    /// Appends the reports of one read, `sample` tells what triggered it.
+    pub fn write(&self, sample: &str, track: &str, stats: &[RtcStats]) {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let line = json!({
+            "timestamp": timestamp,
+            "run": self.run,
+            "sample": sample,
+            "track": track,
+            "stats": stats.iter().map(debug_to_json).collect::<Vec<_>>(),
+            SCHEMA_VERSION_KEY: SCHEMA_VERSION,
+        });
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            log::warn!("Failed to write the RTC stats dump: {}", e);
+        }
+    }
+}
+
+fn debug_to_json(stats: &RtcStats) -> Value {
+    let debug = format!("{:?}", stats);
+    parse_debug(&debug).unwrap_or_else(|| json!({ "debug": debug }))
+}
+
+/* The whole of `debug` as one value, None when anything is left over. */
+fn parse_debug(debug: &str) -> Option<Value> {
+    let mut parser = Parser {
+        chars: debug.chars().peekable(),
+    };
+    parser.value().filter(|_| parser.at_end())
+}
+
+/* Recursive descent over the `Debug` output of derived impls and std types. */
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_spaces(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_spaces();
+        self.chars.peek().is_none()
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        self.skip_spaces();
+        self.chars.next_if_eq(&expected).is_some()
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.skip_spaces();
+        match *self.chars.peek()? {
+            '"' => self.string().map(Value::String),
+            '[' => {
+                self.chars.next();
+                self.list(']').map(Value::Array)
+            }
+            '{' => {
+                self.chars.next();
+                self.map()
+            }
+            c if c == '-' || c.is_ascii_digit() => self.number(),
+            c if c.is_alphabetic() || c == '_' => self.named(),
+            _ => None,
+        }
+    }
+
+    /* Values up to `close`, separated by commas, with an optional trailing one. */
+    fn list(&mut self, close: char) -> Option<Vec<Value>> {
+        let mut values = vec![];
+        while !self.eat(close) {
+            values.push(self.value()?);
+            if !self.eat(',') {
+                return self.eat(close).then_some(values);
+            }
+        }
+        Some(values)
+    }
+
+    /* A `HashMap`, `{"key": value, ...}`. */
+    fn map(&mut self) -> Option<Value> {
+        let mut map = Map::new();
+        while !self.eat('}') {
+            let key = match self.value()? {
+                Value::String(key) => key,
+                key => key.to_string(),
+            };
+            if !self.eat(':') {
+                return None;
+            }
+            map.insert(key, self.value()?);
+            if !self.eat(',') {
+                return self.eat('}').then_some(Value::Object(map));
+            }
+        }
+        Some(Value::Object(map))
+    }
+
+    /* A struct, a tuple or unit variant, a bool, or a non-finite float. */
+    fn named(&mut self) -> Option<Value> {
+        let name = self.ident();
+        if self.eat('{') {
+            let mut fields = Map::new();
+            while !self.eat('}') {
+                let field = self.ident();
+                if field.is_empty() || !self.eat(':') {
+                    return None;
+                }
+                fields.insert(field, self.value()?);
+                if !self.eat(',') {
+                    return self.eat('}').then_some(Value::Object(fields));
+                }
+            }
+            return Some(Value::Object(fields));
+        }
+        if self.eat('(') {
+            let mut values = self.list(')')?;
+            let value = match values.len() {
+                1 => values.pop().unwrap(),
+                _ => Value::Array(values),
+            };
+            return Some(match name.as_str() {
+                "Some" => value,
+                _ => json!({ name: value }),
+            });
+        }
+        Some(match name.as_str() {
+            "None" | "NaN" | "inf" => Value::Null,
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => Value::String(name),
+        })
+    }
+
+    fn ident(&mut self) -> String {
+        self.skip_spaces();
+        let mut ident = String::new();
+        while let Some(c) = self.chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+            ident.push(c);
+        }
+        ident
+    }
+
+    fn number(&mut self) -> Option<Value> {
+        let mut number = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.'))
+        {
+            number.push(c);
+        }
+        if let Ok(n) = number.parse::<i64>() {
+            return Some(n.into());
+        }
+        if let Ok(n) = number.parse::<u64>() {
+            return Some(n.into());
+        }
+        /* `-inf` isn't valid JSON either. */
+        let n: f64 = number.parse().ok()?;
+        Some(Number::from_f64(n).map_or(Value::Null, Value::Number))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.chars.next();
+        let mut string = String::new();
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(string),
+                '\\' => string.push(match self.chars.next()? {
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    '0' => '\0',
+                    'u' => {
+                        if self.chars.next()? != '{' {
+                            return None;
+                        }
+                        let hex: String = self.chars.by_ref().take_while(|c| *c != '}').collect();
+                        char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                    }
+                    c => c,
+                }),
+                c => string.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug)]
+    enum Kind {
+        Inbound,
+        Codec(u32),
+        Pair(u32, String),
+    }
+
+    #[derive(Debug)]
+    struct Inner {
+        kind: Kind,
+        mime_type: String,
+    }
+
+    #[derive(Debug)]
+    struct Report {
+        id: String,
+        bytes: u64,
+        jitter: f64,
+        level: Option<i32>,
+        missing: Option<f64>,
+        active: bool,
+        kinds: Vec<Kind>,
+        inner: Inner,
+        totals: BTreeMap<String, f64>,
+        empty: Vec<u8>,
+    }
+
+    fn parse(value: impl std::fmt::Debug) -> Option<Value> {
+        parse_debug(&format!("{:?}", value))
+    }
+
+    #[test]
+    fn parses_derived_debug_output() {
+        let report = Report {
+            id: "RTCInboundRTPVideoStream_1".to_string(),
+            bytes: u64::MAX,
+            jitter: 0.25,
+            level: Some(-3),
+            missing: None,
+            active: true,
+            kinds: vec![
+                Kind::Inbound,
+                Kind::Codec(96),
+                Kind::Pair(1, "a".to_string()),
+            ],
+            inner: Inner {
+                kind: Kind::Inbound,
+                mime_type: "video/VP9".to_string(),
+            },
+            totals: BTreeMap::from([("frames".to_string(), 1.5)]),
+            empty: vec![],
+        };
+        assert_eq!(
+            parse(&report),
+            Some(json!({
+                "id": "RTCInboundRTPVideoStream_1",
+                "bytes": u64::MAX,
+                "jitter": 0.25,
+                "level": -3,
+                "missing": null,
+                "active": true,
+                "kinds": ["Inbound", {"Codec": 96}, {"Pair": [1, "a"]}],
+                "inner": {"kind": "Inbound", "mime_type": "video/VP9"},
+                "totals": {"frames": 1.5},
+                "empty": [],
+            }))
+        );
+    }
+
+    #[test]
+    fn parses_pretty_debug_output() {
+        let inner = Inner {
+            kind: Kind::Codec(35),
+            mime_type: "video/AV1".to_string(),
+        };
+        assert_eq!(
+            parse_debug(&format!("{:#?}", inner)),
+            Some(json!({"kind": {"Codec": 35}, "mime_type": "video/AV1"}))
+        );
+    }
+
+    #[test]
+    fn unescapes_strings() {
+        assert_eq!(
+            parse("tab\t \"quoted\" \\ \u{7f} é\n"),
+            Some(json!("tab\t \"quoted\" \\ \u{7f} é\n"))
+        );
+    }
+
+    #[test]
+    fn non_finite_floats_become_null() {
+        assert_eq!(parse(f64::NAN), Some(Value::Null));
+        assert_eq!(parse(f64::INFINITY), Some(Value::Null));
+        assert_eq!(parse(f64::NEG_INFINITY), Some(Value::Null));
+        assert_eq!(parse(1e300), Some(json!(1e300)));
+    }
+
+    #[test]
+    fn rejects_incomplete_output() {
+        assert_eq!(parse_debug("Inner { kind: Inbound"), None);
+        assert_eq!(parse_debug("[1, 2"), None);
+        assert_eq!(parse_debug("\"open"), None);
+        assert_eq!(parse_debug("Some(1) trailing"), None);
+        assert_eq!(parse_debug(""), None);
+    }
+}
@@ -0,0 +1,57 @@
+use common::events::Event;
+use common::fingerprint::{self, CORRUPTION_DISTANCE};
+use common::metadata::RunMetadata;
+use livekit::webrtc::prelude::I420Buffer;
+
+/* Corrupted frames recorded as events at most, the count goes on. */
+const MAX_EVENTS: u64 = 100;
+
+/*
+ * Compares the decoded frames with the content hash the publisher marked
+ * them with (`--fingerprint`, see `common::fingerprint`). A frame whose
+ * hash differs in more bits than compression explains is logged and
+ * recorded as a `corrupted_frame` event. Markers that can't be read, e.g.
+ * smeared at a very low bitrate, are only counted.
+ */
+#[derive(Default)]
+pub struct FingerprintCheck {
+    checked: u64,
+    unreadable: u64,
+    corrupted: u64,
+    max_distance: u32,
+}
+
+impl FingerprintCheck {
+    /// Checks decoded frame number `frame`, returns the event to record
+    /// when it is corrupted.
+    pub fn check(&mut self, buffer: &I420Buffer, frame: u64) -> Option<Event> {
+        let (width, height) = (buffer.width() as usize, buffer.height() as usize);
+        let (stride_y, _, _) = buffer.strides();
+        let (data_y, _, _) = buffer.data();
+        self.checked += 1;
+        let Some(sent) = fingerprint::read_marker(data_y, stride_y as usize, width, height) else {
+            self.unreadable += 1;
+            return None;
+        };
+        let received = fingerprint::hash(data_y, stride_y as usize, width, height);
+        let (distance, compared) = received.distance(sent);
+        self.max_distance = self.max_distance.max(distance);
+        if distance <= CORRUPTION_DISTANCE {
+            return None;
+        }
+        self.corrupted += 1;
+        let detail = format!(
+            "frame {}, {} of {} hash bits differ",
+            frame, distance, compared
+        );
+        log::warn!("Corrupted frame: {}", detail);
+        (self.corrupted <= MAX_EVENTS).then(|| Event::new("corrupted_frame", detail))
+    }
+
+    pub fn record(&self, metadata: &mut RunMetadata) {
+        metadata.set("fingerprint_frames", self.checked);
+        metadata.set("fingerprint_unreadable", self.unreadable);
+        metadata.set("corrupted_frames", self.corrupted);
+        metadata.set("max_fingerprint_distance", self.max_distance);
+    }
+}
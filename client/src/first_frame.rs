@@ -0,0 +1,147 @@
+use crate::track_filter::TrackFilter;
+use common::events::Event;
+use futures::StreamExt;
+use livekit::prelude::*;
+use livekit::webrtc::video_stream::native::NativeVideoStream;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/* The first decoded frame, how long after the subscription it arrived. */
+struct Arrival {
+    after: Duration,
+    /// Milliseconds since the unix epoch, for the event.
+    timestamp: u128,
+    resolution: (u32, u32),
+}
+
+/* The wait for the first frame of every matching track, by track sid. */
+type Arrivals = Arc<Mutex<HashMap<TrackSid, JoinHandle<Option<Arrival>>>>>;
+
+/*
+ * Time to first frame: from the subscription of the screenshare track to
+ * its first decoded frame, the wait a viewer sees when joining a share.
+ * The room events are followed from right after connecting, and every
+ * matching track gets a video stream of its own the moment its
+ * `TrackSubscribed` event arrives, so neither the control exchanges before
+ * the measurement nor the warm-up delay it. Which of the tracks is
+ * reported is only known once the measurement picks one, e.g. the
+ * republished track when the client changes the publisher's parameters.
+ * A track already subscribed when the client connected has no known
+ * subscription time and isn't timed.
+ */
+pub struct FirstFrame {
+    watcher: JoinHandle<()>,
+    arrivals: Arrivals,
+    track: Option<TrackSid>,
+}
+
+impl FirstFrame {
+    /// Starts following the subscriptions of tracks `filter` matches. Call
+    /// it right after connecting, events before are missed.
+    pub fn watch(room: &Room, filter: TrackFilter) -> Self {
+        let mut room_events = room.subscribe();
+        let arrivals = Arrivals::default();
+        let watched = arrivals.clone();
+        let watcher = tokio::spawn(async move {
+            while let Some(event) = room_events.recv().await {
+                let RoomEvent::TrackSubscribed {
+                    track: RemoteTrack::Video(track),
+                    publication,
+                    participant,
+                } = event
+                else {
+                    continue;
+                };
+                if filter.matches(&publication, &participant) {
+                    let arrival = first_arrival(&track, Instant::now());
+                    watched.lock().unwrap().insert(track.sid(), arrival);
+                }
+            }
+        });
+        FirstFrame {
+            watcher,
+            arrivals,
+            track: None,
+        }
+    }
+
+    /// The track the measurement runs on, whose first frame is reported.
+    pub fn select(&mut self, track: TrackSid) {
+        self.track = Some(track);
+    }
+
+    /// The time to first frame of the selected track, once the measurement
+    /// is over, recorded as a `first_frame` event in `events`. `None` when
+    /// no frame arrived or the track wasn't timed.
+    pub async fn finish(mut self, events: &Mutex<Vec<Event>>) -> Option<Duration> {
+        self.watcher.abort();
+        let track = self.track.take();
+        let task = track.and_then(|track| self.arrivals.lock().unwrap().remove(&track));
+        drop(self);
+        let Some(task) = task else {
+            log::warn!("Track subscribed before the client connected, time to first frame unknown");
+            return None;
+        };
+        /* By now the frame either came or never will. */
+        if !task.is_finished() {
+            task.abort();
+        }
+        let Some(arrival) = task.await.ok().flatten() else {
+            log::warn!("No frame decoded, time to first frame unknown");
+            return None;
+        };
+        let mut event = Event::new(
+            "first_frame",
+            format!(
+                "{} ms after subscribing, {}x{}",
+                arrival.after.as_millis(),
+                arrival.resolution.0,
+                arrival.resolution.1
+            ),
+        );
+        /* It came before the measurement started, the events stay in time order. */
+        event.timestamp = arrival.timestamp;
+        let mut events = events.lock().unwrap();
+        let position = events.partition_point(|other| other.timestamp <= event.timestamp);
+        events.insert(position, event);
+        Some(arrival.after)
+    }
+}
+
+/* Stops the watching, also when no track was found to measure. */
+impl Drop for FirstFrame {
+    fn drop(&mut self) {
+        self.watcher.abort();
+        for (_, task) in self.arrivals.lock().unwrap().drain() {
+            task.abort();
+        }
+    }
+}
+
+/* Waits for the first frame of `track`, subscribed at `subscribed`. */
+fn first_arrival(track: &RemoteVideoTrack, subscribed: Instant) -> JoinHandle<Option<Arrival>> {
+    let mut video_sink = NativeVideoStream::new(track.rtc_track());
+    let sid = track.sid();
+    tokio::spawn(async move {
+        let frame = video_sink.next().await?;
+        let after = subscribed.elapsed();
+        let arrival = Arrival {
+            after,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_millis(),
+            resolution: (frame.buffer.width(), frame.buffer.height()),
+        };
+        log::info!(
+            "First frame of {} {} ms after subscribing, {}x{}",
+            sid,
+            after.as_millis(),
+            arrival.resolution.0,
+            arrival.resolution.1
+        );
+        Some(arrival)
+    })
+}
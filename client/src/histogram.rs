@@ -0,0 +1,141 @@
+/*
+ * Distribution of the latency samples of a run, written next to the
+ * per-sample table at the end. Percentiles of a few dozen samples jump
+ * with every outlier, the shape of the distribution shows whether a run
+ * had a steady latency, a long tail or two modes (e.g. with and without a
+ * key frame in flight) and compares between runs as it is.
+ */
+use common::sink::Value;
+
+/// Bucket width unless `--latency-bucket-ms` sets one.
+pub const DEFAULT_BUCKET_MS: u64 = 10;
+
+/// Most buckets a histogram has before the overflow bucket, 5 s at the
+/// default width. A single stalled tick would otherwise add a row for
+/// every bucket up to it.
+pub const MAX_BUCKETS: u64 = 500;
+
+pub const HISTOGRAM_HEADER: [&str; 6] = [
+    "run",
+    "bucket_start_ms",
+    "bucket_end_ms",
+    "count",
+    "percent",
+    "cumulative_percent",
+];
+
+pub const CDF_HEADER: [&str; 4] = ["run", "latency_ms", "count", "cumulative_percent"];
+
+/// One row per `bucket_ms` wide bucket from 0 up to the highest latency,
+/// empty ones included so the buckets of two runs line up. Past
+/// `MAX_BUCKETS` the samples share one last bucket without an end.
+pub fn histogram_rows(latencies: &[u64], bucket_ms: u64, run: &str) -> Vec<Vec<Value>> {
+    let Some(max) = latencies.iter().max() else {
+        return vec![];
+    };
+    let bucket_ms = bucket_ms.max(1);
+    let overflow = MAX_BUCKETS as usize;
+    let mut counts = vec![0u64; ((max / bucket_ms) as usize).min(overflow) + 1];
+    for latency in latencies {
+        counts[((latency / bucket_ms) as usize).min(overflow)] += 1;
+    }
+    let total = latencies.len() as f64;
+    let mut cumulative = 0;
+    counts
+        .iter()
+        .enumerate()
+        .map(|(bucket, count)| {
+            cumulative += count;
+            let start = bucket as u64 * bucket_ms;
+            let end = if bucket < overflow {
+                Value::from(start + bucket_ms)
+            } else {
+                Value::from("")
+            };
+            vec![
+                run.into(),
+                start.into(),
+                end,
+                (*count).into(),
+                Value::float(*count as f64 / total * 100., 2),
+                Value::float(cumulative as f64 / total * 100., 2),
+            ]
+        })
+        .collect()
+}
+
+/// The empirical CDF, one row per distinct latency with the share of the
+/// samples at or below it.
+pub fn cdf_rows(latencies: &[u64], run: &str) -> Vec<Vec<Value>> {
+    let mut sorted = latencies.to_vec();
+    sorted.sort_unstable();
+    let total = sorted.len() as f64;
+    let mut rows = vec![];
+    let mut start = 0;
+    while start < sorted.len() {
+        let latency = sorted[start];
+        let end = start + sorted[start..].partition_point(|value| *value == latency);
+        rows.push(vec![
+            run.into(),
+            latency.into(),
+            ((end - start) as u64).into(),
+            Value::float(end as f64 / total * 100., 2),
+        ]);
+        start = end;
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(rows: &[Vec<Value>], index: usize) -> Vec<Value> {
+        rows.iter().map(|row| row[index].clone()).collect()
+    }
+
+    #[test]
+    fn histogram_includes_empty_buckets() {
+        let rows = histogram_rows(&[3, 12, 15, 41], 10, "run");
+        assert_eq!(rows.len(), 5);
+        assert_eq!(column(&rows, 1), [0u64, 10, 20, 30, 40].map(Value::from));
+        assert_eq!(column(&rows, 3), [1u64, 2, 0, 0, 1].map(Value::from));
+        assert_eq!(
+            rows[1],
+            vec![
+                Value::from("run"),
+                Value::from(10u64),
+                Value::from(20u64),
+                Value::from(2u64),
+                Value::Float(50.),
+                Value::Float(75.),
+            ]
+        );
+        assert_eq!(rows[4][5], Value::Float(100.));
+    }
+
+    #[test]
+    fn histogram_caps_the_range() {
+        let rows = histogram_rows(&[5, 4_999, 60_000, 120_000], 10, "run");
+        assert_eq!(rows.len(), MAX_BUCKETS as usize + 1);
+        let overflow = rows.last().unwrap();
+        assert_eq!(overflow[1], Value::from(MAX_BUCKETS * 10));
+        assert_eq!(overflow[2], Value::from(""));
+        assert_eq!(overflow[3], Value::from(2u64));
+        assert_eq!(rows[MAX_BUCKETS as usize - 1][3], Value::from(1u64));
+    }
+
+    #[test]
+    fn histogram_of_nothing_is_empty() {
+        assert!(histogram_rows(&[], 10, "run").is_empty());
+        assert!(cdf_rows(&[], "run").is_empty());
+    }
+
+    #[test]
+    fn cdf_has_a_row_per_distinct_latency() {
+        let rows = cdf_rows(&[30, 10, 30, 20], "run");
+        assert_eq!(column(&rows, 1), [10u64, 20, 30].map(Value::from));
+        assert_eq!(column(&rows, 2), [1u64, 1, 2].map(Value::from));
+        assert_eq!(column(&rows, 3), [25., 50., 100.].map(Value::Float));
+    }
+}
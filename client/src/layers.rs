@@ -0,0 +1,114 @@
+/*
+ * Subscriber side layer selection. The client asks the SFU for a given
+ * quality by requesting the matching dimensions on the publication, the way
+ * `setPreferredLayers` does in the JS SDK, following a schedule so layer
+ * switches of simulcast and SVC codecs can be compared.
+ */
+use common::events::Event;
+use livekit::prelude::{RemoteTrackPublication, TrackDimension};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayerQuality {
+    /// Quarter of the published dimensions.
+    Low,
+    /// Half of the published dimensions.
+    Medium,
+    High,
+}
+
+impl LayerQuality {
+    fn name(&self) -> &'static str {
+        match self {
+            LayerQuality::Low => "low",
+            LayerQuality::Medium => "medium",
+            LayerQuality::High => "high",
+        }
+    }
+
+    fn dimension(&self, published: &TrackDimension) -> TrackDimension {
+        let divisor = match self {
+            LayerQuality::Low => 4,
+            LayerQuality::Medium => 2,
+            LayerQuality::High => 1,
+        };
+        TrackDimension(published.0 / divisor, published.1 / divisor)
+    }
+}
+
+impl std::str::FromStr for LayerQuality {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(LayerQuality::Low),
+            "medium" => Ok(LayerQuality::Medium),
+            "high" => Ok(LayerQuality::High),
+            _ => Err(format!(
+                "Invalid layer quality: {}. Use 'low', 'medium' or 'high'",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerSwitch {
+    /// Time since the start of the measurement.
+    pub at: Duration,
+    pub quality: LayerQuality,
+}
+
+/// Layer switches parsed from `<seconds>:<quality>,...`, e.g. `0:high,30:low`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerSchedule(pub Vec<LayerSwitch>);
+
+impl std::str::FromStr for LayerSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut switches = s
+            .split(',')
+            .map(|entry| {
+                let (at, quality) = entry.split_once(':').ok_or_else(|| {
+                    format!("Invalid layer switch: {}. Use <seconds>:<quality>", entry)
+                })?;
+                let at = at
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid layer switch time: {}", at))?;
+                Ok(LayerSwitch {
+                    at: Duration::from_secs(at),
+                    quality: quality.trim().parse()?,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        switches.sort_by_key(|switch| switch.at);
+        Ok(LayerSchedule(switches))
+    }
+}
+
+/// Applies the schedule on `publication`, recording every switch in `events`.
+pub fn run_layer_schedule(
+    publication: RemoteTrackPublication,
+    schedule: LayerSchedule,
+    events: Arc<Mutex<Vec<Event>>>,
+) -> tokio::task::JoinHandle<()> {
+    /* Requesting a layer overwrites the publication dimensions, keep the original. */
+    let published = publication.dimension();
+    tokio::spawn(async move {
+        let start = tokio::time::Instant::now();
+        for switch in schedule.0 {
+            tokio::time::sleep_until(start + switch.at).await;
+            let dimension = switch.quality.dimension(&published);
+            let detail = format!("{} {}x{}", switch.quality.name(), dimension.0, dimension.1);
+            log::info!("Requesting layer {}", detail);
+            publication.update_video_dimensions(dimension);
+            events
+                .lock()
+                .unwrap()
+                .push(Event::new("layer_requested", detail));
+        }
+    })
+}
@@ -1,12 +1,200 @@
-use futures::StreamExt;
-use livekit::{
-    prelude::*,
-    webrtc::{prelude::RtcVideoTrack, video_stream::native::NativeVideoStream},
+use abort::{AbortCondition, AbortMonitor};
+use common::adherence::bitrate_adherence;
+use common::clock::{ClockOffset, now_us};
+use common::control::{CONTROL_TOPIC, ControlMessage, PublisherConfig};
+use common::delta::Counter;
+use common::events::{EVENTS_HEADER, Event, event_rows};
+use common::exclusion::{
+    EXCLUSION_ENDED_EVENT, EXCLUSION_STARTED_EVENT, EXCLUSIONS_START_KEY, ExclusionWindows,
 };
+use common::metadata::{METADATA_HEADER, RunMetadata};
+use common::output::{OutputLayout, RUN_ID_TOPIC, RunId, Side};
+use common::schemas::{SCHEMA_VERSION, SCHEMA_VERSION_KEY};
+use common::sink::{CsvSink, RollingCsv, SinkConfig, SinkKind, Value, create_sinks, write_table};
+use common::system_events::{self, SystemMonitor};
+use common::transport::{CandidatePair, IceTransport, RoomFeatures};
+use common::validity::{RECONNECTED_EVENT, RECONNECTING_EVENT};
+use common::watermark::WATERMARK;
+use dump::RtcStatsDump;
+use fingerprint::FingerprintCheck;
+use first_frame::FirstFrame;
+use futures::StreamExt;
+use livekit::webrtc::prelude::{I420Buffer, VideoBuffer};
+use livekit::{prelude::*, webrtc::video_stream::native::NativeVideoStream};
+use quality::{FrameQuality, QualityAverage};
+use record::FrameRecorder;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, Write};
-use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use sysinfo::{Components, Networks, Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+use tracing::Instrument;
+use track_filter::TrackFilter;
+use watermark::WatermarkScanner;
+
+pub mod abort;
+pub mod audio;
+pub mod control;
+pub mod dump;
+pub mod fingerprint;
+pub mod first_frame;
+pub mod histogram;
+pub mod layers;
+pub mod quality;
+pub mod record;
+#[cfg(feature = "render")]
+pub mod renderer;
+pub mod return_track;
+pub mod status;
+pub mod track_filter;
+pub mod transport;
+pub mod watermark;
+
+/// How the end-to-end latency of a frame is measured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LatencyMode {
+    /// Ask the publisher to darken the top rows and detect them in the Y plane.
+    Watermark,
+    /// Use the capture timestamp carried with the frame, when the SDK exposes it.
+    CaptureTime,
+}
+
+impl std::str::FromStr for LatencyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "watermark" => Ok(LatencyMode::Watermark),
+            "capture-time" => Ok(LatencyMode::CaptureTime),
+            _ => Err(format!(
+                "Invalid latency mode: {}. Use 'watermark' or 'capture-time'",
+                s
+            )),
+        }
+    }
+}
+
+impl LatencyMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LatencyMode::Watermark => "watermark",
+            LatencyMode::CaptureTime => "capture-time",
+        }
+    }
+}
+
+pub struct MeasurementOptions {
+    /// Number of received frames to skip before sampling.
+    pub warmup_frames: u64,
+    pub latency_mode: LatencyMode,
+    /// Root of the results directory, used when no output file is given.
+    pub output_dir: String,
+    /// Label recorded in the run metadata.
+    pub tag: Option<String>,
+    /// Where the latency samples and events are written.
+    pub sinks: SinkConfig,
+    /// Start/parameter requests sent to the publisher before measuring.
+    pub control: control::ControlOptions,
+    /// Simulcast/SVC layers to request during the run.
+    pub layer_schedule: Option<layers::LayerSchedule>,
+    /// How long to wait for the screenshare track to come back after it
+    /// was unsubscribed, zero to stop right away.
+    pub resubscribe_timeout: Duration,
+    /// Which track is measured, also after a resubscribe.
+    pub track_filter: TrackFilter,
+    /// The run is aborted when no frame arrives for this long while the
+    /// track is still subscribed.
+    pub watchdog_timeout: Duration,
+    /// Fail the run when the received resolution or frame rate falls short
+    /// of the publisher config.
+    pub strict_resolution: bool,
+    /// How often the RTC stats are polled independently of the latency
+    /// samples, zero to disable.
+    pub stats_interval: Duration,
+    /// JSON lines file receiving every raw RTC stats report read.
+    pub dump_rtc_stats: Option<PathBuf>,
+    /// Every how many frames the arrival time is logged, zero to disable.
+    pub frame_log: u64,
+    /// Width of the buckets of the latency histogram, ms.
+    pub latency_bucket_ms: u64,
+    /// ICE candidates the room connection was restricted to, recorded in
+    /// the metadata.
+    pub ice_transport: IceTransport,
+    /// Room features of the connection, recorded in the metadata.
+    pub room_features: RoomFeatures,
+    /// Every how many frames the watermark is looked for, at most
+    /// `WATERMARK_FRAMES` so a marked frame is never missed.
+    pub scan_interval: u64,
+    /// Every how many frames the quality is estimated, zero to disable.
+    pub quality_interval: u64,
+    /// Every how many frames the fingerprint is checked.
+    pub fingerprint_interval: u64,
+    /// Y4M file receiving every decoded frame, see `record`.
+    pub record: Option<PathBuf>,
+    /// Video published back while measuring, see `return_track`.
+    pub return_video: Option<return_track::ReturnVideo>,
+    /// JSON log of the run, opened next to its results.
+    pub json_log: Option<common::logging::RunLog>,
+    /// End the run, on the publisher too, once this many latency samples
+    /// are collected.
+    pub samples: Option<u64>,
+    /// End the run, on the publisher too, this long after the measurement
+    /// started.
+    pub duration: Option<Duration>,
+    /// End the run, on the publisher too, once any of these holds, see
+    /// `abort`.
+    pub abort_conditions: Vec<AbortCondition>,
+    /// Stretches of the measurement whose samples are marked to be left
+    /// out of the statistics, see `common::exclusion`.
+    pub exclusions: ExclusionWindows,
+    /// Where the results are uploaded once they are written.
+    #[cfg(feature = "upload")]
+    pub upload: Option<common::upload::UploadTarget>,
+    /// Window showing the received video, when rendering is enabled.
+    #[cfg(feature = "render")]
+    pub render: Option<renderer::RenderHandle>,
+    /// Terminal dashboard showing the samples as they come in.
+    #[cfg(feature = "tui")]
+    pub dashboard: Option<common::tui::Dashboard>,
+}
+
+/// Why `end_to_end_latency` failed. All but `Io` still write the samples.
+#[derive(Debug)]
+pub enum MeasurementError {
+    /// The results couldn't be written.
+    Io(io::Error),
+    /// The watchdog stopped the run after this long without frames.
+    Stalled(Duration),
+    /// An `--abort-if` condition held, see `abort`.
+    Aborted(String),
+    /// Less was received than configured, with `strict_resolution`.
+    Shortfall(String),
+}
+
+impl std::fmt::Display for MeasurementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeasurementError::Io(e) => write!(f, "{}", e),
+            MeasurementError::Stalled(timeout) => {
+                write!(f, "no frames received for {} s", timeout.as_secs())
+            }
+            MeasurementError::Aborted(reason) => write!(f, "aborted early, {}", reason),
+            MeasurementError::Shortfall(shortfall) => {
+                write!(f, "received less than configured: {}", shortfall)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MeasurementError {}
+
+impl From<io::Error> for MeasurementError {
+    fn from(e: io::Error) -> Self {
+        MeasurementError::Io(e)
+    }
+}
+
 #[derive(Debug, Clone)]
 struct LatencyEntry {
     id: u64,
@@ -14,6 +202,16 @@ struct LatencyEntry {
     receive_timestamp: u128,
     rtc_stats: Option<LatencyStats>,
     cpu_usage: f32,
+    /* Share of one core spent converting and scanning frames since the last sample. */
+    measurement_cpu_usage: f64,
+    /* Mean quality estimate of the frames since the last sample, see `quality`. */
+    quality: FrameQuality,
+    /* Decoded frame size when the sample was taken. */
+    resolution: (u32, u32),
+    /* Label of the exclusion window the sample was taken in, see `MeasurementOptions::exclusions`. */
+    excluded: Option<String>,
+    /* How the latency was measured, capture time runs fall back to watermarks. */
+    method: LatencyMode,
 }
 
 impl std::fmt::Display for LatencyEntry {
@@ -23,7 +221,7 @@ impl std::fmt::Display for LatencyEntry {
                 f,
                 "{} latency: {} stats: {}, cpu_usage: {}",
                 self.id,
-                self.receive_timestamp - self.timestamp,
+                self.receive_timestamp.saturating_sub(self.timestamp),
                 stats,
                 self.cpu_usage
             )
@@ -32,7 +230,7 @@ impl std::fmt::Display for LatencyEntry {
                 f,
                 "{} latency: {} stats: no stats available",
                 self.id,
-                self.receive_timestamp - self.timestamp
+                self.receive_timestamp.saturating_sub(self.timestamp)
             )
         }
     }
@@ -53,14 +251,28 @@ struct LatencyStats {
     freeze_count: f64,
     total_bytes: f64,
     dropped_frames: f64,
+    /// Receive side bandwidth estimate of the nominated candidate pair, bps.
+    available_incoming_bitrate: f64,
+    /// ICE round trip times of the nominated candidate pair, ms.
+    current_round_trip_time: f64,
+    total_round_trip_time: f64,
+    /// Loss and its recovery: packets lost, NACKs sent, and the
+    /// retransmitted and FEC packets received.
+    packets_lost: i64,
+    nack_count: u64,
+    retransmitted_packets_received: u64,
+    fec_packets_received: u64,
+    fec_packets_discarded: u64,
     codec: String,
+    /// The candidate pair the media arrives over, see `transport`.
+    candidate_pair: Option<CandidatePair>,
 }
 
 impl std::fmt::Display for LatencyStats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "processing_delay: {}, jitter_buffer_delay: {}, jitter_buffer_target_delay: {}, jitter_buffer_minimum_delay: {}, frames_per_second: {:.2}, total_frames: {}, freeze_count: {}, total_bytes: {}, dropped_frames: {}, codec: {}",
+            "processing_delay: {}, jitter_buffer_delay: {}, jitter_buffer_target_delay: {}, jitter_buffer_minimum_delay: {}, frames_per_second: {:.2}, total_frames: {}, freeze_count: {}, total_bytes: {}, dropped_frames: {}, available_incoming_bitrate: {}, current_round_trip_time: {}, total_round_trip_time: {}, packets_lost: {}, nack_count: {}, retransmitted_packets_received: {}, fec_packets_received: {}, codec: {}",
             self.processing_delay,
             self.jitter_buffer_delay,
             self.jitter_buffer_target_delay,
@@ -70,12 +282,23 @@ impl std::fmt::Display for LatencyStats {
             self.freeze_count,
             self.total_bytes,
             self.dropped_frames,
+            self.available_incoming_bitrate,
+            self.current_round_trip_time,
+            self.total_round_trip_time,
+            self.packets_lost,
+            self.nack_count,
+            self.retransmitted_packets_received,
+            self.fec_packets_received,
             self.codec
         )
     }
 }
 
-async fn get_rtc_stats(room: &Room) -> LatencyStats {
+/*
+ * None when the stats of a track can't be read, the reason is logged. With
+ * a `dump`, the raw reports are written to it labelled with the sample.
+ */
+async fn get_rtc_stats(room: &Room, dump: Option<(&RtcStatsDump, &str)>) -> Option<LatencyStats> {
     let mut latency_stats = LatencyStats {
         processing_delay: 0.,
         jitter_buffer_delay: 0.,
@@ -86,7 +309,16 @@ async fn get_rtc_stats(room: &Room) -> LatencyStats {
         total_bytes: 0.,
         dropped_frames: 0.,
         total_frames: 0.,
+        available_incoming_bitrate: f64::NAN,
+        current_round_trip_time: f64::NAN,
+        total_round_trip_time: f64::NAN,
+        packets_lost: 0,
+        nack_count: 0,
+        retransmitted_packets_received: 0,
+        fec_packets_received: 0,
+        fec_packets_discarded: 0,
         codec: String::new(),
+        candidate_pair: None,
     };
     for (_, remote_participant) in room.remote_participants() {
         for (_, publication) in remote_participant.track_publications() {
@@ -96,7 +328,16 @@ async fn get_rtc_stats(room: &Room) -> LatencyStats {
             }
             let track = track.unwrap();
             if let RemoteTrack::Video(track) = track {
-                let stats = track.get_stats().await.unwrap();
+                let stats = match track.get_stats().await {
+                    Ok(stats) => stats,
+                    Err(e) => {
+                        log::warn!("Failed to get the RTC stats of {}: {:?}", track.sid(), e);
+                        return None;
+                    }
+                };
+                if let Some((dump, sample)) = dump {
+                    dump.write(sample, track.sid().as_str(), &stats);
+                }
 
                 let mut codec_map: HashMap<String, String> = HashMap::new();
                 for stat in &stats {
@@ -105,6 +346,26 @@ async fn get_rtc_stats(room: &Room) -> LatencyStats {
                     }
                 }
 
+                /* The nominated pair carries the transport wide bandwidth estimate and RTT. */
+                let (available_incoming_bitrate, current_round_trip_time, total_round_trip_time) =
+                    stats
+                        .iter()
+                        .find_map(|stat| match stat {
+                            livekit::webrtc::stats::RtcStats::CandidatePair(pair)
+                                if pair.candidate_pair.nominated =>
+                            {
+                                let pair = &pair.candidate_pair;
+                                Some((
+                                    pair.available_incoming_bitrate,
+                                    pair.current_round_trip_time * 1000.,
+                                    pair.total_round_trip_time * 1000.,
+                                ))
+                            }
+                            _ => None,
+                        })
+                        .unwrap_or((f64::NAN, f64::NAN, f64::NAN));
+                let candidate_pair = transport::selected_candidate_pair(&stats);
+
                 for stat in &stats {
                     match stat {
                         livekit::webrtc::stats::RtcStats::InboundRtp(stats) => {
@@ -162,7 +423,18 @@ async fn get_rtc_stats(room: &Room) -> LatencyStats {
                                 total_bytes,
                                 dropped_frames: stats.inbound.frames_dropped as f64,
                                 total_frames: stats.inbound.frames_received as f64,
+                                available_incoming_bitrate,
+                                current_round_trip_time,
+                                total_round_trip_time,
+                                packets_lost: stats.received.packets_lost,
+                                nack_count: stats.inbound.nack_count as u64,
+                                retransmitted_packets_received: stats
+                                    .inbound
+                                    .retransmitted_packets_received,
+                                fec_packets_received: stats.inbound.fec_packets_received,
+                                fec_packets_discarded: stats.inbound.fec_packets_discarded,
                                 codec,
+                                candidate_pair: candidate_pair.clone(),
                             };
                         }
                         _ => {}
@@ -171,10 +443,128 @@ async fn get_rtc_stats(room: &Room) -> LatencyStats {
             }
         }
     }
-    latency_stats
+    Some(latency_stats)
 }
 
-async fn measure_latency(room: Room, track: RtcVideoTrack) -> Vec<LatencyEntry> {
+/*
+ * Frame timestamps are only usable as capture times when the SDK carries
+ * the sender's wall clock capture time through. Otherwise they are local
+ * render times on an unrelated clock, which this check rejects.
+ */
+fn is_wall_clock_timestamp(capture_timestamp: i64, receive_timestamp: u128) -> bool {
+    let max_skew_ms = 60_000;
+    capture_timestamp > 0 && (receive_timestamp as i64 - capture_timestamp).abs() < max_skew_ms
+}
+
+/* Waits for a matching track to be subscribed again, e.g. after the publisher restarted. */
+async fn wait_for_screenshare(
+    room_events: &mut tokio::sync::mpsc::UnboundedReceiver<RoomEvent>,
+    timeout: Duration,
+    filter: &TrackFilter,
+) -> Option<RemoteVideoTrack> {
+    let resubscribed = async {
+        while let Some(event) = room_events.recv().await {
+            if let RoomEvent::TrackSubscribed {
+                track: RemoteTrack::Video(track),
+                publication,
+                participant,
+            } = event
+            {
+                if filter.matches(&publication, &participant) {
+                    return Some(track);
+                }
+            }
+        }
+        None
+    };
+    tokio::time::timeout(timeout, resubscribed)
+        .await
+        .ok()
+        .flatten()
+}
+
+/* RTC stats read on the polling schedule, see `poll_rtc_stats`. */
+struct PolledStats {
+    /* Unix ms when the stats were read. */
+    timestamp: u128,
+    stats: LatencyStats,
+}
+
+/*
+ * The latency samples read the stats when a tick arrives, at irregular
+ * intervals and not at all between ticks. This reads them on a fixed wall
+ * clock schedule instead, for evenly spaced frame rate and jitter series.
+ * Runs until dropped.
+ */
+async fn poll_rtc_stats(
+    room: &Room,
+    interval: Duration,
+    samples: &Mutex<Vec<PolledStats>>,
+    dump: Option<&RtcStatsDump>,
+) {
+    if interval.is_zero() {
+        return std::future::pending().await;
+    }
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Some(stats) = get_rtc_stats(room, dump.map(|dump| (dump, "poll"))).await else {
+            continue;
+        };
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        samples
+            .lock()
+            .unwrap()
+            .push(PolledStats { timestamp, stats });
+    }
+}
+
+/* A received frame and the time since the previous one, see `--frame-log`. */
+struct FrameArrival {
+    frame: u64,
+    timestamp: u128,
+    interval: Duration,
+}
+
+/* Samples of a run, and whether it was cut short by the watchdog. */
+struct Measurement {
+    latency: Vec<LatencyEntry>,
+    arrivals: Vec<FrameArrival>,
+    stalled: bool,
+    /* Samples dropped because the RTC stats couldn't be read. */
+    skipped_samples: u64,
+    /* Set when the publisher marks its frames with a content hash. */
+    fingerprint: Option<FingerprintCheck>,
+    /* Ended by `--samples` or `--duration`, with the publisher told to finish. */
+    finished: bool,
+    /* Why an abort condition ended the run, with the publisher told to finish. */
+    aborted: Option<String>,
+    /* What else happened on the machine, to annotate the samples with. */
+    system_monitor: SystemMonitor,
+    /* Watermark ticks requested after the warm-up, found, and found by the fallback scan only. */
+    watermark_ticks: u64,
+    detected_ticks: u64,
+    fallback_ticks: u64,
+    /* Unix ms of the start of the measurement, which the exclusion windows count from. */
+    started_ms: u64,
+}
+
+async fn measure_latency(
+    room: &Room,
+    track: RemoteVideoTrack,
+    options: &MeasurementOptions,
+    events: &Mutex<Vec<Event>>,
+    clock_offset: Option<ClockOffset>,
+    dump: Option<&RtcStatsDump>,
+    recorder: Option<&FrameRecorder>,
+    protocol: u32,
+    publisher_config: &Mutex<Option<PublisherConfig>>,
+    mut rolling: Option<RollingLatency>,
+) -> Measurement {
     let pid = std::process::id() as usize;
     let mut system = System::new_all();
     std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
@@ -184,9 +574,13 @@ async fn measure_latency(room: Room, track: RtcVideoTrack) -> Vec<LatencyEntry>
         true,
         ProcessRefreshKind::nothing().with_cpu(),
     );
+    let mut system_monitor = SystemMonitor::new();
+    let mut networks = Networks::new_with_refreshed_list();
+    let mut components = Components::new_with_refreshed_list();
 
     /* Vector for storing the measurements. */
     let mut latency_results: Vec<LatencyEntry> = vec![];
+    let mut arrivals: Vec<FrameArrival> = vec![];
     /* Total frame counter. */
     let mut frames = 0;
     /* Next frame to send tick. */
@@ -197,83 +591,545 @@ async fn measure_latency(room: Room, track: RtcVideoTrack) -> Vec<LatencyEntry>
     /* FPS calculation variables */
     let mut start_time = std::time::SystemTime::now();
     let mut last_frame_for_fps = 0;
+    #[cfg(feature = "tui")]
+    let mut received_bytes = Counter::new();
+
+    /* Decoded resolution, to record when a requested layer switch takes effect. */
+    let mut resolution = (0, 0);
+    /* Orientation of the decoded frames, checked against what the publisher announced. */
+    let mut rotation = None;
+    let published_rotation = published_rotation(room);
+    let mut fingerprint = published_fingerprint(room).then(FingerprintCheck::default);
+    let mut measurement_usage = MeasurementUsage::new();
+    let mut quality = QualityAverage::default();
+    let mut scanner = WatermarkScanner::new(WATERMARK);
+    let (mut watermark_ticks, mut detected_ticks, mut fallback_ticks) = (0, 0, 0);
+    /* The ticks requested during the warm-up don't count towards the detection rate. */
+    let mut first_counted_tick = None;
+
+    let mut latency_mode = options.latency_mode;
+    let mut room_events = room.subscribe();
+    let mut track_sid = track.sid();
+    let mut video_sink = NativeVideoStream::new(track.rtc_track());
+    let mut last_frame = tokio::time::Instant::now();
+    /*
+     * The watchdog counts from the last frame or the end of the last pause,
+     * the publisher sends nothing while its track is muted or it doesn't capture.
+     */
+    let (mut muted, mut idle) = (false, false);
+    let mut watchdog_from = last_frame;
+    /* A track subscribed while the current one is live, the publisher republishes before unpublishing. */
+    let mut replacement: Option<RemoteVideoTrack> = None;
+    /* The last frame of a lost track, until the first one of its replacement. */
+    let mut resumed_from: Option<tokio::time::Instant> = None;
+    /* When the received frames turned black, if they are. */
+    let mut black_since: Option<std::time::Instant> = None;
+    let mut stalled = false;
+    let mut skipped_samples = 0;
+    let mut collected_samples = 0;
+    let mut finished = false;
+    let mut aborted = None;
+    let mut abort_monitor = AbortMonitor::new(options.abort_conditions.clone());
+    let started = std::time::Instant::now();
+    let started_ms = now_us() / 1000;
+    /* Label of the exclusion window of the last sample. */
+    let mut excluding: Option<String> = None;
+    loop {
+        let frame = tokio::select! {
+            frame = video_sink.next() => frame,
+            _ = tokio::time::sleep_until(watchdog_from + options.watchdog_timeout),
+                if !muted && !idle && !options.watchdog_timeout.is_zero() => {
+                /* The track is still there but nothing comes through, the run is wedged. */
+                let stats = get_rtc_stats(room, None)
+                    .await
+                    .map_or("unavailable".to_string(), |stats| stats.to_string());
+                let detail = format!(
+                    "no frame for {} s on track {}, {} frames received, last decoded {}x{}",
+                    options.watchdog_timeout.as_secs(),
+                    track_sid,
+                    frames,
+                    resolution.0,
+                    resolution.1
+                );
+                log::error!("Watchdog: {}, last stats: {}", detail, stats);
+                events.lock().unwrap().push(Event::new("watchdog", detail));
+                stalled = true;
+                break;
+            }
+            Some(event) = room_events.recv() => match event {
+                RoomEvent::TrackUnsubscribed {
+                    track: RemoteTrack::Video(track),
+                    ..
+                } if track.sid() == track_sid => None,
+                RoomEvent::TrackSubscribed {
+                    track: RemoteTrack::Video(track),
+                    publication,
+                    participant,
+                } if replacement.is_none()
+                    && options.track_filter.matches(&publication, &participant) =>
+                {
+                    replacement = Some(track);
+                    continue;
+                }
+                RoomEvent::TrackMuted { publication, .. } if publication.sid() == track_sid => {
+                    muted = true;
+                    continue;
+                }
+                RoomEvent::TrackUnmuted { publication, .. } if publication.sid() == track_sid => {
+                    muted = false;
+                    watchdog_from = tokio::time::Instant::now();
+                    continue;
+                }
+                RoomEvent::DataReceived { payload, topic, .. }
+                    if topic.as_deref() == Some(CONTROL_TOPIC) =>
+                {
+                    if let Some(ControlMessage::Heartbeat { capturing, .. }) =
+                        ControlMessage::decode(&payload)
+                    {
+                        if idle && capturing {
+                            watchdog_from = tokio::time::Instant::now();
+                        }
+                        idle = !capturing;
+                    }
+                    continue;
+                }
+                _ => continue,
+            },
+        };
+        /*
+         * Keep measuring on the new track when the publisher comes back, the
+         * time without frames is recorded as a gap.
+         */
+        let Some(frame) = frame else {
+            let detail = format!("track {}", track_sid);
+            log::warn!("Screenshare track lost: {}", detail);
+            events
+                .lock()
+                .unwrap()
+                .push(Event::new("track_lost", detail));
+            let lost = last_frame;
+            let next = match replacement.take() {
+                Some(track) => Some(track),
+                None => {
+                    wait_for_screenshare(
+                        &mut room_events,
+                        options.resubscribe_timeout,
+                        &options.track_filter,
+                    )
+                    .await
+                }
+            };
+            match next {
+                Some(track) => {
+                    track_sid = track.sid();
+                    let detail = format!(
+                        "track {} after a {} ms gap",
+                        track_sid,
+                        lost.elapsed().as_millis()
+                    );
+                    log::info!("Screenshare track resubscribed: {}", detail);
+                    events
+                        .lock()
+                        .unwrap()
+                        .push(Event::new("track_resubscribed", detail));
+                    video_sink = NativeVideoStream::new(track.rtc_track());
+                    resumed_from = Some(lost);
+                    last_frame = tokio::time::Instant::now();
+                    watchdog_from = last_frame;
+                    resolution = (0, 0);
+                    rotation = None;
+                    /* The watermark requested on the old track never arrives, ask again. */
+                    next_frame_request = frames;
+                    continue;
+                }
+                None => break,
+            }
+        };
+        /* A frozen screen on the publisher shows up as a gap between frames. */
+        let gap = last_frame.elapsed();
+        if gap >= RECEIVE_GAP && frames > 0 {
+            let detail = format!(
+                "{} ms without frames on track {}",
+                gap.as_millis(),
+                track_sid
+            );
+            log::info!("Receive gap: {}", detail);
+            events
+                .lock()
+                .unwrap()
+                .push(Event::new("receive_gap", detail));
+        }
+        last_frame = tokio::time::Instant::now();
+        watchdog_from = last_frame;
+        /* The whole gap of a republish, e.g. a codec switch, up to the first frame decoded. */
+        if let Some(lost) = resumed_from.take() {
+            let codec = publisher_config
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|config| config.codec.clone())
+                .unwrap_or_default();
+            let detail = format!(
+                "first frame on track {} {} ms after the last one of the old track, codec {}",
+                track_sid,
+                lost.elapsed().as_millis(),
+                codec
+            );
+            log::info!("Stream resumed: {}", detail);
+            events
+                .lock()
+                .unwrap()
+                .push(Event::new("stream_resumed", detail));
+        }
 
-    let mut video_sink = NativeVideoStream::new(track);
-    while let Ok(Some(frame)) =
-        tokio::time::timeout(std::time::Duration::from_millis(10000), video_sink.next()).await
-    {
         let receive_timestamp = std::time::SystemTime::now()
             .duration_since(std::time::SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_millis();
-        /*
-         * Access the buffer and read the first 200
-         * Y samples.
-         */
-        let buffer = frame.buffer.to_i420();
-        let (data_y, _, _) = buffer.data();
+        if options.frame_log > 0
+            && frames > options.warmup_frames
+            && frames % options.frame_log == 0
+        {
+            arrivals.push(FrameArrival {
+                frame: frames,
+                timestamp: receive_timestamp,
+                interval: gap,
+            });
+        }
+
+        #[cfg(feature = "render")]
+        if let Some(render) = &options.render {
+            render.submit(&frame);
+        }
+
+        let measuring = std::time::Instant::now();
+        if let Some(recorder) = recorder {
+            recorder.record(
+                frames,
+                receive_timestamp,
+                frame.timestamp_us,
+                &frame.buffer.to_i420(),
+                fingerprint.is_some(),
+            );
+        }
+
+        let frame_resolution = (frame.buffer.width(), frame.buffer.height());
+        if frame_resolution != resolution {
+            resolution = frame_resolution;
+            let published = publisher_config
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|config| (config.width, config.height));
+            scanner.set_resolution(resolution, published);
+            let detail = format!("{}x{}", resolution.0, resolution.1);
+            log::info!("Decoded resolution changed to {}", detail);
+            events
+                .lock()
+                .unwrap()
+                .push(Event::new("resolution_changed", detail));
+        }
 
-        let mut watermark_count = 0;
-        for i in 0..200 {
-            if data_y[i] == 0xa {
-                watermark_count += 1;
+        let frame_rotation = frame.rotation as u32;
+        if rotation != Some(frame_rotation) {
+            rotation = Some(frame_rotation);
+            let detail = rotation_detail(frame_rotation, resolution, published_rotation.as_deref());
+            log::info!("Frame rotation: {}", detail);
+            events
+                .lock()
+                .unwrap()
+                .push(Event::new("rotation_changed", detail));
+        }
+
+        /* A black screen on the publisher, sampled every few frames to keep it cheap. */
+        if frames % BLACK_CHECK_INTERVAL == 0 {
+            let black = is_black(&*frame.buffer);
+            if black != black_since.is_some() {
+                let (kind, detail) = match black_since {
+                    None => ("black_frames", format!("track {}", track_sid)),
+                    Some(since) => (
+                        "black_frames_ended",
+                        format!("after {} ms", since.elapsed().as_millis()),
+                    ),
+                };
+                log::info!("{}: {}", kind, detail);
+                events.lock().unwrap().push(Event::new(kind, detail));
+                black_since = black.then(std::time::Instant::now);
             }
         }
 
-        /* Limit for accepting the watermark. */
-        let min_watermark_count = 10;
-        /* Delay sampling by 500 frames. */
-        let start_sampling_frame = 500;
-        if watermark_count >= min_watermark_count && frames > start_sampling_frame {
-            if let Some(entry) = latency_results.last_mut() {
-                /* If the entry has a receive timestamp don't overwrite it. */
-                if entry.receive_timestamp == 0 {
-                    entry.receive_timestamp = receive_timestamp;
-
-                    /* Get rtc stats. */
-                    let rtc_stats = get_rtc_stats(&room).await;
-                    entry.rtc_stats = Some(rtc_stats);
-
-                    system.refresh_processes_specifics(
-                        ProcessesToUpdate::All,
-                        true,
-                        ProcessRefreshKind::nothing().with_cpu(),
-                    );
-                    if let Some(process) = system.process(Pid::from(pid)) {
-                        entry.cpu_usage = process.cpu_usage();
+        /* Garbled frames the RTC stats don't show, when the publisher marks its frames. */
+        let corrupted = match &mut fingerprint {
+            Some(fingerprint)
+                if frames > options.warmup_frames && frames % options.fingerprint_interval == 0 =>
+            {
+                let converted;
+                let buffer = match frame.buffer.as_i420() {
+                    Some(buffer) => buffer,
+                    None => {
+                        converted = frame.buffer.to_i420();
+                        &converted
+                    }
+                };
+                fingerprint.check(buffer, frames)
+            }
+            _ => None,
+        };
+        if options.quality_interval > 0
+            && frames > options.warmup_frames
+            && frames % options.quality_interval == 0
+        {
+            let _span = tracing::trace_span!("quality", frame = frames).entered();
+            let converted;
+            let buffer = match frame.buffer.as_i420() {
+                Some(buffer) => buffer,
+                None => {
+                    converted = frame.buffer.to_i420();
+                    &converted
+                }
+            };
+            quality.add(quality::estimate(buffer));
+        }
+        measurement_usage.add(measuring.elapsed());
+        if let Some(event) = corrupted {
+            events.lock().unwrap().push(event);
+        }
+
+        let mut sample_ready = false;
+        /* Every frame is scanned while a tick waits for its watermark, so the first marked one is caught. */
+        let outstanding = latency_results
+            .last()
+            .is_some_and(|entry| entry.receive_timestamp == 0);
+        match latency_mode {
+            LatencyMode::Watermark if outstanding || frames % options.scan_interval == 0 => {
+                let _span = tracing::trace_span!("watermark_scan", frame = frames).entered();
+                let measuring = std::time::Instant::now();
+                /* Decoders handing out I420 are read in place, only the others are converted. */
+                let converted;
+                let buffer = match frame.buffer.as_i420() {
+                    Some(buffer) => buffer,
+                    None => {
+                        converted = frame.buffer.to_i420();
+                        &converted
+                    }
+                };
+                /* Only an outstanding tick is worth the full scan, the rows tried first are cheap. */
+                let marked = scanner.scan(buffer, outstanding);
+                measurement_usage.add(measuring.elapsed());
+
+                if marked && frames > options.warmup_frames {
+                    if let Some(entry) = latency_results.last_mut() {
+                        /* If the entry has a receive timestamp don't overwrite it. */
+                        if entry.receive_timestamp == 0 {
+                            entry.receive_timestamp = receive_timestamp;
+                            sample_ready = true;
+                            if first_counted_tick.is_some_and(|first| entry.id >= first) {
+                                detected_ticks += 1;
+                                if scanner.last_by_fallback {
+                                    fallback_ticks += 1;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            LatencyMode::CaptureTime => {
+                if frames > options.warmup_frames && frames % frames_offset == 0 {
+                    /* Capture times are on the publisher clock. */
+                    let capture_timestamp = frame.timestamp_us / 1000;
+                    let capture_timestamp = match clock_offset {
+                        Some(offset) => offset.to_client_ms(capture_timestamp),
+                        None => capture_timestamp,
+                    };
+                    if is_wall_clock_timestamp(capture_timestamp, receive_timestamp) {
+                        latency_results.push(LatencyEntry {
+                            id: frames / frames_offset,
+                            timestamp: capture_timestamp as u128,
+                            receive_timestamp,
+                            rtc_stats: None,
+                            cpu_usage: 0.,
+                            measurement_cpu_usage: f64::NAN,
+                            quality: FrameQuality::UNKNOWN,
+                            resolution,
+                            excluded: None,
+                            method: LatencyMode::CaptureTime,
+                        });
+                        sample_ready = true;
                     } else {
-                        log::warn!("Process with PID {} not found", pid);
+                        let detail = format!(
+                            "frame timestamp {}ms is not a wall clock capture time, the SDK doesn't expose it",
+                            capture_timestamp
+                        );
+                        log::warn!("{}. Falling back to watermark mode", detail);
+                        events
+                            .lock()
+                            .unwrap()
+                            .push(Event::new("latency_mode_fallback", detail));
+                        latency_mode = LatencyMode::Watermark;
+                        next_frame_request = frames;
                     }
+                }
+            }
+            LatencyMode::Watermark => {}
+        }
 
-                    /* Calculate local FPS every second */
-                    let elapsed_time_since_start = start_time.elapsed().unwrap().as_secs();
-                    let frames_per_second =
-                        (frames - last_frame_for_fps) as f64 / elapsed_time_since_start as f64;
-                    entry.rtc_stats.as_mut().unwrap().frames_per_second = frames_per_second;
+        /* A sample without stats is dropped, latency_rows skips it. */
+        if sample_ready {
+            let entry = latency_results.last_mut().unwrap();
+            let sample = format!("latency {}", entry.id);
+            entry.rtc_stats = get_rtc_stats(room, dump.map(|dump| (dump, sample.as_str()))).await;
+            if entry.rtc_stats.is_none() {
+                log::warn!("Sample {} skipped, no RTC stats", entry.id);
+                skipped_samples += 1;
+                sample_ready = false;
+            }
+        }
 
-                    log::info!("{}", entry);
-                    start_time = std::time::SystemTime::now();
-                    last_frame_for_fps = frames;
+        if sample_ready {
+            let entry = latency_results.last_mut().unwrap();
+            entry.resolution = resolution;
+            entry.excluded = options
+                .exclusions
+                .label_at(started.elapsed())
+                .map(str::to_string);
+            if entry.excluded != excluding {
+                let mut events = events.lock().unwrap();
+                if let Some(label) = &excluding {
+                    log::info!("Exclusion window {} ended", label);
+                    events.push(Event::new(EXCLUSION_ENDED_EVENT, label.clone()));
+                }
+                if let Some(label) = &entry.excluded {
+                    log::info!("Exclusion window {} started, samples marked", label);
+                    events.push(Event::new(EXCLUSION_STARTED_EVENT, label.clone()));
                 }
+                excluding = entry.excluded.clone();
+            }
+
+            system.refresh_processes_specifics(
+                ProcessesToUpdate::All,
+                true,
+                ProcessRefreshKind::nothing().with_cpu(),
+            );
+            if let Some(process) = system.process(Pid::from(pid)) {
+                entry.cpu_usage = process.cpu_usage();
+            } else {
+                log::warn!("Process with PID {} not found", pid);
+            }
+            entry.measurement_cpu_usage = measurement_usage.sample();
+            entry.quality = quality.take();
+            let temperature = system_events::hottest_component(&mut components);
+            let snapshot = system_events::snapshot(&mut system, &mut networks, pid, temperature);
+            for event in system_monitor.update(snapshot) {
+                log::info!("System event {}: {}", event.kind, event.detail);
+                events.lock().unwrap().push(event);
+            }
+
+            /* Calculate local FPS every second */
+            let elapsed_time_since_start = start_time.elapsed().unwrap().as_secs();
+            let frames_per_second =
+                (frames - last_frame_for_fps) as f64 / elapsed_time_since_start as f64;
+            entry.rtc_stats.as_mut().unwrap().frames_per_second = frames_per_second;
+
+            #[cfg(feature = "render")]
+            if let Some(render) = &options.render {
+                let stats = entry.rtc_stats.as_ref().unwrap();
+                render.update_stats(
+                    entry.receive_timestamp.saturating_sub(entry.timestamp),
+                    stats.total_bytes,
+                    stats.freeze_count,
+                );
+            }
+
+            #[cfg(feature = "tui")]
+            if let Some(dashboard) = &options.dashboard {
+                let stats = entry.rtc_stats.as_ref().unwrap();
+                let latency = entry.receive_timestamp.saturating_sub(entry.timestamp);
+                let received = received_bytes.update(stats.total_bytes, entry.receive_timestamp);
+                dashboard.record("latency", "ms", latency as f64);
+                dashboard.record("fps", "fps", frames_per_second);
+                dashboard.record("receive", "kbps", received.kbps());
+                dashboard.record("cpu", "%", entry.cpu_usage as f64);
+                dashboard.status(format!(
+                    "{} frames received, {} freezes",
+                    frames, stats.freeze_count
+                ));
+            }
+
+            log::info!("{}", entry);
+            if let Some(file) = &mut rolling {
+                let elapsed = started.elapsed().as_secs_f64();
+                if !file.append(entry, elapsed, &system_monitor) {
+                    rolling = None;
+                }
+            }
+            start_time = std::time::SystemTime::now();
+            last_frame_for_fps = frames;
+            collected_samples += 1;
+
+            let target_kbps = publisher_config
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|config| config.bitrate as f64);
+            /* Excluded samples don't count towards an abort either. */
+            if entry.excluded.is_none() {
+                aborted = abort_monitor.update(
+                    entry.receive_timestamp,
+                    entry.receive_timestamp.saturating_sub(entry.timestamp) as f64,
+                    entry.rtc_stats.as_ref().unwrap().total_bytes,
+                    entry.rtc_stats.as_ref().unwrap().frames_per_second,
+                    target_kbps,
+                );
             }
         }
 
+        /* Enough collected, the publisher stops with us so both sides cover the same time. */
+        let enough_samples = options
+            .samples
+            .is_some_and(|samples| collected_samples >= samples);
+        let timed_out = options
+            .duration
+            .is_some_and(|duration| started.elapsed() >= duration);
+        if let Some(reason) = &aborted {
+            log::error!("Run aborted: {}", reason);
+            events
+                .lock()
+                .unwrap()
+                .push(Event::new("run_aborted", reason.clone()));
+        } else if enough_samples || timed_out {
+            let detail = format!(
+                "{} samples in {} s",
+                collected_samples,
+                started.elapsed().as_secs()
+            );
+            log::info!("Run finished: {}", detail);
+            events
+                .lock()
+                .unwrap()
+                .push(Event::new("run_finished", detail));
+        }
+        if aborted.is_some() || enough_samples || timed_out {
+            if !control::finish_publisher(room, protocol).await {
+                log::warn!("The publisher keeps running until its duration runs out");
+            }
+            finished = aborted.is_none();
+            break;
+        }
+
         /* Send tick and create next measurement entry. */
-        if frames == next_frame_request {
+        if latency_mode == LatencyMode::Watermark && frames == next_frame_request {
             next_frame_request += frames_offset;
-            /* Trigger next measurement frame. */
-            room.local_participant()
-                .publish_data(DataPacket {
-                    payload: "watermark".to_owned().into_bytes(),
-                    reliable: true,
-                    ..Default::default()
-                })
-                .await
-                .unwrap();
+            let id = next_frame_request / frames_offset;
+            /* Trigger next measurement frame, the id lets the publisher tag its sample. */
+            control::request_watermark(room, id, protocol).await;
+            if frames > options.warmup_frames {
+                watermark_ticks += 1;
+                first_counted_tick.get_or_insert(id);
+            }
 
             /* Create new measurement entry. */
             latency_results.push(LatencyEntry {
-                id: next_frame_request / frames_offset,
+                id,
                 timestamp: std::time::SystemTime::now()
                     .duration_since(std::time::SystemTime::UNIX_EPOCH)
                     .unwrap()
@@ -281,66 +1137,956 @@ async fn measure_latency(room: Room, track: RtcVideoTrack) -> Vec<LatencyEntry>
                 receive_timestamp: 0,
                 rtc_stats: None,
                 cpu_usage: 0.,
+                measurement_cpu_usage: f64::NAN,
+                quality: FrameQuality::UNKNOWN,
+                resolution: (0, 0),
+                excluded: None,
+                method: LatencyMode::Watermark,
             });
         }
         frames += 1;
     }
     log::info!("stopped receiving frames");
 
-    latency_results
+    if skipped_samples > 0 {
+        log::warn!(
+            "{} samples skipped because the RTC stats were unavailable",
+            skipped_samples
+        );
+    }
+    /* The last tick may still have been on its way. */
+    if latency_results
+        .last()
+        .is_some_and(|entry| entry.receive_timestamp == 0)
+    {
+        watermark_ticks = watermark_ticks.saturating_sub(1);
+    }
+    Measurement {
+        latency: latency_results,
+        arrivals,
+        stalled,
+        skipped_samples,
+        fingerprint,
+        finished,
+        aborted,
+        system_monitor,
+        watermark_ticks,
+        detected_ticks,
+        fallback_ticks,
+        started_ms,
+    }
+}
+
+/* Y4M frame rate of a recording when the publisher didn't send its config. */
+const DEFAULT_RECORD_FPS: u32 = 30;
+
+/* Turns the time spent converting and scanning frames into a usage over the last sample window. */
+struct MeasurementUsage {
+    time: Duration,
+    last_sample: std::time::Instant,
 }
 
+impl MeasurementUsage {
+    fn new() -> Self {
+        MeasurementUsage {
+            time: Duration::ZERO,
+            last_sample: std::time::Instant::now(),
+        }
+    }
+
+    fn add(&mut self, time: Duration) {
+        self.time += time;
+    }
+
+    fn sample(&mut self) -> f64 {
+        let usage = self.time.as_secs_f64() / self.last_sample.elapsed().as_secs_f64() * 100.;
+        self.time = Duration::ZERO;
+        self.last_sample = std::time::Instant::now();
+        usage
+    }
+}
+
+/* Share of the watermark ticks found below which the run is warned about. */
+const WATERMARK_WARNING_PERCENT: f64 = 90.;
+/* Frames further apart than this are recorded as a receive gap. */
+const RECEIVE_GAP: Duration = Duration::from_millis(500);
+/* Every how many frames the black frame check runs. */
+const BLACK_CHECK_INTERVAL: u64 = 10;
+/* Mean luma below which a frame counts as black, limited range black is 16. */
+const BLACK_LUMA: u64 = 20;
+
+/*
+ * Mean of a sparse grid of Y samples, enough to tell a black screen. The
+ * luma plane of I420 and NV12 frames is read in place, a frame of another
+ * format is converted.
+ */
+fn is_black(buffer: &dyn VideoBuffer) -> bool {
+    let (width, height) = (buffer.width() as usize, buffer.height() as usize);
+    let converted;
+    let (data_y, stride_y) = if let Some(i420) = buffer.as_i420() {
+        (i420.data().0, i420.strides().0)
+    } else if let Some(nv12) = buffer.as_nv12() {
+        (nv12.data().0, nv12.strides().0)
+    } else {
+        converted = buffer.to_i420();
+        (converted.data().0, converted.strides().0)
+    };
+    let (step_x, step_y) = ((width / 16).max(1), (height / 16).max(1));
+    let (mut sum, mut count) = (0u64, 0u64);
+    for y in (0..height).step_by(step_y) {
+        for x in (0..width).step_by(step_x) {
+            sum += data_y[y * stride_y as usize + x] as u64;
+            count += 1;
+        }
+    }
+    count > 0 && sum / count < BLACK_LUMA
+}
+
+/* Share of the configured frame rate below which the received one is flagged. */
+const MIN_FPS_RATIO: f64 = 0.9;
+
+/* Long and short side, a rotated stream decodes with the sides swapped. */
+fn sides((width, height): (u32, u32)) -> (u32, u32) {
+    (width.max(height), width.min(height))
+}
+
+/*
+ * Checks the received resolution and frame rate against the publisher
+ * config. libwebrtc lowers both on its own when the encoder or the network
+ * can't keep up, so a 1080p run may have delivered 540p. The findings go to
+ * the metadata; the shortfall, if any, is returned.
+ */
+fn verify_received(
+    latency: &[LatencyEntry],
+    config: &PublisherConfig,
+    metadata: &mut RunMetadata,
+) -> Option<String> {
+    let samples: Vec<&LatencyEntry> = latency
+        .iter()
+        .filter(|entry| entry.receive_timestamp != 0 && entry.rtc_stats.is_some())
+        .collect();
+    let last = samples.last()?;
+    let configured = sides((config.width, config.height));
+    let downscaled = samples
+        .iter()
+        .filter(|entry| {
+            let (long, short) = sides(entry.resolution);
+            long < configured.0 || short < configured.1
+        })
+        .count();
+    let rates: Vec<f64> = samples
+        .iter()
+        .map(|entry| entry.rtc_stats.as_ref().unwrap().frames_per_second)
+        .filter(|fps| fps.is_finite())
+        .collect();
+    let fps = rates.iter().sum::<f64>() / rates.len().max(1) as f64;
+
+    metadata.set("received_width", last.resolution.0);
+    metadata.set("received_height", last.resolution.1);
+    metadata.set("received_fps", format!("{:.1}", fps));
+    metadata.set("downscaled_samples", downscaled);
+
+    let mut shortfalls = vec![];
+    if downscaled > 0 {
+        shortfalls.push(format!(
+            "{} of {} samples below the configured {}x{}, last {}x{}",
+            downscaled,
+            samples.len(),
+            config.width,
+            config.height,
+            last.resolution.0,
+            last.resolution.1
+        ));
+    }
+    if !rates.is_empty() && fps < config.fps as f64 * MIN_FPS_RATIO {
+        shortfalls.push(format!(
+            "{:.1} fps received, {} configured",
+            fps, config.fps
+        ));
+    }
+    metadata.set("received_matches_config", shortfalls.is_empty());
+    (!shortfalls.is_empty()).then(|| shortfalls.join(", "))
+}
+
+/* Unix ms and receive rate in kbps of the samples with stats, as in the latency table. */
+fn receive_rates(latency: &[LatencyEntry]) -> Vec<(u128, f64)> {
+    let mut bytes = Counter::new();
+    latency
+        .iter()
+        .filter_map(|entry| Some((entry.receive_timestamp, entry.rtc_stats.as_ref()?)))
+        .filter(|(timestamp, _)| *timestamp != 0)
+        .map(|(timestamp, stats)| (timestamp, bytes.update(stats.total_bytes, timestamp).kbps()))
+        .collect()
+}
+
+/* Rotation the publisher announced, e.g. `90-signal`, see `--rotation`. */
+fn published_rotation(room: &Room) -> Option<String> {
+    room.remote_participants()
+        .values()
+        .find_map(|participant| participant.attributes().get("rotation").cloned())
+}
+
+fn published_fingerprint(room: &Room) -> bool {
+    room.remote_participants().values().any(|participant| {
+        participant
+            .attributes()
+            .get("fingerprint")
+            .is_some_and(|f| f == "true")
+    })
+}
+
+/*
+ * Describes how a rotation reached us. A signaled rotation arrives as frame
+ * metadata (the CVO RTP extension), otherwise the pixels were rotated before
+ * encoding: on purpose with `pre-encode`, or by libwebrtc itself when the
+ * extension wasn't negotiated.
+ */
+fn rotation_detail(degrees: u32, decoded: (u32, u32), published: Option<&str>) -> String {
+    let how = if degrees != 0 {
+        "signaled via RTP"
+    } else {
+        match published.and_then(|p| p.split_once('-')) {
+            Some(("0", _)) | None => "none",
+            Some((_, "pre-encode")) => "applied pre-encode",
+            Some(_) => "applied pre-encode by libwebrtc, the orientation extension wasn't negotiated",
+        }
+    };
+    format!(
+        "{} degrees {}, decoded {}x{}, published {}",
+        degrees,
+        how,
+        decoded.0,
+        decoded.1,
+        published.unwrap_or("unknown")
+    )
+}
+
+/* Asks the publisher for its run id, so the client files land next to its own. */
+async fn request_run_id(room: &Room) -> Option<RunId> {
+    let mut events = room.subscribe();
+    let res = room
+        .local_participant()
+        .publish_data(DataPacket {
+            payload: RUN_ID_TOPIC.as_bytes().to_vec(),
+            topic: Some(RUN_ID_TOPIC.to_string()),
+            reliable: true,
+            ..Default::default()
+        })
+        .await;
+    if let Err(e) = res {
+        log::warn!("Failed to request run id: {:?}", e);
+        return None;
+    }
+
+    let response = async {
+        while let Some(event) = events.recv().await {
+            if let RoomEvent::DataReceived { payload, topic, .. } = event {
+                if topic.as_deref() == Some(RUN_ID_TOPIC) {
+                    return RunId::decode(&String::from_utf8_lossy(&payload));
+                }
+            }
+        }
+        None
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(5), response)
+        .await
+        .ok()
+        .flatten()
+}
+
+/* With an explicit output file the other tables are named after it. */
+fn named_after(output_file: &Path, kind: &str) -> PathBuf {
+    if kind == "latency" {
+        return output_file.to_path_buf();
+    }
+    let stem = output_file.file_stem().unwrap_or_default().to_string_lossy();
+    output_file.with_file_name(format!("{}_{}.csv", stem, kind))
+}
+
+fn connection_quality_name(quality: ConnectionQuality) -> &'static str {
+    match quality {
+        ConnectionQuality::Excellent => "excellent",
+        ConnectionQuality::Good => "good",
+        ConnectionQuality::Poor => "poor",
+        ConnectionQuality::Lost => "lost",
+    }
+}
+
+/*
+ * Records connection quality transitions of every participant, so latency
+ * spikes can be told apart from network quality changes, and the room
+ * reconnecting, after which the stream starts over.
+ */
+fn record_room_events(
+    room: &Room,
+    events: Arc<Mutex<Vec<Event>>>,
+) -> tokio::task::JoinHandle<()> {
+    let mut room_events = room.subscribe();
+    tokio::spawn(async move {
+        while let Some(event) = room_events.recv().await {
+            let event = match event {
+                RoomEvent::ConnectionQualityChanged {
+                    quality,
+                    participant,
+                } => {
+                    let detail = format!(
+                        "participant {} quality {}",
+                        participant.identity(),
+                        connection_quality_name(quality)
+                    );
+                    log::info!("Connection quality changed: {}", detail);
+                    Event::new("connection_quality", detail)
+                }
+                RoomEvent::Reconnecting => {
+                    log::warn!("Room reconnecting");
+                    Event::new(RECONNECTING_EVENT, String::new())
+                }
+                RoomEvent::Reconnected => {
+                    log::info!("Room reconnected");
+                    Event::new(RECONNECTED_EVENT, String::new())
+                }
+                _ => continue,
+            };
+            events.lock().unwrap().push(event);
+        }
+    })
+}
+
+fn find_publication(room: &Room, track: &RemoteVideoTrack) -> Option<RemoteTrackPublication> {
+    room.remote_participants().values().find_map(|participant| {
+        participant
+            .track_publications()
+            .get(&track.sid())
+            .cloned()
+    })
+}
+
+/// Measures on `track` until the run ends and writes the results. A run
+/// stopped by the watchdog fails with `TimedOut`, one that received less
+/// than configured with `--strict-resolution` with `InvalidData`, after
+/// the results are written.
 pub async fn end_to_end_latency(
     room: Room,
     track: RemoteVideoTrack,
-    output_file: &str,
-) -> io::Result<()> {
+    output_file: Option<&str>,
+    options: &MeasurementOptions,
+    publisher_config: &Mutex<Option<PublisherConfig>>,
+    first_frame: FirstFrame,
+) -> Result<(), MeasurementError> {
+    /* The run id is needed for the join key, even with an explicit output file. */
+    let run_id = match request_run_id(&room).await {
+        Some(run_id) => run_id,
+        None => {
+            log::warn!("Publisher didn't send its run id, using a local one");
+            RunId::new("unknown", "unknown", 0, "test")
+        }
+    };
+    let run = run_id.uuid.clone();
+    let layout = OutputLayout::new(&options.output_dir, run_id);
+    tracing::Span::current().record("run", run.as_str());
+    if let Some(run_log) = &options.json_log {
+        let path = layout.file(Side::Client, "log").with_extension("jsonl");
+        if let Err(e) = run_log.open(&path) {
+            log::error!("Failed to create the JSON log {}: {}", path.display(), e);
+        }
+    }
+    let mut sinks = create_sinks(&options.sinks, &layout, Side::Client)?;
+    /* The latency rows so far, on disk in case the process dies before the end. */
+    let rolling = if options.sinks.kinds.contains(&SinkKind::Csv) {
+        let path = match output_file {
+            Some(output_file) => named_after(Path::new(output_file), "latency"),
+            None => layout.file(Side::Client, "latency"),
+        };
+        RollingLatency::create(&path, &run)
+    } else {
+        None
+    };
+    if let Some(output_file) = output_file {
+        if options.sinks.kinds.contains(&SinkKind::Csv) {
+            let output_file = PathBuf::from(output_file);
+            sinks.retain(|sink| sink.name() != "csv");
+            sinks.push(Box::new(CsvSink::with_paths(move |kind| {
+                named_after(&output_file, kind)
+            })));
+        }
+    }
+
+    /*
+     * Capture times and the publisher tick timestamps `merge` compares with
+     * the receive times are on the publisher clock, watermark latency itself
+     * is timed locally.
+     */
+    let clock_offset = control::estimate_clock_offset(&room).await;
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let quality_task = record_room_events(&room, events.clone());
+    let heartbeat_task = control::monitor_heartbeat(&room, events.clone());
+    let audio_samples = Arc::new(Mutex::new(Vec::new()));
+    let audio_task = audio::record_audio_stats(&room, audio_samples.clone());
+    let return_track = match options.return_video {
+        Some(video) => match return_track::publish(&room, video).await {
+            Ok(return_track) => Some(return_track),
+            Err(e) => {
+                log::error!("{}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let track_name = track.name();
+    let layer_task = match (&options.layer_schedule, find_publication(&room, &track)) {
+        (Some(schedule), Some(publication)) => Some(layers::run_layer_schedule(
+            publication,
+            schedule.clone(),
+            events.clone(),
+        )),
+        (Some(_), None) => {
+            log::warn!("Track publication not found, ignoring the layer schedule");
+            None
+        }
+        (None, _) => None,
+    };
+
     let now = std::time::SystemTime::now()
         .duration_since(std::time::SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_secs_f64();
-    let latency = measure_latency(room, track.rtc_track()).await;
+    let dump = match &options.dump_rtc_stats {
+        Some(path) => Some(RtcStatsDump::create(path, &run)?),
+        None => None,
+    };
+    let recorder = match &options.record {
+        Some(path) => {
+            let fps = publisher_config
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map_or(DEFAULT_RECORD_FPS, |config| config.fps);
+            Some(FrameRecorder::create(path, fps)?)
+        }
+        None => None,
+    };
+    let polled_stats = Mutex::new(Vec::new());
+    /* A publisher without a config is taken for one from before the protocol version. */
+    let protocol = publisher_config
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map_or(0, |config| config.protocol);
+    let measure = measure_latency(
+        &room,
+        track,
+        options,
+        &events,
+        clock_offset,
+        dump.as_ref(),
+        recorder.as_ref(),
+        protocol,
+        publisher_config,
+        rolling,
+    )
+    .instrument(tracing::info_span!("measure"));
+    let poll = poll_rtc_stats(&room, options.stats_interval, &polled_stats, dump.as_ref())
+        .instrument(tracing::debug_span!("stats"));
+    let measurement = tokio::select! {
+        measurement = measure => measurement,
+        _ = poll => unreachable!(),
+    };
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = &options.dashboard {
+        dashboard.stop();
+    }
     let end = std::time::SystemTime::now()
         .duration_since(std::time::SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_secs_f64();
     let duration = end - now;
-    write_latency_to_csv(&latency, output_file, duration)?;
+    quality_task.abort();
+    heartbeat_task.abort();
+    audio_task.abort();
+    let return_samples = match return_track {
+        Some(return_track) => return_track.finish(&room).await,
+        None => Vec::new(),
+    };
+    if let Some(layer_task) = layer_task {
+        layer_task.abort();
+    }
+    let time_to_first_frame = first_frame.finish(&events).await;
+    write_table(
+        &mut sinks,
+        "latency",
+        &LATENCY_HEADER,
+        &latency_rows(
+            &measurement.latency,
+            &run,
+            duration,
+            &measurement.system_monitor,
+        ),
+    );
+    /* The samples of the latency table, those with a reply and stats, outside of exclusion windows. */
+    let latencies: Vec<u64> = measurement
+        .latency
+        .iter()
+        .filter(|entry| entry.receive_timestamp != 0 && entry.rtc_stats.is_some())
+        .filter(|entry| entry.excluded.is_none())
+        .map(|entry| entry.receive_timestamp.saturating_sub(entry.timestamp) as u64)
+        .collect();
+    if !latencies.is_empty() {
+        write_table(
+            &mut sinks,
+            "latency_histogram",
+            &histogram::HISTOGRAM_HEADER,
+            &histogram::histogram_rows(&latencies, options.latency_bucket_ms, &run),
+        );
+        write_table(
+            &mut sinks,
+            "latency_cdf",
+            &histogram::CDF_HEADER,
+            &histogram::cdf_rows(&latencies, &run),
+        );
+    }
+
+    let polled_stats = polled_stats.into_inner().unwrap();
+    if !polled_stats.is_empty() {
+        write_table(
+            &mut sinks,
+            "stats",
+            &STATS_HEADER,
+            &stats_rows(&polled_stats, &run),
+        );
+    }
+
+    if options.frame_log > 0 {
+        write_table(
+            &mut sinks,
+            "frames",
+            &FRAMES_HEADER,
+            &frame_rows(&measurement.arrivals, &run),
+        );
+    }
+
+    let events = event_rows(&events.lock().unwrap());
+    write_table(&mut sinks, "events", &EVENTS_HEADER, &events);
+
+    /* Only written when the room had audio tracks. */
+    let audio_rows = audio::audio_rows(&audio_samples.lock().unwrap(), &run);
+    if !audio_rows.is_empty() {
+        write_table(&mut sinks, "audio", &audio::AUDIO_HEADER, &audio_rows);
+    }
+
+    if options.return_video.is_some() {
+        write_table(
+            &mut sinks,
+            "return",
+            &return_track::RETURN_HEADER,
+            &return_track::return_rows(&return_samples, &run),
+        );
+    }
+
+    let mut metadata = RunMetadata::new();
+    if let Some(tag) = &options.tag {
+        metadata.set("tag", tag);
+    }
+    metadata.set(SCHEMA_VERSION_KEY, SCHEMA_VERSION);
+    metadata.set("git_commit", env!("BUILD_GIT_COMMIT"));
+    metadata.set("livekit_version", env!("BUILD_LIVEKIT_VERSION"));
+    metadata.set("libwebrtc_version", env!("BUILD_LIBWEBRTC_VERSION"));
+    /* What was actually measured, the last config the publisher reported. */
+    let mut shortfall = None;
+    if let Some(config) = publisher_config.lock().unwrap().as_ref() {
+        for (key, value) in config.entries() {
+            metadata.set(key, value);
+        }
+        shortfall = verify_received(&measurement.latency, config, &mut metadata);
+        match bitrate_adherence(&receive_rates(&measurement.latency), config.bitrate as f64) {
+            Some(adherence) => {
+                log::info!("Bitrate adherence: {}", adherence.summary());
+                adherence.record(&mut metadata);
+            }
+            None => log::warn!("No receive rate samples, bitrate adherence unknown"),
+        }
+    }
+    /* Lower layers requested on purpose aren't a shortfall. */
+    if options.layer_schedule.is_some() {
+        shortfall = None;
+    }
+    if let Some(shortfall) = &shortfall {
+        log::warn!("Received less than configured: {}", shortfall);
+    }
+    metadata.set("scan_interval", options.scan_interval);
+    if measurement.watermark_ticks > 0 {
+        let percent = measurement.detected_ticks as f64 / measurement.watermark_ticks as f64 * 100.;
+        log::info!(
+            "Watermark found for {} of {} ticks ({:.1}%), {} by the fallback scan",
+            measurement.detected_ticks,
+            measurement.watermark_ticks,
+            percent,
+            measurement.fallback_ticks
+        );
+        if percent < WATERMARK_WARNING_PERCENT {
+            log::warn!("Many watermarks missed, the latency samples are sparse");
+        }
+        metadata.set("watermark_ticks", measurement.watermark_ticks);
+        metadata.set("watermark_detected", measurement.detected_ticks);
+        metadata.set("watermark_detection_percent", format!("{:.1}", percent));
+        metadata.set("watermark_fallback", measurement.fallback_ticks);
+    }
+    metadata.set("quality_interval", options.quality_interval);
+    if let Some(video) = &options.return_video {
+        metadata.set("return_video", video);
+        metadata.set("return_bitrate", video.bitrate);
+        metadata.set(
+            "return_send_kbps",
+            format!("{:.1}", return_track::mean_send_kbps(&return_samples)),
+        );
+    }
+    metadata.set("ice_transport", options.ice_transport.as_str());
+    metadata.set("track_name", track_name);
+    options.room_features.record(&mut metadata);
+    /* The pair of the last sample, ICE may have switched during the run. */
+    let candidate_pair = measurement
+        .latency
+        .iter()
+        .rev()
+        .find_map(|entry| entry.rtc_stats.as_ref()?.candidate_pair.as_ref());
+    match candidate_pair {
+        Some(pair) => {
+            log::info!("Candidate pair: {}", pair);
+            pair.record(&mut metadata);
+        }
+        None => log::warn!("No selected candidate pair in the RTC stats"),
+    }
+    metadata.set(
+        "time_to_first_frame_ms",
+        time_to_first_frame.map_or(String::new(), |time| {
+            format!("{:.1}", time.as_secs_f64() * 1000.)
+        }),
+    );
+    metadata.set("skipped_stats_samples", measurement.skipped_samples);
+    metadata.set("finished_by_client", measurement.finished);
+    metadata.set(
+        "aborted",
+        measurement.aborted.as_deref().unwrap_or_default(),
+    );
+    if !options.exclusions.is_empty() {
+        metadata.set("exclusions", &options.exclusions);
+        metadata.set(EXCLUSIONS_START_KEY, measurement.started_ms);
+    }
+    if let Some(fingerprint) = &measurement.fingerprint {
+        metadata.set("fingerprint_interval", options.fingerprint_interval);
+        fingerprint.record(&mut metadata);
+    }
+    if let Some(offset) = clock_offset {
+        metadata.set("clock_offset_ms", format!("{:.3}", offset.offset_ms));
+        metadata.set("clock_sync_rtt_ms", format!("{:.3}", offset.rtt_ms));
+        metadata.set("clock_sync_samples", offset.samples);
+    }
+    if let Some(recorder) = recorder {
+        match recorder.finish() {
+            Ok(summary) => {
+                if summary.dropped > 0 {
+                    log::warn!(
+                        "{} frames left out of the recording, the disk didn't keep up",
+                        summary.dropped
+                    );
+                }
+                metadata.set("recorded_frames", summary.frames);
+                metadata.set("record_dropped_frames", summary.dropped);
+                metadata.set(
+                    "record_files",
+                    summary
+                        .files
+                        .iter()
+                        .map(|file| file.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                );
+            }
+            Err(e) => log::error!("Failed to write the recording: {}", e),
+        }
+    }
+    write_table(&mut sinks, "metadata", &METADATA_HEADER, &metadata.rows());
+
+    #[cfg(feature = "upload")]
+    if let Some(target) = &options.upload {
+        if output_file.is_some() {
+            log::warn!("Not uploading, --output-file writes outside the results layout");
+        } else {
+            match common::upload::upload_run(&layout, Side::Client, target) {
+                Ok(files) => log::info!("Uploaded {} result files", files),
+                Err(e) => log::error!("Failed to upload the results: {}", e),
+            }
+        }
+    }
+
+    /* The samples are written either way, the error tells the caller the run is incomplete. */
+    if measurement.stalled {
+        return Err(MeasurementError::Stalled(options.watchdog_timeout));
+    }
+    if let Some(reason) = measurement.aborted {
+        return Err(MeasurementError::Aborted(reason));
+    }
+    if let Some(shortfall) = shortfall.filter(|_| options.strict_resolution) {
+        return Err(MeasurementError::Shortfall(shortfall));
+    }
     Ok(())
 }
 
-fn write_latency_to_csv(
+/* Cumulative counters are followed by their per-sample deltas at the end. */
+const LATENCY_HEADER: [&str; 39] = [
+    "id",
+    "latency",
+    "processing_delay",
+    "jitter_buffer_delay",
+    "jitter_buffer_target_delay",
+    "jitter_buffer_minimum_delay",
+    "frames_per_second",
+    "freeze_count",
+    "total_bytes",
+    "dropped_frames",
+    "duration",
+    "cpu_usage",
+    "codec",
+    "run",
+    "total_bytes_delta",
+    "receive_kbps",
+    "frames_delta",
+    "freeze_count_delta",
+    "dropped_frames_delta",
+    "available_incoming_kbps",
+    "current_round_trip_time",
+    "total_round_trip_time",
+    "packets_lost",
+    "nack_count",
+    "retransmitted_packets_received",
+    "fec_packets_received",
+    "fec_packets_discarded",
+    "packets_lost_delta",
+    "fec_packets_received_delta",
+    "frame_width",
+    "frame_height",
+    "system_events",
+    "measurement_cpu_usage",
+    "blockiness",
+    "sharpness",
+    "banding",
+    "excluded",
+    "receive_timestamp",
+    "latency_method",
+];
+
+/* The counters behind the per-sample deltas of the latency table. */
+#[derive(Default)]
+struct LatencyRows {
+    bytes: Counter,
+    frames: Counter,
+    freezes: Counter,
+    dropped: Counter,
+    lost: Counter,
+    fec: Counter,
+}
+
+impl LatencyRows {
+    /* None for an entry without a reply or stats, those aren't written. */
+    fn row(
+        &mut self,
+        entry: &LatencyEntry,
+        run: &str,
+        duration: f64,
+        system_monitor: &SystemMonitor,
+    ) -> Option<Vec<Value>> {
+        if entry.receive_timestamp == 0 {
+            return None;
+        }
+        let stats = entry.rtc_stats.as_ref()?;
+        let timestamp = entry.receive_timestamp;
+        let received = self.bytes.update(stats.total_bytes, timestamp);
+        Some(vec![
+            entry.id.into(),
+            entry.receive_timestamp.saturating_sub(entry.timestamp).into(),
+            stats.processing_delay.into(),
+            stats.jitter_buffer_delay.into(),
+            stats.jitter_buffer_target_delay.into(),
+            stats.jitter_buffer_minimum_delay.into(),
+            stats.frames_per_second.into(),
+            stats.freeze_count.into(),
+            stats.total_bytes.into(),
+            stats.dropped_frames.into(),
+            duration.into(),
+            entry.cpu_usage.into(),
+            stats.codec.as_str().into(),
+            run.into(),
+            received.delta.into(),
+            Value::float(received.kbps(), 1),
+            self.frames.update(stats.total_frames, timestamp).delta.into(),
+            self.freezes.update(stats.freeze_count, timestamp).delta.into(),
+            self.dropped.update(stats.dropped_frames, timestamp).delta.into(),
+            Value::float(stats.available_incoming_bitrate / 1000., 1),
+            Value::float(stats.current_round_trip_time, 1),
+            Value::float(stats.total_round_trip_time, 1),
+            stats.packets_lost.into(),
+            stats.nack_count.into(),
+            stats.retransmitted_packets_received.into(),
+            stats.fec_packets_received.into(),
+            stats.fec_packets_discarded.into(),
+            self.lost.update(stats.packets_lost as f64, timestamp).delta.into(),
+            self.fec
+                .update(stats.fec_packets_received as f64, timestamp)
+                .delta
+                .into(),
+            entry.resolution.0.into(),
+            entry.resolution.1.into(),
+            system_monitor.annotate(timestamp).into(),
+            Value::float(entry.measurement_cpu_usage, 2),
+            Value::float(entry.quality.blockiness, 3),
+            Value::float(entry.quality.sharpness, 1),
+            Value::float(entry.quality.banding, 2),
+            entry.excluded.as_deref().unwrap_or_default().into(),
+            entry.receive_timestamp.into(),
+            entry.method.as_str().into(),
+        ])
+    }
+}
+
+fn latency_rows(
     latency: &[LatencyEntry],
-    output_file: &str,
+    run: &str,
     duration: f64,
-) -> io::Result<()> {
-    let mut file = File::create(output_file)?;
-    writeln!(
-        file,
-        "id,latency,processing_delay,jitter_buffer_delay,jitter_buffer_target_delay,jitter_buffer_minimum_delay,frames_per_second,freeze_count,total_bytes,dropped_frames,duration,cpu_usage,codec"
-    )?;
-    for entry in latency {
-        if entry.receive_timestamp == 0 || entry.rtc_stats.is_none() {
-            continue;
+    system_monitor: &SystemMonitor,
+) -> Vec<Vec<Value>> {
+    let mut rows = LatencyRows::default();
+    latency
+        .iter()
+        .filter_map(|entry| rows.row(entry, run, duration, system_monitor))
+        .collect()
+}
+
+/*
+ * The latency table appended to as the samples come in, see `RollingCsv`.
+ * The run duration isn't known yet, these rows carry the time so far.
+ */
+struct RollingLatency {
+    file: RollingCsv,
+    rows: LatencyRows,
+    run: String,
+}
+
+impl RollingLatency {
+    fn create(path: &Path, run: &str) -> Option<Self> {
+        match RollingCsv::create(path, &LATENCY_HEADER) {
+            Ok(file) => Some(RollingLatency {
+                file,
+                rows: LatencyRows::default(),
+                run: run.to_string(),
+            }),
+            Err(e) => {
+                log::error!("Failed to create {}: {}", path.display(), e);
+                None
+            }
         }
-        let stats = entry.rtc_stats.as_ref().unwrap();
-        writeln!(
-            file,
-            "{},{},{},{},{},{},{},{},{},{},{},{},{}",
-            entry.id,
-            entry.receive_timestamp - entry.timestamp,
-            stats.processing_delay,
-            stats.jitter_buffer_delay,
-            stats.jitter_buffer_target_delay,
-            stats.jitter_buffer_minimum_delay,
-            stats.frames_per_second,
-            stats.freeze_count,
-            stats.total_bytes,
-            stats.dropped_frames,
-            duration,
-            entry.cpu_usage,
-            stats.codec,
-        )?;
     }
-    Ok(())
+
+    /* False once writing failed, the file is then left as it is. */
+    fn append(
+        &mut self,
+        entry: &LatencyEntry,
+        elapsed: f64,
+        system_monitor: &SystemMonitor,
+    ) -> bool {
+        let Some(row) = self.rows.row(entry, &self.run, elapsed, system_monitor) else {
+            return true;
+        };
+        match self.file.append(&row) {
+            Ok(()) => true,
+            Err(e) => {
+                log::error!("Failed to append to {}: {}", self.file.path().display(), e);
+                false
+            }
+        }
+    }
+}
+
+/* One row per logged frame, the interval is the time since the frame before it. */
+const FRAMES_HEADER: [&str; 4] = ["run", "frame", "timestamp", "interval_ms"];
+
+fn frame_rows(arrivals: &[FrameArrival], run: &str) -> Vec<Vec<Value>> {
+    arrivals
+        .iter()
+        .map(|arrival| {
+            vec![
+                run.into(),
+                arrival.frame.into(),
+                arrival.timestamp.into(),
+                Value::float(arrival.interval.as_secs_f64() * 1000., 2),
+            ]
+        })
+        .collect()
+}
+
+/* Cumulative counters are followed by their per-sample deltas at the end. */
+const STATS_HEADER: [&str; 25] = [
+    "timestamp",
+    "run",
+    "processing_delay",
+    "jitter_buffer_delay",
+    "jitter_buffer_target_delay",
+    "jitter_buffer_minimum_delay",
+    "frames_per_second",
+    "total_frames",
+    "freeze_count",
+    "total_bytes",
+    "dropped_frames",
+    "available_incoming_kbps",
+    "current_round_trip_time",
+    "total_round_trip_time",
+    "packets_lost",
+    "nack_count",
+    "retransmitted_packets_received",
+    "fec_packets_received",
+    "fec_packets_discarded",
+    "codec",
+    "total_bytes_delta",
+    "receive_kbps",
+    "frames_delta",
+    "freeze_count_delta",
+    "packets_lost_delta",
+];
+
+fn stats_rows(samples: &[PolledStats], run: &str) -> Vec<Vec<Value>> {
+    let (mut bytes, mut frames, mut freezes, mut lost) =
+        (Counter::new(), Counter::new(), Counter::new(), Counter::new());
+    samples
+        .iter()
+        .map(|sample| {
+            let (stats, timestamp) = (&sample.stats, sample.timestamp);
+            let received = bytes.update(stats.total_bytes, timestamp);
+            vec![
+                timestamp.into(),
+                run.into(),
+                stats.processing_delay.into(),
+                stats.jitter_buffer_delay.into(),
+                stats.jitter_buffer_target_delay.into(),
+                stats.jitter_buffer_minimum_delay.into(),
+                stats.frames_per_second.into(),
+                stats.total_frames.into(),
+                stats.freeze_count.into(),
+                stats.total_bytes.into(),
+                stats.dropped_frames.into(),
+                Value::float(stats.available_incoming_bitrate / 1000., 1),
+                Value::float(stats.current_round_trip_time, 1),
+                Value::float(stats.total_round_trip_time, 1),
+                stats.packets_lost.into(),
+                stats.nack_count.into(),
+                stats.retransmitted_packets_received.into(),
+                stats.fec_packets_received.into(),
+                stats.fec_packets_discarded.into(),
+                stats.codec.as_str().into(),
+                received.delta.into(),
+                Value::float(received.kbps(), 1),
+                frames.update(stats.total_frames, timestamp).delta.into(),
+                freezes.update(stats.freeze_count, timestamp).delta.into(),
+                lost.update(stats.packets_lost as f64, timestamp).delta.into(),
+            ]
+        })
+        .collect()
 }
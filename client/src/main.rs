@@ -1,75 +1,502 @@
-use client::end_to_end_latency;
+use client::abort::AbortCondition;
+use client::control::{control_publisher, follow_publisher_config, ControlOptions};
+use client::first_frame::FirstFrame;
+use client::histogram::DEFAULT_BUCKET_MS;
+use client::layers::LayerSchedule;
+use client::return_track::{ReturnVideo, DEFAULT_RETURN_BITRATE};
+use client::status::{self, RunStatus};
+use client::track_filter::TrackFilter;
+use client::transport::room_options;
+use common::control::WATERMARK_FRAMES;
+use common::exclusion::ExclusionWindows;
+use common::logging::{init_tracing, RunLog};
+use common::sink::{SinkConfig, SinkKind};
+use common::transport::{IceTransport, RoomFeatures};
+#[cfg(feature = "upload")]
+use common::upload::UploadTarget;
+use client::{end_to_end_latency, LatencyMode, MeasurementOptions};
 use clap::Parser;
 use livekit::prelude::*;
 use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::Instrument;
 
 #[derive(Parser)]
 #[command(name = "livekit-client")]
 #[command(about = "LiveKit client for end-to-end latency measurement")]
 struct Args {
-    /// Output file path for latency measurements
+    /// Output file path for latency measurements, overrides the results layout
     #[arg(short, long)]
-    output_file: String,
+    output_file: Option<String>,
+
+    /// Root directory for the results
+    #[arg(long, default_value = "results")]
+    output_dir: String,
+
+    /// Free form label recorded in the run metadata, e.g. the machine or experiment
+    #[arg(long)]
+    tag: Option<String>,
+
+    /// Number of received frames to skip before sampling latency
+    #[arg(short, long, default_value_t = 500)]
+    warmup_frames: u64,
+
+    /// Latency measurement mode (watermark or capture-time)
+    #[arg(short, long, default_value = "watermark")]
+    latency_mode: LatencyMode,
+
+    /// Where to write the results (csv, sqlite, influx), comma separated
+    #[arg(long, value_delimiter = ',', default_value = "csv")]
+    sink: Vec<SinkKind>,
+
+    /// Database of the sqlite sink (default <output-dir>/results.sqlite)
+    #[arg(long)]
+    sqlite_path: Option<String>,
+
+    /// Write endpoint of the influx sink, the token is read from INFLUX_TOKEN
+    #[arg(long)]
+    influx_url: Option<String>,
+
+    /// Layers to request during the run, e.g. 0:high,30:low,60:medium (seconds:quality)
+    #[arg(long)]
+    layer_schedule: Option<LayerSchedule>,
+
+    /// Start a publisher launched with --wait-for-start
+    #[arg(long)]
+    start_publisher: bool,
+
+    /// Switch the publisher to this bitrate (kbps) before measuring
+    #[arg(long)]
+    publisher_bitrate: Option<u64>,
+
+    /// Switch the publisher to this frame rate before measuring
+    #[arg(long)]
+    publisher_fps: Option<u32>,
+
+    /// Seconds to wait for the screenshare track to come back after it went away, 0 to stop
+    #[arg(long, default_value_t = 15)]
+    resubscribe_timeout: u64,
+
+    /// Seconds to wait for a screenshare track before giving up, 0 to wait forever
+    #[arg(long, default_value_t = 0)]
+    subscribe_timeout: u64,
+
+    /// Measure the video track with this name, e.g. screen_share_vp9, instead of the first screenshare track
+    #[arg(long)]
+    track_name: Option<String>,
+
+    /// Measure a video track of the participant with this metadata
+    #[arg(long)]
+    participant_metadata: Option<String>,
+
+    /// Also write the JSON status printed on exit to this file
+    #[arg(long)]
+    status_file: Option<PathBuf>,
+
+    /// Seconds without a frame on a subscribed track before the run is aborted, 0 to disable
+    #[arg(long, default_value_t = 30)]
+    watchdog_timeout: u64,
+
+    /// Fail the run when the received resolution or frame rate falls short of the publisher config
+    #[arg(long)]
+    strict_resolution: bool,
+
+    /// Seconds between RTC stats reads independent of the latency samples, 0 to disable
+    #[arg(long, default_value_t = 1)]
+    stats_interval: u64,
+
+    /// Write every raw RTC stats report read to this file, as JSON lines
+    #[arg(long)]
+    dump_rtc_stats: Option<PathBuf>,
+
+    /// Log the arrival of every n-th frame for inter-frame interval histograms, 0 to disable
+    #[arg(long, default_value_t = 0)]
+    frame_log: u64,
+
+    /// Width of the buckets of the latency histogram written at the end, ms
+    #[arg(long, default_value_t = DEFAULT_BUCKET_MS, value_parser = clap::value_parser!(u64).range(1..))]
+    latency_bucket_ms: u64,
+
+    /// ICE candidates to connect over: all, relay (TURN only) or nohost
+    #[arg(long, default_value = "all")]
+    ice_transport: IceTransport,
+
+    /// TURN server to use instead of those of the LiveKit server, e.g. turns:turn.example.com:443, repeatable
+    #[arg(long)]
+    turn_url: Vec<String>,
+
+    /// Request only the layers the view needs and pause the track while it isn't shown
+    #[arg(long)]
+    adaptive_stream: bool,
+
+    /// Enable dynacast, the publisher pauses the layers no subscriber receives
+    #[arg(long)]
+    dynacast: bool,
+
+    /// Look for the watermark in every n-th frame only while no tick waits for it, to keep the measurement off the CPU usage
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..=WATERMARK_FRAMES as u64))]
+    scan_interval: u64,
+
+    /// Estimate blockiness, sharpness and banding on every n-th frame, 0 to disable
+    #[arg(long, default_value_t = 30)]
+    quality_interval: u64,
+
+    /// Check the content hash of every n-th frame when the publisher marks its frames
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    fingerprint_interval: u64,
+
+    /// Write every decoded frame to this Y4M file, with a frame index in a .csv next to it
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Also publish a synthetic video back while measuring, as in a call, e.g. 640x360@15
+    #[arg(long)]
+    return_video: Option<ReturnVideo>,
+
+    /// Send bitrate of the return video, kbps
+    #[arg(long, default_value_t = DEFAULT_RETURN_BITRATE, requires = "return_video")]
+    return_bitrate: u32,
+
+    /// Also log as JSON lines with the spans of every record, next to the results of the run
+    #[arg(long)]
+    json_log: bool,
+
+    /// End the run, on the publisher too, after this many latency samples
+    #[arg(long)]
+    samples: Option<u64>,
+
+    /// End the run, on the publisher too, after this many seconds of measuring
+    #[arg(long)]
+    duration: Option<u64>,
+
+    /// End the run early when a condition holds over its window, e.g. latency_p95>2000:30 or bitrate<30%:30, repeatable
+    #[arg(long)]
+    abort_if: Vec<AbortCondition>,
+
+    /// Mark the samples of these windows to be left out of the statistics, e.g. 30-45:loss,90-100 (seconds since the measurement started)
+    #[arg(long)]
+    exclude: Option<ExclusionWindows>,
+
+    /// Upload the results to s3://, gs:// or an http(s):// endpoint after the run (default RESULTS_UPLOAD_URL)
+    #[cfg(feature = "upload")]
+    #[arg(long)]
+    upload: Option<UploadTarget>,
+
+    /// Show the received video with a live stats overlay
+    #[cfg(feature = "render")]
+    #[arg(long)]
+    render: bool,
+
+    /// Show a live terminal dashboard of the run, logs go to client.log
+    #[cfg(feature = "tui")]
+    #[arg(long)]
+    tui: bool,
 }
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
     let args = Args::parse();
-    let url = env::var("LIVEKIT_URL").expect("LIVEKIT_URL environment variable not set");
-    let token = env::var("LIVEKIT_TOKEN").expect("LIVEKIT_TOKEN environment variable not set");
-
-    let (room, mut rx) = Room::connect(&url, &token, RoomOptions::default())
-        .await
-        .unwrap();
-
-    /* Check for already-subscribed video tracks. */
-    let existing_track = room.remote_participants().iter().find_map(|(_, p)| {
-        p.track_publications().iter().find_map(|(_, pub_)| {
-            if pub_.source() == TrackSource::Screenshare {
-                if let Some(RemoteTrack::Video(track)) = pub_.track() {
-                    return Some(track);
-                }
+    let json_log = init_logging(&args);
+    let status_file = args.status_file.clone();
+    let subscribe_timeout = Duration::from_secs(args.subscribe_timeout);
+    let room_features = RoomFeatures {
+        adaptive_stream: args.adaptive_stream,
+        dynacast: args.dynacast,
+    };
+    #[allow(unused_mut)]
+    let mut options = MeasurementOptions {
+        warmup_frames: args.warmup_frames,
+        latency_mode: args.latency_mode,
+        output_dir: args.output_dir,
+        tag: args.tag,
+        sinks: SinkConfig {
+            kinds: args.sink,
+            sqlite_path: args.sqlite_path.map(Into::into),
+            influx_url: args.influx_url,
+            influx_token: env::var("INFLUX_TOKEN").ok(),
+        },
+        control: ControlOptions {
+            start: args.start_publisher,
+            bitrate: args.publisher_bitrate,
+            fps: args.publisher_fps,
+        },
+        layer_schedule: args.layer_schedule,
+        resubscribe_timeout: Duration::from_secs(args.resubscribe_timeout),
+        track_filter: TrackFilter {
+            name: args.track_name,
+            participant_metadata: args.participant_metadata,
+        },
+        watchdog_timeout: Duration::from_secs(args.watchdog_timeout),
+        strict_resolution: args.strict_resolution,
+        stats_interval: Duration::from_secs(args.stats_interval),
+        dump_rtc_stats: args.dump_rtc_stats,
+        frame_log: args.frame_log,
+        latency_bucket_ms: args.latency_bucket_ms,
+        ice_transport: args.ice_transport,
+        room_features,
+        scan_interval: args.scan_interval,
+        quality_interval: args.quality_interval,
+        fingerprint_interval: args.fingerprint_interval,
+        record: args.record,
+        return_video: args
+            .return_video
+            .map(|video| ReturnVideo { bitrate: args.return_bitrate, ..video }),
+        json_log,
+        samples: args.samples,
+        duration: args.duration.map(Duration::from_secs),
+        abort_conditions: args.abort_if,
+        exclusions: args.exclude.unwrap_or_default(),
+        #[cfg(feature = "upload")]
+        upload: upload_target(args.upload),
+        #[cfg(feature = "render")]
+        render: None,
+        #[cfg(feature = "tui")]
+        dashboard: None,
+    };
+    #[cfg(feature = "render")]
+    let renderer = if args.render {
+        let (renderer, handle) = client::renderer::renderer();
+        options.render = Some(handle);
+        Some(renderer)
+    } else {
+        None
+    };
+
+    let (Ok(url), Ok(token)) = (env::var("LIVEKIT_URL"), env::var("LIVEKIT_TOKEN")) else {
+        let detail = "LIVEKIT_URL and LIVEKIT_TOKEN have to be set";
+        log::error!("{}", detail);
+        status::exit(RunStatus::Failed, Some(detail), status_file.as_deref());
+    };
+
+    let room_options = room_options(args.ice_transport, args.turn_url, room_features);
+    let (room, rx) = match Room::connect(&url, &token, room_options).await {
+        Ok(connected) => connected,
+        Err(e) => {
+            let detail = format!("failed to connect to {}: {}", url, e);
+            log::error!("{}", detail);
+            status::exit(
+                RunStatus::ConnectionFailed,
+                Some(&detail),
+                status_file.as_deref(),
+            );
+        }
+    };
+    /* Before anything else waits, the time to first frame counts from the subscription. */
+    let first_frame = FirstFrame::watch(&room, options.track_filter.clone());
+
+    /* Started once connected, so a failed connection is reported on a normal terminal. */
+    #[cfg(feature = "tui")]
+    let dashboard = if args.tui {
+        match common::tui::Dashboard::start(&format!("client, {}", room.name()), None) {
+            Ok(dashboard) => {
+                options.dashboard = Some(dashboard.clone());
+                Some(dashboard)
             }
-            None
-        })
-    });
+            Err(e) => {
+                log::error!("Failed to start the dashboard: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let room_name = room.name();
+    let measurement = tokio::spawn(
+        measure(
+            room,
+            rx,
+            args.output_file,
+            options,
+            subscribe_timeout,
+            first_frame,
+        )
+        .instrument(tracing::info_span!("client", room = %room_name)),
+    );
+
+    /* The window has to live on the main thread, measurement runs on the runtime workers. */
+    #[cfg(feature = "render")]
+    if let Some(renderer) = renderer {
+        renderer.run(|| measurement.is_finished());
+    }
+
+    let (status, detail) = match measurement.await {
+        Ok(outcome) => outcome,
+        Err(e) => (
+            RunStatus::Failed,
+            Some(format!("measurement panicked: {}", e)),
+        ),
+    };
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = dashboard {
+        dashboard.stop();
+    }
+    status::exit(status, detail.as_deref(), status_file.as_deref());
+}
+
+/* With `--tui` the dashboard owns the terminal, the logs go to `client.log`. */
+fn init_logging(args: &Args) -> Option<RunLog> {
+    #[cfg(feature = "tui")]
+    if args.tui {
+        match std::fs::File::create("client.log") {
+            Ok(file) => return init_tracing(Some(file), args.json_log),
+            Err(e) => eprintln!("Failed to create client.log, logging to stderr: {}", e),
+        }
+    }
+    init_tracing(None, args.json_log)
+}
+
+async fn measure(
+    room: Room,
+    mut rx: tokio::sync::mpsc::UnboundedReceiver<RoomEvent>,
+    output_file: Option<String>,
+    options: MeasurementOptions,
+    subscribe_timeout: Duration,
+    mut first_frame: FirstFrame,
+) -> (RunStatus, Option<String>) {
+    /* Following the broadcasts also catches a publisher that starts after us. */
+    let publisher_config = Arc::new(Mutex::new(None));
+    let config_task = follow_publisher_config(&room, publisher_config.clone());
+    control_publisher(&room, &options.control).await;
 
-    if let Some(track) = existing_track {
-        log::info!("Found existing video track, starting measurement");
-        end_to_end_latency(room, track, &args.output_file).await.unwrap();
+    /* Check for already-subscribed video tracks, unless they are about to be republished. */
+    let existing_track = if options.control.changes_parameters() {
+        None
     } else {
-        while let Some(msg) = rx.recv().await {
-            match msg {
-                RoomEvent::TrackSubscribed {
-                    track,
-                    publication,
-                    participant,
-                } => {
-                    log::info!(
-                        "TrackSubscribed: participant={} (id={}), track sid={}, source={:?}, kind={:?}, mime_type={}",
-                        participant.name(),
-                        participant.identity(),
-                        publication.sid(),
-                        publication.source(),
-                        publication.kind(),
-                        publication.mime_type(),
-                    );
-                    log::info!("Track: {:?}", track);
-                    if let RemoteTrack::Video(track) = track {
-                        if publication.source() == TrackSource::Screenshare {
-                            log::info!("Starting measurement on screenshare track");
-                            end_to_end_latency(room, track, &args.output_file).await.unwrap();
-                            break;
-                        } else {
-                            log::info!("Skipping non-screenshare video track (source={:?})", publication.source());
-                        }
+        room.remote_participants().iter().find_map(|(_, p)| {
+            p.track_publications().iter().find_map(|(_, pub_)| {
+                if options.track_filter.matches(pub_, p) {
+                    if let Some(RemoteTrack::Video(track)) = pub_.track() {
+                        return Some(track);
                     }
                 }
-                other => {
-                    log::info!("RoomEvent: {:?}", other);
+                None
+            })
+        })
+    };
+
+    let track = match existing_track {
+        Some(track) => {
+            log::info!("Found existing video track, starting measurement");
+            Ok(track)
+        }
+        None => match subscribe_timeout {
+            Duration::ZERO => wait_for_track(&mut rx, &options.track_filter).await,
+            timeout => {
+                tokio::time::timeout(timeout, wait_for_track(&mut rx, &options.track_filter))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err((
+                            RunStatus::NoTrack,
+                            format!("no {} within {} s", options.track_filter, timeout.as_secs()),
+                        ))
+                    })
+            }
+        },
+    };
+    let outcome = match track {
+        Ok(track) => {
+            first_frame.select(track.sid());
+            log::info!("Starting measurement on screenshare track");
+            /* The run id is only known once the publisher answers, see `end_to_end_latency`. */
+            let span = tracing::info_span!("run", run = tracing::field::Empty);
+            let result = end_to_end_latency(
+                room,
+                track,
+                output_file.as_deref(),
+                &options,
+                &publisher_config,
+                first_frame,
+            )
+            .instrument(span)
+            .await;
+            match result {
+                Ok(()) => (RunStatus::Complete, None),
+                /* A failed run, e.g. one aborted by the watchdog, has to fail the matrix script too. */
+                Err(e) => {
+                    log::error!("Measurement failed: {}", e);
+                    (RunStatus::of_error(&e), Some(e.to_string()))
                 }
             }
         }
+        Err((status, detail)) => {
+            log::error!("{}", detail);
+            (status, Some(detail))
+        }
+    };
+    config_task.abort();
+    outcome
+}
+
+/* The first track `filter` matches subscribed, or why there was none. */
+async fn wait_for_track(
+    rx: &mut tokio::sync::mpsc::UnboundedReceiver<RoomEvent>,
+    filter: &TrackFilter,
+) -> Result<RemoteVideoTrack, (RunStatus, String)> {
+    while let Some(msg) = rx.recv().await {
+        match msg {
+            RoomEvent::TrackSubscribed {
+                track,
+                publication,
+                participant,
+            } => {
+                log::info!(
+                    "TrackSubscribed: participant={} (id={}), track sid={}, source={:?}, kind={:?}, mime_type={}",
+                    participant.name(),
+                    participant.identity(),
+                    publication.sid(),
+                    publication.source(),
+                    publication.kind(),
+                    publication.mime_type(),
+                );
+                log::info!("Track: {:?}", track);
+                if let Some(rotation) = participant.attributes().get("rotation") {
+                    log::info!("Publisher rotation: {}", rotation);
+                }
+                if let RemoteTrack::Video(track) = track {
+                    /* Skip a track the publisher unpublished while changing parameters. */
+                    if !participant
+                        .track_publications()
+                        .contains_key(&publication.sid())
+                    {
+                        log::info!("Skipping unpublished track {}", publication.sid());
+                    } else if filter.matches(&publication, &participant) {
+                        return Ok(track);
+                    } else {
+                        log::info!(
+                            "Skipping video track {} (source={:?}), not the {}",
+                            publication.name(),
+                            publication.source(),
+                            filter
+                        );
+                    }
+                }
+            }
+            other => {
+                log::info!("RoomEvent: {:?}", other);
+            }
+        }
     }
+    Err((
+        RunStatus::ConnectionFailed,
+        format!("the room closed before a {} was subscribed", filter),
+    ))
+}
+
+/* The `--upload` destination, else RESULTS_UPLOAD_URL, with the token of UPLOAD_TOKEN. */
+#[cfg(feature = "upload")]
+fn upload_target(upload: Option<UploadTarget>) -> Option<UploadTarget> {
+    let target = match upload {
+        Some(target) => target,
+        None => match env::var("RESULTS_UPLOAD_URL").ok()?.parse() {
+            Ok(target) => target,
+            Err(e) => {
+                eprintln!("RESULTS_UPLOAD_URL: {}", e);
+                std::process::exit(1);
+            }
+        },
+    };
+    Some(target.with_token(env::var("UPLOAD_TOKEN").ok()))
 }
@@ -0,0 +1,139 @@
+use livekit::webrtc::prelude::I420Buffer;
+
+/*
+ * No-reference quality estimates of decoded frames, for runs without the
+ * reference frames PSNR or VMAF need. They are cheap proxies computed on
+ * the luma of every few frames, only meaningful to compare encoders on the
+ * same content:
+ * - blockiness: how much larger luma steps are across the 8x8 block grid
+ *   of the codecs than inside the blocks, 1 when the grid doesn't show;
+ * - sharpness: mean gradient over the edges, lower when detail was blurred;
+ * - banding: small steps between flat runs, the contours a coarse
+ *   quantizer leaves in gradients, per 1000 pixels.
+ */
+
+/* Codec block size the grid of the blockiness is checked at. */
+const BLOCK: usize = 8;
+/* Every how many rows are read, and the rows skipped at the top, where the watermark goes. */
+const ROW_STEP: usize = 4;
+const FIRST_ROW: usize = BLOCK;
+/* Gradient from which a pixel counts as an edge for the sharpness. */
+const EDGE: u32 = 24;
+/* Flat pixels in a row before a step of at most `BAND_STEP` counts as a band edge. */
+const BAND_RUN: u32 = 8;
+const BAND_STEP: u32 = 2;
+
+/// Estimates of one frame, or the mean over the frames of a sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameQuality {
+    pub blockiness: f64,
+    pub sharpness: f64,
+    pub banding: f64,
+}
+
+impl FrameQuality {
+    /// No frame was estimated.
+    pub const UNKNOWN: FrameQuality = FrameQuality {
+        blockiness: f64::NAN,
+        sharpness: f64::NAN,
+        banding: f64::NAN,
+    };
+}
+
+/// Estimates the quality of `buffer` from every `ROW_STEP`-th row.
+pub fn estimate(buffer: &I420Buffer) -> FrameQuality {
+    let (width, height) = (buffer.width() as usize, buffer.height() as usize);
+    let (stride_y, _, _) = buffer.strides();
+    let stride = stride_y as usize;
+    let (data_y, _, _) = buffer.data();
+    /* Sum and count of the steps on the block grid and inside the blocks, per direction. */
+    let mut steps = [[(0u64, 0u64); 2]; 2];
+    let (mut edges, mut edge_count) = (0u64, 0u64);
+    let (mut bands, mut pixels) = (0u64, 0u64);
+    for y in (FIRST_ROW..height).step_by(ROW_STEP) {
+        let row = &data_y[y * stride..y * stride + width];
+        let above = &data_y[(y - 1) * stride..(y - 1) * stride + width];
+        let mut flat = 0;
+        for x in 1..width {
+            let dx = row[x].abs_diff(row[x - 1]) as u32;
+            let dy = row[x].abs_diff(above[x]) as u32;
+            /* Steps between x - 1 and x, and between rows y - 1 and y. */
+            for (direction, (step, on_grid)) in [(dx, x % BLOCK == 0), (dy, y % BLOCK == 0)]
+                .into_iter()
+                .enumerate()
+            {
+                let (sum, count) = &mut steps[direction][on_grid as usize];
+                *sum += step as u64;
+                *count += 1;
+            }
+            let gradient = dx + dy;
+            if gradient >= EDGE {
+                edges += gradient as u64;
+                edge_count += 1;
+            }
+            match dx {
+                0 => flat += 1,
+                1..=BAND_STEP if flat >= BAND_RUN => {
+                    bands += 1;
+                    flat = 0;
+                }
+                _ => flat = 0,
+            }
+            pixels += 1;
+        }
+    }
+    let mean = |sum: u64, count: u64| {
+        if count > 0 {
+            sum as f64 / count as f64
+        } else {
+            f64::NAN
+        }
+    };
+    /* Offset by a level, so flat screen content doesn't divide by nearly 0. */
+    let ratio = |[(inner, inner_count), (grid, grid_count)]: [(u64, u64); 2]| {
+        (mean(grid, grid_count) + 1.) / (mean(inner, inner_count) + 1.)
+    };
+    FrameQuality {
+        blockiness: (ratio(steps[0]) + ratio(steps[1])) / 2.,
+        sharpness: if edge_count > 0 {
+            mean(edges, edge_count)
+        } else {
+            0.
+        },
+        banding: mean(bands * 1000, pixels),
+    }
+}
+
+/// Mean of the estimates between two latency samples.
+#[derive(Debug, Default)]
+pub struct QualityAverage {
+    sums: [f64; 3],
+    count: u32,
+}
+
+impl QualityAverage {
+    pub fn add(&mut self, quality: FrameQuality) {
+        for (sum, value) in
+            self.sums
+                .iter_mut()
+                .zip([quality.blockiness, quality.sharpness, quality.banding])
+        {
+            *sum += value;
+        }
+        self.count += 1;
+    }
+
+    /// The mean since the last call, `UNKNOWN` without estimates.
+    pub fn take(&mut self) -> FrameQuality {
+        if self.count == 0 {
+            return FrameQuality::UNKNOWN;
+        }
+        let [blockiness, sharpness, banding] = self.sums.map(|sum| sum / self.count as f64);
+        *self = QualityAverage::default();
+        FrameQuality {
+            blockiness,
+            sharpness,
+            banding,
+        }
+    }
+}
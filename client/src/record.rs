@@ -0,0 +1,220 @@
+/*
+ * Recording of the decoded frames for offline quality metrics, e.g. VMAF
+ * with the libvmaf filter of ffmpeg against a reference of the same
+ * frames. The frames are written as Y4M, raw I420, so the recording adds
+ * no loss of its own, at the price of size: 1080p at 30 fps is about
+ * 90 MB/s. A thread of its own writes them behind a short queue, frames
+ * arriving while it is full are left out of the recording and counted
+ * instead of holding up the receive loop. Every frame header carries the
+ * client frame number as an `X` parameter, which Y4M readers skip, and a
+ * `.csv` next to the recording lists every recorded frame with its file,
+ * its index there, the receive time, the capture timestamp of the frame
+ * and the fingerprint marker when the publisher draws one, to line the
+ * frames up with the reference. A Y4M file has a single frame size, so a resolution change
+ * starts a new file, `<stem>_<n>.y4m`.
+ */
+use common::sink::{Value, csv_header, csv_row};
+use livekit::webrtc::prelude::I420Buffer;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+
+/* Frames waiting for the writer, a few hundred MB at 4K. */
+const QUEUE_FRAMES: usize = 30;
+
+const INDEX_HEADER: [&str; 8] = [
+    "frame",
+    "file",
+    "index",
+    "receive_timestamp",
+    "capture_timestamp_us",
+    "width",
+    "height",
+    "fingerprint",
+];
+
+struct RecordedFrame {
+    frame: u64,
+    receive_timestamp: u128,
+    capture_timestamp_us: i64,
+    width: u32,
+    height: u32,
+    /* The planes without their stride padding. */
+    data: Vec<u8>,
+    marker: Option<u64>,
+}
+
+/// What ended up in the recording.
+pub struct RecordSummary {
+    pub frames: u64,
+    /// Frames left out because the writer fell behind.
+    pub dropped: u64,
+    pub files: Vec<PathBuf>,
+}
+
+pub struct FrameRecorder {
+    tx: SyncSender<RecordedFrame>,
+    writer: JoinHandle<io::Result<(u64, Vec<PathBuf>)>>,
+    dropped: AtomicU64,
+}
+
+impl FrameRecorder {
+    /// Starts the writer, `fps` goes into the Y4M headers.
+    pub fn create(path: &Path, fps: u32) -> io::Result<Self> {
+        let index = BufWriter::new(File::create(path.with_extension("csv"))?);
+        let (tx, rx) = mpsc::sync_channel(QUEUE_FRAMES);
+        let path = path.to_path_buf();
+        let writer = std::thread::spawn(move || write_frames(&path, fps.max(1), index, rx));
+        Ok(FrameRecorder {
+            tx,
+            writer,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Queues a decoded frame, `with_marker` when the publisher draws the
+    /// fingerprint marker.
+    pub fn record(
+        &self,
+        frame: u64,
+        receive_timestamp: u128,
+        capture_timestamp_us: i64,
+        buffer: &I420Buffer,
+        with_marker: bool,
+    ) {
+        let (width, height) = (buffer.width(), buffer.height());
+        let (chroma_width, chroma_height) = (buffer.chroma_width(), buffer.chroma_height());
+        let (stride_y, stride_u, stride_v) = buffer.strides();
+        let (data_y, data_u, data_v) = buffer.data();
+        let mut data =
+            Vec::with_capacity((width * height + 2 * chroma_width * chroma_height) as usize);
+        for (plane, stride, plane_width, rows) in [
+            (data_y, stride_y, width, height),
+            (data_u, stride_u, chroma_width, chroma_height),
+            (data_v, stride_v, chroma_width, chroma_height),
+        ] {
+            for row in 0..rows as usize {
+                let start = row * stride as usize;
+                data.extend_from_slice(&plane[start..start + plane_width as usize]);
+            }
+        }
+        let marker = with_marker
+            .then(|| {
+                common::fingerprint::read_marker(
+                    data_y,
+                    stride_y as usize,
+                    width as usize,
+                    height as usize,
+                )
+            })
+            .flatten();
+        let frame = RecordedFrame {
+            frame,
+            receive_timestamp,
+            capture_timestamp_us,
+            width,
+            height,
+            data,
+            marker,
+        };
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(frame) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Waits for the queued frames to be written.
+    pub fn finish(self) -> io::Result<RecordSummary> {
+        drop(self.tx);
+        let (frames, files) = self
+            .writer
+            .join()
+            .map_err(|_| io::Error::other("the recording writer panicked"))??;
+        Ok(RecordSummary {
+            frames,
+            dropped: self.dropped.into_inner(),
+            files,
+        })
+    }
+}
+
+/* `path` for the first frame size, `<stem>_<n>.<extension>` for the next ones. */
+fn segment_path(path: &Path, segment: usize) -> PathBuf {
+    if segment == 0 {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, segment, extension.to_string_lossy()),
+        None => format!("{}_{}", stem, segment),
+    };
+    path.with_file_name(name)
+}
+
+fn write_frames(
+    path: &Path,
+    fps: u32,
+    mut index: BufWriter<File>,
+    rx: Receiver<RecordedFrame>,
+) -> io::Result<(u64, Vec<PathBuf>)> {
+    writeln!(index, "{}", csv_header(&INDEX_HEADER))?;
+    let mut files: Vec<PathBuf> = vec![];
+    let mut file: Option<(BufWriter<File>, (u32, u32))> = None;
+    let mut frames_in_file = 0u64;
+    let mut frames = 0;
+    for frame in rx {
+        let size = (frame.width, frame.height);
+        if file
+            .as_ref()
+            .is_none_or(|(_, file_size)| *file_size != size)
+        {
+            if let Some((mut output, _)) = file.take() {
+                output.flush()?;
+            }
+            let segment = segment_path(path, files.len());
+            let mut output = BufWriter::new(File::create(&segment)?);
+            writeln!(
+                output,
+                "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420 XCOLORRANGE=LIMITED",
+                frame.width, frame.height, fps
+            )?;
+            log::info!(
+                "Recording {}x{} frames to {}",
+                frame.width,
+                frame.height,
+                segment.display()
+            );
+            files.push(segment);
+            file = Some((output, size));
+            frames_in_file = 0;
+        }
+        let (output, _) = file.as_mut().unwrap();
+        writeln!(output, "FRAME Xframe={}", frame.frame)?;
+        output.write_all(&frame.data)?;
+        let name = files.last().unwrap().file_name().unwrap().to_string_lossy();
+        let row: [Value; 8] = [
+            frame.frame.into(),
+            name.as_ref().into(),
+            frames_in_file.into(),
+            frame.receive_timestamp.into(),
+            frame.capture_timestamp_us.into(),
+            frame.width.into(),
+            frame.height.into(),
+            frame
+                .marker
+                .map(|marker| format!("{:016x}", marker))
+                .unwrap_or_default()
+                .into(),
+        ];
+        writeln!(index, "{}", csv_row(&row))?;
+        frames_in_file += 1;
+        frames += 1;
+    }
+    if let Some((mut output, _)) = file {
+        output.flush()?;
+    }
+    index.flush()?;
+    Ok((frames, files))
+}
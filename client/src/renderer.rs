@@ -0,0 +1,265 @@
+/*
+ * Optional window that shows the received video with a live overlay of the
+ * latest measurements. The measurement loop hands frames over through a
+ * `RenderHandle`, only converting a frame to ARGB when the window has
+ * consumed the previous one, to keep the extra CPU load low.
+ */
+use common::font;
+use livekit::webrtc::native::yuv_helper;
+use livekit::webrtc::prelude::{BoxVideoFrame, VideoBuffer};
+use minifb::{ScaleMode, Window, WindowOptions};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, TryRecvError, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const WINDOW_WIDTH: usize = 1280;
+const WINDOW_HEIGHT: usize = 720;
+/* How long the freeze indicator stays on after a new freeze. */
+const FREEZE_HIGHLIGHT: Duration = Duration::from_secs(2);
+const TEXT_COLOR: u32 = 0x00ff_ffff;
+const FREEZE_COLOR: u32 = 0x00ff_3030;
+
+#[derive(Debug, Clone, Default)]
+pub struct RenderStats {
+    pub latency_ms: Option<u128>,
+    pub total_bytes: f64,
+    pub freeze_count: f64,
+}
+
+struct RenderFrame {
+    width: usize,
+    height: usize,
+    pixels: Vec<u32>,
+}
+
+#[derive(Clone)]
+pub struct RenderHandle {
+    frames: SyncSender<RenderFrame>,
+    frame_pending: Arc<AtomicBool>,
+    frame_count: Arc<AtomicU64>,
+    stats: Arc<Mutex<RenderStats>>,
+}
+
+impl RenderHandle {
+    pub fn submit(&self, frame: &BoxVideoFrame) {
+        self.frame_count.fetch_add(1, Ordering::Relaxed);
+        if self.frame_pending.load(Ordering::Acquire) {
+            return;
+        }
+
+        let buffer = frame.buffer.to_i420();
+        let width = buffer.width() as usize;
+        let height = buffer.height() as usize;
+        let (stride_y, stride_u, stride_v) = buffer.strides();
+        let (data_y, data_u, data_v) = buffer.data();
+        let mut argb = vec![0u8; width * height * 4];
+        yuv_helper::i420_to_argb(
+            data_y,
+            stride_y,
+            data_u,
+            stride_u,
+            data_v,
+            stride_v,
+            &mut argb,
+            (width * 4) as u32,
+            width as i32,
+            height as i32,
+        );
+        let pixels = argb
+            .chunks_exact(4)
+            .map(|p| u32::from_le_bytes([p[0], p[1], p[2], p[3]]))
+            .collect();
+
+        self.frame_pending.store(true, Ordering::Release);
+        if self
+            .frames
+            .try_send(RenderFrame {
+                width,
+                height,
+                pixels,
+            })
+            .is_err()
+        {
+            self.frame_pending.store(false, Ordering::Release);
+        }
+    }
+
+    pub fn update_stats(&self, latency_ms: u128, total_bytes: f64, freeze_count: f64) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.latency_ms = Some(latency_ms);
+        stats.total_bytes = total_bytes;
+        stats.freeze_count = freeze_count;
+    }
+}
+
+pub struct Renderer {
+    frames: Receiver<RenderFrame>,
+    frame_pending: Arc<AtomicBool>,
+    frame_count: Arc<AtomicU64>,
+    stats: Arc<Mutex<RenderStats>>,
+}
+
+pub fn renderer() -> (Renderer, RenderHandle) {
+    let (tx, rx) = sync_channel(1);
+    let frame_pending = Arc::new(AtomicBool::new(false));
+    let frame_count = Arc::new(AtomicU64::new(0));
+    let stats = Arc::new(Mutex::new(RenderStats::default()));
+    (
+        Renderer {
+            frames: rx,
+            frame_pending: frame_pending.clone(),
+            frame_count: frame_count.clone(),
+            stats: stats.clone(),
+        },
+        RenderHandle {
+            frames: tx,
+            frame_pending,
+            frame_count,
+            stats,
+        },
+    )
+}
+
+/* Values derived from the raw stats between overlay refreshes. */
+struct OverlayState {
+    fps: f64,
+    bitrate_kbps: f64,
+    last_frame_count: u64,
+    last_fps_update: Instant,
+    last_total_bytes: f64,
+    last_bytes_update: Instant,
+    last_freeze_count: f64,
+    last_freeze: Option<Instant>,
+}
+
+impl Renderer {
+    /// Runs the window on the calling thread until it is closed or `done`
+    /// returns true. On macOS this has to be the main thread.
+    pub fn run(self, done: impl Fn() -> bool) {
+        let mut window = match Window::new(
+            "livekit-client",
+            WINDOW_WIDTH,
+            WINDOW_HEIGHT,
+            WindowOptions {
+                resize: true,
+                scale_mode: ScaleMode::AspectRatioStretch,
+                ..WindowOptions::default()
+            },
+        ) {
+            Ok(window) => window,
+            Err(e) => {
+                log::error!("Failed to open render window: {}", e);
+                return;
+            }
+        };
+        window.set_target_fps(60);
+
+        let now = Instant::now();
+        let mut state = OverlayState {
+            fps: 0.,
+            bitrate_kbps: 0.,
+            last_frame_count: 0,
+            last_fps_update: now,
+            last_total_bytes: 0.,
+            last_bytes_update: now,
+            last_freeze_count: 0.,
+            last_freeze: None,
+        };
+
+        while window.is_open() && !done() {
+            match self.frames.try_recv() {
+                Ok(mut frame) => {
+                    self.frame_pending.store(false, Ordering::Release);
+                    self.draw_overlay(&mut frame, &mut state);
+                    if let Err(e) =
+                        window.update_with_buffer(&frame.pixels, frame.width, frame.height)
+                    {
+                        log::warn!("Failed to render frame: {}", e);
+                    }
+                }
+                Err(TryRecvError::Empty) => window.update(),
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+    }
+
+    fn draw_overlay(&self, frame: &mut RenderFrame, state: &mut OverlayState) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_fps_update).as_secs_f64();
+        if elapsed >= 1. {
+            let frame_count = self.frame_count.load(Ordering::Relaxed);
+            state.fps = (frame_count - state.last_frame_count) as f64 / elapsed;
+            state.last_frame_count = frame_count;
+            state.last_fps_update = now;
+        }
+
+        let stats = self.stats.lock().unwrap().clone();
+        if stats.total_bytes != state.last_total_bytes {
+            let elapsed = now.duration_since(state.last_bytes_update).as_secs_f64();
+            if state.last_total_bytes > 0. && elapsed > 0. {
+                state.bitrate_kbps =
+                    (stats.total_bytes - state.last_total_bytes) * 8. / elapsed / 1000.;
+            }
+            state.last_total_bytes = stats.total_bytes;
+            state.last_bytes_update = now;
+        }
+        if stats.freeze_count > state.last_freeze_count {
+            state.last_freeze = Some(now);
+        }
+        state.last_freeze_count = stats.freeze_count;
+
+        let latency = match stats.latency_ms {
+            Some(latency) => format!("LATENCY {} MS", latency),
+            None => "LATENCY -".to_string(),
+        };
+        let lines = [
+            (latency, TEXT_COLOR),
+            (format!("FPS {:.1}", state.fps), TEXT_COLOR),
+            (format!("BITRATE {:.0} KBPS", state.bitrate_kbps), TEXT_COLOR),
+            (
+                format!("FREEZES {}", stats.freeze_count),
+                match state.last_freeze {
+                    Some(at) if now.duration_since(at) < FREEZE_HIGHLIGHT => FREEZE_COLOR,
+                    _ => TEXT_COLOR,
+                },
+            ),
+        ];
+
+        let scale = (frame.height / 270).max(2);
+        let line_height = (font::GLYPH_HEIGHT + 2) * scale;
+        let margin = 2 * scale;
+        let panel_width = lines
+            .iter()
+            .map(|(text, _)| font::text_width(text, scale))
+            .max()
+            .unwrap_or(0)
+            + 2 * margin;
+        let panel_height = lines.len() * line_height + 2 * margin;
+
+        /* Darken the area behind the text so it stays readable. */
+        for row in 0..panel_height.min(frame.height) {
+            let start = row * frame.width;
+            for pixel in &mut frame.pixels[start..start + panel_width.min(frame.width)] {
+                *pixel = (*pixel >> 2) & 0x003f_3f3f;
+            }
+        }
+
+        let mut plane = font::Plane {
+            data: &mut frame.pixels,
+            stride: frame.width,
+            width: frame.width,
+            height: frame.height,
+        };
+        for (i, (text, color)) in lines.iter().enumerate() {
+            font::draw_text(
+                &mut plane,
+                margin as i64,
+                (margin + i * line_height) as i64,
+                scale,
+                text,
+                *color,
+            );
+        }
+    }
+}
@@ -0,0 +1,308 @@
+/*
+ * Return video published by the client while it measures, as the other
+ * side of a call would send its camera. Both directions then compete for
+ * the CPU of both machines and for the bandwidth of the same connections,
+ * which a one-way benchmark never sees. The frames are synthetic, a moving
+ * bar over a gradient, and the send side stats of the track are sampled
+ * like the audio ones.
+ */
+use common::delta::Counter;
+use common::sink::Value;
+use livekit::options::{TrackPublishOptions, VideoCodec, VideoEncoding};
+use livekit::prelude::*;
+use livekit::webrtc::prelude::{I420Buffer, RtcVideoSource, VideoFrame, VideoRotation};
+use livekit::webrtc::stats::RtcStats;
+use livekit::webrtc::video_source::VideoResolution;
+use livekit::webrtc::video_source::native::NativeVideoSource;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often the return track is sampled.
+pub const RETURN_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub const RETURN_TRACK_NAME: &str = "return";
+
+/// Default send bitrate of the return track, kbps.
+pub const DEFAULT_RETURN_BITRATE: u32 = 300;
+
+/* Width of the moving bar in pixels, and how far it moves per frame. */
+const BAR_WIDTH: u32 = 32;
+const BAR_SPEED: u32 = 4;
+
+/// Size and rate of the return video, parsed from `<width>x<height>` or
+/// `<width>x<height>@<fps>`, e.g. `640x360@15`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReturnVideo {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    /// kbps.
+    pub bitrate: u32,
+}
+
+impl std::fmt::Display for ReturnVideo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}@{}", self.width, self.height, self.fps)
+    }
+}
+
+impl std::str::FromStr for ReturnVideo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (size, fps) = s.split_once('@').unwrap_or((s, "15"));
+        let (width, height) = size
+            .split_once('x')
+            .ok_or_else(|| format!("Invalid return video: {}. Use <width>x<height>[@<fps>]", s))?;
+        /* I420 needs even dimensions. */
+        let dimension = |value: &str| {
+            value
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|value| *value > 0 && value % 2 == 0)
+                .ok_or_else(|| format!("Invalid return video size: {}", value))
+        };
+        let fps = fps
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|fps| *fps > 0)
+            .ok_or_else(|| format!("Invalid return video frame rate: {}", fps))?;
+        Ok(ReturnVideo {
+            width: dimension(width)?,
+            height: dimension(height)?,
+            fps,
+            bitrate: DEFAULT_RETURN_BITRATE,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReturnSample {
+    /// Unix ms when the stats were read.
+    pub timestamp: u128,
+    pub bytes_sent: u64,
+    pub frames_encoded: u64,
+    pub frames_sent: u64,
+    /// Total time spent encoding, s.
+    pub total_encode_time: f64,
+    /// Encoder target, bps.
+    pub target_bitrate: f64,
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub fps: f64,
+    pub quality_limitation: String,
+}
+
+/// The published return track, until `finish`.
+pub struct ReturnTrack {
+    sid: TrackSid,
+    frames_task: tokio::task::JoinHandle<()>,
+    stats_task: tokio::task::JoinHandle<()>,
+    samples: Arc<Mutex<Vec<ReturnSample>>>,
+}
+
+/// Publishes the return video and starts feeding and sampling it.
+pub async fn publish(room: &Room, video: ReturnVideo) -> Result<ReturnTrack, String> {
+    let source = NativeVideoSource::new(VideoResolution {
+        width: video.width,
+        height: video.height,
+    });
+    let track = LocalVideoTrack::create_video_track(
+        RETURN_TRACK_NAME,
+        RtcVideoSource::Native(source.clone()),
+    );
+    let options = TrackPublishOptions {
+        source: TrackSource::Camera,
+        video_codec: VideoCodec::VP8,
+        video_encoding: Some(VideoEncoding {
+            max_bitrate: video.bitrate as u64 * 1000,
+            max_framerate: video.fps as f64,
+        }),
+        simulcast: false,
+        ..Default::default()
+    };
+    let publication = room
+        .local_participant()
+        .publish_track(LocalTrack::Video(track.clone()), options)
+        .await
+        .map_err(|e| format!("Failed to publish the return video: {:?}", e))?;
+    log::info!(
+        "Publishing return video {} at {} kbps",
+        video,
+        video.bitrate
+    );
+
+    let frames_task = tokio::spawn(async move {
+        let mut frame = VideoFrame {
+            rotation: VideoRotation::VideoRotation0,
+            buffer: I420Buffer::new(video.width, video.height),
+            timestamp_us: 0,
+        };
+        let started = Instant::now();
+        let mut interval = tokio::time::interval(Duration::from_secs_f64(1. / video.fps as f64));
+        for index in 0u64.. {
+            interval.tick().await;
+            render(&mut frame.buffer, index);
+            frame.timestamp_us = started.elapsed().as_micros() as i64;
+            source.capture_frame(&frame);
+        }
+    });
+
+    let samples = Arc::new(Mutex::new(Vec::new()));
+    let stats_task = {
+        let samples = samples.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RETURN_SAMPLE_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Some(sample) = sample_track(&track).await {
+                    samples.lock().unwrap().push(sample);
+                }
+            }
+        })
+    };
+
+    Ok(ReturnTrack {
+        sid: publication.sid(),
+        frames_task,
+        stats_task,
+        samples,
+    })
+}
+
+/* A gradient in luma with a bar moving across it, neutral chroma. */
+fn render(buffer: &mut I420Buffer, index: u64) {
+    let (width, height) = (buffer.width(), buffer.height());
+    let (stride_y, _, _) = buffer.strides();
+    let bar_start = ((index * BAR_SPEED as u64) % width as u64) as u32;
+    let (y, u, v) = buffer.data_mut();
+    for (row, line) in y.chunks_exact_mut(stride_y as usize).enumerate() {
+        let shade = (row as u32 * 200 / height.max(1)) as u8 + 16;
+        for (x, pixel) in line[..width as usize].iter_mut().enumerate() {
+            let x = x as u32;
+            *pixel = if x >= bar_start && x < bar_start + BAR_WIDTH {
+                235
+            } else {
+                shade
+            };
+        }
+    }
+    u.fill(128);
+    v.fill(128);
+}
+
+async fn sample_track(track: &LocalVideoTrack) -> Option<ReturnSample> {
+    let stats = match track.get_stats().await {
+        Ok(stats) => stats,
+        Err(e) => {
+            log::warn!("Failed to get stats of the return video: {:?}", e);
+            return None;
+        }
+    };
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    stats.iter().find_map(|stat| match stat {
+        RtcStats::OutboundRtp(stats) => Some(ReturnSample {
+            timestamp,
+            bytes_sent: stats.sent.bytes_sent,
+            frames_encoded: stats.outbound.frames_encoded as u64,
+            frames_sent: stats.outbound.frames_sent as u64,
+            total_encode_time: stats.outbound.total_encode_time,
+            target_bitrate: stats.outbound.target_bitrate,
+            frame_width: stats.outbound.frame_width,
+            frame_height: stats.outbound.frame_height,
+            fps: stats.outbound.frames_per_second,
+            /* The stats enums name their values in lower case, as the W3C stats do. */
+            quality_limitation: format!("{:?}", stats.outbound.quality_limitation_reason)
+                .to_lowercase(),
+        }),
+        _ => None,
+    })
+}
+
+impl ReturnTrack {
+    /// Stops the return video, unpublishes it and returns its samples.
+    pub async fn finish(self, room: &Room) -> Vec<ReturnSample> {
+        self.frames_task.abort();
+        self.stats_task.abort();
+        if let Err(e) = room.local_participant().unpublish_track(&self.sid).await {
+            log::warn!("Failed to unpublish the return video: {:?}", e);
+        }
+        std::mem::take(&mut *self.samples.lock().unwrap())
+    }
+}
+
+/* Cumulative counters are followed by their per-sample deltas and rates. */
+pub const RETURN_HEADER: [&str; 15] = [
+    "timestamp",
+    "run",
+    "bytes_sent",
+    "frames_encoded",
+    "frames_sent",
+    "total_encode_time",
+    "target_kbps",
+    "frame_width",
+    "frame_height",
+    "fps",
+    "quality_limitation",
+    "bytes_sent_delta",
+    "send_kbps",
+    "frames_sent_delta",
+    "encode_ms",
+];
+
+/// The samples as rows for a stats sink.
+pub fn return_rows(samples: &[ReturnSample], run: &str) -> Vec<Vec<Value>> {
+    let (mut bytes_sent, mut frames_sent) = (Counter::new(), Counter::new());
+    let (mut encoded, mut encode_time) = (Counter::new(), Counter::new());
+    samples
+        .iter()
+        .map(|sample| {
+            let timestamp = sample.timestamp;
+            let sent = bytes_sent.update(sample.bytes_sent as f64, timestamp);
+            let frames = encoded.update(sample.frames_encoded as f64, timestamp);
+            let time = encode_time.update(sample.total_encode_time * 1000., timestamp);
+            /* Average encode time of the frames since the previous sample, ms. */
+            let encode_ms = if frames.delta > 0. {
+                time.delta / frames.delta
+            } else {
+                f64::NAN
+            };
+            vec![
+                sample.timestamp.into(),
+                run.into(),
+                sample.bytes_sent.into(),
+                sample.frames_encoded.into(),
+                sample.frames_sent.into(),
+                Value::float(sample.total_encode_time, 3),
+                Value::float(sample.target_bitrate / 1000., 1),
+                sample.frame_width.into(),
+                sample.frame_height.into(),
+                Value::float(sample.fps, 1),
+                sample.quality_limitation.as_str().into(),
+                sent.delta.into(),
+                Value::float(sent.kbps(), 1),
+                frames_sent
+                    .update(sample.frames_sent as f64, timestamp)
+                    .delta
+                    .into(),
+                Value::float(encode_ms, 2),
+            ]
+        })
+        .collect()
+}
+
+/// Mean send rate over the samples, kbps, NaN without any.
+pub fn mean_send_kbps(samples: &[ReturnSample]) -> f64 {
+    match (samples.first(), samples.last()) {
+        (Some(first), Some(last)) if last.timestamp > first.timestamp => {
+            last.bytes_sent.saturating_sub(first.bytes_sent) as f64 * 8.
+                / (last.timestamp - first.timestamp) as f64
+        }
+        _ => f64::NAN,
+    }
+}
@@ -0,0 +1,76 @@
+/*
+ * How a run ended, for the scripts driving the client. Each outcome has an
+ * exit code of its own, so a matrix can retry a run that never got a track
+ * and keep going after one that only fell short, and a JSON status with the
+ * reason goes to stdout, the logs go to stderr, and to `--status-file`.
+ */
+use crate::MeasurementError;
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// The measurement ran to its end.
+    Complete,
+    /// Anything else, e.g. the results couldn't be written.
+    Failed,
+    /// The room couldn't be joined, or was left before a track came.
+    ConnectionFailed,
+    /// No screenshare track within `--subscribe-timeout`.
+    NoTrack,
+    /// The watchdog stopped the run, the results are partial.
+    Incomplete,
+    /// Less was received than configured, with `--strict-resolution`.
+    Shortfall,
+    /// An `--abort-if` condition held, the results are partial.
+    Aborted,
+}
+
+impl RunStatus {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            RunStatus::Complete => 0,
+            RunStatus::Failed => 1,
+            RunStatus::ConnectionFailed => 2,
+            RunStatus::NoTrack => 3,
+            RunStatus::Incomplete => 4,
+            RunStatus::Shortfall => 5,
+            RunStatus::Aborted => 6,
+        }
+    }
+
+    /// The status of a measurement that returned `error`, see
+    /// `end_to_end_latency`.
+    pub fn of_error(error: &MeasurementError) -> Self {
+        match error {
+            MeasurementError::Io(_) => RunStatus::Failed,
+            MeasurementError::Stalled(_) => RunStatus::Incomplete,
+            MeasurementError::Aborted(_) => RunStatus::Aborted,
+            MeasurementError::Shortfall(_) => RunStatus::Shortfall,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StatusReport<'a> {
+    status: RunStatus,
+    exit_code: i32,
+    detail: Option<&'a str>,
+}
+
+/// Reports `status` on stdout and in `status_file`, then exits with its code.
+pub fn exit(status: RunStatus, detail: Option<&str>, status_file: Option<&Path>) -> ! {
+    let report = StatusReport {
+        status,
+        exit_code: status.exit_code(),
+        detail,
+    };
+    let json = serde_json::to_string(&report).unwrap();
+    println!("{}", json);
+    let written = status_file.map(|path| (path, std::fs::write(path, format!("{}\n", json))));
+    if let Some((path, Err(e))) = written {
+        log::error!("Failed to write the status to {}: {}", path.display(), e);
+    }
+    std::process::exit(status.exit_code());
+}
@@ -0,0 +1,48 @@
+use livekit::prelude::*;
+
+/// Which published video track is measured. Without criteria it is the
+/// first screenshare track, as in rooms with a single publisher; with any,
+/// the first video track matching all of them, whatever its source.
+#[derive(Debug, Clone, Default)]
+pub struct TrackFilter {
+    /// Name the publisher gave the track, e.g. `screen_share_vp9`.
+    pub name: Option<String>,
+    /// Metadata of the publishing participant.
+    pub participant_metadata: Option<String>,
+}
+
+impl TrackFilter {
+    pub fn matches(
+        &self,
+        publication: &RemoteTrackPublication,
+        participant: &RemoteParticipant,
+    ) -> bool {
+        if self.name.is_none() && self.participant_metadata.is_none() {
+            return publication.source() == TrackSource::Screenshare;
+        }
+        self.name
+            .as_ref()
+            .is_none_or(|name| publication.name() == *name)
+            && self
+                .participant_metadata
+                .as_ref()
+                .is_none_or(|metadata| participant.metadata() == *metadata)
+    }
+}
+
+impl std::fmt::Display for TrackFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut criteria = vec![];
+        if let Some(name) = &self.name {
+            criteria.push(format!("named {}", name));
+        }
+        if let Some(metadata) = &self.participant_metadata {
+            criteria.push(format!("of a participant with metadata {}", metadata));
+        }
+        if criteria.is_empty() {
+            write!(f, "screenshare track")
+        } else {
+            write!(f, "video track {}", criteria.join(" "))
+        }
+    }
+}
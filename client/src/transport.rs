@@ -0,0 +1,83 @@
+use common::transport::{
+    Candidate, CandidatePair, IceTransport, RoomFeatures, TURN_PASSWORD_ENV, TURN_USERNAME_ENV,
+};
+use livekit::prelude::*;
+use livekit::webrtc::peer_connection_factory::{IceServer, IceTransportsType};
+use livekit::webrtc::stats::{RtcStats, dictionaries::IceCandidateStats};
+
+/*
+ * The livekit side of `common::transport`: the room options and reading
+ * the selected candidate pair out of the RTC stats. Both binaries carry
+ * this same file, because common stays free of the SDK (the analysis tool
+ * builds without it) and there is no crate the two share that depends on
+ * it. Change both copies together.
+ */
+
+/// Room options restricting ICE to `ice_transport`, over the given TURN
+/// servers instead of those of the LiveKit server when there are any, with
+/// the room `features` enabled.
+pub fn room_options(
+    ice_transport: IceTransport,
+    turn_urls: Vec<String>,
+    features: RoomFeatures,
+) -> RoomOptions {
+    let mut options = RoomOptions::default();
+    options.adaptive_stream = features.adaptive_stream;
+    options.dynacast = features.dynacast;
+    options.rtc_config.ice_transport_type = match ice_transport {
+        IceTransport::All => IceTransportsType::All,
+        IceTransport::Relay => IceTransportsType::Relay,
+        IceTransport::NoHost => IceTransportsType::NoHost,
+    };
+    if !turn_urls.is_empty() {
+        options.rtc_config.ice_servers = vec![IceServer {
+            urls: turn_urls,
+            username: std::env::var(TURN_USERNAME_ENV).unwrap_or_default(),
+            password: std::env::var(TURN_PASSWORD_ENV).unwrap_or_default(),
+        }];
+    }
+    options
+}
+
+/// The candidate pair the transport of `stats` selected, if it is connected.
+pub fn selected_candidate_pair(stats: &[RtcStats]) -> Option<CandidatePair> {
+    let (pair_id, changes) = stats.iter().find_map(|stat| match stat {
+        RtcStats::Transport(transport)
+            if !transport.transport.selected_candidate_pair_id.is_empty() =>
+        {
+            Some((
+                &transport.transport.selected_candidate_pair_id,
+                transport.transport.selected_candidate_pair_changes,
+            ))
+        }
+        _ => None,
+    })?;
+    let (local_id, remote_id) = stats.iter().find_map(|stat| match stat {
+        RtcStats::CandidatePair(pair) if &pair.rtc.id == pair_id => Some((
+            &pair.candidate_pair.local_candidate_id,
+            &pair.candidate_pair.remote_candidate_id,
+        )),
+        _ => None,
+    })?;
+    let local = stats.iter().find_map(|stat| match stat {
+        RtcStats::LocalCandidate(local) if &local.rtc.id == local_id => {
+            Some(candidate(&local.local_candidate))
+        }
+        _ => None,
+    })?;
+    let remote = stats.iter().find_map(|stat| match stat {
+        RtcStats::RemoteCandidate(remote) if &remote.rtc.id == remote_id => {
+            Some(candidate(&remote.remote_candidate))
+        }
+        _ => None,
+    })?;
+    Some(CandidatePair {
+        local,
+        remote,
+        changes,
+    })
+}
+
+fn candidate(stats: &IceCandidateStats) -> Candidate {
+    Candidate::new(stats.candidate_type, &stats.protocol, stats.relay_protocol)
+}
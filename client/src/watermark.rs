@@ -0,0 +1,108 @@
+use common::watermark::WatermarkConfig;
+use livekit::webrtc::prelude::I420Buffer;
+
+/* Samples read along a line, spread over its length. */
+const LINE_SAMPLES: usize = 64;
+/* Every how many rows or columns the fallback scan reads. */
+const FALLBACK_STEP: usize = 2;
+
+/* A row or a column of the decoded picture the band was seen on. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Line {
+    Row(usize),
+    Column(usize),
+}
+
+/*
+ * Looks for the watermark in decoded frames. The band is expected in the
+ * middle of the marked rows, scaled from the published to the decoded
+ * resolution and moved below the bars of a letterboxed picture. When it
+ * isn't there while a tick waits for its mark, every other row of the top and bottom quarter and every
+ * other column of the left and right quarter are scanned, which finds it
+ * in frames rotated or cropped on the way, and the line it was found on is
+ * tried first from then on.
+ */
+pub struct WatermarkScanner {
+    config: WatermarkConfig,
+    expected: Option<usize>,
+    found: Option<Line>,
+    /// Whether the last mark was found by the fallback scan.
+    pub last_by_fallback: bool,
+}
+
+impl WatermarkScanner {
+    pub fn new(config: WatermarkConfig) -> Self {
+        WatermarkScanner {
+            config,
+            expected: None,
+            found: None,
+            last_by_fallback: false,
+        }
+    }
+
+    /// Derives the expected row from the resolutions, when the decoded one
+    /// changes.
+    pub fn set_resolution(&mut self, decoded: (u32, u32), published: Option<(u32, u32)>) {
+        /* Without the publisher config the frame is taken to be unscaled. */
+        let published = published.unwrap_or(decoded);
+        self.expected = self
+            .config
+            .expected_row(decoded, published)
+            .map(|row| row as usize);
+        self.found = None;
+    }
+
+    /// Whether `buffer` is marked. The whole picture is only scanned with
+    /// `fallback`, when a mark is expected, since that reads a lot more of it.
+    pub fn scan(&mut self, buffer: &I420Buffer, fallback: bool) -> bool {
+        let candidates = [self.found, self.expected.map(Line::Row)];
+        if candidates
+            .into_iter()
+            .flatten()
+            .any(|line| self.marked(buffer, line))
+        {
+            self.last_by_fallback = false;
+            return true;
+        }
+        if !fallback {
+            self.last_by_fallback = false;
+            return false;
+        }
+        let (width, height) = (buffer.width() as usize, buffer.height() as usize);
+        let rows = (0..height / 4).chain(height - height / 4..height);
+        let columns = (0..width / 4).chain(width - width / 4..width);
+        let found = rows
+            .step_by(FALLBACK_STEP)
+            .map(Line::Row)
+            .chain(columns.step_by(FALLBACK_STEP).map(Line::Column))
+            .find(|line| self.marked(buffer, *line));
+        self.last_by_fallback = found.is_some();
+        if let Some(line) = found {
+            if self.found != Some(line) {
+                log::info!("Watermark found on {:?} by the fallback scan", line);
+            }
+            self.found = Some(line);
+        }
+        found.is_some()
+    }
+
+    fn marked(&self, buffer: &I420Buffer, line: Line) -> bool {
+        let (width, height) = (buffer.width() as usize, buffer.height() as usize);
+        let (stride_y, _, _) = buffer.strides();
+        let (data_y, _, _) = buffer.data();
+        let (start, step, length) = match line {
+            Line::Row(row) if row < height => (row * stride_y as usize, 1, width),
+            Line::Column(column) if column < width => (column, stride_y as usize, height),
+            _ => return false,
+        };
+        let samples = LINE_SAMPLES.min(length);
+        if samples == 0 {
+            return false;
+        }
+        let matching = (0..samples)
+            .map(|i| data_y[start + i * length / samples * step])
+            .filter(|luma| self.config.matches(*luma))
+            .count();
+        matching as f64 >= samples as f64 * self.config.min_share
+    }
+}
@@ -0,0 +1,212 @@
+/*
+ * End-to-end benchmark harness.
+ *
+ * Runs the screen_sharer (with its synthetic source) and the client against a
+ * LiveKit server for a short benchmark and checks that both sides produced
+ * well formed, non-empty CSV files. With `E2E_VIRTUAL_DISPLAY` set to a
+ * size, e.g. `1280x720`, the screen_sharer captures an Xvfb display of
+ * that size instead, which exercises the capturer too.
+ *
+ * Requirements:
+ * - `livekit-server` on PATH (started in `--dev` mode), or `LIVEKIT_URL`
+ *   pointing to an already running server together with `LIVEKIT_API_KEY`
+ *   and `LIVEKIT_API_SECRET`.
+ * - A built screen_sharer binary, at `SCREEN_SHARER_BIN` or in
+ *   `../screen_sharer/target/debug/screen_sharer`.
+ * - For `E2E_VIRTUAL_DISPLAY`, `Xvfb` and `ffplay` on PATH.
+ *
+ * Run with `cargo test --features e2e`.
+ */
+#![cfg(feature = "e2e")]
+
+use livekit_api::access_token::{AccessToken, VideoGrants};
+use std::env;
+use std::fs;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+const DEV_URL: &str = "ws://127.0.0.1:7880";
+const DEV_API_KEY: &str = "devkey";
+const DEV_API_SECRET: &str = "secret";
+const BENCHMARK_SECONDS: u64 = 10;
+const PROCESS_TIMEOUT: Duration = Duration::from_secs(90);
+
+/* Kills the wrapped process when the test ends, even on panic. */
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn start_server() -> (String, Option<KillOnDrop>) {
+    if let Ok(url) = env::var("LIVEKIT_URL") {
+        return (url, None);
+    }
+
+    let server = Command::new("livekit-server")
+        .args(["--dev", "--bind", "127.0.0.1"])
+        .spawn()
+        .expect("failed to start livekit-server, is it on PATH?");
+    let server = KillOnDrop(server);
+
+    let start = Instant::now();
+    while TcpStream::connect("127.0.0.1:7880").is_err() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "livekit-server did not start listening"
+        );
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    (DEV_URL.to_string(), Some(server))
+}
+
+fn token(room: &str, identity: &str) -> String {
+    let api_key = env::var("LIVEKIT_API_KEY").unwrap_or_else(|_| DEV_API_KEY.to_string());
+    let api_secret = env::var("LIVEKIT_API_SECRET").unwrap_or_else(|_| DEV_API_SECRET.to_string());
+    AccessToken::with_api_key(&api_key, &api_secret)
+        .with_identity(identity)
+        .with_name(identity)
+        .with_grants(VideoGrants {
+            room_join: true,
+            room: room.to_string(),
+            ..Default::default()
+        })
+        .to_jwt()
+        .expect("failed to create access token")
+}
+
+fn screen_sharer_bin() -> PathBuf {
+    match env::var("SCREEN_SHARER_BIN") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../screen_sharer/target/debug/screen_sharer"),
+    }
+}
+
+fn wait_with_timeout(child: &mut KillOnDrop, name: &str) {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.0.try_wait().unwrap() {
+            assert!(status.success(), "{} exited with {}", name, status);
+            return;
+        }
+        assert!(start.elapsed() < PROCESS_TIMEOUT, "{} timed out", name);
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/* Asserts the CSV has a header, at least one row and consistent columns. */
+fn assert_csv_well_formed(path: &Path) {
+    let content = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e));
+    let mut lines = content.lines();
+    let header = lines.next().expect("csv is empty");
+    let columns = header.split(',').count();
+    assert!(columns > 1, "{}: unexpected header {}", path.display(), header);
+
+    let mut rows = 0;
+    for line in lines {
+        let fields: Vec<&str> = line.split(',').collect();
+        assert_eq!(
+            fields.len(),
+            columns,
+            "{}: row {} has wrong column count",
+            path.display(),
+            line
+        );
+        assert!(
+            fields.iter().all(|f| !f.is_empty()),
+            "{}: row {} has empty fields",
+            path.display(),
+            line
+        );
+        rows += 1;
+    }
+    assert!(rows > 0, "{} has no data rows", path.display());
+}
+
+/* Searches `dir` recursively for a file whose name ends in `suffix`. */
+fn find_file_with_suffix(dir: &Path, suffix: &str) -> Option<PathBuf> {
+    for entry in fs::read_dir(dir).ok()?.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(found) = find_file_with_suffix(&path, suffix) {
+                return Some(found);
+            }
+        } else if path.to_string_lossy().ends_with(suffix) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+#[test]
+fn benchmark_produces_csvs() {
+    let (url, _server) = start_server();
+
+    let room = format!(
+        "e2e-{}",
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis()
+    );
+    let output_dir = env::temp_dir().join(&room);
+    fs::create_dir_all(&output_dir).unwrap();
+    let results_dir = output_dir.join("results");
+
+    let source = match env::var("E2E_VIRTUAL_DISPLAY") {
+        Ok(size) => vec!["--virtual-display".to_string(), size],
+        Err(_) => vec!["--synthetic".to_string()],
+    };
+    let screen_sharer = Command::new(screen_sharer_bin())
+        .args(&source)
+        .args([
+            "--codec",
+            "VP8",
+            "--duration",
+            &BENCHMARK_SECONDS.to_string(),
+            "--name",
+            "e2e",
+            "--output-dir",
+            results_dir.to_str().unwrap(),
+        ])
+        .env("LIVEKIT_URL", &url)
+        .env("LIVEKIT_TOKEN", token(&room, "screen_sharer"))
+        .spawn()
+        .expect("failed to start screen_sharer");
+    let mut screen_sharer = KillOnDrop(screen_sharer);
+
+    let client = Command::new(env!("CARGO_BIN_EXE_client"))
+        .args([
+            "--output-dir",
+            results_dir.to_str().unwrap(),
+            "--warmup-frames",
+            "30",
+        ])
+        .env("LIVEKIT_URL", &url)
+        .env("LIVEKIT_TOKEN", token(&room, "client"))
+        .spawn()
+        .expect("failed to start client");
+    let mut client = KillOnDrop(client);
+
+    wait_with_timeout(&mut screen_sharer, "screen_sharer");
+    wait_with_timeout(&mut client, "client");
+
+    let publisher_csv = find_file_with_suffix(&results_dir, "e2e_publisher_stats.csv")
+        .expect("publisher stats file missing");
+    let client_csv = find_file_with_suffix(&results_dir, "e2e_client_latency.csv")
+        .expect("client latency file missing");
+    assert_eq!(
+        publisher_csv.parent(),
+        client_csv.parent(),
+        "publisher and client files of the same run are in different directories"
+    );
+    assert_csv_well_formed(&publisher_csv);
+    assert_csv_well_formed(&client_csv);
+}
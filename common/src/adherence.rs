@@ -0,0 +1,142 @@
+use crate::metadata::RunMetadata;
+
+/*
+ * How much of the configured bitrate a run delivered. Encoders differ in
+ * how well they hit their target on screen content, static slides leave
+ * little to spend the bits on and some rate controllers stay far below the
+ * target throughout. The first seconds are left out, the bandwidth
+ * estimate ramps up then whatever the encoder does. Unlike the warm-up
+ * detection of the analysis tool this doesn't wait for the target to be
+ * reached, an encoder that never reaches it is what this is meant to show.
+ */
+
+/* Start of the run left out, while the bandwidth estimate ramps up. */
+const RAMP_UP_MS: u128 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitrateAdherence {
+    /// Mean rate over the stable part of the run, kbps.
+    pub delivered_kbps: f64,
+    pub target_kbps: f64,
+    /// Rate samples the mean is taken over.
+    pub samples: usize,
+}
+
+impl BitrateAdherence {
+    /// Delivered share of the target, %.
+    pub fn percent(&self) -> f64 {
+        self.delivered_kbps / self.target_kbps * 100.
+    }
+
+    pub fn record(&self, metadata: &mut RunMetadata) {
+        metadata.set("delivered_kbps", format!("{:.1}", self.delivered_kbps));
+        metadata.set("bitrate_adherence", format!("{:.1}", self.percent()));
+    }
+
+    /// One line for the end of run output.
+    pub fn summary(&self) -> String {
+        format!(
+            "{:.1} kbps of the {} kbps target ({:.1}%) over {} samples",
+            self.delivered_kbps,
+            self.target_kbps,
+            self.percent(),
+            self.samples
+        )
+    }
+}
+
+/// Adherence of `samples`, (unix ms, kbps) pairs, to `target_kbps`. The
+/// samples after the ramp-up count, or the second half of a run too
+/// short for it. None without a target or a rate in that part.
+pub fn bitrate_adherence(samples: &[(u128, f64)], target_kbps: f64) -> Option<BitrateAdherence> {
+    let (first, last) = (samples.first()?.0, samples.last()?.0);
+    if target_kbps <= 0. {
+        return None;
+    }
+    let start = if last.saturating_sub(first) >= RAMP_UP_MS * 2 {
+        first + RAMP_UP_MS
+    } else {
+        first + last.saturating_sub(first) / 2
+    };
+    let rates: Vec<f64> = samples
+        .iter()
+        .filter(|(timestamp, kbps)| *timestamp >= start && kbps.is_finite())
+        .map(|(_, kbps)| *kbps)
+        .collect();
+    if rates.is_empty() {
+        return None;
+    }
+    Some(BitrateAdherence {
+        delivered_kbps: rates.iter().sum::<f64>() / rates.len() as f64,
+        target_kbps,
+        samples: rates.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* One sample a second from `start` ms, the rate given by the second. */
+    fn samples(start: u128, rates: impl IntoIterator<Item = f64>) -> Vec<(u128, f64)> {
+        rates
+            .into_iter()
+            .enumerate()
+            .map(|(second, kbps)| (start + second as u128 * 1000, kbps))
+            .collect()
+    }
+
+    #[test]
+    fn leaves_out_the_ramp_up() {
+        /* 10 s ramping up at 100 kbps, then 21 s at 900. */
+        let rates = (0..31).map(|second| if second < 10 { 100. } else { 900. });
+        let adherence = bitrate_adherence(&samples(5_000, rates), 1000.).unwrap();
+        assert_eq!(adherence.samples, 21);
+        assert_eq!(adherence.delivered_kbps, 900.);
+        assert_eq!(adherence.percent(), 90.);
+    }
+
+    #[test]
+    fn short_runs_use_their_second_half() {
+        let rates = (0..11).map(|second| if second < 5 { 0. } else { 1500. });
+        let adherence = bitrate_adherence(&samples(0, rates), 1000.).unwrap();
+        assert_eq!(adherence.samples, 6);
+        assert_eq!(adherence.delivered_kbps, 1500.);
+        assert_eq!(adherence.percent(), 150.);
+    }
+
+    #[test]
+    fn skips_rates_that_arent_finite() {
+        let rates = [0., 0., 0., 600., f64::NAN, 800., f64::INFINITY];
+        let adherence = bitrate_adherence(&samples(0, rates), 1000.).unwrap();
+        assert_eq!(adherence.samples, 2);
+        assert_eq!(adherence.delivered_kbps, 700.);
+    }
+
+    #[test]
+    fn needs_a_target_and_rates() {
+        assert_eq!(bitrate_adherence(&[], 1000.), None);
+        assert_eq!(bitrate_adherence(&samples(0, [500., 500.]), 0.), None);
+        assert_eq!(
+            bitrate_adherence(&samples(0, [500., f64::NAN]), 1000.),
+            None
+        );
+    }
+
+    #[test]
+    fn records_the_delivered_rate() {
+        let adherence = BitrateAdherence {
+            delivered_kbps: 1234.56,
+            target_kbps: 2000.,
+            samples: 12,
+        };
+        let mut metadata = RunMetadata::new();
+        adherence.record(&mut metadata);
+        assert_eq!(metadata.get("delivered_kbps"), Some("1234.6"));
+        assert_eq!(metadata.get("bitrate_adherence"), Some("61.7"));
+        assert_eq!(
+            adherence.summary(),
+            "1234.6 kbps of the 2000 kbps target (61.7%) over 12 samples"
+        );
+    }
+}
@@ -0,0 +1,76 @@
+/*
+ * What a binary was built from, recorded in the run metadata so results
+ * taken against different SDK versions or commits aren't compared by
+ * accident. The build scripts call `emit` and the binaries read the values
+ * back with `env!("BUILD_GIT_COMMIT")`, `env!("BUILD_LIVEKIT_VERSION")` and
+ * `env!("BUILD_LIBWEBRTC_VERSION")`.
+ */
+use std::path::Path;
+use std::process::Command;
+
+/// Version of `package` in a Cargo.lock, with the commit for git sources,
+/// e.g. `0.7.25 (git 29a0f14abd13)`.
+pub fn locked_version(lock: &str, package: &str) -> Option<String> {
+    lock.split("[[package]]").find_map(|entry| {
+        let field = |key: &str| {
+            entry.lines().find_map(|line| {
+                let value = line.strip_prefix(key)?.trim_start().strip_prefix('=')?;
+                Some(value.trim().trim_matches('"').to_string())
+            })
+        };
+        if field("name")? != package {
+            return None;
+        }
+        let version = field("version")?;
+        match field("source") {
+            Some(source) if source.starts_with("git+") => {
+                let commit = source.rsplit_once('#').map(|(_, commit)| commit)?;
+                Some(format!("{} (git {})", version, &commit[..commit.len().min(12)]))
+            }
+            _ => Some(version),
+        }
+    })
+}
+
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).current_dir(dir).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Commit checked out at `dir`, suffixed with `-dirty` when there are
+/// uncommitted changes.
+pub fn git_commit(dir: &Path) -> Option<String> {
+    let commit = git(dir, &["rev-parse", "HEAD"])?;
+    let dirty = git(dir, &["status", "--porcelain"]).is_some_and(|status| !status.is_empty());
+    Some(if dirty { commit + "-dirty" } else { commit })
+}
+
+/// Sets the `BUILD_*` variables from a build script, `unknown` for the
+/// ones that can't be found.
+pub fn emit() {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let manifest_dir = Path::new(&manifest_dir);
+    let lock_path = manifest_dir.join("Cargo.lock");
+    let lock = std::fs::read_to_string(&lock_path).unwrap_or_default();
+    println!("cargo:rerun-if-changed={}", lock_path.display());
+    if let Some(git_dir) = git(manifest_dir, &["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        println!("cargo:rerun-if-changed={}/index", git_dir);
+    }
+
+    let values = [
+        ("BUILD_GIT_COMMIT", git_commit(manifest_dir)),
+        ("BUILD_LIVEKIT_VERSION", locked_version(&lock, "livekit")),
+        ("BUILD_LIBWEBRTC_VERSION", locked_version(&lock, "libwebrtc")),
+    ];
+    for (var, value) in values {
+        println!(
+            "cargo:rustc-env={}={}",
+            var,
+            value.as_deref().unwrap_or("unknown")
+        );
+    }
+}
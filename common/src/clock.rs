@@ -0,0 +1,168 @@
+/*
+ * NTP-style clock offset estimation between the client and the publisher.
+ * The client stamps a ping when sending it (t1) and when the pong comes
+ * back (t4), the publisher stamps when it got the ping (t2) and when it
+ * answered (t3). Assuming symmetric paths, the publisher clock is ahead of
+ * the client clock by ((t2 - t1) + (t3 - t4)) / 2.
+ */
+
+/// Current unix time in microseconds.
+pub fn now_us() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as u64
+}
+
+/// One ping exchange, unix microseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockSample {
+    /// Ping sent, client clock.
+    pub sent: u64,
+    /// Ping received, publisher clock.
+    pub received: u64,
+    /// Pong sent, publisher clock.
+    pub replied: u64,
+    /// Pong received, client clock.
+    pub returned: u64,
+}
+
+impl ClockSample {
+    /// How far the publisher clock is ahead of the client clock, in ms.
+    pub fn offset_ms(&self) -> f64 {
+        let there = self.received as f64 - self.sent as f64;
+        let back = self.replied as f64 - self.returned as f64;
+        (there + back) / 2. / 1000.
+    }
+
+    /// Network round trip time without the publisher's processing, in ms.
+    pub fn rtt_ms(&self) -> f64 {
+        let total = self.returned as f64 - self.sent as f64;
+        let processing = self.replied as f64 - self.received as f64;
+        (total - processing) / 1000.
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockOffset {
+    /// Median offset of the samples, publisher minus client, in ms.
+    pub offset_ms: f64,
+    /// Median round trip time of the samples, in ms.
+    pub rtt_ms: f64,
+    pub samples: usize,
+}
+
+impl ClockOffset {
+    /// Median of the samples, so one delayed exchange doesn't skew the estimate.
+    pub fn estimate(samples: &[ClockSample]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        Some(ClockOffset {
+            offset_ms: median(samples.iter().map(ClockSample::offset_ms).collect()),
+            rtt_ms: median(samples.iter().map(ClockSample::rtt_ms).collect()),
+            samples: samples.len(),
+        })
+    }
+
+    /// Converts a publisher timestamp in ms to the client clock.
+    pub fn to_client_ms(&self, publisher_ms: i64) -> i64 {
+        publisher_ms - self.offset_ms.round() as i64
+    }
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.
+    } else {
+        values[middle]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* An exchange at client time `sent` with the publisher clock `offset_ms` ahead, all in ms. */
+    fn exchange(
+        sent: u64,
+        offset_ms: u64,
+        there_ms: u64,
+        processing_ms: u64,
+        back_ms: u64,
+    ) -> ClockSample {
+        let received = sent + there_ms + offset_ms;
+        let replied = received + processing_ms;
+        ClockSample {
+            sent: sent * 1000,
+            received: received * 1000,
+            replied: replied * 1000,
+            returned: (replied - offset_ms + back_ms) * 1000,
+        }
+    }
+
+    #[test]
+    fn offset_and_rtt_of_one_exchange() {
+        let sample = ClockSample {
+            sent: 1_000_000,
+            received: 1_060_000,
+            replied: 1_061_000,
+            returned: 1_021_000,
+        };
+        assert_eq!(sample.offset_ms(), 50.);
+        assert_eq!(sample.rtt_ms(), 20.);
+        /* A publisher behind the client is a negative offset. */
+        let behind = ClockSample {
+            sent: 1_000_000,
+            received: 980_000,
+            replied: 981_000,
+            returned: 1_021_000,
+        };
+        assert_eq!(behind.offset_ms(), -30.);
+        assert_eq!(behind.rtt_ms(), 20.);
+    }
+
+    #[test]
+    fn asymmetric_paths_skew_the_offset_by_half_the_difference() {
+        let sample = exchange(1000, 50, 30, 1, 10);
+        assert_eq!(sample.offset_ms(), 60.);
+        assert_eq!(sample.rtt_ms(), 40.);
+    }
+
+    #[test]
+    fn takes_the_median_of_an_odd_count() {
+        let samples = [
+            exchange(1000, 50, 10, 1, 10),
+            /* Delayed on the way there. */
+            exchange(2000, 50, 90, 1, 10),
+            exchange(3000, 50, 14, 1, 10),
+        ];
+        let offset = ClockOffset::estimate(&samples).unwrap();
+        assert_eq!(offset.offset_ms, 52.);
+        assert_eq!(offset.rtt_ms, 24.);
+        assert_eq!(offset.samples, 3);
+    }
+
+    #[test]
+    fn averages_the_middle_two_of_an_even_count() {
+        let samples = [
+            exchange(1000, 50, 10, 1, 10),
+            exchange(2000, 50, 90, 1, 10),
+            exchange(3000, 50, 14, 1, 10),
+            exchange(4000, 50, 12, 1, 10),
+        ];
+        let offset = ClockOffset::estimate(&samples).unwrap();
+        assert_eq!(offset.offset_ms, 51.5);
+        assert_eq!(offset.rtt_ms, 23.);
+        assert_eq!(offset.samples, 4);
+        /* Rounded to whole ms. */
+        assert_eq!(offset.to_client_ms(10_052), 10_000);
+    }
+
+    #[test]
+    fn no_estimate_without_samples() {
+        assert_eq!(ClockOffset::estimate(&[]), None);
+    }
+}
@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+
+/// Topic of the control messages exchanged by the client and the publisher.
+pub const CONTROL_TOPIC: &str = "control";
+
+/// Version of the control protocol, sent with every message and in the
+/// publisher config. Bump it when a message changes meaning or is added.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// First `PROTOCOL_VERSION` whose publishers end the run on `Finish`.
+pub const FINISH_PROTOCOL: u32 = 2;
+
+/// Payload of the watermark request of publishers before the control
+/// protocol carried it, `watermark:<tick id>` without a topic.
+pub const LEGACY_WATERMARK: &str = "watermark";
+
+/// Frames the publisher marks for a watermark request.
+pub const WATERMARK_FRAMES: u32 = 15;
+
+/*
+ * Control protocol between the client and the publisher, JSON over the
+ * reliable data channel. The client drives the publisher (start, stop,
+ * parameter changes) and asks for its configuration, the publisher answers
+ * with `Config` and sends a `Heartbeat` every second. `Ping`/`Pong` pairs
+ * estimate the clock offset between the two machines, see `clock`, and
+ * `Watermark` asks for the next frames to be marked for a latency tick.
+ * A client that has collected enough samples ends the run with `Finish`,
+ * so both sides cover the same time without matching durations.
+ *
+ * Every message carries the `version` of the sender. Fields and messages
+ * only get added, fields with a default, so a peer of another version
+ * still understands the messages it knows and decodes the others as
+ * `Unknown`, which is ignored.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/* A few messages a second at most, the size of `Config` doesn't matter. */
+#[allow(clippy::large_enum_variant)]
+pub enum ControlMessage {
+    /// Resume capturing frames.
+    Start,
+    /// Stop capturing frames, the track stays published.
+    Stop,
+    /// End the run and write the results, as when the duration runs out.
+    Finish,
+    /// Republish with a new encoding, answered with the new `Config`.
+    SetParameters {
+        /// Bitrate in kbps.
+        bitrate: Option<u64>,
+        fps: Option<u32>,
+    },
+    GetConfig,
+    /// Broadcast by the publisher once its tracks are published and
+    /// whenever the settings change, and sent in answer to `GetConfig`.
+    Config(PublisherConfig),
+    Heartbeat {
+        /// Unix milliseconds.
+        timestamp: u64,
+        capturing: bool,
+    },
+    /// Clock sync request, answered right away with a `Pong`.
+    Ping {
+        id: u32,
+        /// Unix microseconds, client clock.
+        sent: u64,
+    },
+    Pong {
+        id: u32,
+        sent: u64,
+        /// Unix microseconds, publisher clock.
+        received: u64,
+        replied: u64,
+    },
+    /// Mark the next `frames` frames, `id` tags the publisher sample of the tick.
+    Watermark {
+        id: u64,
+        frames: u32,
+    },
+    /// A message of a newer peer.
+    #[serde(other)]
+    Unknown,
+}
+
+impl ControlMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut message = serde_json::to_value(self).expect("control messages always serialize");
+        if let Some(fields) = message.as_object_mut() {
+            fields.insert("version".to_string(), PROTOCOL_VERSION.into());
+        }
+        serde_json::to_vec(&message).expect("control messages always serialize")
+    }
+
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        Self::decode_versioned(payload).map(|(_, message)| message)
+    }
+
+    /// The message with the protocol version of the sender, 0 for peers
+    /// from before the version was sent.
+    pub fn decode_versioned(payload: &[u8]) -> Option<(u32, Self)> {
+        let message: serde_json::Value = serde_json::from_slice(payload).ok()?;
+        let version = message
+            .get("version")
+            .and_then(|version| version.as_u64())
+            .unwrap_or(0) as u32;
+        Some((version, serde_json::from_value(message).ok()?))
+    }
+}
+
+/// Encoder settings the publisher is currently using.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PublisherConfig {
+    pub run: String,
+    pub codec: String,
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    /// Bitrate in kbps.
+    pub bitrate: u64,
+    pub simulcast: bool,
+    pub sources: Vec<u32>,
+    /// `prescale` when frames are scaled on the CPU before encoding,
+    /// `encoder` when they are submitted at the native resolution.
+    #[serde(default)]
+    pub scaling: String,
+    #[serde(default)]
+    pub rotation: String,
+    /// The codec the track is actually sent with, empty until it is
+    /// known. Differs from `codec` when libwebrtc fell back to another one.
+    #[serde(default)]
+    pub sent_codec: String,
+    /// Run duration in seconds, 0 when the client ends the run.
+    #[serde(default)]
+    pub duration: u64,
+    /// `PROTOCOL_VERSION` of the publisher, 0 when it only understands
+    /// the legacy watermark request.
+    #[serde(default)]
+    pub protocol: u32,
+}
+
+impl PublisherConfig {
+    /// The settings as `key, value` pairs, e.g. for the run metadata.
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("run", self.run.clone()),
+            ("codec", self.codec.clone()),
+            ("width", self.width.to_string()),
+            ("height", self.height.to_string()),
+            ("fps", self.fps.to_string()),
+            ("bitrate", self.bitrate.to_string()),
+            ("simulcast", self.simulcast.to_string()),
+            (
+                "sources",
+                self.sources
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            ("scaling", self.scaling.clone()),
+            ("rotation", self.rotation.clone()),
+            ("sent_codec", self.sent_codec.clone()),
+            ("duration", self.duration.to_string()),
+            ("protocol", self.protocol.to_string()),
+        ]
+    }
+}
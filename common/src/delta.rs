@@ -0,0 +1,107 @@
+/*
+ * WebRTC stats counters (bytes, frames, freezes) are cumulative since the
+ * track started. `Counter` turns consecutive samples into per-interval
+ * deltas and rates, so tables can carry both without post-processing.
+ */
+
+/// Change of a counter since the previous sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Delta {
+    pub delta: f64,
+    /// Per second, NaN for the first sample since the interval is unknown.
+    pub rate: f64,
+}
+
+impl Delta {
+    /// The rate of a byte counter in kbps.
+    pub fn kbps(&self) -> f64 {
+        self.rate * 8. / 1000.
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Counter {
+    /* Value and unix ms timestamp of the previous sample. */
+    last: Option<(f64, u128)>,
+}
+
+impl Counter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `value` sampled at `timestamp` (unix ms).
+    pub fn update(&mut self, value: f64, timestamp: u128) -> Delta {
+        let delta = match self.last {
+            /* The counter went back, e.g. the track was republished: count from 0. */
+            Some((last, _)) if value < last => Delta {
+                delta: value,
+                rate: f64::NAN,
+            },
+            Some((last, last_timestamp)) => {
+                let interval = timestamp.saturating_sub(last_timestamp) as f64 / 1000.;
+                let delta = value - last;
+                Delta {
+                    delta,
+                    rate: if interval > 0. { delta / interval } else { f64::NAN },
+                }
+            }
+            None => Delta {
+                delta: value,
+                rate: f64::NAN,
+            },
+        };
+        self.last = Some((value, timestamp));
+        delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_sample_has_no_rate() {
+        let mut counter = Counter::new();
+        let first = counter.update(5000., 1_000);
+        assert_eq!(first.delta, 5000.);
+        assert!(first.rate.is_nan());
+        assert!(first.kbps().is_nan());
+    }
+
+    #[test]
+    fn rates_are_per_second() {
+        let mut counter = Counter::new();
+        counter.update(1000., 10_000);
+        let next = counter.update(6000., 10_500);
+        assert_eq!(next.delta, 5000.);
+        assert_eq!(next.rate, 10_000.);
+        assert_eq!(next.kbps(), 80.);
+    }
+
+    #[test]
+    fn counts_from_0_after_a_decrease() {
+        let mut counter = Counter::new();
+        counter.update(9000., 1_000);
+        let reset = counter.update(2000., 2_000);
+        assert_eq!(reset.delta, 2000.);
+        assert!(reset.rate.is_nan());
+        /* The next sample follows on from the value after the reset. */
+        let next = counter.update(3000., 3_000);
+        assert_eq!(next.delta, 1000.);
+        assert_eq!(next.rate, 1000.);
+    }
+
+    #[test]
+    fn zero_interval_has_no_rate() {
+        let mut counter = Counter::new();
+        counter.update(1000., 5_000);
+        let same_time = counter.update(1500., 5_000);
+        assert_eq!(same_time.delta, 500.);
+        assert!(same_time.rate.is_nan());
+        /* A timestamp going back counts as no interval too. */
+        let earlier = counter.update(1700., 4_000);
+        assert_eq!(earlier.delta, 200.);
+        assert!(earlier.rate.is_nan());
+    }
+}
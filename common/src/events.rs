@@ -0,0 +1,39 @@
+use crate::sink::Value;
+
+/* Something noteworthy that happened during the run. */
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u128,
+    pub kind: &'static str,
+    pub detail: String,
+}
+
+impl Event {
+    pub fn new(kind: &'static str, detail: String) -> Self {
+        Event {
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_millis(),
+            kind,
+            detail,
+        }
+    }
+}
+
+pub const EVENTS_HEADER: [&str; 3] = ["timestamp", "event", "detail"];
+
+/// The events as rows for a stats sink, see `EVENTS_HEADER`.
+pub fn event_rows(events: &[Event]) -> Vec<Vec<Value>> {
+    events
+        .iter()
+        .map(|event| {
+            vec![
+                event.timestamp.into(),
+                event.kind.into(),
+                event.detail.clone().into(),
+            ]
+        })
+        .collect()
+}
@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+/*
+ * Stretches of a run whose samples are kept but left out of the
+ * statistics, e.g. while the network is changed on purpose: the samples
+ * are marked with the label of the window in the result files, and the
+ * analysis skips marked samples in its aggregates while still writing
+ * them out for inspection.
+ */
+
+/// Label of a window given without one.
+pub const DEFAULT_LABEL: &str = "excluded";
+
+/// Event kind of a window starting, its label as the detail.
+pub const EXCLUSION_STARTED_EVENT: &str = "exclusion_started";
+/// Event kind of a window ending.
+pub const EXCLUSION_ENDED_EVENT: &str = "exclusion_ended";
+
+/// Client metadata entry with the unix ms of the start of the measurement,
+/// to place the windows on the files of the publisher.
+pub const EXCLUSIONS_START_KEY: &str = "exclusions_start_ms";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExclusionWindow {
+    /// Time since the start of the measurement.
+    pub start: Duration,
+    pub end: Duration,
+    pub label: String,
+}
+
+/// Windows parsed from `<start>-<end>[:<label>],...` in seconds since the
+/// start of the measurement, e.g. `30-45:loss,90-100`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ExclusionWindows(pub Vec<ExclusionWindow>);
+
+impl std::str::FromStr for ExclusionWindows {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut windows = s
+            .split(',')
+            .map(|entry| {
+                let invalid = || {
+                    format!(
+                        "Invalid exclusion window: {}. Use <start>-<end>[:<label>] in seconds",
+                        entry
+                    )
+                };
+                let (range, label) = match entry.split_once(':') {
+                    Some((range, label)) if !label.trim().is_empty() => (range, label.trim()),
+                    Some(_) => return Err(invalid()),
+                    None => (entry, DEFAULT_LABEL),
+                };
+                let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+                let seconds = |value: &str| {
+                    let seconds = value.trim().parse::<f64>().ok()?;
+                    Duration::try_from_secs_f64(seconds).ok()
+                };
+                let (Some(start), Some(end)) = (seconds(start), seconds(end)) else {
+                    return Err(invalid());
+                };
+                if start >= end {
+                    return Err(invalid());
+                }
+                Ok(ExclusionWindow {
+                    start,
+                    end,
+                    label: label.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        windows.sort_by_key(|window| window.start);
+        Ok(ExclusionWindows(windows))
+    }
+}
+
+impl std::fmt::Display for ExclusionWindows {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let windows: Vec<String> = self
+            .0
+            .iter()
+            .map(|window| {
+                format!(
+                    "{}-{}:{}",
+                    window.start.as_secs_f64(),
+                    window.end.as_secs_f64(),
+                    window.label
+                )
+            })
+            .collect();
+        write!(f, "{}", windows.join(","))
+    }
+}
+
+impl ExclusionWindows {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The label of the first window `elapsed` falls into, start included
+    /// and end excluded.
+    pub fn label_at(&self, elapsed: Duration) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|window| window.start <= elapsed && elapsed < window.end)
+            .map(|window| window.label.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn windows(s: &str) -> ExclusionWindows {
+        s.parse().unwrap()
+    }
+
+    fn secs(seconds: f64) -> Duration {
+        Duration::from_secs_f64(seconds)
+    }
+
+    #[test]
+    fn parses_labels_and_sorts_the_windows() {
+        let parsed = windows("90-100, 30-45.5:loss");
+        assert_eq!(
+            parsed.0,
+            vec![
+                ExclusionWindow {
+                    start: secs(30.),
+                    end: secs(45.5),
+                    label: "loss".to_string(),
+                },
+                ExclusionWindow {
+                    start: secs(90.),
+                    end: secs(100.),
+                    label: DEFAULT_LABEL.to_string(),
+                },
+            ]
+        );
+        assert_eq!(parsed.to_string(), "30-45.5:loss,90-100:excluded");
+        assert_eq!(windows(&parsed.to_string()), parsed);
+    }
+
+    #[test]
+    fn rejects_malformed_windows() {
+        for invalid in [
+            "", "30", "30-", "-45", "a-45", "30-45:", "30-45: ", "45-30", "30-30", "-5-10",
+            "30-45,",
+        ] {
+            assert!(
+                invalid.parse::<ExclusionWindows>().is_err(),
+                "{:?} was accepted",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn looks_labels_up_with_the_start_in_and_the_end_out() {
+        let parsed = windows("30-45:loss,90-100");
+        assert_eq!(parsed.label_at(secs(29.999)), None);
+        assert_eq!(parsed.label_at(secs(30.)), Some("loss"));
+        assert_eq!(parsed.label_at(secs(44.999)), Some("loss"));
+        assert_eq!(parsed.label_at(secs(45.)), None);
+        assert_eq!(parsed.label_at(secs(90.)), Some(DEFAULT_LABEL));
+        assert_eq!(parsed.label_at(secs(100.)), None);
+    }
+
+    #[test]
+    fn overlapping_windows_go_to_the_earliest_start() {
+        let parsed = windows("20-40:handover,10-30:loss");
+        assert_eq!(parsed.label_at(secs(15.)), Some("loss"));
+        assert_eq!(parsed.label_at(secs(25.)), Some("loss"));
+        assert_eq!(parsed.label_at(secs(35.)), Some("handover"));
+    }
+}
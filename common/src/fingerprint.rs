@@ -0,0 +1,274 @@
+/*
+ * Content fingerprint carried in every frame, to catch frames an encoder
+ * or decoder garbled, which the RTC stats don't show. The publisher hashes
+ * the luma of the pre-encode frame into 64 bits, an average hash of an 8x8
+ * grid that survives compression and scaling, and writes it into a strip
+ * of black and white cells along the bottom of the frame. The client reads
+ * the strip back, hashes the decoded frame the same way and compares the
+ * two: compression moves a few bits at most, a corrupted frame many.
+ */
+
+/* Cells of the hash grid per side, one bit each. */
+const GRID: usize = 8;
+/* Every STEP-th pixel of every STEP-th row goes into the hash. */
+const STEP: usize = 4;
+/* Cells whose mean is this close to the frame mean flip with the slightest compression. */
+const CONFIDENCE_MARGIN: u64 = 4;
+
+/* The marker is a sync byte, the hash and a checksum byte, one cell per bit. */
+const SYNC: u8 = 0b1011_0010;
+const MARKER_BITS: usize = 80;
+const MARKER_HIGH: u8 = 235;
+const MARKER_LOW: u8 = 16;
+const MARKER_THRESHOLD: u64 = 128;
+
+/// Confident bits that may differ before a frame counts as corrupted.
+pub const CORRUPTION_DISTANCE: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hash {
+    pub bits: u64,
+    /// Bits whose cell is clearly brighter or darker than the frame, the
+    /// others are left out of comparisons.
+    pub confident: u64,
+}
+
+impl Hash {
+    /// Differing confident bits against the hash read from the marker, and
+    /// the number of bits compared.
+    pub fn distance(&self, sent: u64) -> (u32, u32) {
+        (
+            ((self.bits ^ sent) & self.confident).count_ones(),
+            self.confident.count_ones(),
+        )
+    }
+}
+
+/// Height of the marker strip, proportional to the frame so it scales
+/// with simulcast layers.
+pub fn marker_rows(height: usize) -> usize {
+    (height / 60).max(4) & !1
+}
+
+/* The marker needs a few pixels per cell to survive the encoder, and room above it. */
+fn has_marker(width: usize, height: usize) -> bool {
+    width >= MARKER_BITS * 2 && height >= GRID * STEP * 2
+}
+
+/// Average hash of the luma plane above the marker strip.
+pub fn hash(y: &[u8], stride: usize, width: usize, height: usize) -> Hash {
+    let rows = height.saturating_sub(marker_rows(height));
+    let mut sums = [0u64; GRID * GRID];
+    let mut counts = [0u64; GRID * GRID];
+    for row in (0..rows).step_by(STEP) {
+        let cell_row = row * GRID / rows;
+        let line = &y[row * stride..row * stride + width];
+        for column in (0..width).step_by(STEP) {
+            let cell = cell_row * GRID + column * GRID / width;
+            sums[cell] += line[column] as u64;
+            counts[cell] += 1;
+        }
+    }
+    let total: u64 = sums.iter().sum();
+    let count: u64 = counts.iter().sum();
+    let mut hash = Hash {
+        bits: 0,
+        confident: 0,
+    };
+    if count == 0 {
+        return hash;
+    }
+    let mean = total / count;
+    for cell in 0..GRID * GRID {
+        if counts[cell] == 0 {
+            continue;
+        }
+        let cell_mean = sums[cell] / counts[cell];
+        if cell_mean > mean {
+            hash.bits |= 1 << cell;
+        }
+        if cell_mean.abs_diff(mean) > CONFIDENCE_MARGIN {
+            hash.confident |= 1 << cell;
+        }
+    }
+    hash
+}
+
+fn checksum(bits: u64) -> u8 {
+    bits.to_le_bytes().iter().fold(0, |sum, byte| sum ^ byte)
+}
+
+fn marker_bits(bits: u64) -> impl Iterator<Item = bool> {
+    let bytes = [&[SYNC][..], &bits.to_le_bytes(), &[checksum(bits)]].concat();
+    (0..MARKER_BITS).map(move |bit| bytes[bit / 8] & (1 << (bit % 8)) != 0)
+}
+
+/// Writes `bits` into the bottom rows of an NV12 frame, with neutral
+/// chroma so the cells compress well. Frames too small for the cells are
+/// left alone.
+pub fn write_marker(
+    y: &mut [u8],
+    stride_y: usize,
+    uv: &mut [u8],
+    stride_uv: usize,
+    width: usize,
+    height: usize,
+    bits: u64,
+) {
+    if !has_marker(width, height) {
+        return;
+    }
+    let top = height - marker_rows(height);
+    for (cell, bit) in marker_bits(bits).enumerate() {
+        let (start, end) = (cell * width / MARKER_BITS, (cell + 1) * width / MARKER_BITS);
+        let luma = if bit { MARKER_HIGH } else { MARKER_LOW };
+        for row in top..height {
+            y[row * stride_y + start..row * stride_y + end].fill(luma);
+        }
+    }
+    for row in top / 2..height / 2 {
+        uv[row * stride_uv..row * stride_uv + width].fill(128);
+    }
+}
+
+/// Reads the hash from the marker, None when there is no readable one.
+pub fn read_marker(y: &[u8], stride: usize, width: usize, height: usize) -> Option<u64> {
+    if !has_marker(width, height) {
+        return None;
+    }
+    /* The middle of every cell, away from the edges the encoder smears. */
+    let row = height - marker_rows(height) / 2;
+    let line = &y[row * stride..row * stride + width];
+    let mut bytes = [0u8; MARKER_BITS / 8];
+    for cell in 0..MARKER_BITS {
+        let (start, end) = (cell * width / MARKER_BITS, (cell + 1) * width / MARKER_BITS);
+        let quarter = (end - start) / 4;
+        let samples = &line[start + quarter..end - quarter];
+        let mean = samples.iter().map(|&v| v as u64).sum::<u64>() / samples.len() as u64;
+        if mean >= MARKER_THRESHOLD {
+            bytes[cell / 8] |= 1 << (cell % 8);
+        }
+    }
+    let bits = u64::from_le_bytes(bytes[1..9].try_into().unwrap());
+    (bytes[0] == SYNC && bytes[9] == checksum(bits)).then_some(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 640;
+    const HEIGHT: usize = 360;
+
+    /* An NV12 frame of diagonal bands, with padded rows like a real buffer. */
+    struct Frame {
+        y: Vec<u8>,
+        uv: Vec<u8>,
+        stride: usize,
+    }
+
+    impl Frame {
+        fn new() -> Self {
+            let stride = WIDTH + 64;
+            let mut y = vec![0u8; stride * HEIGHT];
+            for row in 0..HEIGHT {
+                for column in 0..WIDTH {
+                    y[row * stride + column] = if (row + column) / 90 % 2 == 0 {
+                        40
+                    } else {
+                        200
+                    };
+                }
+            }
+            Frame {
+                y,
+                uv: vec![128; stride * HEIGHT / 2],
+                stride,
+            }
+        }
+
+        fn mark(&mut self) -> Hash {
+            let hash = hash(&self.y, self.stride, WIDTH, HEIGHT);
+            write_marker(
+                &mut self.y,
+                self.stride,
+                &mut self.uv,
+                self.stride,
+                WIDTH,
+                HEIGHT,
+                hash.bits,
+            );
+            hash
+        }
+
+        fn read(&self) -> Option<u64> {
+            read_marker(&self.y, self.stride, WIDTH, HEIGHT)
+        }
+
+        fn hash(&self) -> Hash {
+            hash(&self.y, self.stride, WIDTH, HEIGHT)
+        }
+    }
+
+    #[test]
+    fn marker_round_trips() {
+        let mut frame = Frame::new();
+        let sent = frame.mark();
+        assert_ne!(sent.confident, 0);
+        assert_eq!(frame.read(), Some(sent.bits));
+        /* The marker is below the hashed rows, writing it changes nothing. */
+        assert_eq!(frame.hash(), sent);
+        assert_eq!(frame.hash().distance(sent.bits).0, 0);
+    }
+
+    #[test]
+    fn compression_noise_stays_within_the_distance() {
+        let mut frame = Frame::new();
+        let sent = frame.mark();
+        for (i, luma) in frame.y.iter_mut().enumerate() {
+            *luma = luma.saturating_add((i % 7) as u8).saturating_sub(3);
+        }
+        assert_eq!(frame.read(), Some(sent.bits));
+        let (distance, compared) = frame.hash().distance(sent.bits);
+        assert!(
+            distance <= CORRUPTION_DISTANCE,
+            "{} of {}",
+            distance,
+            compared
+        );
+    }
+
+    #[test]
+    fn garbled_content_is_detected() {
+        let mut frame = Frame::new();
+        let sent = frame.mark();
+        let rows = HEIGHT - marker_rows(HEIGHT);
+        for luma in &mut frame.y[..rows / 2 * frame.stride] {
+            *luma = 255 - *luma;
+        }
+        assert_eq!(frame.read(), Some(sent.bits));
+        let (distance, _) = frame.hash().distance(sent.bits);
+        assert!(distance > CORRUPTION_DISTANCE, "{}", distance);
+    }
+
+    #[test]
+    fn unmarked_or_damaged_markers_are_unreadable() {
+        let mut frame = Frame::new();
+        assert_eq!(frame.read(), None);
+        frame.mark();
+        /* A smeared strip fails the checksum or the sync byte. */
+        let row = HEIGHT - marker_rows(HEIGHT) / 2;
+        frame.y[row * frame.stride..row * frame.stride + WIDTH / 4].fill(MARKER_HIGH);
+        assert_eq!(frame.read(), None);
+    }
+
+    #[test]
+    fn small_frames_are_left_unmarked() {
+        let (width, height) = (MARKER_BITS, 40);
+        let mut y = vec![90u8; width * height];
+        let mut uv = vec![128u8; width * height / 2];
+        write_marker(&mut y, width, &mut uv, width, width, height, u64::MAX);
+        assert!(y.iter().all(|&luma| luma == 90));
+        assert_eq!(read_marker(&y, width, width, height), None);
+    }
+}
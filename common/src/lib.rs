@@ -0,0 +1,29 @@
+/*
+ * Code shared between the screen_sharer and the client. Keep this crate
+ * free of livekit dependencies, the two binaries build against different
+ * SDK versions.
+ */
+pub mod adherence;
+pub mod build_info;
+pub mod clock;
+pub mod control;
+pub mod delta;
+pub mod events;
+pub mod exclusion;
+pub mod fingerprint;
+pub mod font;
+pub mod metadata;
+pub mod output;
+pub mod schemas;
+pub mod sink;
+pub mod system_events;
+pub mod transport;
+pub mod validity;
+pub mod warmup;
+pub mod watermark;
+#[cfg(feature = "logging")]
+pub mod logging;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "upload")]
+pub mod upload;
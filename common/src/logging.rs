@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{EnvFilter, filter::LevelFilter};
+
+/*
+ * Logging of the screen_sharer and the client goes through tracing, the
+ * `log` records of the binaries and of the SDK included, so what a process
+ * was doing when something odd shows up in the data can be followed: every
+ * record carries the spans it happened in (the run, the measurement, a
+ * stats read, a frame conversion). Besides
+ * the text log filtered by RUST_LOG on stderr, `--json-log` writes the info
+ * level and above as JSON lines next to the CSVs of the run, with the spans
+ * of every record.
+ */
+
+/// JSON lines log of a run, records are dropped until `open` is called
+/// with the path in the results directory.
+#[derive(Clone, Default)]
+pub struct RunLog(Arc<Mutex<Option<LineWriter<File>>>>);
+
+impl RunLog {
+    pub fn open(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        *self.0.lock().unwrap() = Some(LineWriter::new(File::create(path)?));
+        Ok(())
+    }
+}
+
+/// Writer handed out by `RunLog` for every record.
+pub struct RunLogWriter(Arc<Mutex<Option<LineWriter<File>>>>);
+
+impl Write for RunLogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0.lock().unwrap().as_mut() {
+            Some(file) => file.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.0.lock().unwrap().as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for RunLog {
+    type Writer = RunLogWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RunLogWriter(self.0.clone())
+    }
+}
+
+/// Sets up the text log, to `file` instead of stderr when given, and the
+/// JSON log of the run when `json` is set, returned to be opened later.
+pub fn init_tracing(file: Option<File>, json: bool) -> Option<RunLog> {
+    let (writer, ansi) = match file {
+        Some(file) => (BoxMakeWriter::new(Arc::new(file)), false),
+        None => (BoxMakeWriter::new(io::stderr), true),
+    };
+    let text = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi)
+        .with_filter(EnvFilter::from_default_env());
+    let run_log = json.then(RunLog::default);
+    let json = run_log.clone().map(|run_log| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(run_log)
+            .with_filter(LevelFilter::INFO)
+    });
+    tracing_subscriber::registry().with(text).with(json).init();
+    run_log
+}
@@ -0,0 +1,46 @@
+use crate::sink::Value;
+
+pub const METADATA_HEADER: [&str; 2] = ["key", "value"];
+
+/*
+ * Key/value description of a run (configuration, environment, detected
+ * settings), written as `key,value` rows through the stats sinks.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct RunMetadata {
+    entries: Vec<(String, String)>,
+}
+
+impl RunMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `key`, replacing an earlier value but keeping its position.
+    pub fn set(&mut self, key: &str, value: impl ToString) {
+        let value = value.to_string();
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((key.to_string(), value)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+
+    /// The entries as rows for a stats sink, see `METADATA_HEADER`.
+    pub fn rows(&self) -> Vec<Vec<Value>> {
+        self.entries
+            .iter()
+            .map(|(key, value)| vec![key.as_str().into(), value.as_str().into()])
+            .collect()
+    }
+}
@@ -0,0 +1,172 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Topic of the data packets used by the client to ask the publisher for
+/// its run id, and by the publisher to answer.
+pub const RUN_ID_TOPIC: &str = "run_id";
+
+/// Which binary produced an output file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Publisher,
+    Client,
+}
+
+impl Side {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Publisher => "publisher",
+            Side::Client => "client",
+        }
+    }
+}
+
+/*
+ * Identifies a single benchmark run. It is created by the publisher and
+ * handed to the client, so both sides agree on where their files go.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunId {
+    /// UTC start time, e.g. 20250131T142501Z.
+    pub timestamp: String,
+    pub codec: String,
+    pub resolution: String,
+    /// Bitrate in kbps.
+    pub bitrate: u64,
+    pub name: String,
+    /// Unique id of the run, used with the tick id as join key between the
+    /// publisher and client samples.
+    pub uuid: String,
+}
+
+impl RunId {
+    pub fn new(codec: &str, resolution: &str, bitrate: u64, name: &str) -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        RunId {
+            timestamp: utc_timestamp(now),
+            codec: sanitize(codec),
+            resolution: sanitize(resolution),
+            bitrate,
+            name: sanitize(name),
+            uuid: uuid::Uuid::new_v4().simple().to_string(),
+        }
+    }
+
+    /// Serializes the run id for sending it over the data channel.
+    pub fn encode(&self) -> String {
+        format!(
+            "{}/{}/{}/{}/{}/{}",
+            self.timestamp, self.codec, self.resolution, self.bitrate, self.name, self.uuid
+        )
+    }
+
+    pub fn decode(s: &str) -> Option<Self> {
+        let parts: Vec<&str> = s.split('/').collect();
+        if parts.len() != 6 {
+            return None;
+        }
+        Some(RunId {
+            timestamp: parts[0].to_string(),
+            codec: parts[1].to_string(),
+            resolution: parts[2].to_string(),
+            bitrate: parts[3].parse().ok()?,
+            name: parts[4].to_string(),
+            uuid: parts[5].to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for RunId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.encode())
+    }
+}
+
+/*
+ * Directory layout of the benchmark results:
+ *
+ *   <root>/<timestamp>/<codec>/<resolution>/<bitrate>/<name>_<side>_<kind>.csv
+ */
+#[derive(Debug, Clone)]
+pub struct OutputLayout {
+    root: PathBuf,
+    run: RunId,
+}
+
+impl OutputLayout {
+    pub fn new(root: impl Into<PathBuf>, run: RunId) -> Self {
+        OutputLayout {
+            root: root.into(),
+            run,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn run(&self) -> &RunId {
+        &self.run
+    }
+
+    pub fn run_dir(&self) -> PathBuf {
+        self.root
+            .join(&self.run.timestamp)
+            .join(&self.run.codec)
+            .join(&self.run.resolution)
+            .join(self.run.bitrate.to_string())
+    }
+
+    /// Creates the run directory if it doesn't exist yet.
+    pub fn create(&self) -> io::Result<PathBuf> {
+        let dir = self.run_dir();
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// Path of an output file, e.g. `file(Side::Publisher, "stats")`.
+    pub fn file(&self, side: Side, kind: &str) -> PathBuf {
+        self.run_dir()
+            .join(format!("{}_{}_{}.csv", self.run.name, side.as_str(), kind))
+    }
+}
+
+/* Path components must not contain separators or whitespace. */
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c == '/' || c == '\\' || c.is_whitespace() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/* Formats seconds since the unix epoch as a compact UTC timestamp. */
+fn utc_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    let (hour, minute, second) = (rem / 3600, (rem % 3600) / 60, rem % 60);
+
+    /* Civil date from days since 1970-01-01 (Howard Hinnant's algorithm). */
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year, month, day, hour, minute, second
+    )
+}
@@ -0,0 +1,79 @@
+/*
+ * Versioned layout of the result files. The rows of the tables the
+ * analysis reads are described by the structs of the submodules, one field
+ * per column, named like the column. Readers look columns up by name, so
+ * new columns are only appended and need no new version; renaming or
+ * removing a column, or changing its unit or meaning, does.
+ *
+ * Versions:
+ * 1. Results written before the version was recorded. Columns were added
+ *    over time, older files lack the fields with a default.
+ * 2. `schema_version` in the metadata of both sides, the same columns.
+ * 3. `pipeline_cpu_usage` of the publisher stats renamed to
+ *    `pipeline_time_percent`, it is a share of the wall time, and likewise
+ *    `capture_cpu_usage`, `convert_cpu_usage` and `scale_cpu_usage` to
+ *    `capture_time_percent`, `convert_time_percent` and
+ *    `scale_time_percent`. `capture_time_percent` no longer includes the
+ *    frame callback, which is the pipeline's time.
+ */
+pub mod frames;
+pub mod latency;
+pub mod ticks;
+
+/// Version of the files written by this build.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// Oldest version the readers still understand.
+pub const OLDEST_SCHEMA_VERSION: u32 = 1;
+
+/// Metadata entry with the version of the files of a run.
+pub const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// The version of a run from its `schema_version` metadata entry, version
+/// 1 without one. Fails for versions this build can't read.
+pub fn version(entry: Option<&str>) -> Result<u32, String> {
+    let version = match entry {
+        None => return Ok(OLDEST_SCHEMA_VERSION),
+        Some(entry) => entry
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| format!("Invalid schema version: {}", entry))?,
+    };
+    if !(OLDEST_SCHEMA_VERSION..=SCHEMA_VERSION).contains(&version) {
+        return Err(format!(
+            "Schema version {} isn't supported, this build reads {} to {}",
+            version, OLDEST_SCHEMA_VERSION, SCHEMA_VERSION
+        ));
+    }
+    Ok(version)
+}
+
+/* Default of the number columns older files lack. */
+fn missing() -> f64 {
+    f64::NAN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_supported_versions() {
+        assert_eq!(version(None), Ok(OLDEST_SCHEMA_VERSION));
+        for supported in OLDEST_SCHEMA_VERSION..=SCHEMA_VERSION {
+            assert_eq!(version(Some(&supported.to_string())), Ok(supported));
+        }
+        assert_eq!(version(Some(" 2 ")), Ok(2));
+    }
+
+    #[test]
+    fn refuses_other_versions() {
+        assert!(version(Some(&(SCHEMA_VERSION + 1).to_string())).is_err());
+        assert!(version(Some("0")).is_err());
+        assert_eq!(
+            version(Some("v2")),
+            Err("Invalid schema version: v2".to_string())
+        );
+        assert!(version(Some("")).is_err());
+    }
+}
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+/// A row of `<name>_client_frames.csv`, written with `--frame-log`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameRow {
+    pub run: String,
+    pub frame: u64,
+    /// Unix ms of the arrival.
+    pub timestamp: u64,
+    /// Time since the frame before it, ms.
+    pub interval_ms: f64,
+}
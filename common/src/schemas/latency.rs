@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+
+/// A row of `<name>_client_latency.csv`, one latency tick. The RTC stats
+/// are those read with the tick: delays in ms averaged since the start of
+/// the stream, counters cumulative and followed by their `_delta` since
+/// the previous row.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LatencyRow {
+    pub id: u64,
+    /// End-to-end latency, ms.
+    pub latency: u64,
+    #[serde(default = "super::missing")]
+    pub processing_delay: f64,
+    #[serde(default = "super::missing")]
+    pub jitter_buffer_delay: f64,
+    #[serde(default = "super::missing")]
+    pub jitter_buffer_target_delay: f64,
+    #[serde(default = "super::missing")]
+    pub jitter_buffer_minimum_delay: f64,
+    #[serde(default = "super::missing")]
+    pub frames_per_second: f64,
+    #[serde(default = "super::missing")]
+    pub freeze_count: f64,
+    pub total_bytes: f64,
+    #[serde(default = "super::missing")]
+    pub dropped_frames: f64,
+    /// Length of the measurement, s.
+    #[serde(default = "super::missing")]
+    pub duration: f64,
+    pub cpu_usage: f64,
+    #[serde(default)]
+    pub codec: String,
+    pub run: String,
+    #[serde(default = "super::missing")]
+    pub total_bytes_delta: f64,
+    #[serde(default = "super::missing")]
+    pub receive_kbps: f64,
+    #[serde(default = "super::missing")]
+    pub frames_delta: f64,
+    #[serde(default = "super::missing")]
+    pub freeze_count_delta: f64,
+    #[serde(default = "super::missing")]
+    pub dropped_frames_delta: f64,
+    #[serde(default = "super::missing")]
+    pub available_incoming_kbps: f64,
+    #[serde(default = "super::missing")]
+    pub current_round_trip_time: f64,
+    #[serde(default = "super::missing")]
+    pub total_round_trip_time: f64,
+    #[serde(default)]
+    pub packets_lost: Option<i64>,
+    #[serde(default)]
+    pub nack_count: Option<u64>,
+    #[serde(default)]
+    pub retransmitted_packets_received: Option<u64>,
+    #[serde(default)]
+    pub fec_packets_received: Option<u64>,
+    #[serde(default)]
+    pub fec_packets_discarded: Option<u64>,
+    #[serde(default = "super::missing")]
+    pub packets_lost_delta: f64,
+    #[serde(default = "super::missing")]
+    pub fec_packets_received_delta: f64,
+    /// Decoded frame size when the tick was read.
+    #[serde(default)]
+    pub frame_width: Option<u32>,
+    #[serde(default)]
+    pub frame_height: Option<u32>,
+    /// Kinds of the system events the tick fell into, `;` separated.
+    #[serde(default)]
+    pub system_events: String,
+    /// Share of a core spent on the measurement itself, percent.
+    #[serde(default = "super::missing")]
+    pub measurement_cpu_usage: f64,
+    /// Mean no-reference quality estimates of the frames since the
+    /// previous row, see the client `quality` module.
+    #[serde(default = "super::missing")]
+    pub blockiness: f64,
+    #[serde(default = "super::missing")]
+    pub sharpness: f64,
+    #[serde(default = "super::missing")]
+    pub banding: f64,
+    /// Label of the exclusion window the tick fell into, empty outside of
+    /// them, see `crate::exclusion`.
+    #[serde(default)]
+    pub excluded: String,
+    /// Unix ms on the client clock, when the marked frame was decoded.
+    #[serde(default)]
+    pub receive_timestamp: Option<u64>,
+    /// `watermark` or `capture-time`, how the latency was measured.
+    #[serde(default)]
+    pub latency_method: String,
+}
@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+/// A row of `<name>_publisher_ticks.csv`, the publisher sample taken when a
+/// latency tick of the client arrives.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TickRow {
+    pub run: String,
+    pub id: u64,
+    /// Unix ms.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    pub cpu_usage: f64,
+    pub bytes_sent: u64,
+    #[serde(default = "super::missing")]
+    pub bytes_sent_delta: f64,
+    #[serde(default = "super::missing")]
+    pub send_kbps: f64,
+    /// ms.
+    #[serde(default = "super::missing")]
+    pub current_round_trip_time: f64,
+    /// Average capture to submit time per frame since the previous tick, ms.
+    #[serde(default = "super::missing")]
+    pub pipeline_ms: f64,
+    /// Average encode time per frame since the previous tick, ms.
+    #[serde(default = "super::missing")]
+    pub encode_ms: f64,
+    /// Kinds of the system events the tick fell into, `;` separated.
+    #[serde(default)]
+    pub system_events: String,
+}
@@ -0,0 +1,426 @@
+/*
+ * Destinations for the stats tables of a run. Every table is identified by
+ * its kind (`stats`, `events`, `latency`, ...) like the output files, and
+ * written to each configured sink.
+ */
+#[cfg(any(feature = "sqlite", feature = "influx"))]
+use crate::output::RunId;
+use crate::output::{OutputLayout, Side};
+use crate::schemas::{SCHEMA_VERSION, SCHEMA_VERSION_KEY};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "influx")]
+mod influx;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl Value {
+    /// Float rounded to `decimals`, to keep the files readable.
+    pub fn float(value: f64, decimals: i32) -> Self {
+        let factor = 10f64.powi(decimals);
+        Value::Float((value * factor).round() / factor)
+    }
+}
+
+/* Counters past `i64::MAX` keep their digits as text rather than wrapping around. */
+fn checked_int<T: TryInto<i64> + ToString + Copy>(value: T) -> Value {
+    value
+        .try_into()
+        .map_or_else(|_| Value::Text(value.to_string()), Value::Int)
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        checked_int(value)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Value::Int(value as i64)
+    }
+}
+
+impl From<u128> for Value {
+    fn from(value: u128) -> Self {
+        checked_int(value)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        checked_int(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Int(value as i64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<f32> for Value {
+    /* Through the shortest f32 representation, so 0.1f32 stays 0.1. */
+    fn from(value: f32) -> Self {
+        Value::Float(value.to_string().parse().unwrap_or(f64::NAN))
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Text(value.to_string())
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+pub trait StatsSink: Send {
+    /// Short name for log messages.
+    fn name(&self) -> &'static str;
+
+    /// Writes one table, `header` names the columns of the rows.
+    fn write(&mut self, kind: &str, header: &[&str], rows: &[Vec<Value>]) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SinkKind {
+    Csv,
+    Sqlite,
+    Influx,
+}
+
+impl std::str::FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(SinkKind::Csv),
+            "sqlite" => Ok(SinkKind::Sqlite),
+            "influx" => Ok(SinkKind::Influx),
+            _ => Err(format!(
+                "Invalid sink: {}. Use 'csv', 'sqlite' or 'influx'",
+                s
+            )),
+        }
+    }
+}
+
+/// Comma separated list of sinks, e.g. `csv,sqlite`.
+pub fn parse_sink_kinds(s: &str) -> Result<Vec<SinkKind>, String> {
+    s.split(',').map(|kind| kind.trim().parse()).collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct SinkConfig {
+    pub kinds: Vec<SinkKind>,
+    /// Database file, `<output-dir>/results.sqlite` if unset.
+    pub sqlite_path: Option<PathBuf>,
+    /// InfluxDB write endpoint, e.g.
+    /// `http://localhost:8086/api/v2/write?org=me&bucket=benchmarks`.
+    pub influx_url: Option<String>,
+    pub influx_token: Option<String>,
+}
+
+impl Default for SinkConfig {
+    fn default() -> Self {
+        SinkConfig {
+            kinds: vec![SinkKind::Csv],
+            sqlite_path: None,
+            influx_url: None,
+            influx_token: None,
+        }
+    }
+}
+
+/// Creates the configured sinks for one side of a run.
+pub fn create_sinks(
+    config: &SinkConfig,
+    layout: &OutputLayout,
+    side: Side,
+) -> io::Result<Vec<Box<dyn StatsSink>>> {
+    let mut sinks: Vec<Box<dyn StatsSink>> = vec![];
+    for kind in &config.kinds {
+        match kind {
+            SinkKind::Csv => sinks.push(Box::new(CsvSink::new(layout.clone(), side))),
+            #[cfg(feature = "sqlite")]
+            SinkKind::Sqlite => {
+                let path = config
+                    .sqlite_path
+                    .clone()
+                    .unwrap_or_else(|| layout.root().join("results.sqlite"));
+                sinks.push(Box::new(sqlite::SqliteSink::open(
+                    &path,
+                    layout.run(),
+                    side,
+                )?));
+            }
+            #[cfg(feature = "influx")]
+            SinkKind::Influx => {
+                let url = config
+                    .influx_url
+                    .clone()
+                    .ok_or_else(|| io::Error::other("the influx sink needs a write url"))?;
+                sinks.push(Box::new(influx::InfluxSink::new(
+                    url,
+                    config.influx_token.clone(),
+                    layout.run(),
+                    side,
+                )));
+            }
+            #[allow(unreachable_patterns)]
+            kind => {
+                return Err(io::Error::other(format!(
+                    "built without support for the {:?} sink",
+                    kind
+                )));
+            }
+        }
+    }
+    Ok(sinks)
+}
+
+/// Writes a table to every sink, returning the sinks that failed.
+pub fn write_all(
+    sinks: &mut [Box<dyn StatsSink>],
+    kind: &str,
+    header: &[&str],
+    rows: &[Vec<Value>],
+) -> Vec<(&'static str, io::Error)> {
+    sinks
+        .iter_mut()
+        .filter_map(|sink| {
+            sink.write(kind, header, rows)
+                .err()
+                .map(|e| (sink.name(), e))
+        })
+        .collect()
+}
+
+/// Writes a table to every sink and logs the sinks that failed, a failed
+/// sink doesn't keep the others from getting the table.
+pub fn write_table(
+    sinks: &mut [Box<dyn StatsSink>],
+    kind: &str,
+    header: &[&str],
+    rows: &[Vec<Value>],
+) {
+    for (sink, e) in write_all(sinks, kind, header, rows) {
+        log::error!("Failed to write {} to the {} sink: {}", kind, sink, e);
+    }
+    log::info!("{} saved", kind);
+}
+
+/// One CSV file per table, by default the files of the results layout.
+pub struct CsvSink {
+    path: Box<dyn Fn(&str) -> PathBuf + Send>,
+}
+
+impl CsvSink {
+    pub fn new(layout: OutputLayout, side: Side) -> Self {
+        CsvSink {
+            path: Box::new(move |kind| layout.file(side, kind)),
+        }
+    }
+
+    /// Writes each table to the file returned by `path` for its kind.
+    pub fn with_paths(path: impl Fn(&str) -> PathBuf + Send + 'static) -> Self {
+        CsvSink {
+            path: Box::new(path),
+        }
+    }
+}
+
+impl StatsSink for CsvSink {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn write(&mut self, kind: &str, header: &[&str], rows: &[Vec<Value>]) -> io::Result<()> {
+        let path = (self.path)(kind);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(path)?;
+        writeln!(file, "{}", csv_header(header))?;
+        for row in rows {
+            writeln!(file, "{}", csv_row(row))?;
+        }
+        Ok(())
+    }
+}
+
+/*
+ * The tables are only complete at the end of a run, so they are written
+ * then and a crash loses them. The per-sample tables are additionally
+ * appended to their CSV file as the samples come in, flushed after every
+ * row: after a crash the file holds the rows up to it, at a clean stop the
+ * complete table is written over it as before.
+ */
+
+/// A CSV table appended to row by row, each row flushed to disk.
+pub struct RollingCsv {
+    file: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl RollingCsv {
+    /// Creates the file at `path` with the header row.
+    pub fn create(path: &Path, header: &[&str]) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "{}", csv_header(header))?;
+        file.flush()?;
+        Ok(RollingCsv {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&mut self, row: &[Value]) -> io::Result<()> {
+        writeln!(self.file, "{}", csv_row(row))?;
+        self.file.flush()
+    }
+}
+
+/// Header line of a CSV file. Every file ends with a `schema_version`
+/// column, so a file passed on without its metadata still tells its layout.
+pub fn csv_header(header: &[&str]) -> String {
+    format!("{},{}", header.join(","), SCHEMA_VERSION_KEY)
+}
+
+/// A row of a CSV file with the header of `csv_header`.
+pub fn csv_row(row: &[Value]) -> String {
+    let fields: Vec<String> = row
+        .iter()
+        .map(csv_field)
+        .chain([SCHEMA_VERSION.to_string()])
+        .collect();
+    fields.join(",")
+}
+
+/* Free text is quoted, numbers and identifiers are written as is. */
+fn csv_field(value: &Value) -> String {
+    match value {
+        Value::Text(text) if text.contains([',', '"', ' ']) => {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        }
+        value => value.to_string(),
+    }
+}
+
+/* Columns identifying the run, added by the database sinks. */
+#[cfg(any(feature = "sqlite", feature = "influx"))]
+fn run_columns(run: &RunId) -> [(&'static str, String); 6] {
+    [
+        ("run", run.uuid.clone()),
+        ("started", run.timestamp.clone()),
+        ("name", run.name.clone()),
+        ("codec", run.codec.clone()),
+        ("resolution", run.resolution.clone()),
+        ("bitrate", run.bitrate.to_string()),
+    ]
+}
+
+/* Table or measurement name, e.g. `publisher_stats`. */
+#[cfg(any(feature = "sqlite", feature = "influx"))]
+fn table_name(side: Side, kind: &str) -> String {
+    format!("{}_{}", side.as_str(), kind)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_counters_past_i64_as_text() {
+        assert_eq!(Value::from(42u128), Value::Int(42));
+        assert_eq!(Value::from(i64::MAX as u64), Value::Int(i64::MAX));
+        assert_eq!(
+            Value::from(u64::MAX),
+            Value::Text("18446744073709551615".to_string())
+        );
+        assert_eq!(Value::from(u128::MAX).to_string(), u128::MAX.to_string());
+    }
+
+    /* Fails every write, to check the others still get the table. */
+    struct FailingSink;
+
+    impl StatsSink for FailingSink {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        fn write(&mut self, _: &str, _: &[&str], _: &[Vec<Value>]) -> io::Result<()> {
+            Err(io::Error::other("unavailable"))
+        }
+    }
+
+    #[test]
+    fn writes_to_the_other_sinks_when_one_fails() {
+        let dir = std::env::temp_dir().join(format!("common_sink_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv_dir = dir.clone();
+        let mut sinks: Vec<Box<dyn StatsSink>> = vec![
+            Box::new(FailingSink),
+            Box::new(CsvSink::with_paths(move |kind| {
+                csv_dir.join(format!("{}.csv", kind))
+            })),
+        ];
+        let rows = vec![vec![Value::from(1u64), Value::from("a b")]];
+        let failed = write_all(&mut sinks, "stats", &["frame", "label"], &rows);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, "failing");
+        write_table(&mut sinks, "events", &["frame", "label"], &rows);
+        let text = std::fs::read_to_string(dir.join("stats.csv")).unwrap();
+        assert_eq!(
+            text,
+            format!("frame,label,schema_version\n1,\"a b\",{}\n", SCHEMA_VERSION)
+        );
+        assert!(dir.join("events.csv").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
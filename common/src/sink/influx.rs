@@ -0,0 +1,107 @@
+use super::{StatsSink, Value, run_columns, table_name};
+use crate::output::{RunId, Side};
+use std::io;
+
+/*
+ * Pushes the tables as InfluxDB line protocol, one measurement per side and
+ * kind, tagged with the run columns. Rows with a `timestamp` column (unix
+ * milliseconds) keep it, the others are spread 1ns apart from the write
+ * time so they don't overwrite each other.
+ */
+pub struct InfluxSink {
+    url: String,
+    token: Option<String>,
+    tags: String,
+    side: Side,
+}
+
+impl InfluxSink {
+    pub fn new(url: String, token: Option<String>, run: &RunId, side: Side) -> Self {
+        let tags = run_columns(run)
+            .iter()
+            .map(|(name, value)| format!(",{}={}", name, escape_tag(value)))
+            .collect();
+        InfluxSink {
+            url,
+            token,
+            tags,
+            side,
+        }
+    }
+
+    fn lines(&self, kind: &str, header: &[&str], rows: &[Vec<Value>]) -> String {
+        let measurement = table_name(self.side, kind);
+        let timestamp_column = header.iter().position(|c| *c == "timestamp");
+        let now_ns = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+
+        let mut lines = String::new();
+        for (i, row) in rows.iter().enumerate() {
+            let fields: Vec<String> = header
+                .iter()
+                .zip(row)
+                .enumerate()
+                .filter(|(column, _)| Some(*column) != timestamp_column)
+                .filter_map(|(_, (name, value))| {
+                    field(value).map(|v| format!("{}={}", escape_tag(name), v))
+                })
+                .collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let timestamp = match timestamp_column.and_then(|c| row.get(c)) {
+                Some(Value::Int(ms)) => *ms as u128 * 1_000_000,
+                _ => now_ns + i as u128,
+            };
+            lines.push_str(&format!(
+                "{}{} {} {}\n",
+                measurement,
+                self.tags,
+                fields.join(","),
+                timestamp
+            ));
+        }
+        lines
+    }
+}
+
+/* Line protocol field value, None for values it can't represent. */
+fn field(value: &Value) -> Option<String> {
+    match value {
+        Value::Int(value) => Some(format!("{}i", value)),
+        Value::Float(value) if value.is_finite() => Some(value.to_string()),
+        Value::Float(_) => None,
+        Value::Text(value) => Some(format!(
+            "\"{}\"",
+            value.replace('\\', "\\\\").replace('"', "\\\"")
+        )),
+    }
+}
+
+fn escape_tag(value: &str) -> String {
+    value
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+impl StatsSink for InfluxSink {
+    fn name(&self) -> &'static str {
+        "influx"
+    }
+
+    fn write(&mut self, kind: &str, header: &[&str], rows: &[Vec<Value>]) -> io::Result<()> {
+        let body = self.lines(kind, header, rows);
+        if body.is_empty() {
+            return Ok(());
+        }
+        let mut request = ureq::post(&self.url);
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", &format!("Token {}", token));
+        }
+        request.send(body).map_err(io::Error::other)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use super::{StatsSink, Value, run_columns, table_name};
+use crate::output::{RunId, Side};
+use rusqlite::{Connection, params_from_iter, types::ToSqlOutput};
+use std::io;
+use std::path::Path;
+
+/*
+ * Appends the tables of every run to one database, one SQL table per side
+ * and kind with the run columns in front, so runs can be compared with a
+ * single query.
+ */
+pub struct SqliteSink {
+    connection: Connection,
+    run: RunId,
+    side: Side,
+}
+
+impl SqliteSink {
+    pub fn open(path: &Path, run: &RunId, side: Side) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let connection = Connection::open(path).map_err(io::Error::other)?;
+        /* The publisher and the client may write to the same file. */
+        connection
+            .busy_timeout(std::time::Duration::from_secs(10))
+            .map_err(io::Error::other)?;
+        Ok(SqliteSink {
+            connection,
+            run: run.clone(),
+            side,
+        })
+    }
+
+    /* Creates the table, adding columns that newer versions introduced. */
+    fn ensure_table(&self, table: &str, columns: &[&str]) -> rusqlite::Result<()> {
+        let definition: Vec<String> = columns.iter().map(|c| format!("\"{}\"", c)).collect();
+        self.connection.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS \"{}\" ({})",
+                table,
+                definition.join(", ")
+            ),
+            [],
+        )?;
+
+        let mut statement = self
+            .connection
+            .prepare(&format!("PRAGMA table_info(\"{}\")", table))?;
+        let existing: Vec<String> = statement
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<rusqlite::Result<_>>()?;
+        for column in columns {
+            if !existing.iter().any(|e| e == column) {
+                self.connection.execute(
+                    &format!("ALTER TABLE \"{}\" ADD COLUMN \"{}\"", table, column),
+                    [],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, kind: &str, header: &[&str], rows: &[Vec<Value>]) -> rusqlite::Result<()> {
+        let table = table_name(self.side, kind);
+        let run_columns = run_columns(&self.run);
+        let columns: Vec<&str> = run_columns
+            .iter()
+            .map(|(name, _)| *name)
+            .chain(header.iter().copied())
+            .collect();
+        self.ensure_table(&table, &columns)?;
+
+        let names: Vec<String> = columns.iter().map(|c| format!("\"{}\"", c)).collect();
+        let placeholders = vec!["?"; columns.len()].join(", ");
+        let sql = format!(
+            "INSERT INTO \"{}\" ({}) VALUES ({})",
+            table,
+            names.join(", "),
+            placeholders
+        );
+
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(&sql)?;
+            for row in rows {
+                let values = run_columns
+                    .iter()
+                    .map(|(_, value)| Value::Text(value.clone()))
+                    .chain(row.iter().cloned());
+                statement.execute(params_from_iter(values))?;
+            }
+        }
+        transaction.commit()
+    }
+}
+
+impl rusqlite::ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match self {
+            Value::Int(value) => value.to_sql(),
+            Value::Float(value) => value.to_sql(),
+            Value::Text(value) => value.to_sql(),
+        }
+    }
+}
+
+impl StatsSink for SqliteSink {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn write(&mut self, kind: &str, header: &[&str], rows: &[Vec<Value>]) -> io::Result<()> {
+        self.insert(kind, header, rows).map_err(io::Error::other)
+    }
+}
@@ -0,0 +1,232 @@
+use crate::events::Event;
+
+/*
+ * Things happening on the machine that have nothing to do with the run but
+ * show up in its latency: another process taking the CPU, a network
+ * interface coming or going (VPN, Wi-Fi roaming, a dock), the machine
+ * overheating. Both sides read a `SystemSnapshot` with every stats sample
+ * and feed it to a `SystemMonitor`, which records when such an event
+ * started and ended as events of the run and annotates the latency samples
+ * that fall into one, so the analysis can tell them apart.
+ */
+
+/* Other processes together using more than this share of the machine, %. */
+const CPU_SPIKE_PERCENT: f64 = 50.;
+/* Hottest sensor above this, celsius. */
+const OVERHEATING_CELSIUS: f64 = 90.;
+
+/// Separates the kinds of the events a sample falls into.
+pub const ANNOTATION_SEPARATOR: char = ';';
+
+/// What the monitor reads from the system with every sample.
+#[derive(Debug, Clone)]
+pub struct SystemSnapshot {
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u128,
+    /// CPU usage of all other processes since the previous snapshot, % of
+    /// the whole machine.
+    pub other_cpu_usage: f64,
+    /// The busiest other process and its CPU usage, % of one core.
+    pub top_process: Option<(String, f64)>,
+    /// Network interfaces with an address, e.g. `en0 192.168.1.20/24`.
+    pub interfaces: Vec<String>,
+    /// Hottest sensor, celsius, NaN when there is none.
+    pub temperature: f64,
+}
+
+/// Reads a snapshot right after the processes of `system` were refreshed
+/// for the CPU usage of a sample. `pid` is the own process, left out of
+/// the other processes, and `temperature` the hottest sensor as the caller
+/// reads it, see `hottest_component`.
+#[cfg(feature = "system")]
+pub fn snapshot(
+    system: &mut sysinfo::System,
+    networks: &mut sysinfo::Networks,
+    pid: usize,
+    temperature: f64,
+) -> SystemSnapshot {
+    system.refresh_cpu_usage();
+    let pid = sysinfo::Pid::from(pid);
+    let own_usage = system
+        .process(pid)
+        .map_or(0., |process| process.cpu_usage() as f64);
+    let cores = system.cpus().len().max(1) as f64;
+    let top_process = system
+        .processes()
+        .values()
+        .filter(|process| process.pid() != pid)
+        .max_by(|a, b| a.cpu_usage().total_cmp(&b.cpu_usage()))
+        .map(|process| {
+            (
+                process.name().to_string_lossy().into_owned(),
+                process.cpu_usage() as f64,
+            )
+        });
+    networks.refresh(true);
+    let mut interfaces: Vec<String> = networks
+        .iter()
+        .flat_map(|(name, network)| {
+            network
+                .ip_networks()
+                .iter()
+                .map(move |ip| format!("{} {}/{}", name, ip.addr, ip.prefix))
+        })
+        .collect();
+    interfaces.sort();
+    SystemSnapshot {
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis(),
+        other_cpu_usage: (system.global_cpu_usage() as f64 - own_usage / cores).max(0.),
+        top_process,
+        interfaces,
+        temperature,
+    }
+}
+
+/// Hottest sensor of `components`, celsius, NaN when none reports one.
+#[cfg(feature = "system")]
+pub fn hottest_component(components: &mut sysinfo::Components) -> f64 {
+    components.refresh(false);
+    components
+        .list()
+        .iter()
+        .filter_map(|component| component.temperature())
+        .filter(|temperature| !temperature.is_nan())
+        .reduce(f32::max)
+        .map_or(f64::NAN, |temperature| temperature as f64)
+}
+
+/* When an event lasted, `to` is None while it goes on. */
+struct Span {
+    kind: &'static str,
+    from: u128,
+    to: Option<u128>,
+}
+
+#[derive(Default)]
+pub struct SystemMonitor {
+    last: Option<SystemSnapshot>,
+    spans: Vec<Span>,
+}
+
+impl SystemMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a snapshot, returns the events that started or ended with it.
+    pub fn update(&mut self, snapshot: SystemSnapshot) -> Vec<Event> {
+        /* The CPU usage covers the time since the previous snapshot, so does a change. */
+        let since = self
+            .last
+            .as_ref()
+            .map_or(snapshot.timestamp, |last| last.timestamp);
+        let mut events = vec![];
+        let top_process = match &snapshot.top_process {
+            Some((name, usage)) => format!(", busiest {} at {:.0}%", name, usage),
+            None => String::new(),
+        };
+        if let Some(event) = self.track(
+            ("cpu_spike", "cpu_spike_ended"),
+            snapshot.other_cpu_usage > CPU_SPIKE_PERCENT,
+            since,
+            format!(
+                "other processes at {:.0}% of the CPU{}",
+                snapshot.other_cpu_usage, top_process
+            ),
+        ) {
+            events.push(event);
+        }
+        if let Some(event) = self.track(
+            ("overheating", "overheating_ended"),
+            snapshot.temperature > OVERHEATING_CELSIUS,
+            since,
+            format!("{:.1} C", snapshot.temperature),
+        ) {
+            events.push(event);
+        }
+        if let Some(last) = &self.last {
+            let added: Vec<&str> = snapshot
+                .interfaces
+                .iter()
+                .filter(|interface| !last.interfaces.contains(interface))
+                .map(String::as_str)
+                .collect();
+            let removed: Vec<&str> = last
+                .interfaces
+                .iter()
+                .filter(|interface| !snapshot.interfaces.contains(interface))
+                .map(String::as_str)
+                .collect();
+            if !added.is_empty() || !removed.is_empty() {
+                self.spans.push(Span {
+                    kind: "network_changed",
+                    from: since,
+                    to: Some(snapshot.timestamp),
+                });
+                let mut event = Event::new(
+                    "network_changed",
+                    format!(
+                        "added [{}] removed [{}]",
+                        added.join(", "),
+                        removed.join(", ")
+                    ),
+                );
+                event.timestamp = since;
+                events.push(event);
+            }
+        }
+        self.last = Some(snapshot);
+        events
+    }
+
+    /*
+     * Opens a span of `kind` when `active` and none is open, closes the open
+     * one when no longer, with the started or ended event to record.
+     */
+    fn track(
+        &mut self,
+        (kind, ended): (&'static str, &'static str),
+        active: bool,
+        since: u128,
+        detail: String,
+    ) -> Option<Event> {
+        let open = self
+            .spans
+            .iter_mut()
+            .find(|span| span.kind == kind && span.to.is_none());
+        let name = match (open, active) {
+            (None, true) => {
+                self.spans.push(Span {
+                    kind,
+                    from: since,
+                    to: None,
+                });
+                kind
+            }
+            (Some(span), false) => {
+                span.to = Some(since);
+                ended
+            }
+            _ => return None,
+        };
+        let mut event = Event::new(name, detail);
+        event.timestamp = since;
+        Some(event)
+    }
+
+    /// Kinds of the events going on at `timestamp`, unix ms, joined by
+    /// `ANNOTATION_SEPARATOR`, empty when there were none.
+    pub fn annotate(&self, timestamp: u128) -> String {
+        let mut kinds: Vec<&str> = vec![];
+        for span in &self.spans {
+            let within = span.from <= timestamp && span.to.is_none_or(|to| timestamp <= to);
+            if within && !kinds.contains(&span.kind) {
+                kinds.push(span.kind);
+            }
+        }
+        kinds.join(&ANNOTATION_SEPARATOR.to_string())
+    }
+}
@@ -0,0 +1,178 @@
+use crate::metadata::RunMetadata;
+
+/*
+ * The network path of a run. Encoders compared over a direct UDP path
+ * behave very differently over a TURN relay or TCP, where losses turn into
+ * retransmission delays, so the transport is chosen with `--ice-transport`
+ * and `--turn-url` and the candidate pair ICE settled on is recorded on
+ * both sides.
+ */
+
+/// Environment variables with the credentials of the `--turn-url` servers.
+pub const TURN_USERNAME_ENV: &str = "TURN_USERNAME";
+pub const TURN_PASSWORD_ENV: &str = "TURN_PASSWORD";
+
+/// The ICE candidates the peer connections may use.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IceTransport {
+    /// Every candidate, ICE picks the best path.
+    #[default]
+    All,
+    /// Only TURN relay candidates.
+    Relay,
+    /// No host candidates, server reflexive and relay only.
+    NoHost,
+}
+
+impl IceTransport {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IceTransport::All => "all",
+            IceTransport::Relay => "relay",
+            IceTransport::NoHost => "nohost",
+        }
+    }
+}
+
+impl std::str::FromStr for IceTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(IceTransport::All),
+            "relay" => Ok(IceTransport::Relay),
+            "nohost" => Ok(IceTransport::NoHost),
+            _ => Err(format!(
+                "Invalid ICE transport: {}. Use 'all', 'relay' or 'nohost'",
+                s
+            )),
+        }
+    }
+}
+
+/// Room level features of the SDK, off by default, that change what the
+/// publisher is asked to encode.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RoomFeatures {
+    /// Subscribers request the layers their view needs and pause hidden
+    /// tracks.
+    pub adaptive_stream: bool,
+    /// The publisher pauses the layers no subscriber receives.
+    pub dynacast: bool,
+}
+
+impl RoomFeatures {
+    pub fn record(&self, metadata: &mut RunMetadata) {
+        metadata.set("adaptive_stream", self.adaptive_stream);
+        metadata.set("dynacast", self.dynacast);
+    }
+}
+
+/// One end of the selected candidate pair, as named in the RTC stats.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Candidate {
+    /// `host`, `srflx`, `prflx` or `relay`.
+    pub kind: String,
+    /// `udp` or `tcp`.
+    pub protocol: String,
+    /// How a relay candidate reaches its TURN server, `udp`, `tcp` or `tls`.
+    pub relay_protocol: Option<String>,
+}
+
+impl Candidate {
+    /// A candidate from the values of its RTC stats. The SDK's stats enums
+    /// are named in lower case, as the W3C stats name them, e.g. `Srflx`
+    /// becomes `srflx`; a candidate without a type is `unknown`.
+    pub fn new(
+        kind: Option<impl std::fmt::Debug>,
+        protocol: &str,
+        relay_protocol: Option<impl std::fmt::Debug>,
+    ) -> Self {
+        let name = |value: &dyn std::fmt::Debug| format!("{:?}", value).to_lowercase();
+        Candidate {
+            kind: kind.map_or("unknown".to_string(), |kind| name(&kind)),
+            protocol: protocol.to_string(),
+            relay_protocol: relay_protocol.map(|protocol| name(&protocol)),
+        }
+    }
+}
+
+impl std::fmt::Display for Candidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.kind, self.protocol)?;
+        if let Some(relay_protocol) = &self.relay_protocol {
+            write!(f, " (turn over {})", relay_protocol)?;
+        }
+        Ok(())
+    }
+}
+
+/// The candidate pair the media of a run went over.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CandidatePair {
+    pub local: Candidate,
+    pub remote: Candidate,
+    /// How often ICE switched to another pair during the run.
+    pub changes: u32,
+}
+
+impl CandidatePair {
+    pub fn record(&self, metadata: &mut RunMetadata) {
+        metadata.set("candidate_type", &self.local.kind);
+        metadata.set("candidate_protocol", &self.local.protocol);
+        metadata.set(
+            "relay_protocol",
+            self.local.relay_protocol.as_deref().unwrap_or_default(),
+        );
+        metadata.set("remote_candidate_type", &self.remote.kind);
+        metadata.set("remote_candidate_protocol", &self.remote.protocol);
+        metadata.set("candidate_pair_changes", self.changes);
+    }
+}
+
+impl std::fmt::Display for CandidatePair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}", self.local, self.remote)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Named like the SDK's stats enums. */
+    #[derive(Debug)]
+    enum CandidateType {
+        Srflx,
+        Relay,
+    }
+
+    #[derive(Debug)]
+    enum RelayProtocol {
+        Tls,
+    }
+
+    #[test]
+    fn names_candidates_as_the_w3c_stats_do() {
+        let direct = Candidate::new(Some(CandidateType::Srflx), "udp", None::<RelayProtocol>);
+        assert_eq!(direct.to_string(), "srflx/udp");
+        let relayed = Candidate::new(Some(CandidateType::Relay), "tcp", Some(RelayProtocol::Tls));
+        assert_eq!(relayed.relay_protocol.as_deref(), Some("tls"));
+        let pair = CandidatePair {
+            local: relayed,
+            remote: direct,
+            changes: 1,
+        };
+        assert_eq!(pair.to_string(), "relay/tcp (turn over tls) -> srflx/udp");
+        let untyped = Candidate::new(None::<CandidateType>, "udp", None::<RelayProtocol>);
+        assert_eq!(untyped.kind, "unknown");
+    }
+
+    #[test]
+    fn parses_ice_transports() {
+        for transport in [IceTransport::All, IceTransport::Relay, IceTransport::NoHost] {
+            assert_eq!(transport.as_str().parse::<IceTransport>(), Ok(transport));
+        }
+        assert!("host".parse::<IceTransport>().is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use ratatui::Terminal;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::cursor::{Hide, Show};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::widgets::{Block, Gauge, Paragraph, Sparkline};
+use std::collections::VecDeque;
+use std::io::{self, Stdout};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/*
+ * Live view of a run in the terminal, for watching long matrix runs without
+ * tailing logs: the elapsed time against the run duration and, for every
+ * metric the binary records (frame rate, bitrate, CPU, latency), the last
+ * value, its range and a sparkline of the recent samples. It draws on the
+ * alternate screen from its own thread, so logs have to go to a file while
+ * it is shown. The terminal isn't put in raw mode, Ctrl+C keeps working.
+ */
+#[derive(Clone)]
+pub struct Dashboard {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    renderer: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct State {
+    title: String,
+    started: Instant,
+    duration: Option<Duration>,
+    status: String,
+    /* In the order they were first recorded. */
+    metrics: Vec<Metric>,
+    stopped: bool,
+}
+
+struct Metric {
+    name: &'static str,
+    unit: &'static str,
+    history: VecDeque<f64>,
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: u64,
+}
+
+/* Samples kept for the sparklines, wider terminals show the last of them. */
+const HISTORY: usize = 300;
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+impl Dashboard {
+    /// Switches the terminal to the dashboard until `stop` is called.
+    pub fn start(title: &str, duration: Option<Duration>) -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen, Hide)?;
+        let terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+        let dashboard = Dashboard {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    title: title.to_string(),
+                    started: Instant::now(),
+                    duration,
+                    status: String::new(),
+                    metrics: vec![],
+                    stopped: false,
+                }),
+                renderer: Mutex::new(None),
+            }),
+        };
+        let inner = dashboard.inner.clone();
+        let renderer = std::thread::spawn(move || render_loop(terminal, inner));
+        *dashboard.inner.renderer.lock().unwrap() = Some(renderer);
+        Ok(dashboard)
+    }
+
+    /// Adds a sample of a metric, NaN values are skipped.
+    pub fn record(&self, name: &'static str, unit: &'static str, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        let mut state = self.inner.state.lock().unwrap();
+        let index = match state.metrics.iter().position(|metric| metric.name == name) {
+            Some(index) => index,
+            None => {
+                state.metrics.push(Metric {
+                    name,
+                    unit,
+                    history: VecDeque::with_capacity(HISTORY),
+                    min: f64::MAX,
+                    max: f64::MIN,
+                    sum: 0.,
+                    count: 0,
+                });
+                state.metrics.len() - 1
+            }
+        };
+        let metric = &mut state.metrics[index];
+        if metric.history.len() == HISTORY {
+            metric.history.pop_front();
+        }
+        metric.history.push_back(value);
+        metric.min = metric.min.min(value);
+        metric.max = metric.max.max(value);
+        metric.sum += value;
+        metric.count += 1;
+    }
+
+    /// One line shown under the progress, e.g. the last event.
+    pub fn status(&self, status: impl Into<String>) {
+        self.inner.state.lock().unwrap().status = status.into();
+    }
+
+    /// Restores the terminal, the last frame drawn is lost with the alternate screen.
+    pub fn stop(&self) {
+        self.inner.state.lock().unwrap().stopped = true;
+        if let Some(renderer) = self.inner.renderer.lock().unwrap().take() {
+            let _ = renderer.join();
+        }
+    }
+}
+
+fn render_loop(mut terminal: Terminal<CrosstermBackend<Stdout>>, inner: Arc<Inner>) {
+    loop {
+        {
+            let state = inner.state.lock().unwrap();
+            if state.stopped {
+                break;
+            }
+            /* A failed draw, e.g. while the terminal is resized, is redone with the next one. */
+            let _ = terminal.draw(|frame| draw(frame, &state));
+        }
+        std::thread::sleep(REFRESH_INTERVAL);
+    }
+    let _ = execute!(terminal.backend_mut(), LeaveAlternateScreen, Show);
+}
+
+fn draw(frame: &mut ratatui::Frame, state: &State) {
+    let mut constraints = vec![Constraint::Length(3), Constraint::Length(1)];
+    constraints.extend(state.metrics.iter().map(|_| Constraint::Length(3)));
+    constraints.push(Constraint::Min(0));
+    let areas = Layout::vertical(constraints).split(frame.area());
+
+    let elapsed = state.started.elapsed();
+    let (ratio, label) = match state.duration {
+        Some(duration) if !duration.is_zero() => (
+            (elapsed.as_secs_f64() / duration.as_secs_f64()).min(1.),
+            format!("{} / {}", clock(elapsed), clock(duration)),
+        ),
+        _ => (0., clock(elapsed)),
+    };
+    let gauge = Gauge::default()
+        .block(Block::bordered().title(state.title.as_str()))
+        .gauge_style(Style::default().fg(Color::Green))
+        .ratio(ratio)
+        .label(label);
+    frame.render_widget(gauge, areas[0]);
+    frame.render_widget(Paragraph::new(state.status.as_str()), areas[1]);
+
+    for (metric, area) in state.metrics.iter().zip(&areas[2..]) {
+        let [values, history] =
+            Layout::horizontal([Constraint::Length(44), Constraint::Min(0)]).areas(*area);
+        let last = metric.history.back().copied().unwrap_or(f64::NAN);
+        let text = format!(
+            "{:.1} {}\nmin {:.1}  mean {:.1}  max {:.1}",
+            last,
+            metric.unit,
+            metric.min,
+            metric.sum / metric.count as f64,
+            metric.max
+        );
+        frame.render_widget(
+            Paragraph::new(text).block(Block::bordered().title(metric.name)),
+            values,
+        );
+        /* Sparklines take integers, scaled so small values still show. */
+        let width = history.width.saturating_sub(2) as usize;
+        let data: Vec<u64> = metric
+            .history
+            .iter()
+            .skip(metric.history.len().saturating_sub(width))
+            .map(|value| (value.max(0.) * 10.) as u64)
+            .collect();
+        let sparkline = Sparkline::default()
+            .block(Block::bordered())
+            .style(Style::default().fg(Color::Cyan))
+            .data(&data);
+        frame.render_widget(sparkline, history);
+    }
+}
+
+fn clock(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,138 @@
+use crate::output::{OutputLayout, Side};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/*
+ * Pushes the files of a run to remote storage once it is written, so runs
+ * on lab machines don't have to be collected by hand. S3 and GCS go through
+ * the `aws` and `gcloud` CLIs, which bring their own credentials; a plain
+ * HTTP endpoint receives one PUT per file. The files keep their path below
+ * the results root, e.g. `<destination>/<timestamp>/<codec>/...`.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum UploadTarget {
+    /// `s3://bucket/prefix`
+    S3(String),
+    /// `gs://bucket/prefix`
+    Gcs(String),
+    /// `http(s)://host/path`, the token is sent as a bearer token.
+    Http { url: String, token: Option<String> },
+}
+
+impl std::str::FromStr for UploadTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let destination = s.trim_end_matches('/').to_string();
+        if s.starts_with("s3://") {
+            Ok(UploadTarget::S3(destination))
+        } else if s.starts_with("gs://") {
+            Ok(UploadTarget::Gcs(destination))
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(UploadTarget::Http {
+                url: destination,
+                token: None,
+            })
+        } else {
+            Err(format!(
+                "Invalid upload destination: {}. Use s3://, gs://, http:// or https://",
+                s
+            ))
+        }
+    }
+}
+
+impl UploadTarget {
+    /// Sets the bearer token of an HTTP destination, e.g. from `UPLOAD_TOKEN`.
+    pub fn with_token(self, token: Option<String>) -> Self {
+        match self {
+            UploadTarget::Http { url, .. } => UploadTarget::Http { url, token },
+            target => target,
+        }
+    }
+
+    fn upload(&self, file: &Path, key: &str) -> io::Result<()> {
+        match self {
+            UploadTarget::S3(destination) => cli(
+                "aws",
+                &["s3", "cp", "--only-show-errors"],
+                file,
+                &format!("{}/{}", destination, key),
+            ),
+            UploadTarget::Gcs(destination) => cli(
+                "gcloud",
+                &["storage", "cp", "--quiet"],
+                file,
+                &format!("{}/{}", destination, key),
+            ),
+            UploadTarget::Http { url, token } => {
+                let mut request = ureq::put(&format!("{}/{}", url, key));
+                if let Some(token) = token {
+                    request = request.header("Authorization", &format!("Bearer {}", token));
+                }
+                request.send(fs::read(file)?).map_err(io::Error::other)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn cli(program: &str, args: &[&str], file: &Path, destination: &str) -> io::Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .arg(file)
+        .arg(destination)
+        .output()
+        .map_err(|e| io::Error::other(format!("failed to run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Uploads the files one side wrote to the run directory, returning how
+/// many were uploaded. Every file is attempted, the first error is returned.
+pub fn upload_run(layout: &OutputLayout, side: Side, target: &UploadTarget) -> io::Result<usize> {
+    let run_dir = layout.run_dir();
+    let marker = format!("_{}_", side.as_str());
+    let mut files: Vec<_> = fs::read_dir(&run_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().contains(&marker))
+        })
+        .collect();
+    files.sort();
+
+    let mut uploaded = 0;
+    let mut first_error = None;
+    for file in &files {
+        /* Object keys use forward slashes whatever the platform. */
+        let key = file
+            .strip_prefix(layout.root())
+            .unwrap_or(file)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        match target.upload(file, &key) {
+            Ok(()) => uploaded += 1,
+            Err(e) => {
+                let e = io::Error::other(format!("{}: {}", file.display(), e));
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(uploaded),
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/*
+ * Whether a finished run can be trusted. A run can end successfully and
+ * still have measured something else than the encoder: the room
+ * reconnected and the stream restarted, the capture kept failing, or the
+ * CPU was throttled. Such runs are told from the events both sides wrote
+ * and marked with a `<name>_invalid.txt` next to their files, listing the
+ * reasons, which the analysis leaves out of the aggregates.
+ */
+
+/// Event kind of a room starting to reconnect, on either side.
+pub const RECONNECTING_EVENT: &str = "room_reconnecting";
+/// Event kind of a room back after reconnecting.
+pub const RECONNECTED_EVENT: &str = "room_reconnected";
+
+/* Event kinds that invalidate a run whenever they occur. */
+const DEGRADING_EVENTS: [(&str, &str); 2] = [
+    ("capture_errors", "capture errors"),
+    ("throttling_started", "CPU throttling"),
+];
+
+/// What a run may go through and still count.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    /// Room reconnects of either side.
+    pub reconnects: u32,
+}
+
+/// Why the run `name` in `run_dir` is invalid, from the events files of
+/// both sides, every screen of the publisher included. Empty when it is
+/// valid or has no events files.
+pub fn degradations(run_dir: &Path, name: &str, tolerance: Tolerance) -> io::Result<Vec<String>> {
+    let mut reasons = vec![];
+    /*
+     * The publisher writes its reconnects into the events file of every
+     * screen, so a side counts the reconnects of its fullest file.
+     */
+    let (mut publisher_reconnects, mut client_reconnects) = (0, 0);
+    for entry in fs::read_dir(run_dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let Some(side) = file_name
+            .strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('_'))
+            .and_then(|rest| rest.strip_suffix("_events.csv"))
+            .filter(|side| side.starts_with("publisher") || side.starts_with("client"))
+        else {
+            continue;
+        };
+        let side = side.to_string();
+        let mut reconnects = 0;
+        /* The kind is the second column, timestamps and kinds have no commas. */
+        for line in fs::read_to_string(&path)?.lines().skip(1) {
+            let Some(kind) = line.split(',').nth(1) else {
+                continue;
+            };
+            if kind == RECONNECTING_EVENT {
+                reconnects += 1;
+            }
+            if let Some((_, reason)) = DEGRADING_EVENTS.iter().find(|(event, _)| *event == kind) {
+                let reason = format!("{} on the {} side", reason, side);
+                if !reasons.contains(&reason) {
+                    reasons.push(reason);
+                }
+            }
+        }
+        let side_reconnects = if side.starts_with("publisher") {
+            &mut publisher_reconnects
+        } else {
+            &mut client_reconnects
+        };
+        *side_reconnects = reconnects.max(*side_reconnects);
+    }
+    let reconnects = publisher_reconnects + client_reconnects;
+    if reconnects > tolerance.reconnects {
+        reasons.insert(
+            0,
+            format!(
+                "{} room reconnects, {} tolerated",
+                reconnects, tolerance.reconnects
+            ),
+        );
+    }
+    Ok(reasons)
+}
+
+fn marker(run_dir: &Path, name: &str) -> std::path::PathBuf {
+    run_dir.join(format!("{}_invalid.txt", name))
+}
+
+/// Marks the run `name` in `run_dir` invalid, one reason per line.
+pub fn mark_invalid(run_dir: &Path, name: &str, reasons: &[String]) -> io::Result<()> {
+    fs::write(marker(run_dir, name), reasons.join("\n") + "\n")
+}
+
+/// The reasons the run `name` in `run_dir` was marked invalid for, None
+/// for a valid run.
+pub fn invalid_reasons(run_dir: &Path, name: &str) -> Option<Vec<String>> {
+    let reasons = fs::read_to_string(marker(run_dir, name)).ok()?;
+    Some(reasons.lines().map(str::to_string).collect())
+}
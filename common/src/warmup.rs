@@ -0,0 +1,49 @@
+/*
+ * Encoders ramp up over the first seconds of a run: the bandwidth estimate
+ * grows towards the target bitrate and the frame rate settles. Those
+ * samples dominate short-run averages, so the steady part of a run starts
+ * at the first sample where the received bitrate reached the target and the
+ * frame rate stayed stable for a few samples. Static content never needs
+ * the target, there the steady part starts once the received bitrate stayed
+ * level for a while instead. The analysis leaves those samples out of the
+ * aggregates and the client out of its abort checks.
+ */
+
+/* Share of the target bitrate that counts as reached. */
+const BITRATE_REACHED: f64 = 0.9;
+/* Samples the frame rate has to stay within `FPS_TOLERANCE` of its mean. */
+const FPS_WINDOW: usize = 3;
+const FPS_TOLERANCE: f64 = 0.1;
+/*
+ * Samples the received bitrate has to stay within `PLATEAU_TOLERANCE` of
+ * its mean below the target, longer than `FPS_WINDOW` so a pause in the
+ * ramp doesn't pass for the plateau.
+ */
+const PLATEAU_WINDOW: usize = 6;
+const PLATEAU_TOLERANCE: f64 = 0.1;
+
+/* Whether the values stay within `tolerance` of their mean. */
+fn stable(values: &[f64], tolerance: f64) -> bool {
+    if values.iter().any(|value| !value.is_finite()) {
+        return false;
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let (min, max) = values
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(min, max), value| {
+            (min.min(*value), max.max(*value))
+        });
+    mean > 0. && (max - min) / mean <= tolerance
+}
+
+/// Index of the first steady sample, `None` when the run never settles.
+pub fn detect(kbps: &[f64], fps: &[f64], target_kbps: f64) -> Option<usize> {
+    (0..kbps.len()).find(|&i| {
+        if i + FPS_WINDOW > fps.len() || !stable(&fps[i..i + FPS_WINDOW], FPS_TOLERANCE) {
+            return false;
+        }
+        kbps[i] >= target_kbps * BITRATE_REACHED
+            || (i + PLATEAU_WINDOW <= kbps.len()
+                && stable(&kbps[i..i + PLATEAU_WINDOW], PLATEAU_TOLERANCE))
+    })
+}
@@ -0,0 +1,54 @@
+/*
+ * The latency watermark: for a tick the publisher overwrites the top rows
+ * of the next frames with one luma value, after rotating and overlays, and
+ * the client times the first decoded frame showing it. Both sides take the
+ * layout from here so they agree on where to look.
+ */
+
+/// Where and how the publisher marks a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatermarkConfig {
+    /// Luma of the marked rows, below the limited range black of 16 so
+    /// screen content never matches it.
+    pub value: u8,
+    /// Rows marked at the top of the published frame.
+    pub rows: u32,
+    /// Luma difference from `value` still accepted after encoding.
+    pub tolerance: u8,
+    /// Share of the samples of a line that have to match.
+    pub min_share: f64,
+}
+
+pub const WATERMARK: WatermarkConfig = WatermarkConfig {
+    value: 0xa,
+    rows: 50,
+    tolerance: 2,
+    min_share: 0.5,
+};
+
+impl WatermarkConfig {
+    pub fn matches(&self, luma: u8) -> bool {
+        luma.abs_diff(self.value) <= self.tolerance
+    }
+
+    /// The row in the middle of the marked band of a `decoded` frame, for a
+    /// frame published at `published` and scaled on the way, leaving out
+    /// the bars of a letterboxed picture. `None` when the picture was
+    /// turned by a quarter, the band is then a column.
+    pub fn expected_row(&self, decoded: (u32, u32), published: (u32, u32)) -> Option<u32> {
+        let ((width, height), (published_width, published_height)) = (decoded, published);
+        if width == 0 || height == 0 || published_width == 0 || published_height == 0 {
+            return None;
+        }
+        let landscape = |(width, height): (u32, u32)| width >= height;
+        if landscape(decoded) != landscape(published) {
+            return None;
+        }
+        /* The picture fills the width, bars above and below take the rest. */
+        let picture_height =
+            (width as u64 * published_height as u64 / published_width as u64).min(height as u64);
+        let top = (height as u64 - picture_height) / 2;
+        let band = self.rows as u64 * picture_height / published_height as u64;
+        Some((top + band / 2) as u32)
+    }
+}
@@ -0,0 +1,406 @@
+/*
+ * Runs the screen_sharer and the measuring client against the same room
+ * with two identities and one results directory, or one such pair per
+ * `--variant` in rooms of their own, for A/B comparisons under the same
+ * load and screen activity. They can't share a
+ * process: the screen_sharer links the gethopp libwebrtc fork and the client
+ * the released SDK, and two libwebrtc builds don't link into one binary.
+ * Both children are in our process group, so a single Ctrl-C reaches them,
+ * and when one fails the other is stopped. Either side can run on another
+ * machine instead, see `remote`. Runs degraded on the way are marked
+ * invalid and can be run again, see `validation`.
+ */
+mod remote;
+mod shaper;
+mod validation;
+
+use clap::Parser;
+use common::validity::Tolerance;
+use livekit_api::access_token::{AccessToken, VideoGrants};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::time::Duration;
+
+use remote::Remote;
+use shaper::{NetworkShaper, parse_loss};
+
+#[derive(Parser)]
+#[command(name = "loopback")]
+#[command(about = "Run the screen sharer and the client together for a local comparison")]
+struct Args {
+    /// Root directory for the results of both sides
+    #[arg(long, default_value = "results")]
+    output_dir: String,
+
+    /// Room to join when the tokens are generated from LIVEKIT_API_KEY/LIVEKIT_API_SECRET
+    #[arg(long, default_value = "loopback")]
+    room: String,
+
+    /// screen_sharer binary
+    #[arg(long, default_value = "../screen_sharer/target/release/screen_sharer")]
+    screen_sharer: PathBuf,
+
+    /// client binary
+    #[arg(long, default_value = "../client/target/release/client")]
+    client: PathBuf,
+
+    /// Argument passed to the screen_sharer, repeatable, e.g. --publisher-arg=--codec=AV1
+    #[arg(long = "publisher-arg", allow_hyphen_values = true)]
+    publisher_args: Vec<String>,
+
+    /// Argument passed to the client, repeatable
+    #[arg(long = "client-arg", allow_hyphen_values = true)]
+    client_args: Vec<String>,
+
+    /// Run a publisher/client pair per variant at the same time, as
+    /// "<label>: <screen_sharer args>", e.g. "vp9: --codec=VP9". Repeatable
+    #[arg(long = "variant")]
+    variants: Vec<Variant>,
+
+    /// Percentage of UDP packets to drop on --shape-interface during the run,
+    /// e.g. 5. Linux only, needs root or CAP_NET_ADMIN
+    #[arg(long, value_parser = parse_loss)]
+    loss: Option<f64>,
+
+    /// Interface the packet loss is applied to, the default suits a local server
+    #[arg(long, default_value = "lo")]
+    shape_interface: String,
+
+    /// Run the screen_sharer on this host over SSH, as [user@]host
+    #[arg(long)]
+    publisher_host: Option<String>,
+
+    /// Run the client on this host over SSH, as [user@]host
+    #[arg(long)]
+    client_host: Option<String>,
+
+    /// Directory on the remote hosts the binary paths are relative to
+    #[arg(long, default_value = "livekit_encoders_compared/loopback")]
+    remote_dir: String,
+
+    /// File copied into --remote-dir on the remote hosts before the run, repeatable
+    #[arg(long = "remote-copy")]
+    remote_copies: Vec<PathBuf>,
+
+    /// Room reconnects of either side a run may have and still count
+    #[arg(long, default_value_t = 0)]
+    reconnect_tolerance: u32,
+
+    /// Times to run everything again while a run is invalid: more reconnects
+    /// than tolerated, capture errors or CPU throttling
+    #[arg(long, default_value_t = 0)]
+    reruns: u32,
+}
+
+/// A labeled screen_sharer configuration of an A/B run.
+#[derive(Debug, Clone)]
+struct Variant {
+    label: String,
+    publisher_args: Vec<String>,
+}
+
+impl std::str::FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (label, args) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid variant: {}. Use <label>: <args>", s))?;
+        let label = label.trim();
+        if label.is_empty() || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!(
+                "Invalid variant label: {}. Use letters, digits and _",
+                label
+            ));
+        }
+        Ok(Variant {
+            label: label.to_string(),
+            publisher_args: args.split_whitespace().map(str::to_string).collect(),
+        })
+    }
+}
+
+/* Kills the wrapped process when dropped, so an early exit doesn't leave it running. */
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn generate_token(room: &str, identity: &str) -> Result<String, String> {
+    let (Ok(api_key), Ok(api_secret)) =
+        (env::var("LIVEKIT_API_KEY"), env::var("LIVEKIT_API_SECRET"))
+    else {
+        return Err("LIVEKIT_API_KEY and LIVEKIT_API_SECRET not set".to_string());
+    };
+    AccessToken::with_api_key(&api_key, &api_secret)
+        .with_identity(identity)
+        .with_name(identity)
+        .with_grants(VideoGrants {
+            room_join: true,
+            room: room.to_string(),
+            ..Default::default()
+        })
+        .to_jwt()
+        .map_err(|e| format!("failed to create the {} token: {}", identity, e))
+}
+
+/*
+ * Tokens for the two identities, generated when the API credentials are
+ * available, otherwise taken from LIVEKIT_PUBLISHER_TOKEN and
+ * LIVEKIT_CLIENT_TOKEN.
+ */
+fn tokens(room: &str) -> Result<(String, String), String> {
+    if env::var("LIVEKIT_API_KEY").is_ok() && env::var("LIVEKIT_API_SECRET").is_ok() {
+        return Ok((
+            generate_token(room, "publisher")?,
+            generate_token(room, "client")?,
+        ));
+    }
+
+    let publisher = env::var("LIVEKIT_PUBLISHER_TOKEN");
+    let client = env::var("LIVEKIT_CLIENT_TOKEN");
+    match (publisher, client) {
+        (Ok(publisher), Ok(client)) => Ok((publisher, client)),
+        _ => Err(
+            "set LIVEKIT_API_KEY and LIVEKIT_API_SECRET, or LIVEKIT_PUBLISHER_TOKEN and LIVEKIT_CLIENT_TOKEN"
+                .to_string(),
+        ),
+    }
+}
+
+fn spawn(
+    binary: &Path,
+    token: &str,
+    output_dir: &str,
+    args: &[String],
+    remote: Option<(&Remote, &str)>,
+) -> Result<Child, String> {
+    if let Some((remote, name)) = remote {
+        return remote.spawn(name, binary, token, args);
+    }
+    Command::new(binary)
+        .env("LIVEKIT_TOKEN", token)
+        .arg("--output-dir")
+        .arg(output_dir)
+        .args(args)
+        .spawn()
+        .map_err(|e| format!("failed to start {}: {}", binary.display(), e))
+}
+
+/* A started process, named for the messages. */
+struct Process {
+    name: String,
+    child: KillOnDrop,
+    status: Option<ExitStatus>,
+}
+
+/*
+ * Waits for all children. A publisher exits after its duration and its
+ * client once the frames stop, a failure of any stops the others when they
+ * are dropped.
+ */
+fn wait(mut processes: Vec<Process>) -> Result<(), String> {
+    loop {
+        for process in processes.iter_mut().filter(|p| p.status.is_none()) {
+            process.status = process.child.0.try_wait().map_err(|e| e.to_string())?;
+            if let Some(status) = process.status.filter(|status| !status.success()) {
+                return Err(format!("{} failed: {}", process.name, status));
+            }
+        }
+        if processes.iter().all(|p| p.status.is_some()) {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/* The remote hosts of the publisher and the client, None for a side run here. */
+struct Remotes {
+    publisher: Option<Remote>,
+    client: Option<Remote>,
+}
+
+impl Remotes {
+    fn new(args: &Args) -> Self {
+        let remote = |host: &Option<String>, side| {
+            host.as_ref()
+                .map(|host| Remote::new(host, &args.remote_dir, side))
+        };
+        Remotes {
+            publisher: remote(&args.publisher_host, "publisher"),
+            client: remote(&args.client_host, "client"),
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Remote> {
+        self.publisher.iter().chain(&self.client)
+    }
+}
+
+/* Starts a publisher and a client in `room`. */
+fn start_pair(
+    args: &Args,
+    remotes: &Remotes,
+    room: &str,
+    tokens: (String, String),
+    publisher_args: &[String],
+    label: Option<&str>,
+) -> Result<Vec<Process>, String> {
+    let suffix = label
+        .map(|label| format!(" ({})", label))
+        .unwrap_or_default();
+    /* Names the env files of the remote processes, unique per host. */
+    let name = |side: &str| match label {
+        Some(label) => format!("{}_{}", side, label),
+        None => side.to_string(),
+    };
+    let publisher_name = name("publisher");
+    let client_name = name("client");
+    let publisher = KillOnDrop(spawn(
+        &args.screen_sharer,
+        &tokens.0,
+        &args.output_dir,
+        publisher_args,
+        remotes
+            .publisher
+            .as_ref()
+            .map(|remote| (remote, publisher_name.as_str())),
+    )?);
+    let client = KillOnDrop(spawn(
+        &args.client,
+        &tokens.1,
+        &args.output_dir,
+        &args.client_args,
+        remotes
+            .client
+            .as_ref()
+            .map(|remote| (remote, client_name.as_str())),
+    )?);
+    let host = |remote: &Option<Remote>| match remote {
+        Some(remote) => format!(" on {}", remote.host()),
+        None => String::new(),
+    };
+    println!(
+        "Started publisher{} and client{}{} in room {}",
+        host(&remotes.publisher),
+        host(&remotes.client),
+        suffix,
+        room
+    );
+    Ok(vec![
+        Process {
+            name: format!("screen_sharer{}", suffix),
+            child: publisher,
+            status: None,
+        },
+        Process {
+            name: format!("client{}", suffix),
+            child: client,
+            status: None,
+        },
+    ])
+}
+
+fn run(args: Args) -> Result<(), String> {
+    if env::var("LIVEKIT_URL").is_err() {
+        return Err("LIVEKIT_URL environment variable not set".to_string());
+    }
+    let remotes = Remotes::new(&args);
+    for remote in remotes.iter() {
+        remote.prepare(&args.remote_copies)?;
+    }
+    /* Kept until the children are done, dropping it restores the interface. */
+    let _shaper = match args.loss {
+        Some(loss) => Some(NetworkShaper::apply(&args.shape_interface, loss)?),
+        None => None,
+    };
+
+    let output_dir = Path::new(&args.output_dir);
+    let tolerance = Tolerance {
+        reconnects: args.reconnect_tolerance,
+    };
+    let mut attempt = 0;
+    loop {
+        let known = validation::runs(output_dir);
+        let result = start_all(&args, &remotes).and_then(wait);
+        /* Incomplete runs are pulled too, like they are uploaded. */
+        for remote in remotes.iter() {
+            if let Err(e) = remote.pull(&args.output_dir) {
+                eprintln!("Failed to pull the results: {}", e);
+            }
+        }
+        result?;
+
+        let invalid = validation::check(output_dir, &known, tolerance)?;
+        if invalid == 0 {
+            return Ok(());
+        }
+        if attempt == args.reruns {
+            eprintln!(
+                "{} invalid runs left, marked with <name>_invalid.txt in their run directory",
+                invalid
+            );
+            return Ok(());
+        }
+        /* All pairs again, the variants of an A/B run are compared under the same load. */
+        attempt += 1;
+        println!("Running again, rerun {} of {}", attempt, args.reruns);
+    }
+}
+
+fn start_all(args: &Args, remotes: &Remotes) -> Result<Vec<Process>, String> {
+    if args.variants.is_empty() {
+        let tokens = tokens(&args.room)?;
+        return start_pair(
+            args,
+            remotes,
+            &args.room,
+            tokens,
+            &args.publisher_args,
+            None,
+        );
+    }
+
+    /*
+     * One room per variant, the label becomes the run name so the results
+     * of both sides of the comparison are told apart by their file names.
+     */
+    let mut processes = Vec::new();
+    for variant in &args.variants {
+        let room = format!("{}-{}", args.room, variant.label);
+        let tokens = (
+            generate_token(&room, "publisher")?,
+            generate_token(&room, "client")?,
+        );
+        let mut publisher_args = args.publisher_args.clone();
+        publisher_args.extend(variant.publisher_args.iter().cloned());
+        let named = publisher_args
+            .iter()
+            .any(|arg| arg == "-n" || arg.starts_with("--name"));
+        if !named {
+            publisher_args.extend(["--name".to_string(), variant.label.clone()]);
+        }
+        processes.extend(start_pair(
+            args,
+            remotes,
+            &room,
+            tokens,
+            &publisher_args,
+            Some(&variant.label),
+        )?);
+    }
+    Ok(processes)
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
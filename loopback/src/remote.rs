@@ -0,0 +1,195 @@
+/*
+ * One side of the run on another machine over SSH, so the client CPU
+ * numbers aren't inflated by the publisher encoding on the same machine.
+ * The binaries are expected in a checkout on the remote host, their paths
+ * and `--remote-copy` files are relative to `--remote-dir` there. The
+ * LiveKit settings go over in an env file that is removed once read, so
+ * tokens don't show up in the remote process list. It lives next to the
+ * output directory rather than in it, so a file left behind by a side
+ * that failed to start is never pulled into the results. Every side writes to an
+ * output directory of its own for the run, pulled back into the local
+ * results when it is done. ssh runs in batch mode, the hosts need key
+ * based authentication, e.g. through the agent or `~/.ssh/config`.
+ */
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+/* Passed on to the remote side when set here. */
+const FORWARDED_ENV: [&str; 5] = [
+    "LIVEKIT_URL",
+    "INFLUX_TOKEN",
+    "RESULTS_UPLOAD_URL",
+    "UPLOAD_TOKEN",
+    "RUST_LOG",
+];
+
+pub struct Remote {
+    host: String,
+    dir: String,
+    /* Relative to `dir`, fresh for every run. */
+    output_dir: String,
+}
+
+/* Single quotes for the remote shell. */
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+fn run(command: &mut Command, what: &str) -> Result<(), String> {
+    let output = command
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", what, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            what,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+impl Remote {
+    /// `side` tells the output directories of the two sides apart when
+    /// they run on the same host.
+    pub fn new(host: &str, dir: &str, side: &str) -> Self {
+        let started = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        Remote {
+            host: host.to_string(),
+            dir: dir.to_string(),
+            output_dir: format!("results-{}-{}", started, side),
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /* Start of the env file names of the run, relative to `dir` and outside of the output directory. */
+    fn env_prefix(&self) -> String {
+        format!(".{}-", self.output_dir)
+    }
+
+    fn ssh(&self) -> Command {
+        let mut command = Command::new("ssh");
+        command.args(["-o", "BatchMode=yes"]);
+        command
+    }
+
+    /// Creates the output directory and copies `files` into the remote directory.
+    pub fn prepare(&self, files: &[PathBuf]) -> Result<(), String> {
+        let mkdir = format!(
+            "mkdir -p {}",
+            quote(&format!("{}/{}", self.dir, self.output_dir))
+        );
+        run(
+            self.ssh().arg(&self.host).arg(mkdir),
+            &format!("ssh {}", self.host),
+        )?;
+        for file in files {
+            run(
+                Command::new("scp")
+                    .args(["-B", "-p"])
+                    .arg(file)
+                    .arg(format!("{}:{}/", self.host, self.dir)),
+                &format!("copying {} to {}", file.display(), self.host),
+            )?;
+            println!("Copied {} to {}:{}", file.display(), self.host, self.dir);
+        }
+        Ok(())
+    }
+
+    /// Starts `binary` on the remote host with the LiveKit settings, the
+    /// remote process is hung up on when the returned ssh is killed.
+    pub fn spawn(
+        &self,
+        name: &str,
+        binary: &Path,
+        token: &str,
+        args: &[String],
+    ) -> Result<Child, String> {
+        let mut env = format!("LIVEKIT_TOKEN={}\n", quote(token));
+        for key in FORWARDED_ENV {
+            if let Ok(value) = std::env::var(key) {
+                env.push_str(&format!("{}={}\n", key, quote(&value)));
+            }
+        }
+        let env_file = format!("{}{}.env", self.env_prefix(), name);
+        let mut upload = self
+            .ssh()
+            .arg(&self.host)
+            .arg(format!(
+                "umask 077 && cat > {}",
+                quote(&format!("{}/{}", self.dir, env_file))
+            ))
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run ssh: {}", e))?;
+        upload
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(env.as_bytes())
+            .map_err(|e| format!("failed to copy the settings to {}: {}", self.host, e))?;
+        let status = upload.wait().map_err(|e| e.to_string())?;
+        if !status.success() {
+            return Err(format!(
+                "failed to copy the settings to {}: {}",
+                self.host, status
+            ));
+        }
+
+        let mut command = format!(
+            "cd {} && set -a && . ./{} && set +a && rm ./{} && exec {} --output-dir {}",
+            quote(&self.dir),
+            quote(&env_file),
+            quote(&env_file),
+            quote(&binary.to_string_lossy()),
+            quote(&self.output_dir)
+        );
+        for arg in args {
+            command.push(' ');
+            command.push_str(&quote(arg));
+        }
+        /* A terminal on the remote side, so it hangs up on the process when ssh goes away. */
+        self.ssh()
+            .arg("-tt")
+            .arg(&self.host)
+            .arg(command)
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("failed to start {} on {}: {}", name, self.host, e))
+    }
+
+    /// Copies the results of the run into `local_dir`, keeping their layout.
+    pub fn pull(&self, local_dir: &str) -> Result<(), String> {
+        /* Settings a side didn't get to read, the tokens shouldn't stay on the host. */
+        let cleanup = format!(
+            "rm -f {}/{}*.env",
+            quote(&self.dir),
+            quote(&self.env_prefix())
+        );
+        if let Err(e) = run(
+            self.ssh().arg(&self.host).arg(cleanup),
+            &format!("ssh {}", self.host),
+        ) {
+            eprintln!("Failed to remove the settings from {}: {}", self.host, e);
+        }
+        std::fs::create_dir_all(local_dir)
+            .map_err(|e| format!("failed to create {}: {}", local_dir, e))?;
+        let source = format!("{}:{}/{}/*", self.host, self.dir, self.output_dir);
+        run(
+            Command::new("scp")
+                .args(["-B", "-r", "-p"])
+                .arg(&source)
+                .arg(format!("{}/", local_dir)),
+            &format!("copying the results from {}", self.host),
+        )?;
+        println!("Copied the results from {} to {}", source, local_dir);
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+/*
+ * Packet loss injected with netem on a network interface for the length of
+ * the run, so the loss recovery of the encoders (NACK and retransmission,
+ * FEC) is compared under the same conditions. Only UDP is shaped, which is
+ * what the media goes over: the signalling websocket and the HTTP of the
+ * room service run over TCP on the same interface and would otherwise
+ * stall the connection. Linux only, and `tc` needs root or CAP_NET_ADMIN.
+ * The qdiscs are removed when the shaper is dropped; a run killed before
+ * that leaves them in place, see `tc qdisc del`.
+ */
+use std::process::Command;
+
+pub struct NetworkShaper {
+    interface: String,
+}
+
+fn tc(args: &[&str]) -> Result<(), String> {
+    let output = Command::new("tc")
+        .args(args)
+        .output()
+        .map_err(|e| format!("failed to run tc: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "tc {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+impl NetworkShaper {
+    /// Drops `loss` percent of the UDP packets leaving `interface`.
+    pub fn apply(interface: &str, loss: f64) -> Result<Self, String> {
+        /*
+         * A fourth band of a prio qdisc takes the loss, the filters send UDP
+         * there and the priomap everything else to the first band. `replace`
+         * also takes over a qdisc left behind by a killed run.
+         */
+        let loss = format!("{}%", loss);
+        let mut root = vec![
+            "qdisc", "replace", "dev", interface, "root", "handle", "1:", "prio", "bands", "4",
+            "priomap",
+        ];
+        root.extend(["0"; 16]);
+        tc(&root)?;
+        let shaper = NetworkShaper {
+            interface: interface.to_string(),
+        };
+        tc(&[
+            "qdisc", "add", "dev", interface, "parent", "1:4", "handle", "40:", "netem", "loss",
+            &loss,
+        ])?;
+        for (protocol, selector) in [("ip", "ip"), ("ipv6", "ip6")] {
+            tc(&[
+                "filter", "add", "dev", interface, "parent", "1:", "protocol", protocol, "prio",
+                "1", "u32", "match", selector, "protocol", "17", "0xff", "flowid", "1:4",
+            ])?;
+        }
+        println!("Dropping {} of the UDP packets on {}", loss, interface);
+        Ok(shaper)
+    }
+}
+
+impl Drop for NetworkShaper {
+    fn drop(&mut self) {
+        if let Err(e) = tc(&["qdisc", "del", "dev", &self.interface, "root"]) {
+            eprintln!("Failed to remove the packet loss: {}", e);
+        }
+    }
+}
+
+pub fn parse_loss(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|loss| (0. ..=100.).contains(loss))
+        .ok_or_else(|| format!("Invalid loss: {}. Use a percentage from 0 to 100", s))
+}
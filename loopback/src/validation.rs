@@ -0,0 +1,74 @@
+/*
+ * Checks the runs that just finished for degradations, see
+ * `common::validity`, and marks the invalid ones, so they don't end up in
+ * the aggregates unnoticed. The runs of an attempt are the ones in the
+ * results that weren't there before it, whether written here or pulled
+ * from a remote host.
+ */
+use common::validity::{self, Tolerance};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const METADATA_SUFFIX: &str = "_publisher_metadata.csv";
+
+/* Directories `depth` levels below `dir`. */
+fn subdirectories(dir: &Path, depth: usize) -> Vec<PathBuf> {
+    if depth == 0 {
+        return vec![dir.to_path_buf()];
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .flat_map(|path| subdirectories(&path, depth - 1))
+        .collect()
+}
+
+/// The runs in the results under `output_dir`, as run directory and name.
+pub fn runs(output_dir: &Path) -> BTreeSet<(PathBuf, String)> {
+    /* <timestamp>/<codec>/<resolution>/<bitrate> */
+    let mut runs = BTreeSet::new();
+    for run_dir in subdirectories(output_dir, 4) {
+        let Ok(entries) = fs::read_dir(&run_dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if let Some(name) = file_name.strip_suffix(METADATA_SUFFIX) {
+                runs.insert((run_dir.clone(), name.to_string()));
+            }
+        }
+    }
+    runs
+}
+
+/// Checks and marks the runs under `output_dir` that aren't in `known`,
+/// returns how many are invalid.
+pub fn check(
+    output_dir: &Path,
+    known: &BTreeSet<(PathBuf, String)>,
+    tolerance: Tolerance,
+) -> Result<usize, String> {
+    let mut invalid = 0;
+    for (run_dir, name) in runs(output_dir).difference(known) {
+        let reasons = validity::degradations(run_dir, name, tolerance)
+            .map_err(|e| format!("failed to read the events of {}: {}", name, e))?;
+        if reasons.is_empty() {
+            continue;
+        }
+        eprintln!(
+            "Run {} in {} is invalid: {}",
+            name,
+            run_dir.display(),
+            reasons.join(", ")
+        );
+        validity::mark_invalid(run_dir, name, &reasons)
+            .map_err(|e| format!("failed to mark {} invalid: {}", name, e))?;
+        invalid += 1;
+    }
+    Ok(invalid)
+}
@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+/*
+ * Frames handed to `NativeVideoSource::capture_frame` faster than WebRTC
+ * takes them don't fail, they pile up or vanish between the source and
+ * the encoder without a trace in the capture stats. The media source
+ * stats count the frames that reached WebRTC, so every stats sample
+ * compares them with the frames submitted since the previous one: when
+ * more than `BEHIND_FRAMES` went missing the source is behind.
+ *
+ * With skipping enabled, submission is then capped at the rate the source
+ * took frames at, and the capture callback skips the frames in between.
+ * While capped the source can't show it could take more, so the cap is
+ * loosened by `RELEASE_STEP` on every sample that isn't behind, until it
+ * is no tighter than the capture interval and lifted.
+ */
+
+/* Frames submitted in a sample but not taken by the source above which it is behind. */
+const BEHIND_FRAMES: i64 = 10;
+/* Share the submission interval shrinks by per sample the source keeps up. */
+const RELEASE_STEP: f64 = 0.1;
+
+/* Change of state at a sample, for the events. */
+pub enum Transition {
+    Started(String),
+    Stopped(String),
+}
+
+pub struct BackpressureMonitor {
+    /* Submitted and source frames and the time in ms at the previous sample. */
+    last: Option<(u64, u64, u128)>,
+    behind: bool,
+    /* Minimum wait between submissions in µs, 0 when not capped. */
+    interval_us: u64,
+}
+
+impl BackpressureMonitor {
+    pub fn new() -> Self {
+        BackpressureMonitor {
+            last: None,
+            behind: false,
+            interval_us: 0,
+        }
+    }
+
+    /// The submission interval cap in µs to apply until the next sample, 0
+    /// for none, and the transition if the source fell behind or caught up.
+    /// Nothing is decided before the source reported any frames.
+    pub fn update(
+        &mut self,
+        submitted: u64,
+        source_frames: u64,
+        timestamp: u128,
+        capture_interval: Duration,
+        skip: bool,
+    ) -> (u64, Option<Transition>) {
+        if source_frames == 0 {
+            return (self.interval_us, None);
+        }
+        let Some((last_submitted, last_source, last_timestamp)) =
+            self.last.replace((submitted, source_frames, timestamp))
+        else {
+            return (self.interval_us, None);
+        };
+        let submitted_delta = submitted.saturating_sub(last_submitted);
+        let taken = source_frames.saturating_sub(last_source);
+        let missing = submitted_delta as i64 - taken as i64;
+        let millis = timestamp.saturating_sub(last_timestamp).max(1);
+
+        let behind = missing > BEHIND_FRAMES;
+        if skip {
+            if behind && taken > 0 {
+                /* The source's own pace, never looser than the current cap. */
+                let pace_us = (millis as u64 * 1000 / taken).max(self.interval_us);
+                self.interval_us = pace_us;
+            } else if !behind && self.interval_us > 0 {
+                self.interval_us = (self.interval_us as f64 * (1. - RELEASE_STEP)) as u64;
+                if self.interval_us <= capture_interval.as_micros() as u64 {
+                    self.interval_us = 0;
+                }
+            }
+        }
+
+        let transition = if behind == self.behind {
+            None
+        } else {
+            self.behind = behind;
+            let detail = format!(
+                "{} frames submitted and {} taken by the source in {} ms",
+                submitted_delta, taken, millis
+            );
+            Some(if behind {
+                Transition::Started(detail)
+            } else {
+                Transition::Stopped(detail)
+            })
+        };
+        (self.interval_us, transition)
+    }
+}
@@ -0,0 +1,129 @@
+use crate::aspect_fit;
+use crate::colorspace::PixelFormat;
+use crate::scale;
+use crate::synthetic::SyntheticSource;
+use livekit::webrtc::prelude::{NV12Buffer, VideoBuffer};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/*
+ * The capture pipeline of the publisher on its own, without a capturer or
+ * an encoder: the synthetic frames are converted to NV12 at the screen
+ * size and scaled to the stream size, as fast as they go, on the buffers
+ * `frame_handler` would use. Both steps run in the libyuv built into the
+ * SDK's libwebrtc, so the bench built against an SDK version times that
+ * version's pipeline: when the publisher `cpu_usage` moves between SDK
+ * versions and the bench numbers don't, the change is in the encoder.
+ * Rendering the frames isn't timed.
+ */
+
+/* Frames run before the timing starts, to fault in the buffers and warm the caches. */
+const WARMUP_FRAMES: u64 = 10;
+
+/// Cost of the pipeline for one screen and stream size.
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineBench {
+    pub screen: (u32, u32),
+    pub stream: (u32, u32),
+    pub frames: u64,
+    pub convert: Duration,
+    pub scale: Duration,
+}
+
+impl PipelineBench {
+    /// Conversion time per frame, ms.
+    pub fn convert_ms(&self) -> f64 {
+        self.convert.as_secs_f64() * 1000. / self.frames as f64
+    }
+
+    /// Scaling time per frame, ms.
+    pub fn scale_ms(&self) -> f64 {
+        self.scale.as_secs_f64() * 1000. / self.frames as f64
+    }
+
+    pub fn frame_ms(&self) -> f64 {
+        self.convert_ms() + self.scale_ms()
+    }
+
+    /// Frames a core gets through per second.
+    pub fn fps(&self) -> f64 {
+        1000. / self.frame_ms()
+    }
+
+    /// Screen pixels a core gets through per second, in millions.
+    pub fn megapixels_per_second(&self) -> f64 {
+        (self.screen.0 * self.screen.1) as f64 * self.fps() / 1e6
+    }
+}
+
+/* The buffers and the time spent in them so far. */
+struct BenchState {
+    screen_buffer: NV12Buffer,
+    stream_buffer: NV12Buffer,
+    pixel_format: PixelFormat,
+    convert: Duration,
+    scale: Duration,
+}
+
+/// Runs `frames` synthetic frames of a `screen` sized screen through the
+/// conversion and the scaling to `stream`, fit to the screen's aspect ratio
+/// as the publisher does.
+pub fn bench_pipeline(
+    screen: (u32, u32),
+    stream: (u32, u32),
+    frames: u64,
+    pixel_format: PixelFormat,
+) -> PipelineBench {
+    let (screen_width, screen_height) = scale::even_size(screen.0, screen.1);
+    let stream = aspect_fit(screen_width, screen_height, stream.0, stream.1);
+    let state = Arc::new(Mutex::new(BenchState {
+        screen_buffer: NV12Buffer::new(screen_width, screen_height),
+        stream_buffer: NV12Buffer::new(stream.0, stream.1),
+        pixel_format,
+        convert: Duration::ZERO,
+        scale: Duration::ZERO,
+    }));
+    let handler_state = state.clone();
+    let mut source = SyntheticSource::new(
+        screen_width,
+        screen_height,
+        Box::new(move |data: &[u8], stride: u32, width: i32, height: i32| {
+            let mut state = handler_state.lock().unwrap();
+            let BenchState {
+                screen_buffer,
+                stream_buffer,
+                pixel_format,
+                convert: convert_time,
+                scale: scale_time,
+            } = &mut *state;
+            let converting = Instant::now();
+            let (s_y, s_uv) = screen_buffer.strides();
+            let (y, uv) = screen_buffer.data_mut();
+            pixel_format.to_nv12(data, stride, y, s_y, uv, s_uv, width, height);
+            let scaling = Instant::now();
+            scale::scale_nv12(screen_buffer, stream_buffer);
+            *scale_time += scaling.elapsed();
+            *convert_time += scaling - converting;
+        }),
+    );
+
+    for _ in 0..WARMUP_FRAMES {
+        source.capture_frame();
+    }
+    {
+        let mut state = state.lock().unwrap();
+        state.convert = Duration::ZERO;
+        state.scale = Duration::ZERO;
+    }
+    for _ in 0..frames {
+        source.capture_frame();
+    }
+    let state = state.lock().unwrap();
+    PipelineBench {
+        screen: (screen_width, screen_height),
+        stream,
+        frames,
+        convert: state.convert,
+        scale: state.scale,
+    }
+}
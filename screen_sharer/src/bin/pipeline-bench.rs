@@ -0,0 +1,144 @@
+use clap::{Arg, Command, value_parser};
+use common::sink::{CsvSink, StatsSink, Value, write_all};
+use screen_sharer::{PipelineBench, PixelFormat, bench_pipeline};
+use std::path::PathBuf;
+
+/*
+ * Times the BGRA to NV12 conversion and the scaling of the publisher for
+ * every pair of screen and stream size, without capturing or encoding.
+ * Run it with the SDK a run was built with to tell whether a change of
+ * the publisher CPU usage between SDK versions came from the pipeline or
+ * from the encoder.
+ */
+
+const HEADER: [&str; 13] = [
+    "screen_width",
+    "screen_height",
+    "stream_width",
+    "stream_height",
+    "frames",
+    "convert_ms",
+    "scale_ms",
+    "frame_ms",
+    "fps",
+    "megapixels_per_second",
+    "pixel_format",
+    "libwebrtc_version",
+];
+
+/* `<width>x<height>`. */
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid size: {}. Use <width>x<height>", s);
+    let (width, height) = s.split_once('x').ok_or_else(invalid)?;
+    let width = width.trim().parse::<u32>().map_err(|_| invalid())?;
+    let height = height.trim().parse::<u32>().map_err(|_| invalid())?;
+    if width < 2 || height < 2 {
+        return Err(invalid());
+    }
+    Ok((width, height))
+}
+
+fn size(size: (u32, u32)) -> String {
+    format!("{}x{}", size.0, size.1)
+}
+
+fn row(bench: &PipelineBench, pixel_format: PixelFormat) -> Vec<Value> {
+    vec![
+        bench.screen.0.into(),
+        bench.screen.1.into(),
+        bench.stream.0.into(),
+        bench.stream.1.into(),
+        bench.frames.into(),
+        Value::float(bench.convert_ms(), 3),
+        Value::float(bench.scale_ms(), 3),
+        Value::float(bench.frame_ms(), 3),
+        Value::float(bench.fps(), 1),
+        Value::float(bench.megapixels_per_second(), 1),
+        pixel_format.as_str().into(),
+        env!("BUILD_LIBWEBRTC_VERSION").into(),
+    ]
+}
+
+fn main() {
+    let matches = Command::new("Pipeline Bench")
+        .version("1.0")
+        .about("Time the frame conversion and scaling of the screen_sharer without capturing or encoding")
+        .args([
+            Arg::new("screen")
+                .long("screen")
+                .help("Screen sizes, comma separated")
+                .value_parser(parse_size)
+                .value_delimiter(',')
+                .default_value("1920x1080,2560x1440,3840x2160"),
+            Arg::new("stream")
+                .long("stream")
+                .help("Stream sizes every screen is scaled to, fit to its aspect ratio, comma separated")
+                .value_parser(parse_size)
+                .value_delimiter(',')
+                .default_value("1280x720,1920x1080"),
+            Arg::new("frames")
+                .long("frames")
+                .help("Frames timed per pair of sizes")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("300"),
+            Arg::new("pixel_format")
+                .long("pixel-format")
+                .help("Byte order of the frames (bgra or rgba)")
+                .value_parser(value_parser!(PixelFormat))
+                .default_value("bgra"),
+            Arg::new("output")
+                .long("output")
+                .help("CSV file the results are written to"),
+        ])
+        .get_matches();
+
+    let screens: Vec<(u32, u32)> = matches.get_many("screen").unwrap().copied().collect();
+    let streams: Vec<(u32, u32)> = matches.get_many("stream").unwrap().copied().collect();
+    let frames = *matches.get_one::<u64>("frames").unwrap();
+    let pixel_format = *matches.get_one::<PixelFormat>("pixel_format").unwrap();
+
+    println!(
+        "livekit {}, libwebrtc {}, {} frames per pair, {}",
+        env!("BUILD_LIVEKIT_VERSION"),
+        env!("BUILD_LIBWEBRTC_VERSION"),
+        frames,
+        pixel_format.as_str()
+    );
+    println!(
+        "{:<12}{:<12}{:>12}{:>10}{:>10}{:>10}{:>10}",
+        "screen", "stream", "convert ms", "scale ms", "frame ms", "fps", "Mpx/s"
+    );
+    let mut results = vec![];
+    for screen in &screens {
+        for stream in &streams {
+            let bench = bench_pipeline(*screen, *stream, frames, pixel_format);
+            println!(
+                "{:<12}{:<12}{:>12.3}{:>10.3}{:>10.3}{:>10.1}{:>10.1}",
+                size(bench.screen),
+                size(bench.stream),
+                bench.convert_ms(),
+                bench.scale_ms(),
+                bench.frame_ms(),
+                bench.fps(),
+                bench.megapixels_per_second()
+            );
+            results.push(bench);
+        }
+    }
+
+    if let Some(path) = matches.get_one::<String>("output") {
+        let file = PathBuf::from(path);
+        let mut sinks: Vec<Box<dyn StatsSink>> =
+            vec![Box::new(CsvSink::with_paths(move |_| file.clone()))];
+        let rows: Vec<Vec<Value>> = results
+            .iter()
+            .map(|bench| row(bench, pixel_format))
+            .collect();
+        let failed = write_all(&mut sinks, "pipeline_bench", &HEADER, &rows);
+        if let Some((_, e)) = failed.first() {
+            eprintln!("Failed to write {}: {}", path, e);
+            std::process::exit(1);
+        }
+        println!("Results written to {}", path);
+    }
+}
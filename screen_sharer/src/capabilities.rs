@@ -0,0 +1,369 @@
+use crate::encoder::describe_fmtp;
+use livekit::webrtc::peer_connection_factory::native::PeerConnectionFactoryExt;
+use livekit::webrtc::prelude::{
+    AnswerOptions, IceCandidate, MediaType, NV12Buffer, OfferOptions, PeerConnection,
+    PeerConnectionFactory, RtcConfiguration, RtpCodecCapability, RtpTransceiverDirection,
+    RtpTransceiverInit, VideoFrame, VideoResolution, VideoRotation,
+};
+use livekit::webrtc::stats::RtcStats;
+use livekit::webrtc::video_source::native::NativeVideoSource;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/*
+ * What the local webrtc build can encode and decode. The codecs and their
+ * profiles are the RTP capabilities of the peer connection factory, which
+ * only say what may be negotiated. Which encoder and decoder end up doing
+ * the work, and whether they are hardware ones, only shows once frames
+ * flow, so every codec the build can send is also tried: two peer
+ * connections in this process negotiate just that codec and profile, a
+ * few frames are pushed through and the stats report the implementations.
+ * No room or server is involved.
+ */
+
+/* Size of the test frames, small enough for every encoder and level. */
+const TEST_WIDTH: u32 = 640;
+const TEST_HEIGHT: u32 = 360;
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+/* Not codecs of their own, they wrap or protect another. */
+const AUXILIARY_CODECS: [&str; 4] = ["rtx", "red", "ulpfec", "flexfec-03"];
+
+/// The encoder and decoder a codec ran with in the test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodecImplementation {
+    /// e.g. `libvpx` or `VideoToolbox`.
+    pub encoder: String,
+    pub hardware_encoder: bool,
+    /// Empty when nothing was decoded before the timeout.
+    pub decoder: String,
+    pub hardware_decoder: bool,
+}
+
+/// A codec and profile the build knows about.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodecCapability {
+    /// e.g. `VP9`, from the mime type.
+    pub codec: String,
+    /// e.g. `high 3.1`, empty when the format parameters name none.
+    pub profile: String,
+    pub fmtp: String,
+    pub encode: bool,
+    pub decode: bool,
+    /// The test result, None for a codec that can't be sent.
+    pub implementation: Option<Result<CodecImplementation, String>>,
+}
+
+fn codec_name(capability: &RtpCodecCapability) -> String {
+    let name = capability
+        .mime_type
+        .split('/')
+        .nth(1)
+        .unwrap_or(&capability.mime_type);
+    name.to_uppercase()
+}
+
+fn fmtp(capability: &RtpCodecCapability) -> String {
+    capability.sdp_fmtp_line.clone().unwrap_or_default()
+}
+
+fn is_auxiliary(capability: &RtpCodecCapability) -> bool {
+    AUXILIARY_CODECS.contains(&codec_name(capability).to_lowercase().as_str())
+}
+
+fn find<'a>(
+    capabilities: &'a [RtpCodecCapability],
+    codec: &str,
+    fmtp_line: &str,
+) -> Option<&'a RtpCodecCapability> {
+    capabilities
+        .iter()
+        .find(|capability| codec_name(capability) == codec && fmtp(capability) == fmtp_line)
+}
+
+/// The video codecs the build can send or receive, each tried with
+/// `timeout` for its test.
+pub async fn probe_encoders(timeout: Duration) -> Vec<CodecCapability> {
+    let factory = PeerConnectionFactory::default();
+    let sendable = factory.get_rtp_sender_capabilities(MediaType::Video).codecs;
+    let receivable = factory
+        .get_rtp_receiver_capabilities(MediaType::Video)
+        .codecs;
+
+    let mut capabilities: Vec<CodecCapability> = vec![];
+    for capability in sendable.iter().chain(receivable.iter()) {
+        if is_auxiliary(capability) {
+            continue;
+        }
+        let (codec, fmtp) = (codec_name(capability), fmtp(capability));
+        if capabilities
+            .iter()
+            .any(|known| known.codec == codec && known.fmtp == fmtp)
+        {
+            continue;
+        }
+        capabilities.push(CodecCapability {
+            profile: describe_fmtp(&fmtp).unwrap_or_default(),
+            encode: find(&sendable, &codec, &fmtp).is_some(),
+            decode: find(&receivable, &codec, &fmtp).is_some(),
+            codec,
+            fmtp,
+            implementation: None,
+        });
+    }
+
+    for capability in &mut capabilities {
+        let Some(codec) = find(&sendable, &capability.codec, &capability.fmtp) else {
+            continue;
+        };
+        log::info!("Testing {} {}", capability.codec, capability.fmtp);
+        capability.implementation = Some(test_codec(&factory, codec, timeout).await);
+    }
+    capabilities
+}
+
+/* Candidates are passed as text, the handles of libwebrtc don't cross tasks. */
+type Candidate = (String, i32, String);
+
+fn forward_candidates(peer_connection: &PeerConnection) -> mpsc::UnboundedReceiver<Candidate> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    peer_connection.on_ice_candidate(Some(Box::new(move |candidate: IceCandidate| {
+        let _ = tx.send((
+            candidate.sdp_mid(),
+            candidate.sdp_mline_index(),
+            candidate.candidate(),
+        ));
+    })));
+    rx
+}
+
+async fn add_candidates(
+    peer_connection: &PeerConnection,
+    candidates: &mut mpsc::UnboundedReceiver<Candidate>,
+) {
+    while let Ok((mid, index, sdp)) = candidates.try_recv() {
+        match IceCandidate::parse(&mid, index, &sdp) {
+            Ok(candidate) => {
+                if let Err(e) = peer_connection.add_ice_candidate(candidate).await {
+                    log::warn!("Failed to add an ICE candidate: {}", e);
+                }
+            }
+            Err(e) => log::warn!("Failed to parse an ICE candidate: {:?}", e),
+        }
+    }
+}
+
+/* Sends frames of `codec` from one peer connection to the other until both implementations show. */
+async fn test_codec(
+    factory: &PeerConnectionFactory,
+    codec: &RtpCodecCapability,
+    timeout: Duration,
+) -> Result<CodecImplementation, String> {
+    let sender = factory
+        .create_peer_connection(RtcConfiguration::default())
+        .map_err(|e| format!("failed to create a peer connection: {}", e))?;
+    let receiver = factory
+        .create_peer_connection(RtcConfiguration::default())
+        .map_err(|e| format!("failed to create a peer connection: {}", e))?;
+    let result = negotiate_and_measure(factory, &sender, &receiver, codec, timeout).await;
+    sender.close();
+    receiver.close();
+    result
+}
+
+async fn negotiate_and_measure(
+    factory: &PeerConnectionFactory,
+    sender: &PeerConnection,
+    receiver: &PeerConnection,
+    codec: &RtpCodecCapability,
+    timeout: Duration,
+) -> Result<CodecImplementation, String> {
+    let mut sender_candidates = forward_candidates(sender);
+    let mut receiver_candidates = forward_candidates(receiver);
+
+    let source = NativeVideoSource::new(
+        VideoResolution {
+            width: TEST_WIDTH,
+            height: TEST_HEIGHT,
+        },
+        true,
+    );
+    let track = factory.create_video_track("probe", source.clone());
+    let transceiver = sender
+        .add_transceiver(
+            track.into(),
+            RtpTransceiverInit {
+                direction: RtpTransceiverDirection::SendOnly,
+                stream_ids: vec!["probe".to_string()],
+                send_encodings: vec![],
+            },
+        )
+        .map_err(|e| format!("failed to add the track: {}", e))?;
+    transceiver
+        .set_codec_preferences(vec![codec.clone()])
+        .map_err(|e| format!("failed to select the codec: {}", e))?;
+
+    let negotiation = async {
+        let offer = sender.create_offer(OfferOptions::default()).await?;
+        sender.set_local_description(offer.clone()).await?;
+        receiver.set_remote_description(offer).await?;
+        let answer = receiver.create_answer(AnswerOptions::default()).await?;
+        receiver.set_local_description(answer.clone()).await?;
+        sender.set_remote_description(answer).await
+    };
+    negotiation
+        .await
+        .map_err(|e| format!("negotiation failed: {}", e))?;
+
+    let mut frame = VideoFrame {
+        rotation: VideoRotation::VideoRotation0,
+        buffer: NV12Buffer::new(TEST_WIDTH, TEST_HEIGHT),
+        timestamp_us: 0,
+    };
+    let started = Instant::now();
+    let mut implementation = CodecImplementation {
+        encoder: String::new(),
+        hardware_encoder: false,
+        decoder: String::new(),
+        hardware_decoder: false,
+    };
+    let mut frames: u64 = 0;
+    while started.elapsed() < timeout {
+        add_candidates(receiver, &mut sender_candidates).await;
+        add_candidates(sender, &mut receiver_candidates).await;
+
+        /* A changing picture, so the encoder has something to do. */
+        let (y, _) = frame.buffer.data_mut();
+        y.fill((frames % 200) as u8 + 16);
+        frame.timestamp_us = started.elapsed().as_micros() as i64;
+        source.capture_frame(&frame);
+        frames += 1;
+
+        if frames % 15 == 0 {
+            for stat in sender.get_stats().await.unwrap_or_default() {
+                if let RtcStats::OutboundRtp(stats) = stat {
+                    if !stats.outbound.encoder_implementation.is_empty() {
+                        implementation.encoder = stats.outbound.encoder_implementation;
+                        implementation.hardware_encoder = stats.outbound.power_efficient_encoder;
+                    }
+                }
+            }
+            for stat in receiver.get_stats().await.unwrap_or_default() {
+                if let RtcStats::InboundRtp(stats) = stat {
+                    if !stats.inbound.decoder_implementation.is_empty() {
+                        implementation.decoder = stats.inbound.decoder_implementation;
+                        implementation.hardware_decoder = stats.inbound.power_efficient_decoder;
+                    }
+                }
+            }
+            if !implementation.encoder.is_empty() && !implementation.decoder.is_empty() {
+                return Ok(implementation);
+            }
+        }
+        tokio::time::sleep(FRAME_INTERVAL).await;
+    }
+    if implementation.encoder.is_empty() {
+        return Err(format!("nothing encoded in {} s", timeout.as_secs()));
+    }
+    Ok(implementation)
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
+}
+
+/// The capabilities as a table, one row per codec and profile.
+pub fn capability_table(capabilities: &[CodecCapability]) -> String {
+    let mut rows = vec![[
+        "Codec".to_string(),
+        "Profile".to_string(),
+        "Encode".to_string(),
+        "Decode".to_string(),
+        "Encoder".to_string(),
+        "HW".to_string(),
+        "Decoder".to_string(),
+        "HW".to_string(),
+    ]];
+    for capability in capabilities {
+        let (encoder, hardware_encoder, decoder, hardware_decoder) =
+            match &capability.implementation {
+                None => ("-".to_string(), "-", "-".to_string(), "-"),
+                Some(Err(e)) => (format!("failed: {}", e), "-", "-".to_string(), "-"),
+                Some(Ok(implementation)) if implementation.decoder.is_empty() => (
+                    implementation.encoder.clone(),
+                    yes_no(implementation.hardware_encoder),
+                    "-".to_string(),
+                    "-",
+                ),
+                Some(Ok(implementation)) => (
+                    implementation.encoder.clone(),
+                    yes_no(implementation.hardware_encoder),
+                    implementation.decoder.clone(),
+                    yes_no(implementation.hardware_decoder),
+                ),
+            };
+        rows.push([
+            capability.codec.clone(),
+            if capability.profile.is_empty() {
+                "-".to_string()
+            } else {
+                capability.profile.clone()
+            },
+            yes_no(capability.encode).to_string(),
+            yes_no(capability.decode).to_string(),
+            encoder,
+            hardware_encoder.to_string(),
+            decoder,
+            hardware_decoder.to_string(),
+        ]);
+    }
+    let widths: Vec<usize> = (0..8)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The capabilities as JSON, with `machine` describing where they were probed.
+pub fn capability_json(
+    capabilities: &[CodecCapability],
+    machine: serde_json::Value,
+) -> serde_json::Value {
+    let codecs = capabilities
+        .iter()
+        .map(|capability| {
+            let mut codec = serde_json::json!({
+                "codec": capability.codec,
+                "profile": capability.profile,
+                "fmtp": capability.fmtp,
+                "encode": capability.encode,
+                "decode": capability.decode,
+            });
+            match &capability.implementation {
+                None => {}
+                Some(Err(e)) => codec["error"] = serde_json::json!(e),
+                Some(Ok(implementation)) => {
+                    codec["encoder"] = serde_json::json!(implementation.encoder);
+                    codec["hardware_encoder"] = serde_json::json!(implementation.hardware_encoder);
+                    codec["decoder"] = serde_json::json!(implementation.decoder);
+                    codec["hardware_decoder"] = serde_json::json!(implementation.hardware_decoder);
+                }
+            }
+            codec
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "machine": machine,
+        "codecs": codecs,
+    })
+}
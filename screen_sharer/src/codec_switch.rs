@@ -0,0 +1,183 @@
+use livekit::options::VideoCodec;
+use std::time::{Duration, Instant};
+use sysinfo::System;
+
+/*
+ * Codec switches during a run, as a call migrating to another codec when a
+ * participant without support for the current one joins. A published
+ * track can't change its codec, so the tracks are unpublished and
+ * published again with the new one, the same as a frame rate change. The
+ * publisher times the switch until the stats show the new codec being
+ * sent and samples the process CPU usage around it, the client records
+ * the gap until the first frame of the new track.
+ */
+
+/* After this the switch is given up on and recorded as such. */
+const SWITCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn parse_video_codec(s: &str) -> Result<VideoCodec, String> {
+    match s.to_uppercase().as_str() {
+        "VP8" => Ok(VideoCodec::VP8),
+        "VP9" => Ok(VideoCodec::VP9),
+        "H264" => Ok(VideoCodec::H264),
+        "AV1" => Ok(VideoCodec::AV1),
+        "H265" => Ok(VideoCodec::H265),
+        _ => Err(format!(
+            "Invalid codec: {}. Use VP8, VP9, H264, H265, or AV1",
+            s
+        )),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodecStep {
+    /// Time since the start of the run.
+    pub at: Duration,
+    pub codec: VideoCodec,
+}
+
+/// Codec switches parsed from `<seconds>:<codec>,...`, e.g. `20:AV1,40:VP9`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodecSchedule(pub Vec<CodecStep>);
+
+impl std::str::FromStr for CodecSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut steps = s
+            .split(',')
+            .map(|entry| {
+                let (at, codec) = entry.split_once(':').ok_or_else(|| {
+                    format!("Invalid codec switch: {}. Use <seconds>:<codec>", entry)
+                })?;
+                let at = at
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid codec switch time: {}", at))?;
+                Ok(CodecStep {
+                    at: Duration::from_secs(at),
+                    codec: parse_video_codec(codec.trim())?,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        steps.sort_by_key(|step| step.at);
+        Ok(CodecSchedule(steps))
+    }
+}
+
+impl CodecSchedule {
+    /// The schedule as given, for the metadata.
+    pub fn label(&self) -> String {
+        self.0
+            .iter()
+            .map(|step| format!("{}:{:?}", step.at.as_secs(), step.codec))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// How a switch went, see `CodecSwitch::finish`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SwitchOutcome {
+    /// The new codec is sent, after this long.
+    Sending(Duration),
+    /// Still not sent after `SWITCH_TIMEOUT`.
+    TimedOut,
+}
+
+/* A switch in progress. */
+struct Switch {
+    from: VideoCodec,
+    to: VideoCodec,
+    started: Instant,
+    /* Process CPU usage before the switch and the highest since, %. */
+    before: f32,
+    peak: f32,
+}
+
+/// Samples the process CPU usage, once a second, so a switch can be
+/// compared with the usage before it.
+pub struct CodecSwitch {
+    system: System,
+    pid: usize,
+    cpu_usage: f32,
+    current: Option<Switch>,
+}
+
+impl Default for CodecSwitch {
+    fn default() -> Self {
+        CodecSwitch {
+            system: System::new(),
+            pid: std::process::id() as usize,
+            cpu_usage: 0.,
+            current: None,
+        }
+    }
+}
+
+impl CodecSwitch {
+    pub fn sample(&mut self) {
+        self.cpu_usage = crate::process_cpu_usage(&mut self.system, self.pid);
+        if let Some(switch) = &mut self.current {
+            switch.peak = switch.peak.max(self.cpu_usage);
+        }
+    }
+
+    /// Starts timing a switch, the event detail for its start.
+    pub fn start(&mut self, from: VideoCodec, to: VideoCodec) -> String {
+        let detail = format!("{:?} to {:?}", from, to);
+        self.current = Some(Switch {
+            from,
+            to,
+            started: Instant::now(),
+            before: self.cpu_usage,
+            peak: self.cpu_usage,
+        });
+        detail
+    }
+
+    /// Drops the switch in progress, e.g. when its tracks couldn't be published.
+    pub fn cancel(&mut self) {
+        self.current = None;
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Ends the switch in progress once `sending` is true or it timed out,
+    /// with the event detail.
+    pub fn finish(&mut self, sending: bool) -> Option<(SwitchOutcome, String)> {
+        let switch = self.current.as_ref()?;
+        let elapsed = switch.started.elapsed();
+        let outcome = if sending {
+            SwitchOutcome::Sending(elapsed)
+        } else if elapsed >= SWITCH_TIMEOUT {
+            SwitchOutcome::TimedOut
+        } else {
+            return None;
+        };
+        let switch = self.current.take()?;
+        let cpu = format!(
+            "CPU peak {:.1}% (before {:.1}%)",
+            switch.peak, switch.before
+        );
+        let detail = match outcome {
+            SwitchOutcome::Sending(elapsed) => format!(
+                "{:?} to {:?}, sending after {} ms, {}",
+                switch.from,
+                switch.to,
+                elapsed.as_millis(),
+                cpu
+            ),
+            SwitchOutcome::TimedOut => format!(
+                "{:?} to {:?}, not sent after {} s, {}",
+                switch.from,
+                switch.to,
+                SWITCH_TIMEOUT.as_secs(),
+                cpu
+            ),
+        };
+        Some((outcome, detail))
+    }
+}
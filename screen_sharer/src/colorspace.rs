@@ -0,0 +1,67 @@
+use livekit::webrtc::native::yuv_helper;
+
+/*
+ * Byte order of the captured pixels. libwebrtc's desktop frames are BGRA
+ * on every platform (DXGI, ScreenCaptureKit, X11 and PipeWire, which it
+ * converts), some Linux capture paths hand over RGBA instead. Converting
+ * one as the other swaps red and blue, which the encoders don't notice but
+ * which changes the content they get, so the order is explicit.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PixelFormat {
+    /// B, G, R, A in memory, libyuv's ARGB.
+    #[default]
+    Bgra,
+    /// R, G, B, A in memory, libyuv's ABGR.
+    Rgba,
+}
+
+impl std::str::FromStr for PixelFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bgra" => Ok(PixelFormat::Bgra),
+            "rgba" => Ok(PixelFormat::Rgba),
+            _ => Err(format!("Invalid pixel format: {}. Use 'bgra' or 'rgba'", s)),
+        }
+    }
+}
+
+impl PixelFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PixelFormat::Bgra => "bgra",
+            PixelFormat::Rgba => "rgba",
+        }
+    }
+
+    /// Converts a frame of 4 byte pixels in this format to NV12.
+    #[allow(clippy::too_many_arguments)]
+    pub fn to_nv12(
+        &self,
+        src: &[u8],
+        src_stride: u32,
+        dst_y: &mut [u8],
+        dst_stride_y: u32,
+        dst_uv: &mut [u8],
+        dst_stride_uv: u32,
+        width: i32,
+        height: i32,
+    ) {
+        let convert = match self {
+            PixelFormat::Bgra => yuv_helper::argb_to_nv12,
+            PixelFormat::Rgba => yuv_helper::abgr_to_nv12,
+        };
+        convert(
+            src,
+            src_stride,
+            dst_y,
+            dst_stride_y,
+            dst_uv,
+            dst_stride_uv,
+            width,
+            height,
+        );
+    }
+}
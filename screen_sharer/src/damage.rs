@@ -0,0 +1,69 @@
+/*
+ * How much of the screen changed between two captured frames. Encoder
+ * efficiency on screen content depends on the change rate: a static
+ * document costs next to nothing, a scrolling page or a video a lot. The
+ * capturer API of the SDK doesn't expose the updated region libwebrtc
+ * tracks, so frames are compared with the previous one in blocks of
+ * `BLOCK` pixels. Unchanged rows cost one comparison, only changed rows are
+ * compared per block and copied.
+ */
+const BLOCK: usize = 16;
+
+#[derive(Default)]
+pub struct DamageTracker {
+    /* Previous frame, BGRA rows packed without padding. */
+    previous: Vec<u8>,
+    width: usize,
+    height: usize,
+    /* Changed blocks of the current band of rows. */
+    dirty: Vec<bool>,
+}
+
+impl DamageTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compares a BGRA frame with the previous one and returns the pixels
+    /// of the blocks that changed. The first frame, and the first one after
+    /// a size change, counts as fully damaged.
+    pub fn update(&mut self, data: &[u8], stride: u32, width: i32, height: i32) -> u64 {
+        let (width, height, stride) = (width as usize, height as usize, stride as usize);
+        let row_bytes = width * 4;
+        if (width, height) != (self.width, self.height) {
+            self.previous.resize(row_bytes * height, 0);
+            for (y, row) in self.previous.chunks_exact_mut(row_bytes).enumerate() {
+                row.copy_from_slice(&data[y * stride..y * stride + row_bytes]);
+            }
+            self.width = width;
+            self.height = height;
+            self.dirty = vec![false; width.div_ceil(BLOCK)];
+            return (width * height) as u64;
+        }
+
+        let block_bytes = BLOCK * 4;
+        let mut damaged = 0;
+        for band in (0..height).step_by(BLOCK) {
+            let band_height = BLOCK.min(height - band);
+            self.dirty.fill(false);
+            for y in band..band + band_height {
+                let row = &data[y * stride..y * stride + row_bytes];
+                let previous = &mut self.previous[y * row_bytes..(y + 1) * row_bytes];
+                if row == previous {
+                    continue;
+                }
+                for (block, dirty) in self.dirty.iter_mut().enumerate() {
+                    let start = block * block_bytes;
+                    let end = (start + block_bytes).min(row_bytes);
+                    *dirty = *dirty || row[start..end] != previous[start..end];
+                }
+                previous.copy_from_slice(row);
+            }
+            for (block, _) in self.dirty.iter().enumerate().filter(|(_, dirty)| **dirty) {
+                let block_width = BLOCK.min(width - block * BLOCK);
+                damaged += (block_width * band_height) as u64;
+            }
+        }
+        damaged
+    }
+}
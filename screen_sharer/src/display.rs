@@ -0,0 +1,319 @@
+/*
+ * Scale factor, physical and logical size and refresh rate of the shared
+ * screens. A "1080p" capture of a HiDPI screen is 2160p content scaled
+ * down, thin text and sharp edges everywhere, which encodes quite unlike
+ * the same resolution from a standard screen, so runs are only comparable
+ * with these alongside.
+ *
+ * On macOS the source id is the CoreGraphics display id, the display mode
+ * has the pixel size and the refresh rate, the display bounds the size in
+ * points. On Linux the X11 capturer titles its screens with the RandR
+ * monitor names, which `xrandr --listmonitors` prints too, so the screens
+ * are looked up by name and the primary monitor stands for the screen the
+ * capturer picks itself. The scale comes from the `Xft.dpi` resource (96
+ * dpi is 1x), which is what toolkits scale X11 applications by; it is one
+ * setting for the whole desktop, not one per screen.
+ */
+use crate::SourceInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayInfo {
+    /// Physical pixels per logical pixel, 2 on a Retina screen.
+    pub scale_factor: f64,
+    /// The scale factor is a desktop wide setting rather than the
+    /// screen's own, `Xft.dpi` on X11.
+    pub scale_global: bool,
+    /// Size of the screen in pixels, what the capturer delivers.
+    pub physical: (u32, u32),
+    /// Size of the screen in points, what the user interface is laid out in.
+    pub logical: (u32, u32),
+    /// NaN when the platform doesn't report it, e.g. some virtual displays.
+    pub refresh_hz: f64,
+}
+
+impl DisplayInfo {
+    /// One line for the logs.
+    pub fn summary(&self) -> String {
+        format!(
+            "{}x{} pixels, {}x{} logical (scale {:.2}), {:.2} Hz",
+            self.physical.0,
+            self.physical.1,
+            self.logical.0,
+            self.logical.1,
+            self.scale_factor,
+            self.refresh_hz
+        )
+    }
+}
+
+/// The display behind `source`, or the main one when the capturer picks
+/// the screen itself. None where it can't be told.
+pub fn display_info(source: Option<&SourceInfo>) -> Option<DisplayInfo> {
+    #[cfg(target_os = "macos")]
+    {
+        macos::display_info(source.map(|source| source.id))
+    }
+    #[cfg(target_os = "linux")]
+    {
+        x11::display_info(source.map(|source| source.title.as_str()))
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        let _ = source;
+        None
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use super::DisplayInfo;
+    use std::ffi::c_void;
+
+    #[repr(C)]
+    struct CGPoint {
+        x: f64,
+        y: f64,
+    }
+
+    #[repr(C)]
+    struct CGSize {
+        width: f64,
+        height: f64,
+    }
+
+    #[repr(C)]
+    struct CGRect {
+        origin: CGPoint,
+        size: CGSize,
+    }
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    unsafe extern "C" {
+        fn CGMainDisplayID() -> u32;
+        fn CGDisplayBounds(display: u32) -> CGRect;
+        fn CGDisplayCopyDisplayMode(display: u32) -> *mut c_void;
+        fn CGDisplayModeGetPixelWidth(mode: *mut c_void) -> usize;
+        fn CGDisplayModeGetPixelHeight(mode: *mut c_void) -> usize;
+        fn CGDisplayModeGetRefreshRate(mode: *mut c_void) -> f64;
+        fn CGDisplayModeRelease(mode: *mut c_void);
+    }
+
+    pub fn display_info(id: Option<u64>) -> Option<DisplayInfo> {
+        let display = match id {
+            Some(id) => u32::try_from(id).ok()?,
+            None => unsafe { CGMainDisplayID() },
+        };
+        let bounds = unsafe { CGDisplayBounds(display) };
+        let mode = unsafe { CGDisplayCopyDisplayMode(display) };
+        if mode.is_null() {
+            return None;
+        }
+        let (width, height, refresh_hz) = unsafe {
+            let size = (
+                CGDisplayModeGetPixelWidth(mode) as u32,
+                CGDisplayModeGetPixelHeight(mode) as u32,
+                CGDisplayModeGetRefreshRate(mode),
+            );
+            CGDisplayModeRelease(mode);
+            size
+        };
+        let logical = (bounds.size.width as u32, bounds.size.height as u32);
+        if logical.0 == 0 {
+            return None;
+        }
+        Some(DisplayInfo {
+            scale_factor: width as f64 / logical.0 as f64,
+            scale_global: false,
+            physical: (width, height),
+            logical,
+            /* Built-in panels report 0, they run at a variable rate. */
+            refresh_hz: if refresh_hz > 0. {
+                refresh_hz
+            } else {
+                f64::NAN
+            },
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use super::DisplayInfo;
+    use std::process::Command;
+
+    const BASE_DPI: f64 = 96.;
+
+    fn output(program: &str, args: &[&str]) -> Option<String> {
+        let output = Command::new(program).args(args).output().ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /* A line of `xrandr --listmonitors`. */
+    #[derive(Debug, PartialEq)]
+    struct Monitor {
+        name: String,
+        primary: bool,
+        width: u32,
+        height: u32,
+        /* The first output the monitor shows, whose mode has the refresh rate. */
+        output: String,
+    }
+
+    /*
+     * ` 0: +*eDP-1 2880/344x1800/215+0+0  eDP-1`, the monitor name marked
+     * `+` when RandR made it for an output and `*` when it is the primary,
+     * the size in pixels and millimeters, then the position, then the
+     * outputs shown on it.
+     */
+    fn parse_monitor(line: &str) -> Option<Monitor> {
+        let mut fields = line.split_whitespace().skip(1);
+        let name = fields.next()?.trim_start_matches('+');
+        let (primary, name) = match name.strip_prefix('*') {
+            Some(name) => (true, name),
+            None => (false, name),
+        };
+        let geometry = fields.next()?;
+        let output = fields.next()?.to_string();
+        let (width, rest) = geometry.split_once('x')?;
+        let width = width.split('/').next()?.parse().ok()?;
+        let height = rest.split(['/', '+']).next()?.parse().ok()?;
+        Some(Monitor {
+            name: name.to_string(),
+            primary,
+            width,
+            height,
+            output,
+        })
+    }
+
+    /*
+     * The monitor titled `title` by the capturer, or the primary one when
+     * the capturer picks the screen itself. None when no monitor has the
+     * title, e.g. under a Wayland session.
+     */
+    fn find_monitor(monitors: &str, title: Option<&str>) -> Option<Monitor> {
+        let mut monitors = monitors.lines().skip(1).filter_map(parse_monitor);
+        match title {
+            Some(title) => monitors.find(|monitor| monitor.name == title),
+            None => monitors.find(|monitor| monitor.primary),
+        }
+    }
+
+    /*
+     * The current mode of `output` in `xrandr --query`, the rate marked
+     * with `*` on the mode lines after the output's line, e.g.
+     * `   2880x1800     60.00*+  59.95`.
+     */
+    fn refresh_rate(query: &str, output: &str) -> Option<f64> {
+        query
+            .lines()
+            .skip_while(|line| !line.starts_with(&format!("{} ", output)))
+            .skip(1)
+            .take_while(|line| line.starts_with(' '))
+            .flat_map(|line| line.split_whitespace().skip(1))
+            .find(|rate| rate.contains('*'))?
+            .trim_end_matches(['*', '+'])
+            .parse()
+            .ok()
+    }
+
+    fn scale_factor() -> f64 {
+        output("xrdb", &["-query"])
+            .and_then(|resources| {
+                resources.lines().find_map(|line| {
+                    let dpi = line.strip_prefix("Xft.dpi:")?;
+                    dpi.trim().parse::<f64>().ok()
+                })
+            })
+            .map_or(1., |dpi| dpi / BASE_DPI)
+    }
+
+    pub fn display_info(title: Option<&str>) -> Option<DisplayInfo> {
+        let monitors = output("xrandr", &["--listmonitors"])?;
+        let Monitor {
+            width,
+            height,
+            output: name,
+            ..
+        } = find_monitor(&monitors, title)?;
+        let refresh_hz = output("xrandr", &["--query"])
+            .and_then(|query| refresh_rate(&query, &name))
+            .unwrap_or(f64::NAN);
+        let scale_factor = scale_factor();
+        Some(DisplayInfo {
+            scale_factor,
+            scale_global: true,
+            physical: (width, height),
+            logical: (
+                (width as f64 / scale_factor).round() as u32,
+                (height as f64 / scale_factor).round() as u32,
+            ),
+            refresh_hz,
+        })
+    }
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const MONITORS: &str = "Monitors: 2
+ 0: +*eDP-1 2880/344x1800/215+0+0  eDP-1
+ 1: +HDMI-1 1920/531x1080/299+2880+0  HDMI-1
+";
+
+        const QUERY: &str =
+            "Screen 0: minimum 320 x 200, current 4800 x 1800, maximum 16384 x 16384
+eDP-1 connected primary 2880x1800+0+0 (normal left inverted right x axis y axis) 344mm x 215mm
+   2880x1800     60.00*+  59.95
+   1920x1200     59.88
+HDMI-1 connected 1920x1080+2880+0 (normal left inverted right x axis y axis) 531mm x 299mm
+   1920x1080     60.00 +  74.97*   50.00
+   1280x720      60.00
+DP-1 disconnected (normal left inverted right x axis y axis)
+";
+
+        #[test]
+        fn parses_monitors() {
+            assert_eq!(
+                parse_monitor(" 0: +*eDP-1 2880/344x1800/215+0+0  eDP-1"),
+                Some(Monitor {
+                    name: "eDP-1".to_string(),
+                    primary: true,
+                    width: 2880,
+                    height: 1800,
+                    output: "eDP-1".to_string(),
+                })
+            );
+            assert_eq!(
+                parse_monitor(" 1: Wide 3840/1062x1080/299+0+0  HDMI-1 DP-1"),
+                Some(Monitor {
+                    name: "Wide".to_string(),
+                    primary: false,
+                    width: 3840,
+                    height: 1080,
+                    output: "HDMI-1".to_string(),
+                })
+            );
+            assert_eq!(parse_monitor("Monitors: 2"), None);
+            assert_eq!(parse_monitor(" 0: +eDP-1 garbage eDP-1"), None);
+        }
+
+        #[test]
+        fn finds_monitors_by_title() {
+            let hdmi = find_monitor(MONITORS, Some("HDMI-1")).unwrap();
+            assert_eq!((hdmi.width, hdmi.height), (1920, 1080));
+            assert_eq!(find_monitor(MONITORS, None).unwrap().name, "eDP-1");
+            assert_eq!(find_monitor(MONITORS, Some("Screen 1")), None);
+        }
+
+        #[test]
+        fn reads_the_current_refresh_rate() {
+            assert_eq!(refresh_rate(QUERY, "eDP-1"), Some(60.));
+            assert_eq!(refresh_rate(QUERY, "HDMI-1"), Some(74.97));
+            assert_eq!(refresh_rate(QUERY, "DP-1"), None);
+            assert_eq!(refresh_rate(QUERY, "eDP"), None);
+        }
+    }
+}
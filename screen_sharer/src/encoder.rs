@@ -0,0 +1,78 @@
+use livekit::options::VideoCodec;
+
+/* The H264 profiles of a negotiated `profile-level-id`. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum H264Profile {
+    /* Constrained baseline, what libwebrtc offers first. */
+    Baseline,
+    Main,
+    High,
+}
+
+impl H264Profile {
+    fn as_str(&self) -> &'static str {
+        match self {
+            H264Profile::Baseline => "baseline",
+            H264Profile::Main => "main",
+            H264Profile::High => "high",
+        }
+    }
+
+    fn from_profile_iop(profile_idc: u8, iop: u8) -> Option<Self> {
+        match (profile_idc, iop & 0x40 != 0) {
+            (0x42, _) => Some(H264Profile::Baseline),
+            (0x4d, true) => Some(H264Profile::Baseline),
+            (0x4d, false) => Some(H264Profile::Main),
+            (0x64, _) => Some(H264Profile::High),
+            _ => None,
+        }
+    }
+}
+
+/* An H264 level as its `level_idc`, 31 for 3.1. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct H264Level(u8);
+
+impl std::fmt::Display for H264Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.0 % 10 {
+            0 => write!(f, "{}", self.0 / 10),
+            minor => write!(f, "{}.{}", self.0 / 10, minor),
+        }
+    }
+}
+
+/// The codec a track is actually sent with, read back from the outbound
+/// RTP and codec stats. libwebrtc silently falls back to another codec,
+/// usually VP8, when there is no encoder for the requested one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentCodec {
+    /// The codec of the mime type, e.g. `VP8` for `video/VP8`.
+    pub name: String,
+    pub fmtp: String,
+    /// The encoder libwebrtc picked, e.g. `libvpx` or `VideoToolbox`.
+    pub implementation: String,
+}
+
+impl SentCodec {
+    pub fn matches(&self, requested: &VideoCodec) -> bool {
+        self.name.eq_ignore_ascii_case(&format!("{:?}", requested))
+    }
+}
+
+/// The profile named by the SDP format parameters of a negotiated codec,
+/// e.g. `high 3.1` for `level-asymmetry-allowed=1;profile-level-id=64001f`.
+pub fn describe_fmtp(fmtp: &str) -> Option<String> {
+    for parameter in fmtp.split(';') {
+        match parameter.trim().split_once('=') {
+            Some(("profile-id", profile)) => return Some(format!("profile {}", profile)),
+            Some(("profile-level-id", id)) if id.len() == 6 => {
+                let byte = |i: usize| u8::from_str_radix(&id[i..i + 2], 16).ok();
+                let profile = H264Profile::from_profile_iop(byte(0)?, byte(2)?)?;
+                return Some(format!("{} {}", profile.as_str(), H264Level(byte(4)?)));
+            }
+            _ => {}
+        }
+    }
+    None
+}
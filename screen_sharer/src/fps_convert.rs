@@ -0,0 +1,208 @@
+use livekit::webrtc::prelude::NV12Buffer;
+
+/*
+ * Frame rate conversion between capture and publish, e.g. a 60 Hz capture
+ * published at 30 fps. Dropping keeps every n-th frame, as most screen
+ * sharing apps do, so fast motion jumps between the published frames.
+ * Blending averages the frames of each published frame instead, which
+ * smears the motion; the blurred edges cost the encoders differently than
+ * the sharp jumps. Frames are assigned by their arrival time: a frame is
+ * published once the next published frame is due, so an irregular or slow
+ * capture still publishes at the target as long as it delivers that many
+ * frames, and one that falls behind doesn't publish a burst to catch up.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FpsMethod {
+    /// Publish one captured frame per published frame, skip the others.
+    Drop,
+    /// Publish the average of the captured frames of each published frame.
+    Blend,
+}
+
+impl std::str::FromStr for FpsMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "drop" => Ok(FpsMethod::Drop),
+            "blend" => Ok(FpsMethod::Blend),
+            _ => Err(format!(
+                "Invalid frame rate conversion: {}. Use 'drop' or 'blend'",
+                s
+            )),
+        }
+    }
+}
+
+impl FpsMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FpsMethod::Drop => "drop",
+            FpsMethod::Blend => "blend",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FpsConversion {
+    /// Rate the screen is captured at.
+    pub capture_fps: u32,
+    /// Rate the frames are published at, at most `capture_fps`.
+    pub publish_fps: u32,
+    pub method: FpsMethod,
+}
+
+/* Per capture pipeline, sees every captured frame in order. */
+pub struct FpsConverter {
+    conversion: FpsConversion,
+    /* When the next published frame is due, µs, from the first frame on. */
+    next_due: Option<f64>,
+    /* Sums of the planes of the frames blended so far, and their count. */
+    sum_y: Vec<u16>,
+    sum_uv: Vec<u16>,
+    blended: u16,
+}
+
+impl FpsConverter {
+    pub fn new(conversion: FpsConversion) -> Self {
+        FpsConverter {
+            conversion,
+            next_due: None,
+            sum_y: vec![],
+            sum_uv: vec![],
+            blended: 0,
+        }
+    }
+
+    pub fn method(&self) -> FpsMethod {
+        self.conversion.method
+    }
+
+    /// Advances by one captured frame that arrived at `timestamp_us`,
+    /// whether it is the last one of a published frame.
+    pub fn advance(&mut self, timestamp_us: u64) -> bool {
+        let period = 1_000_000. / self.conversion.publish_fps as f64;
+        /* A frame a little early for its slot still takes it, captures jitter. */
+        let tolerance = 1_000_000. / self.conversion.capture_fps as f64 / 4.;
+        let timestamp = timestamp_us as f64;
+        let due = *self.next_due.get_or_insert(timestamp);
+        if timestamp + tolerance < due {
+            return false;
+        }
+        /* Missed slots are skipped, not made up for. */
+        self.next_due = Some(if timestamp - due >= period {
+            timestamp + period
+        } else {
+            due + period
+        });
+        true
+    }
+
+    /// Adds the frame in `buffer` to the blend. With `last` the average of
+    /// the frames since the previous published one is written into it.
+    pub fn blend(&mut self, buffer: &mut NV12Buffer, last: bool) {
+        let (y, uv) = buffer.data_mut();
+        /* The stream was resized, the frames blended so far don't fit. */
+        if (self.sum_y.len(), self.sum_uv.len()) != (y.len(), uv.len()) {
+            self.sum_y = vec![0; y.len()];
+            self.sum_uv = vec![0; uv.len()];
+            self.blended = 0;
+        }
+        if self.blended == 0 && last {
+            return;
+        }
+        for (sum, value) in self.sum_y.iter_mut().zip(y.iter()) {
+            *sum += *value as u16;
+        }
+        for (sum, value) in self.sum_uv.iter_mut().zip(uv.iter()) {
+            *sum += *value as u16;
+        }
+        self.blended += 1;
+        if !last {
+            return;
+        }
+        let (count, half) = (self.blended, self.blended / 2);
+        for (value, sum) in y.iter_mut().zip(self.sum_y.iter_mut()) {
+            *value = ((*sum + half) / count) as u8;
+            *sum = 0;
+        }
+        for (value, sum) in uv.iter_mut().zip(self.sum_uv.iter_mut()) {
+            *value = ((*sum + half) / count) as u8;
+            *sum = 0;
+        }
+        self.blended = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn converter(capture_fps: u32, publish_fps: u32) -> FpsConverter {
+        FpsConverter::new(FpsConversion {
+            capture_fps,
+            publish_fps,
+            method: FpsMethod::Drop,
+        })
+    }
+
+    /* Indices of the published frames among `frames` captured at `capture_fps`. */
+    fn published(converter: &mut FpsConverter, capture_fps: u64, frames: u64) -> Vec<u64> {
+        (0..frames)
+            .filter(|i| converter.advance(i * 1_000_000 / capture_fps))
+            .collect()
+    }
+
+    #[test]
+    fn drops_every_other_frame_from_60_to_30() {
+        let published = published(&mut converter(60, 30), 60, 12);
+        assert_eq!(published, [0, 2, 4, 6, 8, 10]);
+    }
+
+    #[test]
+    fn alternates_three_and_two_from_60_to_24() {
+        let published = published(&mut converter(60, 24), 60, 60);
+        assert_eq!(published[..5], [0, 3, 5, 8, 10]);
+        assert_eq!(published.len(), 24);
+    }
+
+    #[test]
+    fn publishes_at_the_target_from_a_jittery_capture() {
+        let mut converter = converter(60, 30);
+        let published = (0..60u64)
+            .map(|i| i * 1_000_000 / 60 + if i % 2 == 0 { 3_000 } else { 0 })
+            .filter(|timestamp| converter.advance(*timestamp))
+            .count();
+        assert_eq!(published, 30);
+    }
+
+    #[test]
+    fn skips_the_slots_a_stalled_capture_missed() {
+        let mut converter = converter(60, 30);
+        assert!(converter.advance(0));
+        /* 200 ms without frames, the next one is published and the one after isn't. */
+        assert!(converter.advance(200_000));
+        assert!(!converter.advance(216_666));
+        assert!(converter.advance(233_333));
+    }
+
+    #[test]
+    fn blends_the_average_of_the_frames() {
+        let mut converter = FpsConverter::new(FpsConversion {
+            capture_fps: 60,
+            publish_fps: 20,
+            method: FpsMethod::Blend,
+        });
+        let mut buffer = NV12Buffer::new(4, 2);
+        for (luma, last) in [(10, false), (20, false), (41, true)] {
+            let (y, uv) = buffer.data_mut();
+            y.fill(luma);
+            uv.fill(128);
+            converter.blend(&mut buffer, last);
+        }
+        let (y, uv) = buffer.data_mut();
+        /* (10 + 20 + 41) / 3 rounded. */
+        assert!(y.iter().all(|luma| *luma == 24));
+        assert!(uv.iter().all(|chroma| *chroma == 128));
+    }
+}
@@ -0,0 +1,54 @@
+/*
+ * Encoded frame sizes between two stats samples. On screen content a key
+ * frame is often tens of times a delta frame, which the aggregate bitrate
+ * hides. WebRTC only reports the bytes and frames so far, so the average
+ * frame size is exact while the key frame size is estimated: the bytes of
+ * a sample with key frames, minus its delta frames at the delta frame size
+ * of the last sample without any, spread over its key frames.
+ */
+#[derive(Default)]
+pub struct FrameSizes {
+    /* Delta frame size of the last sample without key frames, bytes. */
+    delta_frame: Option<f64>,
+}
+
+/// Frame sizes of one sample in bytes, NaN when unknown.
+pub struct FrameSize {
+    pub average: f64,
+    pub delta_frame: f64,
+    pub key_frame: f64,
+}
+
+impl FrameSizes {
+    /// Takes the media bytes, frames and key frames encoded since the
+    /// previous sample.
+    pub fn update(&mut self, bytes: f64, frames: f64, key_frames: f64) -> FrameSize {
+        let average = if frames > 0. {
+            bytes / frames
+        } else {
+            f64::NAN
+        };
+        if key_frames == 0. {
+            if frames > 0. {
+                self.delta_frame = Some(average);
+            }
+            return FrameSize {
+                average,
+                delta_frame: average,
+                key_frame: f64::NAN,
+            };
+        }
+        let delta_frames = frames - key_frames;
+        let key_frame = match self.delta_frame {
+            _ if delta_frames <= 0. => bytes / key_frames,
+            Some(delta_frame) => (bytes - delta_frames * delta_frame) / key_frames,
+            None => f64::NAN,
+        };
+        FrameSize {
+            average,
+            delta_frame: self.delta_frame.unwrap_or(f64::NAN),
+            /* Delta frames bigger than usual make the estimate meaningless. */
+            key_frame: if key_frame > 0. { key_frame } else { f64::NAN },
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+/*
+ * Frame rate changes during a run, e.g. 30 to 15 to 60 fps, as a screen
+ * sharing app lowering the rate for static content and raising it again
+ * for video. The capture is paced to each rate while the track stays
+ * published, which shows how quickly the rate control of each encoder and
+ * the jitter buffer of the receiver adapt. The SDK doesn't expose the
+ * encodings of a published track, so the track is published with the
+ * highest rate of the run as its maximum instead, see `FpsSchedule::max_fps`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FpsStep {
+    /// Time since the start of the run.
+    pub at: Duration,
+    pub fps: u32,
+}
+
+/// Frame rate changes parsed from `<seconds>:<fps>,...`, e.g. `20:15,40:60`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FpsSchedule(pub Vec<FpsStep>);
+
+impl std::str::FromStr for FpsSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut steps = s
+            .split(',')
+            .map(|entry| {
+                let (at, fps) = entry.split_once(':').ok_or_else(|| {
+                    format!("Invalid frame rate change: {}. Use <seconds>:<fps>", entry)
+                })?;
+                let at = at
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid frame rate change time: {}", at))?;
+                let fps = fps
+                    .trim()
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|fps| (1..=240).contains(fps))
+                    .ok_or_else(|| format!("Invalid frame rate: {}", fps))?;
+                Ok(FpsStep {
+                    at: Duration::from_secs(at),
+                    fps,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        steps.sort_by_key(|step| step.at);
+        Ok(FpsSchedule(steps))
+    }
+}
+
+impl FpsSchedule {
+    /// Highest frame rate of the steps.
+    pub fn max_fps(&self) -> Option<u32> {
+        self.0.iter().map(|step| step.fps).max()
+    }
+}
+
+/// Wait between two captures at `fps`.
+pub fn capture_interval(fps: u32) -> Duration {
+    Duration::from_micros(1_000_000 / fps.max(1) as u64)
+}
@@ -0,0 +1,44 @@
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/*
+ * Lock-free hand-off of a value between calls of the capture callback.
+ * A call takes the value out of the slot, works on it without holding
+ * anything and puts it back. The capture loop never runs two callbacks at
+ * once, so `take` only comes back empty if a backend ever does, and that
+ * frame is dropped instead of waiting for the other one.
+ */
+pub struct Slot<T> {
+    value: AtomicPtr<T>,
+}
+
+impl<T> Slot<T> {
+    pub fn new(value: T) -> Self {
+        Slot {
+            value: AtomicPtr::new(Box::into_raw(Box::new(value))),
+        }
+    }
+
+    /// Takes the value out, None while another caller holds it.
+    pub fn take(&self) -> Option<Box<T>> {
+        let value = self.value.swap(ptr::null_mut(), Ordering::Acquire);
+        /* The pointer came from `Box::into_raw` and the swap made it ours. */
+        (!value.is_null()).then(|| unsafe { Box::from_raw(value) })
+    }
+
+    /// Returns a value taken with `take`.
+    pub fn put(&self, value: Box<T>) {
+        let previous = self.value.swap(Box::into_raw(value), Ordering::Release);
+        debug_assert!(previous.is_null());
+    }
+}
+
+impl<T> Drop for Slot<T> {
+    fn drop(&mut self) {
+        drop(self.take());
+    }
+}
+
+/* The value is only ever reached by the caller that took it. */
+unsafe impl<T: Send> Send for Slot<T> {}
+unsafe impl<T: Send> Sync for Slot<T> {}
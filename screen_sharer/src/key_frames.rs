@@ -0,0 +1,35 @@
+use crate::EncoderSnapshot;
+
+/*
+ * Key frames of a track over the run. On screen content a key frame costs
+ * many times a delta frame, so how often they come and what each encoder
+ * spends on them weighs a lot on the bitrate. Every key frame the encoder
+ * produced is read from the `key_frames_encoded` counter of the stats
+ * samples, so following them costs no extra stats reads. The SDK has no
+ * call to request one, so they are all the encoder's own.
+ */
+#[derive(Default)]
+pub struct KeyFrameTracker {
+    key_frames: Option<u64>,
+}
+
+impl KeyFrameTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the counters of a stats sample, returns the detail of the
+    /// event to record when key frames were encoded since the last.
+    pub fn update(&mut self, snapshot: &EncoderSnapshot) -> Option<String> {
+        let last = self.key_frames.replace(snapshot.key_frames_encoded);
+        /* The first snapshot is the baseline, a republish restarts the counter. */
+        let count = snapshot.key_frames_encoded.checked_sub(last?)?;
+        if count == 0 {
+            return None;
+        }
+        Some(format!(
+            "{} at {}x{}",
+            count, snapshot.frame_width, snapshot.frame_height
+        ))
+    }
+}
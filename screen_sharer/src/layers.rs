@@ -0,0 +1,118 @@
+use common::delta::Counter;
+use common::sink::Value;
+
+/*
+ * Per layer outbound RTP counters. With simulcast every layer is an RTP
+ * stream of its own with a rid and an ssrc, and the sums in the stats
+ * table hide which layer the bytes went to, or that the top one was
+ * paused. An SVC stream (VP9/AV1 with several spatial layers) is a single
+ * RTP stream, libwebrtc doesn't report its spatial or temporal layers
+ * apart, so it shows up as one layer.
+ */
+#[derive(Debug, Clone)]
+pub struct LayerStats {
+    /// The rid, or `ssrc_<ssrc>` for a stream without one.
+    pub label: String,
+    pub bytes_sent: u64,
+    /// Encoder target, bps.
+    pub target_bitrate: f64,
+    pub frames_encoded: u64,
+    pub key_frames_encoded: u64,
+    /// Encode time so far, s.
+    pub total_encode_time: f64,
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub frames_per_second: f64,
+}
+
+/* Columns written for every layer, suffixed with its label. */
+const LAYER_COLUMNS: [&str; 9] = [
+    "bytes_sent",
+    "send_kbps",
+    "target_kbps",
+    "frames_encoded",
+    "key_frames_encoded",
+    "encode_ms",
+    "frame_width",
+    "frame_height",
+    "fps",
+];
+
+/* Rate counters of one layer across the samples. */
+#[derive(Default)]
+struct LayerCounters {
+    bytes_sent: Counter,
+    frames_encoded: Counter,
+    encode_time: Counter,
+}
+
+/*
+ * Header and rows of the layer table, one row per stats sample with the
+ * columns of every layer seen during the run, in the order they appeared.
+ * A layer missing from a sample, e.g. one not sent yet, is NaN. None when
+ * the track never had more than one layer, the stats table covers it then.
+ */
+pub fn layer_table(samples: &[(u128, &[LayerStats])]) -> Option<(Vec<String>, Vec<Vec<Value>>)> {
+    if samples.iter().all(|(_, layers)| layers.len() < 2) {
+        return None;
+    }
+    let mut labels: Vec<&str> = vec![];
+    for (_, layers) in samples {
+        for layer in layers.iter() {
+            if !labels.contains(&layer.label.as_str()) {
+                labels.push(&layer.label);
+            }
+        }
+    }
+    let mut header = vec!["frame".to_string(), "timestamp".to_string()];
+    for label in &labels {
+        header.extend(
+            LAYER_COLUMNS
+                .iter()
+                .map(|column| format!("{}_{}", column, label)),
+        );
+    }
+
+    let mut counters: Vec<LayerCounters> =
+        labels.iter().map(|_| LayerCounters::default()).collect();
+    let rows = samples
+        .iter()
+        .enumerate()
+        .map(|(i, (timestamp, layers))| {
+            let mut row: Vec<Value> = vec![i.into(), (*timestamp).into()];
+            for (label, counters) in labels.iter().zip(&mut counters) {
+                let Some(layer) = layers.iter().find(|layer| layer.label == *label) else {
+                    row.extend(LAYER_COLUMNS.iter().map(|_| Value::Float(f64::NAN)));
+                    continue;
+                };
+                let sent = counters
+                    .bytes_sent
+                    .update(layer.bytes_sent as f64, *timestamp);
+                let frames = counters
+                    .frames_encoded
+                    .update(layer.frames_encoded as f64, *timestamp);
+                let time = counters
+                    .encode_time
+                    .update(layer.total_encode_time * 1000., *timestamp);
+                let encode_ms = if frames.delta > 0. {
+                    time.delta / frames.delta
+                } else {
+                    f64::NAN
+                };
+                row.extend([
+                    layer.bytes_sent.into(),
+                    Value::float(sent.kbps(), 1),
+                    Value::float(layer.target_bitrate / 1000., 1),
+                    layer.frames_encoded.into(),
+                    layer.key_frames_encoded.into(),
+                    Value::float(encode_ms, 2),
+                    layer.frame_width.into(),
+                    layer.frame_height.into(),
+                    Value::float(layer.frames_per_second, 1),
+                ]);
+            }
+            row
+        })
+        .collect();
+    Some((header, rows))
+}
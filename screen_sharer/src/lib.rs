@@ -1,37 +1,337 @@
+use common::adherence::{BitrateAdherence, bitrate_adherence};
+use common::clock::now_us;
+use common::control::{
+    CONTROL_TOPIC, ControlMessage, LEGACY_WATERMARK, PROTOCOL_VERSION, WATERMARK_FRAMES,
+};
+use common::delta::Counter;
+use common::events::{EVENTS_HEADER, Event, event_rows};
+use common::fingerprint;
+use common::output::{OutputLayout, RUN_ID_TOPIC, RunId, Side};
+use common::sink::{RollingCsv, SinkConfig, SinkKind, Value, create_sinks, write_table};
+use common::system_events::{self, SystemMonitor};
+use common::transport::CandidatePair;
+use common::validity::{RECONNECTED_EVENT, RECONNECTING_EVENT};
+use common::watermark::WATERMARK;
 use livekit::RoomEvent;
+use livekit::prelude::{DataPacket, LocalParticipant, LocalTrackPublication};
 use livekit::track::LocalTrack;
 use livekit::webrtc::desktop_capturer::{
-    CaptureError, DesktopCaptureSourceType, DesktopCapturer, DesktopCapturerOptions, DesktopFrame,
+    CaptureError, CaptureSource, DesktopCaptureSourceType, DesktopCapturer, DesktopCapturerOptions,
+    DesktopFrame,
 };
-use livekit::webrtc::native::yuv_helper;
 use livekit::webrtc::prelude::VideoBuffer;
 use livekit::webrtc::prelude::{NV12Buffer, VideoFrame, VideoResolution, VideoRotation};
 use livekit::webrtc::video_source::native::NativeVideoSource;
 use std::cmp::max;
-use std::fs::File;
-use std::io::Write;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, mpsc};
-use std::time::Instant;
-use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+use std::time::{Duration, Instant};
+use sysinfo::{Networks, Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+
+mod backpressure;
+mod bench;
+mod capabilities;
+mod codec_switch;
+mod colorspace;
+mod damage;
+mod display;
+mod encoder;
+mod fps_convert;
+mod frame_size;
+mod framerate;
+mod handoff;
+mod key_frames;
+mod layers;
+mod mute;
+mod occlusion;
+mod overlay;
+mod permissions;
+mod power;
+mod profiles;
+mod queue;
+mod resize;
+mod retry;
+mod rotation;
+mod runtime;
+mod scale;
+mod static_content;
+mod synthetic;
+mod thermal;
+mod transport;
+mod virtual_display;
+
+pub use bench::{PipelineBench, bench_pipeline};
+pub use capabilities::{
+    CodecCapability, CodecImplementation, capability_json, capability_table, probe_encoders,
+};
+pub use codec_switch::{CodecSchedule, CodecStep, CodecSwitch, SwitchOutcome, parse_video_codec};
+pub use colorspace::PixelFormat;
+pub use display::{DisplayInfo, display_info};
+pub use encoder::{SentCodec, describe_fmtp};
+pub use fps_convert::{FpsConversion, FpsMethod};
+pub use framerate::{FpsSchedule, FpsStep};
+pub use mute::{EncoderSnapshot, MuteSchedule, MuteStep};
+pub use occlusion::{OcclusionMode, OcclusionSchedule, OcclusionStep};
+pub use overlay::{OverlayConfig, OverlayKind};
+pub use profiles::{DEFAULT_PROFILE_FILE, expand_profile, profile_args};
+pub use queue::{DropPolicy, QueueConfig};
+pub use resize::{ResizeSchedule, ResizeStep};
+pub use retry::retry_with_backoff;
+pub use rotation::{Rotation, RotationAngle, RotationMode};
+pub use runtime::AsyncRuntime;
+pub use static_content::{StaticSchedule, StaticStep};
+pub use transport::room_options;
+pub use virtual_display::{DisplaySpec, NO_WORKLOAD, VirtualDisplay, default_workload};
+
+use backpressure::{BackpressureMonitor, Transition};
+use fps_convert::FpsConverter;
+use handoff::Slot;
+use key_frames::KeyFrameTracker;
+use layers::{LayerStats, layer_table};
+use mute::ResumeTracker;
+use damage::DamageTracker;
+use frame_size::FrameSizes;
+use overlay::{BurnIn, MotionOverlay};
+use queue::FrameQueue;
+use runtime::{StatsRead, StatsReader};
+use static_content::StillFrame;
+use synthetic::SyntheticSource;
+use thermal::{ThermalMonitor, ThermalSample};
+
+/// Optional processing applied to every captured frame.
+#[derive(Debug, Clone, Default)]
+pub struct CaptureOptions {
+    /// Synthetic motion composited onto the frames before submission.
+    pub overlay: Option<OverlayConfig>,
+    /// Submit frames at the native source resolution instead of scaling
+    /// them on the CPU, leaving the downscaling to the encoder.
+    pub no_prescale: bool,
+    /// Byte order of the captured pixels, ignored by the synthetic source.
+    pub pixel_format: PixelFormat,
+    /// How long to wait for the first frame of a screen, 5 seconds if unset.
+    pub probe_timeout: Option<Duration>,
+    /// Orientation of the published frames.
+    pub rotation: Rotation,
+    /// Skip comparing every frame with the previous one for the damage
+    /// stats, which costs a little CPU on busy screens.
+    pub no_damage_stats: bool,
+    /// Draw the wall clock time and a frame counter onto every frame.
+    pub burn_in: bool,
+    /// Convert frames on their own thread behind a bounded queue instead
+    /// of in the capture callback.
+    pub queue: Option<QueueConfig>,
+    /// Write a content hash of every frame into a marker strip at the
+    /// bottom, for the client to detect corrupted frames.
+    pub fingerprint: bool,
+    /// Skip frames while the video source takes them slower than they are
+    /// submitted, instead of only reporting it.
+    pub skip_when_behind: bool,
+    /// Capture faster than the frames are published and drop or blend
+    /// the frames in between.
+    pub fps_conversion: Option<FpsConversion>,
+}
 
 pub struct ScreenSharer {
-    capturer: Arc<Mutex<DesktopCapturer>>,
+    capturer: Arc<Mutex<Capturer>>,
     watermark_count: Arc<Mutex<u32>>,
+    /* Id of the last tick requested by the client, 0 before the first one. */
+    tick: Arc<AtomicU64>,
+    /* Cleared while the client has stopped the capture. */
+    capturing: Arc<AtomicBool>,
+    /* Simulated occlusion applied by the capture callback, see `set_occlusion`. */
+    occlusion: Arc<AtomicU8>,
+    /* Set while the capture callback repeats a frame, see `set_static`. */
+    still: Arc<AtomicBool>,
+    counters: Arc<CaptureCounters>,
+    /* Stream resolution the capture callback scales to, see `resize`. */
+    stream_size: Arc<AtomicU64>,
+    no_prescale: bool,
     buffer_source: NativeVideoSource,
     tx: Option<mpsc::Sender<ScreenshareMessage>>,
     source_index: u32,
+    pixel_format: PixelFormat,
+    /* Set when several screens are shared, see `set_tag`. */
+    tag: Option<String>,
+    /* Name of the published track before the tag, and its stream id, see `set_track_name`. */
+    base_name: String,
+    stream_id: Option<String>,
+    /* Where the stats reads and the background tasks run, see `set_runtime`. */
+    runtime: AsyncRuntime,
+}
+
+/* Updated by the capture callback, read by the stats and the watchdog. */
+#[derive(Default)]
+struct CaptureCounters {
+    /* Frames converted and submitted. */
+    frames: AtomicU64,
+    /* Frames dropped before the encoder saw them, by a full capture queue or
+     * because the previous one was still being converted. */
+    dropped_frames: AtomicU64,
+    /* Send rate of all layers in kbps and process CPU usage of the last stats sample, for the live view. */
+    live_stats: Mutex<Option<(f64, f64)>>,
+    /* Unix ms and send rate of all layers in kbps of every stats sample, for the bitrate adherence. */
+    send_rates: Mutex<Vec<(u128, f64)>>,
+    /* Errors reported by the capturer, in total and since the last frame,
+     * and the permanent ones among them, after which the capturer gives up. */
+    errors: AtomicU64,
+    consecutive_errors: AtomicU64,
+    permanent_errors: AtomicU64,
+    /* Set when the screen disappeared and couldn't be found again. */
+    source_lost: AtomicBool,
+    /* Time spent converting and submitting frames, ns. */
+    pipeline_time: AtomicU64,
+    /*
+     * Parts of it and the capturer itself, ns: the conversion to NV12, the
+     * scaling to the stream size, and the calls to the capturer without
+     * the frame callback they make, which is timed on its own so the
+     * capture time doesn't count the pipeline a second time.
+     */
+    convert_time: AtomicU64,
+    scale_time: AtomicU64,
+    capture_time: AtomicU64,
+    callback_time: AtomicU64,
+    /* Stats and tick samples dropped because the RTC stats couldn't be read. */
+    skipped_stats: AtomicU64,
+    /* Pixels of the captured frames, those that changed from the previous
+     * frame, and the size of the last frame. */
+    captured_pixels: AtomicU64,
+    damaged_pixels: AtomicU64,
+    screen_pixels: AtomicU64,
+    /* Wait between captures in µs, 0 for `CAPTURE_INTERVAL`, see `set_frame_rate`. */
+    capture_interval_us: AtomicU64,
+    /*
+     * Backpressure of the video source, see `backpressure`: whether frames
+     * are skipped while it is behind, the minimum wait between submissions
+     * in µs (0 when not capped), the unix µs of the last submission and the
+     * frames skipped so far.
+     */
+    skip_when_behind: AtomicBool,
+    submit_interval_us: AtomicU64,
+    last_submit_us: AtomicU64,
+    backpressure_skipped: AtomicU64,
+    /* Captured frames dropped or blended into others by the frame rate conversion. */
+    fps_converted: AtomicU64,
+}
+
+/// Name of the published track unless `--track-name` sets one.
+pub const DEFAULT_TRACK_NAME: &str = "screen_share";
+
+/* Wait between captures unless a frame rate is set. */
+const CAPTURE_INTERVAL: Duration = Duration::from_millis(16);
+
+/* How long the stop waits for the stats reads still running. */
+const STATS_READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/* Share of the captures failing over a stats sample above which a `capture_errors` event is recorded. */
+const CAPTURE_ERROR_WARNING_PERCENT: f64 = 5.;
+
+/* Consecutive capture errors after which the screen is taken as gone, about a second. */
+const SOURCE_LOST_ERRORS: u64 = 60;
+/* How long a lost screen is looked for, and how often, before the run is stopped. */
+const SOURCE_RECOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const SOURCE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/* Probe timeout used when `CaptureOptions::probe_timeout` isn't set. */
+const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/* How often the encoder is read after an unmute, and for how long at most. */
+const RESUME_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const RESUME_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum ScreenSharerError {
+    /// libwebrtc couldn't create a desktop capturer.
+    CapturerUnavailable,
+    /// The requested screen isn't in the source list.
+    SourceNotFound { source_index: u32, available: usize },
+    /// The screen recording permission is missing, frames come out black.
+    PermissionDenied,
+    /// No frame arrived in time to learn the source dimensions.
+    ProbeTimeout {
+        source_index: u32,
+        timeout: Duration,
+    },
+    /// The shared screen is no longer in the source list.
+    SourceDisconnected { id: u64 },
+    /// Outside of a tokio runtime, and one couldn't be started.
+    Runtime(String),
 }
 
-fn get_source_dims(source_index: u32) -> (u32, u32) {
+impl std::fmt::Display for ScreenSharerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScreenSharerError::CapturerUnavailable => {
+                write!(f, "failed to create the desktop capturer")
+            }
+            ScreenSharerError::SourceNotFound {
+                source_index,
+                available,
+            } => write!(
+                f,
+                "screen source {} not found, {} sources available",
+                source_index, available
+            ),
+            ScreenSharerError::PermissionDenied => write!(
+                f,
+                "screen recording permission missing, grant it in System Settings > Privacy & Security > Screen Recording and restart the terminal"
+            ),
+            ScreenSharerError::ProbeTimeout {
+                source_index,
+                timeout,
+            } => write!(
+                f,
+                "no frame captured from source {} within {:?}",
+                source_index, timeout
+            ),
+            ScreenSharerError::SourceDisconnected { id } => {
+                write!(f, "screen {} is no longer connected", id)
+            }
+            ScreenSharerError::Runtime(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScreenSharerError {}
+
+fn new_desktop_capturer() -> Result<DesktopCapturer, ScreenSharerError> {
+    let mut options = DesktopCapturerOptions::new(DesktopCaptureSourceType::Screen);
+    #[cfg(target_os = "macos")]
+    {
+        options.set_sck_system_picker(false);
+    }
+    DesktopCapturer::new(options).ok_or(ScreenSharerError::CapturerUnavailable)
+}
+
+/*
+ * The source list doesn't carry the screen size, so capture frames until
+ * one arrives, backing off between attempts up to `timeout`. On macOS black
+ * frames are skipped, they are what capturing without permission returns.
+ */
+fn get_source_dims(
+    source_index: u32,
+    timeout: Duration,
+) -> Result<(u32, u32), ScreenSharerError> {
+    if permissions::screen_capture_allowed() == Some(false) {
+        return Err(ScreenSharerError::PermissionDenied);
+    }
+
     let width = Arc::new(Mutex::new(0));
     let height = Arc::new(Mutex::new(0));
+    let black_frames = Arc::new(AtomicU32::new(0));
 
     let width_clone = width.clone();
     let height_clone = height.clone();
+    let black_frames_clone = black_frames.clone();
     let callback = move |result: Result<DesktopFrame, CaptureError>| match result {
         Ok(frame) => {
             let (width, height) = (frame.width(), frame.height());
+            if permissions::BLACK_FRAMES_MEAN_DENIED
+                && permissions::is_black_frame(frame.data(), frame.stride(), width, height)
+            {
+                black_frames_clone.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
             *width_clone.lock().unwrap() = width as u32;
             *height_clone.lock().unwrap() = height as u32;
         }
@@ -39,157 +339,817 @@ fn get_source_dims(source_index: u32) -> (u32, u32) {
             log::warn!("Capture error: {:?}", error);
         }
     };
-    let mut options = DesktopCapturerOptions::new(DesktopCaptureSourceType::Screen);
-    #[cfg(target_os = "macos")]
-    {
-        options.set_sck_system_picker(false);
-    }
-    let mut capturer = DesktopCapturer::new(options).unwrap();
-    let source = capturer
-        .get_source_list()
-        .get(source_index as usize)
-        .cloned();
+    let mut capturer = new_desktop_capturer()?;
+    let source = find_source(&capturer, source_index)?;
     capturer.start_capture(source, callback);
-    let mut count = 0;
-    while count < 10 {
+
+    let start = Instant::now();
+    let mut backoff = Duration::from_millis(50);
+    loop {
         capturer.capture_frame();
 
-        if *width.lock().unwrap() > 0 && *height.lock().unwrap() > 0 {
-            break;
+        let dims = (*width.lock().unwrap(), *height.lock().unwrap());
+        if dims.0 > 0 && dims.1 > 0 {
+            return Ok(dims);
         }
 
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        count += 1;
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            if black_frames.load(Ordering::Relaxed) > 0 {
+                return Err(ScreenSharerError::PermissionDenied);
+            }
+            return Err(ScreenSharerError::ProbeTimeout {
+                source_index,
+                timeout,
+            });
+        }
+        std::thread::sleep(backoff.min(timeout - elapsed));
+        backoff = (backoff * 2).min(Duration::from_millis(800));
+    }
+}
+
+fn find_source(
+    capturer: &DesktopCapturer,
+    source_index: u32,
+) -> Result<Option<CaptureSource>, ScreenSharerError> {
+    let sources = capturer.get_source_list();
+    if sources.is_empty() {
+        /* Some backends don't enumerate, let the capturer pick the screen. */
+        return Ok(None);
+    }
+    match sources.get(source_index as usize) {
+        Some(source) => Ok(Some(source.clone())),
+        None => Err(ScreenSharerError::SourceNotFound {
+            source_index,
+            available: sources.len(),
+        }),
+    }
+}
+
+/// A screen the capturer can share, as listed by `list_sources`.
+#[derive(Debug, Clone)]
+pub struct SourceInfo {
+    /// Position in the source list, what `--source` takes.
+    pub index: u32,
+    /// Platform id of the screen, stable while it stays connected.
+    pub id: u64,
+    pub title: String,
+}
+
+/// The screens in the order `--source` indexes them. Empty when the
+/// backend doesn't enumerate and picks the screen itself.
+pub fn list_sources() -> Result<Vec<SourceInfo>, ScreenSharerError> {
+    let capturer = new_desktop_capturer()?;
+    Ok(capturer
+        .get_source_list()
+        .iter()
+        .enumerate()
+        .map(|(index, source)| SourceInfo {
+            index: index as u32,
+            id: source.id(),
+            title: source.title(),
+        })
+        .collect())
+}
+
+/// How a screen is picked from the source list. Indices change when
+/// displays are rearranged, ids and titles don't.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceSelector {
+    Index(u32),
+    /// Platform id, as printed by `list-sources`.
+    Id(u64),
+    /// Case insensitive substring of the title.
+    Name(String),
+}
+
+impl SourceSelector {
+    /// The listed screen this selects, a name matching several is an error.
+    pub fn resolve<'a>(&self, sources: &'a [SourceInfo]) -> Result<&'a SourceInfo, String> {
+        let matches: Vec<&SourceInfo> = sources
+            .iter()
+            .filter(|source| match self {
+                SourceSelector::Index(index) => source.index == *index,
+                SourceSelector::Id(id) => source.id == *id,
+                SourceSelector::Name(name) => {
+                    source.title.to_lowercase().contains(&name.to_lowercase())
+                }
+            })
+            .collect();
+        match matches[..] {
+            [source] => Ok(source),
+            [] => Err(format!("no screen matches {}", self)),
+            _ => Err(format!(
+                "{} matches several screens: {}",
+                self,
+                matches
+                    .iter()
+                    .map(|source| source.title.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for SourceSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceSelector::Index(index) => write!(f, "index {}", index),
+            SourceSelector::Id(id) => write!(f, "id {}", id),
+            SourceSelector::Name(name) => write!(f, "name '{}'", name),
+        }
     }
+}
 
-    (*width.lock().unwrap(), *height.lock().unwrap())
+/// Captures frames from a screen until one arrives and returns its size,
+/// without publishing anything.
+pub fn probe_source(source_index: u32, timeout: Duration) -> Result<(u32, u32), ScreenSharerError> {
+    get_source_dims(source_index, timeout)
 }
 
+/// Fits the long side of a `width`x`height` screen to the long side of the
+/// target, keeping the aspect ratio. The result is rounded to even sizes.
 pub fn aspect_fit(width: u32, height: u32, target_width: u32, target_height: u32) -> (u32, u32) {
     let size = max(target_width, target_height);
-    if width >= height {
+    let (width, height) = if width >= height {
         let aspect_ratio = height as f32 / width as f32;
         (size, ((size as f32) * aspect_ratio) as u32)
     } else {
         let aspect_ratio = width as f32 / height as f32;
-        (((size as f32) / aspect_ratio) as u32, size)
+        (((size as f32) * aspect_ratio) as u32, size)
+    };
+    scale::even_size(width, height)
+}
+
+/* Buffers of `frame_handler`, allocated once and reused for every frame. */
+struct FramePipeline {
+    overlay: Option<MotionOverlay>,
+    burn_in: Option<BurnIn>,
+    /* Conversion target at the source size, None without prescaling. */
+    screen_buffer: Option<NV12Buffer>,
+    stream: StreamBuffers,
+    /* None when the damage stats are disabled. */
+    damage: Option<DamageTracker>,
+    /* None without frame rate conversion. */
+    fps: Option<FpsConverter>,
+}
+
+/* The buffers at the stream resolution, reallocated when it changes. */
+struct StreamBuffers {
+    stream_frame: VideoFrame<NV12Buffer>,
+    /* Rotating before the encoder needs a second buffer with the rotated dimensions. */
+    rotated_frame: Option<VideoFrame<NV12Buffer>>,
+}
+
+impl StreamBuffers {
+    fn new(width: u32, height: u32, rotation: Rotation) -> Self {
+        let (output_width, output_height) = rotation.output_size(width, height);
+        StreamBuffers {
+            stream_frame: VideoFrame {
+                rotation: rotation.frame_rotation(),
+                buffer: NV12Buffer::new(width, height),
+                timestamp_us: 0,
+            },
+            rotated_frame: rotation.rotates_pixels().then(|| VideoFrame {
+                rotation: VideoRotation::VideoRotation0,
+                buffer: NV12Buffer::new(output_width, output_height),
+                timestamp_us: 0,
+            }),
+        }
     }
 }
 
-impl ScreenSharer {
-    pub fn new(width: u32, height: u32, source_index: u32) -> Result<Self, ()> {
-        let (screen_width, screen_height) = get_source_dims(source_index);
+/*
+ * Builds the handler that converts a captured BGRA or RGBA frame to NV12, scales it
+ * to the stream resolution (unless prescaling is disabled), applies the
+ * watermark and submits it to the video source, rotated when asked to.
+ * Shared by the desktop capturer and the synthetic source.
+ * The frames and the time spent in it are added to `counters`. The stream
+ * buffers follow `stream_size`, see `ScreenSharer::resize`. While `still` is
+ * set the frame captured when it was set goes through the pipeline instead.
+ * With a frame rate conversion the capture is paced to the capture rate and
+ * only the published frames are submitted, see `fps_convert`.
+ */
+#[allow(clippy::too_many_arguments)]
+fn frame_handler(
+    buffer_source: NativeVideoSource,
+    stream_size: Arc<AtomicU64>,
+    occlusion: Arc<AtomicU8>,
+    still: Arc<AtomicBool>,
+    screen_width: u32,
+    screen_height: u32,
+    watermark_count: Arc<Mutex<u32>>,
+    counters: Arc<CaptureCounters>,
+    options: CaptureOptions,
+) -> impl Fn(&[u8], u32, i32, i32) + Send + Sync + 'static {
+    let rotation = options.rotation;
+    let fingerprint = options.fingerprint;
+    let pixel_format = options.pixel_format;
+    counters
+        .skip_when_behind
+        .store(options.skip_when_behind, Ordering::Relaxed);
+    if let Some(conversion) = options.fps_conversion {
         log::info!(
-            "Screen source dimensions: {}x{}",
-            screen_width,
-            screen_height
+            "Capturing at {} fps, published at {} fps by {} frames",
+            conversion.capture_fps,
+            conversion.publish_fps,
+            match conversion.method {
+                FpsMethod::Drop => "dropping",
+                FpsMethod::Blend => "blending",
+            }
+        );
+        counters.capture_interval_us.store(
+            framerate::capture_interval(conversion.capture_fps).as_micros() as u64,
+            Ordering::Relaxed,
         );
+    }
+    let (width, height) = resize::unpack_size(stream_size.load(Ordering::Relaxed));
+    let pipeline = Slot::new(FramePipeline {
+        overlay: options.overlay.map(MotionOverlay::new),
+        burn_in: options.burn_in.then(|| BurnIn::new(options.fingerprint)),
+        /* Without prescaling frames are converted straight into the stream buffer. */
+        screen_buffer: (!options.no_prescale)
+            .then(|| NV12Buffer::new(screen_width, screen_height)),
+        stream: StreamBuffers::new(width, height, rotation),
+        damage: (!options.no_damage_stats).then(DamageTracker::new),
+        fps: options.fps_conversion.map(FpsConverter::new),
+    });
+    let still_frame: Mutex<Option<StillFrame>> = Mutex::new(None);
+    move |data: &[u8], stride: u32, width: i32, height: i32| {
+        /* The conversion trusts the layout, a short buffer would be read past its end. */
+        if !scale::frame_fits(data.len(), stride, width, height) {
+            log::warn!(
+                "Frame dropped, {} bytes with stride {} don't hold {}x{} pixels",
+                data.len(),
+                stride,
+                width,
+                height
+            );
+            return;
+        }
+        let occluded = OcclusionMode::decode(occlusion.load(Ordering::Relaxed));
+        /* A covered screen that delivers nothing, the frame never reaches the pipeline. */
+        if occluded == Some(OcclusionMode::Freeze) {
+            return;
+        }
+        /* Skipped before any conversion while the video source is behind, see `backpressure`. */
+        let submit_interval = counters.submit_interval_us.load(Ordering::Relaxed);
+        if submit_interval > 0
+            && now_us().saturating_sub(counters.last_submit_us.load(Ordering::Relaxed))
+                < submit_interval
+        {
+            log::trace!("Frame skipped, the video source is behind");
+            counters
+                .backpressure_skipped
+                .fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let Some(mut buffers) = pipeline.take() else {
+            log::warn!("Frame dropped, the previous one is still being converted");
+            counters.dropped_frames.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        /* Frames left out of the published rate go before any conversion, unless blended. */
+        let publish = buffers
+            .fps
+            .as_mut()
+            .is_none_or(|fps| fps.advance(now_us()));
+        if !publish && buffers.fps.as_ref().map(FpsConverter::method) == Some(FpsMethod::Drop) {
+            counters.fps_converted.fetch_add(1, Ordering::Relaxed);
+            pipeline.put(buffers);
+            return;
+        }
+        /* Static content, the frame of the start of the period replaces the captured one. */
+        let mut still_frame = still_frame.lock().unwrap();
+        if !still.load(Ordering::Relaxed) {
+            *still_frame = None;
+        } else if still_frame.is_none() {
+            *still_frame = Some(StillFrame {
+                data: data.to_vec(),
+                stride,
+                width,
+                height,
+            });
+        }
+        let (data, stride, width, height) = match &*still_frame {
+            Some(frame) => (
+                frame.data.as_slice(),
+                frame.stride,
+                frame.width,
+                frame.height,
+            ),
+            None => (data, stride, width, height),
+        };
 
-        let (width, height) = aspect_fit(screen_width, screen_height, width, height);
+        /* Measured before the pipeline timer, it isn't part of the frame submission. */
+        if let Some(damage) = &mut buffers.damage {
+            let pixels = (width * height) as u64;
+            let damaged = damage.update(data, stride, width, height);
+            log::trace!("Frame damage: {} of {} pixels", damaged, pixels);
+            counters
+                .captured_pixels
+                .fetch_add(pixels, Ordering::Relaxed);
+            counters
+                .damaged_pixels
+                .fetch_add(damaged, Ordering::Relaxed);
+            counters.screen_pixels.store(pixels, Ordering::Relaxed);
+        }
+        let _span = tracing::trace_span!("convert", width, height).entered();
+        let start = Instant::now();
 
-        let buffer_source = NativeVideoSource::new(VideoResolution { width, height }, true);
-        let watermark_count = Arc::new(Mutex::new(0));
-        let watermark_count_clone = watermark_count.clone();
+        let (stream_width, stream_height) =
+            resize::unpack_size(stream_size.load(Ordering::Relaxed));
+        let current = &buffers.stream.stream_frame.buffer;
+        if (current.width(), current.height()) != (stream_width, stream_height) {
+            buffers.stream = StreamBuffers::new(stream_width, stream_height, rotation);
+        }
+        /* A screen that reconnected after it was lost may come back at another size. */
+        let screen_size = (width as u32, height as u32);
+        if let Some(screen_buffer) = &mut buffers.screen_buffer {
+            if (screen_buffer.width(), screen_buffer.height()) != screen_size {
+                *screen_buffer = NV12Buffer::new(screen_size.0, screen_size.1);
+            }
+        } else if scale::even_size(screen_size.0, screen_size.1) != (stream_width, stream_height) {
+            log::warn!(
+                "Frame dropped, the screen is {}x{} and the stream {}x{} without prescaling",
+                screen_size.0,
+                screen_size.1,
+                stream_width,
+                stream_height
+            );
+            pipeline.put(buffers);
+            return;
+        }
 
-        let buffer_source_clone = buffer_source.clone();
-        let video_frame = Mutex::new(VideoFrame {
-            rotation: VideoRotation::VideoRotation0,
-            buffer: NV12Buffer::new(width, height),
-            timestamp_us: 0,
-        });
-        let tmp_buffer = Mutex::new(NV12Buffer::new(screen_width, screen_height));
-        let callback = move |result: Result<DesktopFrame, CaptureError>| {
-            let frame = match result {
-                Ok(frame) => frame,
-                Err(error) => {
-                    log::warn!("Capture error: {:?}", error);
-                    return;
-                }
-            };
+        let FramePipeline {
+            overlay,
+            burn_in,
+            screen_buffer,
+            damage: _,
+            fps,
+            stream:
+                StreamBuffers {
+                    stream_frame,
+                    rotated_frame,
+                },
+        } = &mut *buffers;
 
-            let height = frame.height();
-            let width = frame.width();
-            let stride = frame.stride();
-            let data = frame.data();
+        let converting = Instant::now();
+        if let Some(screen_buffer) = screen_buffer {
+            let (s_y, s_uv) = screen_buffer.strides();
+            let (y, uv) = screen_buffer.data_mut();
+            pixel_format.to_nv12(data, stride, y, s_y, uv, s_uv, width, height);
+            let scaling = Instant::now();
+            scale::scale_nv12(screen_buffer, &mut stream_frame.buffer);
+            counters
+                .scale_time
+                .fetch_add(scaling.elapsed().as_nanos() as u64, Ordering::Relaxed);
+            counters
+                .convert_time
+                .fetch_add((scaling - converting).as_nanos() as u64, Ordering::Relaxed);
+        } else {
+            /* An odd last row or column is cropped, see `scale::even_size`. */
+            let (s_y, s_uv) = stream_frame.buffer.strides();
+            let (y, uv) = stream_frame.buffer.data_mut();
+            let (width, height) = (stream_width as i32, stream_height as i32);
+            pixel_format.to_nv12(data, stride, y, s_y, uv, s_uv, width, height);
+            counters
+                .convert_time
+                .fetch_add(converting.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        }
+        if occluded == Some(OcclusionMode::Black) {
+            let (y, uv) = stream_frame.buffer.data_mut();
+            y.fill(16);
+            uv.fill(128);
+        }
 
-            let mut buffer = tmp_buffer.lock().unwrap();
-            let (s_y, s_uv) = buffer.strides();
-            let (y, uv) = buffer.data_mut();
-            yuv_helper::argb_to_nv12(data, stride, y, s_y, uv, s_uv, width, height);
+        if let Some(overlay) = overlay {
+            overlay.apply(&mut stream_frame.buffer);
+        }
+        /* Blended with the overlay, its motion smears like the screen's. */
+        if let Some(fps) = fps.as_mut().filter(|fps| fps.method() == FpsMethod::Blend) {
+            fps.blend(&mut stream_frame.buffer, publish);
+            if !publish {
+                counters
+                    .pipeline_time
+                    .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                counters.fps_converted.fetch_add(1, Ordering::Relaxed);
+                pipeline.put(buffers);
+                return;
+            }
+        }
 
-            // Scale framebuffer to stream resolution
-            let mut stream_buffer = video_frame.lock().unwrap();
-            let stream_width = stream_buffer.buffer.width();
-            let stream_height = stream_buffer.buffer.height();
+        /* The watermark goes on after rotating, the client looks for it in the top rows. */
+        let frame = match rotated_frame {
+            Some(rotated_frame) => {
+                rotation::rotate_nv12(
+                    &stream_frame.buffer,
+                    &mut rotated_frame.buffer,
+                    rotation.angle,
+                );
+                rotated_frame
+            }
+            None => stream_frame,
+        };
+        if let Some(burn_in) = burn_in {
+            burn_in.apply(&mut frame.buffer);
+        }
 
-            let mut scaled_buffer = buffer.scale(stream_width as i32, stream_height as i32);
+        let (s_y, _) = frame.buffer.strides();
+        let (dst_y, _) = frame.buffer.data_mut();
+        let watermark = {
+            let mut watermark_count = watermark_count.lock().unwrap();
+            let watermark = *watermark_count > 0;
+            *watermark_count = watermark_count.saturating_sub(1);
+            watermark
+        };
+        if watermark {
+            unsafe {
+                let dst = dst_y.as_mut_ptr();
+                std::ptr::write_bytes(dst, WATERMARK.value, (WATERMARK.rows * s_y) as usize);
+            }
+        }
+        /* Hashed as it goes to the encoder, watermark included, the client sees the same. */
+        if fingerprint {
+            let width = frame.buffer.width() as usize;
+            let height = frame.buffer.height() as usize;
+            let (s_y, s_uv) = frame.buffer.strides();
+            let (y, uv) = frame.buffer.data_mut();
+            let hash = fingerprint::hash(y, s_y as usize, width, height);
+            fingerprint::write_marker(y, s_y as usize, uv, s_uv as usize, width, height, hash.bits);
+        }
+        buffer_source.capture_frame(frame);
 
-            // Copy scaled buffer to stream buffer
-            let (data_y, data_uv) = scaled_buffer.data_mut();
-            let (s_y, _) = stream_buffer.buffer.strides();
-            let (dst_y, dst_uv) = stream_buffer.buffer.data_mut();
-            dst_y.copy_from_slice(data_y);
-            dst_uv.copy_from_slice(data_uv);
+        counters
+            .pipeline_time
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        counters.frames.fetch_add(1, Ordering::Relaxed);
+        counters.last_submit_us.store(now_us(), Ordering::Relaxed);
+        pipeline.put(buffers);
+    }
+}
 
-            {
-                let mut watermark_count = watermark_count_clone.lock().unwrap();
-                if *watermark_count > 0 {
-                    *watermark_count -= 1;
-                    unsafe {
-                        let dst = dst_y.as_mut_ptr();
-                        std::ptr::write_bytes(dst, 0xa, (50 * s_y) as usize);
-                    }
+/*
+ * The frame handler behind a `FrameQueue` when one is configured, counting
+ * its drops. Either way the time the capturer spends in it is counted as
+ * the callback time.
+ */
+fn queued(
+    handler: impl Fn(&[u8], u32, i32, i32) + Send + Sync + 'static,
+    queue: Option<QueueConfig>,
+    counters: Arc<CaptureCounters>,
+) -> Box<dyn Fn(&[u8], u32, i32, i32) + Send + Sync> {
+    let handler: Box<dyn Fn(&[u8], u32, i32, i32) + Send + Sync> = match queue {
+        None => Box::new(handler),
+        Some(config) => {
+            log::info!(
+                "Capture queue of {} frames, {}",
+                config.depth,
+                config.policy.as_str()
+            );
+            let queue = FrameQueue::new(config, handler);
+            let counters = counters.clone();
+            Box::new(move |data: &[u8], stride: u32, width: i32, height: i32| {
+                if queue.push(data, stride, width, height) {
+                    log::debug!("Frame dropped, the capture queue is full");
+                    counters.dropped_frames.fetch_add(1, Ordering::Relaxed);
                 }
-            }
-            buffer_source_clone.capture_frame(&stream_buffer);
-        };
-        let mut options = DesktopCapturerOptions::new(DesktopCaptureSourceType::Screen);
-        #[cfg(target_os = "macos")]
-        {
-            options.set_sck_system_picker(false);
+            })
         }
-        let capturer = DesktopCapturer::new(options);
-        if capturer.is_none() {
-            return Err(());
+    };
+    Box::new(move |data: &[u8], stride: u32, width: i32, height: i32| {
+        let start = Instant::now();
+        handler(data, stride, width, height);
+        counters
+            .callback_time
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    })
+}
+
+/* The capture callback, kept to hand it to a restarted capturer. */
+type CaptureCallback = Arc<dyn Fn(Result<DesktopFrame, CaptureError>) + Send + Sync>;
+
+enum Capturer {
+    Desktop {
+        capturer: DesktopCapturer,
+        /* Platform id of the screen, None when the backend doesn't enumerate. */
+        source_id: Option<u64>,
+        callback: CaptureCallback,
+    },
+    Synthetic(SyntheticSource),
+}
+
+impl Capturer {
+    fn capture_frame(&mut self) {
+        match self {
+            Capturer::Desktop { capturer, .. } => capturer.capture_frame(),
+            Capturer::Synthetic(source) => source.capture_frame(),
         }
+    }
+
+    /*
+     * Starts a new capture of the same screen. It is looked up by its id,
+     * a reconnected display can come back at another index. Returns the
+     * index it was found at.
+     */
+    fn restart(&mut self) -> Result<Option<u32>, ScreenSharerError> {
+        let Capturer::Desktop {
+            capturer,
+            source_id,
+            callback,
+        } = self
+        else {
+            return Ok(None);
+        };
+        let mut new_capturer = new_desktop_capturer()?;
+        let (index, source) = match source_id {
+            Some(id) => {
+                let sources = new_capturer.get_source_list();
+                let index = sources
+                    .iter()
+                    .position(|source| source.id() == *id)
+                    .ok_or(ScreenSharerError::SourceDisconnected { id: *id })?;
+                (Some(index as u32), Some(sources[index].clone()))
+            }
+            None => (None, None),
+        };
+        let callback = callback.clone();
+        new_capturer.start_capture(source, move |result| callback(result));
+        *capturer = new_capturer;
+        Ok(index)
+    }
+}
+
+impl ScreenSharer {
+    pub fn new(
+        width: u32,
+        height: u32,
+        source_index: u32,
+        options: CaptureOptions,
+    ) -> Result<Self, ScreenSharerError> {
+        let probe_timeout = options.probe_timeout.unwrap_or(DEFAULT_PROBE_TIMEOUT);
+        let (screen_width, screen_height) = get_source_dims(source_index, probe_timeout)?;
+        log::info!(
+            "Screen source dimensions: {}x{}",
+            screen_width,
+            screen_height
+        );
+        let pixel_format = options.pixel_format;
+        let no_prescale = options.no_prescale;
+        log::info!("Pixel format: {}", pixel_format.as_str());
 
-        let mut capturer = capturer.unwrap();
-        let source = capturer
-            .get_source_list()
-            .get(source_index as usize)
-            .cloned();
-        capturer.start_capture(source, callback);
+        let (width, height) = if options.no_prescale {
+            log::info!("Prescaling disabled, submitting frames at the native resolution");
+            scale::even_size(screen_width, screen_height)
+        } else {
+            aspect_fit(screen_width, screen_height, width, height)
+        };
+
+        let (output_width, output_height) = options.rotation.output_size(width, height);
+        let buffer_source = NativeVideoSource::new(
+            VideoResolution {
+                width: output_width,
+                height: output_height,
+            },
+            true,
+        );
+        let watermark_count = Arc::new(Mutex::new(0));
+        let counters = Arc::new(CaptureCounters::default());
+
+        let stream_size = Arc::new(AtomicU64::new(resize::pack_size(width, height)));
+        let occlusion = Arc::new(AtomicU8::new(0));
+        let still = Arc::new(AtomicBool::new(false));
+        let queue = options.queue;
+        let handler = frame_handler(
+            buffer_source.clone(),
+            stream_size.clone(),
+            occlusion.clone(),
+            still.clone(),
+            screen_width,
+            screen_height,
+            watermark_count.clone(),
+            counters.clone(),
+            options,
+        );
+        let handler = queued(handler, queue, counters.clone());
+        let callback_counters = counters.clone();
+        let callback: CaptureCallback =
+            Arc::new(move |result: Result<DesktopFrame, CaptureError>| {
+                let frame = match result {
+                    Ok(frame) => frame,
+                    Err(error) => {
+                        log::warn!("Capture error: {:?}", error);
+                        callback_counters.errors.fetch_add(1, Ordering::Relaxed);
+                        if matches!(error, CaptureError::Permanent) {
+                            callback_counters
+                                .permanent_errors
+                                .fetch_add(1, Ordering::Relaxed);
+                        }
+                        callback_counters
+                            .consecutive_errors
+                            .fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                };
+                callback_counters
+                    .consecutive_errors
+                    .store(0, Ordering::Relaxed);
+
+                handler(frame.data(), frame.stride(), frame.width(), frame.height());
+            });
+        let mut capturer = new_desktop_capturer()?;
+        let source = find_source(&capturer, source_index)?;
+        let source_id = source.as_ref().map(|source| source.id());
+        let capture_callback = callback.clone();
+        capturer.start_capture(source, move |result| capture_callback(result));
 
         Ok(ScreenSharer {
-            capturer: Arc::new(Mutex::new(capturer)),
+            capturer: Arc::new(Mutex::new(Capturer::Desktop {
+                capturer,
+                source_id,
+                callback,
+            })),
             watermark_count: watermark_count,
+            tick: Arc::new(AtomicU64::new(0)),
+            capturing: Arc::new(AtomicBool::new(true)),
+            occlusion,
+            still,
+            counters,
+            stream_size,
+            no_prescale,
             buffer_source,
             tx: None,
             source_index,
+            pixel_format,
+            tag: None,
+            base_name: DEFAULT_TRACK_NAME.to_string(),
+            stream_id: None,
+            runtime: AsyncRuntime::current().map_err(ScreenSharerError::Runtime)?,
+        })
+    }
+
+    /// Creates a screen sharer fed by a synthetic moving pattern of the
+    /// given size instead of a real screen.
+    pub fn new_synthetic(
+        width: u32,
+        height: u32,
+        mut options: CaptureOptions,
+    ) -> Result<Self, ScreenSharerError> {
+        log::info!("Synthetic source dimensions: {}x{}", width, height);
+        /* The pattern is drawn in BGRA whatever the screens deliver. */
+        options.pixel_format = PixelFormat::Bgra;
+        let pixel_format = options.pixel_format;
+        let no_prescale = options.no_prescale;
+
+        let (stream_width, stream_height) = scale::even_size(width, height);
+        let (output_width, output_height) =
+            options.rotation.output_size(stream_width, stream_height);
+        let buffer_source = NativeVideoSource::new(
+            VideoResolution {
+                width: output_width,
+                height: output_height,
+            },
+            true,
+        );
+        let watermark_count = Arc::new(Mutex::new(0));
+        let counters = Arc::new(CaptureCounters::default());
+
+        let size = resize::pack_size(stream_width, stream_height);
+        let stream_size = Arc::new(AtomicU64::new(size));
+        let occlusion = Arc::new(AtomicU8::new(0));
+        let still = Arc::new(AtomicBool::new(false));
+        let queue = options.queue;
+        let handler = frame_handler(
+            buffer_source.clone(),
+            stream_size.clone(),
+            occlusion.clone(),
+            still.clone(),
+            width,
+            height,
+            watermark_count.clone(),
+            counters.clone(),
+            options,
+        );
+        let source = SyntheticSource::new(width, height, queued(handler, queue, counters.clone()));
+
+        Ok(ScreenSharer {
+            capturer: Arc::new(Mutex::new(Capturer::Synthetic(source))),
+            watermark_count: watermark_count,
+            tick: Arc::new(AtomicU64::new(0)),
+            capturing: Arc::new(AtomicBool::new(true)),
+            occlusion,
+            still,
+            counters,
+            stream_size,
+            no_prescale,
+            buffer_source,
+            tx: None,
+            source_index: 0,
+            pixel_format,
+            tag: None,
+            base_name: DEFAULT_TRACK_NAME.to_string(),
+            stream_id: None,
+            runtime: AsyncRuntime::current().map_err(ScreenSharerError::Runtime)?,
         })
     }
 
+    /// Byte order the captured frames are converted from.
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
     pub fn buffer_source(&self) -> NativeVideoSource {
         self.buffer_source.clone()
     }
 
-    pub fn start_capture(&mut self, room: livekit::Room) {
+    /// Tags the track name and the output files, so the tracks and stats of
+    /// several screens shared by one process can be told apart.
+    pub fn set_tag(&mut self, tag: &str) {
+        self.tag = Some(tag.to_string());
+    }
+
+    /// Names the published track and the stream it is published under, so
+    /// a client can pick it among others in the room. The tag of a screen
+    /// is appended to the name.
+    pub fn set_track_name(&mut self, name: &str, stream_id: Option<&str>) {
+        self.base_name = name.to_string();
+        self.stream_id = stream_id.map(str::to_string);
+    }
+
+    /// The stream id to publish under, None for the one the SDK picks.
+    pub fn stream_id(&self) -> Option<&str> {
+        self.stream_id.as_deref()
+    }
+
+    /// Runs the stats reads and background tasks on `runtime` instead of
+    /// the one current at creation, before the capture is started. It
+    /// should be the runtime the room was connected from.
+    pub fn set_runtime(&mut self, runtime: AsyncRuntime) {
+        self.runtime = runtime;
+    }
+
+    pub fn runtime(&self) -> &AsyncRuntime {
+        &self.runtime
+    }
+
+    pub fn track_name(&self) -> String {
+        match &self.tag {
+            Some(tag) => format!("{}_{}", self.base_name, tag),
+            None => self.base_name.clone(),
+        }
+    }
+
+    /// Starts sampling the stats, appending them to the stats CSV of
+    /// `layout` as they come in when `sinks` include the CSV one, see
+    /// `RollingCsv`.
+    pub fn start_capture(
+        &mut self,
+        room: Arc<livekit::Room>,
+        layout: &OutputLayout,
+        sinks: &SinkConfig,
+    ) {
         let (tx, rx) = mpsc::channel();
         self.tx = Some(tx);
+        let kind = match &self.tag {
+            Some(tag) => format!("{}_stats", tag),
+            None => "stats".to_string(),
+        };
+        let rolling_path = sinks
+            .kinds
+            .contains(&SinkKind::Csv)
+            .then(|| layout.file(Side::Publisher, &kind));
 
         let capturer = self.capturer.clone();
+        let tick = self.tick.clone();
+        let capturing = self.capturing.clone();
+        let counters = self.counters.clone();
+        let tag = self.tag.clone();
+        let track_name = self.track_name();
+        let reader = StatsReader::start(&self.runtime, room, track_name.clone());
+        /* Spans don't follow a new thread on their own. */
+        let span = tracing::info_span!("capture", track = %track_name);
         std::thread::spawn(move || {
-            run_capture_frame(rx, capturer, room);
+            let _span = span.entered();
+            run_capture_frame(
+                rx,
+                capturer,
+                tick,
+                capturing,
+                counters,
+                reader,
+                tag,
+                track_name,
+                rolling_path,
+            );
         });
     }
 
-    pub fn stop_capture(&mut self, encoder: &str, resolution: &str, bitrate: u64, name: &str) {
+    pub fn stop_capture(&mut self, layout: &OutputLayout, sinks: &SinkConfig) {
         if let Some(tx) = self.tx.take() {
             let _ = tx.send(ScreenshareMessage::StopCapture {
-                encoder: encoder.to_string(),
-                resolution: resolution.to_string(),
-                bitrate,
-                name: name.to_string(),
+                layout: layout.clone(),
+                sinks: sinks.clone(),
             });
         }
     }
@@ -197,22 +1157,303 @@ impl ScreenSharer {
     pub fn watermark_count(&self) -> Arc<Mutex<u32>> {
         self.watermark_count.clone()
     }
+
+    pub fn tick(&self) -> Arc<AtomicU64> {
+        self.tick.clone()
+    }
+
+    /// Pauses or resumes capturing, the track stays published meanwhile.
+    pub fn set_capturing(&self, capturing: bool) {
+        self.capturing.store(capturing, Ordering::Relaxed);
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capturing.load(Ordering::Relaxed)
+    }
+
+    /// Simulates a locked or covered screen until called with None: the
+    /// capture callback drops the frames (`Freeze`) or blacks them out.
+    pub fn set_occlusion(&self, mode: Option<OcclusionMode>) {
+        self.occlusion
+            .store(OcclusionMode::encode(mode), Ordering::Relaxed);
+    }
+
+    pub fn occlusion(&self) -> Option<OcclusionMode> {
+        OcclusionMode::decode(self.occlusion.load(Ordering::Relaxed))
+    }
+
+    /// Simulates static screen content until called with false: the
+    /// capture callback submits the frame captured next over and over in
+    /// place of the new ones.
+    pub fn set_static(&self, still: bool) {
+        self.still.store(still, Ordering::Relaxed);
+    }
+
+    /// Size of the published frames before any rotation, the native one
+    /// without prescaling.
+    pub fn stream_size(&self) -> (u32, u32) {
+        resize::unpack_size(self.stream_size.load(Ordering::Relaxed))
+    }
+
+    /// Switches the published resolution to `width`x`height`, the capture
+    /// callback reallocates its buffers with the next frame.
+    pub fn resize(&self, width: u32, height: u32) -> Result<(), String> {
+        if self.no_prescale {
+            return Err("frames are published at the native resolution without prescaling".into());
+        }
+        let (width, height) = scale::even_size(width, height);
+        let detail = format!("{}x{}", width, height);
+        log::info!("Resizing {} to {}", self.track_name(), detail);
+        self.stream_size
+            .store(resize::pack_size(width, height), Ordering::Relaxed);
+        self.record_event(Event::new("resolution_changed", detail));
+        Ok(())
+    }
+
+    /// Paces the capture to `fps`, recorded as a `fps_changed` event. The
+    /// published maximum frame rate is set when the track is published.
+    pub fn set_frame_rate(&self, fps: u32) {
+        let interval = framerate::capture_interval(fps);
+        log::info!("Capturing {} at {} fps", self.track_name(), fps);
+        self.counters
+            .capture_interval_us
+            .store(interval.as_micros() as u64, Ordering::Relaxed);
+        self.record_event(Event::new("fps_changed", format!("{} fps", fps)));
+    }
+
+    /// Frames submitted to the video source since the start.
+    pub fn frames_captured(&self) -> u64 {
+        self.counters.frames.load(Ordering::Relaxed)
+    }
+
+    /// Whether the screen disappeared and couldn't be found again, the
+    /// capture is stopped then.
+    pub fn source_lost(&self) -> bool {
+        self.counters.source_lost.load(Ordering::Relaxed)
+    }
+
+    /// Send rate of all the layers in kbps and process CPU usage of the
+    /// last stats sample, NaN before the first ones.
+    pub fn live_stats(&self) -> (f64, f64) {
+        self.counters
+            .live_stats
+            .lock()
+            .unwrap()
+            .unwrap_or((f64::NAN, f64::NAN))
+    }
+
+    /// Send rate over the stable part of the run against `target_kbps`,
+    /// see `common::adherence`.
+    pub fn bitrate_adherence(&self, target_kbps: f64) -> Option<BitrateAdherence> {
+        bitrate_adherence(&self.counters.send_rates.lock().unwrap(), target_kbps)
+    }
+
+    /// Frames dropped so far by a full capture queue.
+    pub fn dropped_frames(&self) -> u64 {
+        self.counters.dropped_frames.load(Ordering::Relaxed)
+    }
+
+    /// Frames skipped so far while the video source was behind.
+    pub fn backpressure_skipped(&self) -> u64 {
+        self.counters.backpressure_skipped.load(Ordering::Relaxed)
+    }
+
+    /// Captured frames dropped or blended away so far by the frame rate
+    /// conversion.
+    pub fn fps_converted(&self) -> u64 {
+        self.counters.fps_converted.load(Ordering::Relaxed)
+    }
+
+    /// Samples skipped so far because the RTC stats couldn't be read.
+    pub fn skipped_stats(&self) -> u64 {
+        self.counters.skipped_stats.load(Ordering::Relaxed)
+    }
+
+    /// Adds an event to the ones written with the stats.
+    pub fn record_event(&self, event: Event) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(ScreenshareMessage::Event(event));
+        }
+    }
+
+    /// The codec the track is sent with, with its SDP format parameters,
+    /// e.g. `profile-id=0`. None until the encoder has produced frames.
+    pub async fn sent_codec(&self, room: &livekit::Room) -> Option<SentCodec> {
+        let publication = self.publication(room)?;
+        let Some(LocalTrack::Video(track)) = publication.track() else {
+            return None;
+        };
+        let stats = match track.get_stats().await {
+            Ok(stats) => stats,
+            Err(e) => {
+                log::warn!("Failed to get the codec of {}: {:?}", self.track_name(), e);
+                return None;
+            }
+        };
+        let (codec_id, implementation) = stats.iter().find_map(|stat| match stat {
+            livekit::webrtc::stats::RtcStats::OutboundRtp(stats)
+                if !stats.stream.codec_id.is_empty() =>
+            {
+                Some((
+                    &stats.stream.codec_id,
+                    &stats.outbound.encoder_implementation,
+                ))
+            }
+            _ => None,
+        })?;
+        stats.iter().find_map(|stat| match stat {
+            livekit::webrtc::stats::RtcStats::Codec(stats) if &stats.rtc.id == codec_id => {
+                let name = stats.codec.mime_type.trim_start_matches("video/");
+                Some(SentCodec {
+                    name: name.to_string(),
+                    fmtp: stats.codec.sdp_fmtp_line.clone(),
+                    implementation: implementation.clone(),
+                })
+            }
+            _ => None,
+        })
+    }
+
+    /// The candidate pair the track is sent over, `None` while ICE hasn't
+    /// settled on one.
+    pub async fn candidate_pair(&self, room: &livekit::Room) -> Option<CandidatePair> {
+        let publication = self.publication(room)?;
+        let Some(LocalTrack::Video(track)) = publication.track() else {
+            return None;
+        };
+        match track.get_stats().await {
+            Ok(stats) => transport::selected_candidate_pair(&stats),
+            Err(e) => {
+                log::warn!(
+                    "Failed to get the candidate pair of {}: {:?}",
+                    self.track_name(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Mutes the published track, returns the encoder state the resume
+    /// after `unmute` is measured against.
+    pub async fn mute(&self, room: &livekit::Room) -> Result<EncoderSnapshot, String> {
+        let publication = self.publication(room).ok_or("the track isn't published")?;
+        let baseline = encoder_snapshot(room, &self.track_name())
+            .await
+            .unwrap_or_default();
+        publication.mute();
+        log::info!("Muted {}", self.track_name());
+        self.record_event(Event::new("track_muted", self.track_name()));
+        Ok(baseline)
+    }
+
+    /// Unmutes the track and follows the encoder in the background until
+    /// it is back to the frame size of `baseline` with a new key frame,
+    /// recorded as a `mute_resumed` event, or `mute_resume_timeout` when
+    /// that takes longer than 10 seconds.
+    pub async fn unmute(
+        &self,
+        room: Arc<livekit::Room>,
+        baseline: EncoderSnapshot,
+    ) -> Result<(), String> {
+        let publication = self.publication(&room).ok_or("the track isn't published")?;
+        let track_name = self.track_name();
+        let at_unmute = encoder_snapshot(&room, &track_name)
+            .await
+            .unwrap_or_default();
+        publication.unmute();
+        let unmuted = Instant::now();
+        log::info!("Unmuted {}", track_name);
+        self.record_event(Event::new("track_unmuted", track_name.clone()));
+
+        let tx = self.tx.clone();
+        self.runtime.handle().spawn(async move {
+            let mut tracker = ResumeTracker::new(baseline, at_unmute);
+            let mut poll = tokio::time::interval(RESUME_POLL_INTERVAL);
+            let resumed = loop {
+                poll.tick().await;
+                let elapsed = unmuted.elapsed();
+                if elapsed >= RESUME_TIMEOUT {
+                    break false;
+                }
+                if let Some(snapshot) = encoder_snapshot(&room, &track_name).await {
+                    if tracker.update(&snapshot, elapsed) {
+                        break true;
+                    }
+                }
+            };
+            let detail = format!("{}: {}", track_name, tracker.describe());
+            let event = if resumed {
+                log::info!("Resumed after the unmute: {}", detail);
+                Event::new("mute_resumed", detail)
+            } else {
+                log::warn!("Not resumed after the unmute: {}", detail);
+                Event::new("mute_resume_timeout", detail)
+            };
+            if let Some(tx) = tx {
+                let _ = tx.send(ScreenshareMessage::Event(event));
+            }
+        });
+        Ok(())
+    }
+
+    fn publication(&self, room: &livekit::Room) -> Option<LocalTrackPublication> {
+        room.local_participant()
+            .track_publications()
+            .into_values()
+            .find(|publication| publication.name() == self.track_name())
+    }
+
+    /// Logs the capturer state and the current RTC stats of the track, for
+    /// when the watchdog fires.
+    pub async fn log_diagnostics(&self, room: &livekit::Room) {
+        log::error!(
+            "Capturer state of {}: source {}, capturing {}, {} frames captured, {} capture errors",
+            self.track_name(),
+            self.source_index,
+            self.is_capturing(),
+            self.frames_captured(),
+            self.counters.errors.load(Ordering::Relaxed)
+        );
+        let Some(stats) = get_rtc_stats(room, &self.track_name(), f32::NAN).await else {
+            log::error!("Last RTC stats of {}: unavailable", self.track_name());
+            return;
+        };
+        log::error!(
+            "Last RTC stats of {}: {} bytes sent, target {:.1} kbps, available outgoing {:.1} kbps, RTT {:.1} ms",
+            self.track_name(),
+            stats.bytes_sent,
+            stats.target_bitrate / 1000.,
+            stats.available_outgoing_bitrate / 1000.,
+            stats.current_round_trip_time
+        );
+    }
 }
 
 enum ScreenshareMessage {
     StopCapture {
-        encoder: String,
-        resolution: String,
-        bitrate: u64,
-        name: String,
+        layout: OutputLayout,
+        sinks: SinkConfig,
     },
+    Event(Event),
 }
 
+#[allow(clippy::too_many_arguments)]
 fn run_capture_frame(
     rx: mpsc::Receiver<ScreenshareMessage>,
-    capturer: Arc<Mutex<DesktopCapturer>>,
-    room: livekit::Room,
+    capturer: Arc<Mutex<Capturer>>,
+    tick: Arc<AtomicU64>,
+    capturing: Arc<AtomicBool>,
+    counters: Arc<CaptureCounters>,
+    mut reader: StatsReader,
+    tag: Option<String>,
+    track_name: String,
+    rolling_path: Option<PathBuf>,
 ) {
+    let kind = |kind: &str| match &tag {
+        Some(tag) => format!("{}_{}", tag, kind),
+        None => kind.to_string(),
+    };
     let mut frames = 0;
     let pid = std::process::id() as usize;
     let mut system = System::new_all();
@@ -224,47 +1465,408 @@ fn run_capture_frame(
         ProcessRefreshKind::nothing().with_cpu(),
     );
     let mut stats = Vec::<Stats>::new();
+    let mut events = Vec::<Event>::new();
+    let mut thermal_monitor = ThermalMonitor::new();
+    let mut system_monitor = SystemMonitor::new();
+    let mut networks = Networks::new_with_refreshed_list();
+    let mut power_sampler = power::power_sampler();
+    let mut pipeline_usage = PipelineUsage::new(&counters.pipeline_time);
+    /* Separate system, so tick samples don't shorten the periodic CPU window. */
+    let mut tick_system = System::new();
+    let mut ticks = Vec::<TickSample>::new();
+    let mut last_tick = 0;
+    /* When the screen was lost and when a restart was last tried. */
+    let mut lost_since: Option<Instant> = None;
+    let mut last_restart: Option<Instant> = None;
+    /* Captures and errors at the previous stats sample, and whether the error rate was high. */
+    let mut last_errors = (0, 0);
+    let mut failing = false;
+    let mut backpressure = BackpressureMonitor::new();
+    let mut key_frames = KeyFrameTracker::new();
+    /* The stats rows so far, on disk in case the process dies before the stop. */
+    let mut rolling = rolling_path.and_then(|path| {
+        RollingCsv::create(&path, &STATS_HEADER)
+            .map_err(|e| log::error!("Failed to create {}: {}", path.display(), e))
+            .ok()
+    });
+    let mut rolling_rows = StatsRows::default();
     loop {
-        match rx.recv_timeout(std::time::Duration::from_millis(16)) {
-            Ok(ScreenshareMessage::StopCapture {
-                encoder,
-                resolution,
-                bitrate,
-                name,
-            }) => {
-                // Write CPU usage data to CSV file
-                let filename = format!("{}_{}_{}_{}.csv", encoder, resolution, bitrate, name);
-                if let Ok(mut file) = File::create(&filename) {
-                    let _ = writeln!(file, "frame,cpu_usage,bytes_sent");
-                    for (i, stat) in stats.iter().enumerate() {
-                        let _ =
-                            writeln!(file, "{},{:.2},{:.2}", i, stat.cpu_usage, stat.bytes_sent);
+        let interval = match counters.capture_interval_us.load(Ordering::Relaxed) {
+            0 => CAPTURE_INTERVAL,
+            us => Duration::from_micros(us),
+        };
+        let message = rx.recv_timeout(interval);
+        /* The reads still running are waited for at the stop, so the tables end with them. */
+        let results = match &message {
+            Ok(ScreenshareMessage::StopCapture { .. }) => reader.finish(STATS_READ_TIMEOUT),
+            _ => reader.ready(),
+        };
+        for (read, sample) in results {
+            let snapshot = match read {
+                StatsRead::Tick {
+                    id,
+                    timestamp,
+                    cpu_usage,
+                    frames_submitted,
+                    pipeline_time_ns,
+                } => {
+                    match sample {
+                        Some(sample) => ticks.push(TickSample {
+                            id,
+                            timestamp,
+                            cpu_usage,
+                            bytes_sent: sample.bytes_sent,
+                            current_round_trip_time: sample.current_round_trip_time,
+                            frames_submitted,
+                            pipeline_time_ns,
+                            frames_encoded: sample.frames_encoded,
+                            total_encode_time: sample.total_encode_time,
+                        }),
+                        None => {
+                            log::warn!("Tick {} skipped, no RTC stats", id);
+                            counters.skipped_stats.fetch_add(1, Ordering::Relaxed);
+                        }
                     }
-                    log::info!("encoder stats data saved to {}", filename);
-                } else {
-                    log::error!("Failed to create encoder stats file: {}", filename);
+                    continue;
+                }
+                StatsRead::Sample(snapshot) => snapshot,
+            };
+            let SampleSnapshot {
+                frames,
+                errors,
+                frames_submitted: submitted,
+                ..
+            } = snapshot;
+            let _span = tracing::debug_span!("stats", frame = frames).entered();
+            let Some(mut sample) = sample else {
+                log::warn!("Stats sample at frame {} skipped, no RTC stats", frames);
+                counters.skipped_stats.fetch_add(1, Ordering::Relaxed);
+                continue;
+            };
+
+            let (submit_interval, transition) = backpressure.update(
+                submitted,
+                sample.source_frames,
+                sample.timestamp,
+                interval,
+                counters.skip_when_behind.load(Ordering::Relaxed),
+            );
+            counters
+                .submit_interval_us
+                .store(submit_interval, Ordering::Relaxed);
+            match transition {
+                Some(Transition::Started(detail)) => {
+                    log::warn!("{}: video source behind, {}", track_name, detail);
+                    events.push(Event::new("backpressure_started", detail));
+                }
+                Some(Transition::Stopped(detail)) => {
+                    log::info!("{}: video source caught up, {}", track_name, detail);
+                    events.push(Event::new("backpressure_stopped", detail));
+                }
+                None => {}
+            }
+            sample.frames_submitted = submitted;
+            sample.backpressure_skipped = snapshot.backpressure_skipped;
+            sample.cpu_temperature = snapshot.thermal.temperature;
+            sample.cpu_frequency_mhz = snapshot.thermal.frequency_mhz;
+            sample.throttled = snapshot.thermal.throttled;
+            sample.pipeline_time_percent = snapshot.pipeline_time_percent;
+            sample.capture_time_ns = snapshot.capture_time_ns;
+            sample.convert_time_ns = snapshot.convert_time_ns;
+            sample.scale_time_ns = snapshot.scale_time_ns;
+            sample.power_watts = snapshot.power_watts;
+            sample.captured_pixels = snapshot.captured_pixels;
+            sample.damaged_pixels = snapshot.damaged_pixels;
+            sample.screen_pixels = snapshot.screen_pixels;
+            sample.dropped_frames = snapshot.dropped_frames;
+            /* Key frames at the stats cadence, the size is the largest layer's. */
+            let largest = top_layer(&sample.layers);
+            let encoder = EncoderSnapshot {
+                frames_encoded: sample.frames_encoded,
+                key_frames_encoded: sample.key_frames_encoded,
+                frame_width: largest.map_or(0, |layer| layer.frame_width),
+                frame_height: largest.map_or(0, |layer| layer.frame_height),
+            };
+            if let Some(detail) = key_frames.update(&encoder) {
+                let detail = format!("{}: {}", track_name, detail);
+                log::info!("Key frame: {}", detail);
+                events.push(Event::new("key_frame", detail));
+            }
+            sample.capture_attempts = frames;
+            sample.capture_errors = errors;
+            sample.permanent_capture_errors = snapshot.permanent_errors;
+
+            /* The whole stream, all simulcast layers, like the bitrate it is compared with. */
+            let send_kbps = stats.last().map_or(f64::NAN, |previous| {
+                let bytes = sample
+                    .layers_bytes_sent
+                    .saturating_sub(previous.layers_bytes_sent);
+                let millis = sample.timestamp.saturating_sub(previous.timestamp);
+                bytes as f64 * 8. / millis as f64
+            });
+            *counters.live_stats.lock().unwrap() = Some((send_kbps, sample.cpu_usage as f64));
+            counters
+                .send_rates
+                .lock()
+                .unwrap()
+                .push((sample.timestamp, send_kbps));
+
+            if let Some(file) = &mut rolling {
+                let row = rolling_rows.row(stats.len(), &sample);
+                if let Err(e) = file.append(&row) {
+                    log::error!("Failed to append to {}: {}", file.path().display(), e);
+                    rolling = None;
+                }
+            }
+            stats.push(sample);
+        }
+
+        match message {
+            Ok(ScreenshareMessage::StopCapture { layout, sinks }) => {
+                /* The complete tables replace the rows appended so far. */
+                drop(rolling.take());
+                let mut sinks = match create_sinks(&sinks, &layout, Side::Publisher) {
+                    Ok(sinks) => sinks,
+                    Err(e) => {
+                        log::error!("Failed to create stats sinks: {}", e);
+                        break;
+                    }
+                };
+
+                let mut rows = StatsRows::default();
+                let stats_rows: Vec<Vec<Value>> = stats
+                    .iter()
+                    .enumerate()
+                    .map(|(i, stat)| rows.row(i, stat))
+                    .collect();
+                write_table(&mut sinks, &kind("stats"), &STATS_HEADER, &stats_rows);
+
+                let samples: Vec<(u128, &[LayerStats])> = stats
+                    .iter()
+                    .map(|stat| (stat.timestamp, stat.layers.as_slice()))
+                    .collect();
+                if let Some((header, rows)) = layer_table(&samples) {
+                    let header: Vec<&str> = header.iter().map(String::as_str).collect();
+                    write_table(&mut sinks, &kind("layers"), &header, &rows);
+                }
+
+                /*
+                 * The run uuid and tick id are the join key with the client
+                 * latency samples, see the analysis tool merge command.
+                 */
+                let mut bytes_sent = Counter::new();
+                let (mut submitted, mut pipeline_time) = (Counter::new(), Counter::new());
+                let (mut encoded, mut encode_time) = (Counter::new(), Counter::new());
+                let tick_rows: Vec<Vec<Value>> = ticks
+                    .iter()
+                    .map(|tick| {
+                        let sent = bytes_sent.update(tick.bytes_sent as f64, tick.timestamp);
+                        /* Average cost of the frames since the previous tick, ms. */
+                        let per_frame = |time: f64, frames: f64| {
+                            if frames > 0. { time / frames } else { f64::NAN }
+                        };
+                        let time = pipeline_time
+                            .update(tick.pipeline_time_ns as f64 / 1e6, tick.timestamp);
+                        let frames = submitted.update(tick.frames_submitted as f64, tick.timestamp);
+                        let pipeline_ms = per_frame(time.delta, frames.delta);
+                        let time =
+                            encode_time.update(tick.total_encode_time * 1000., tick.timestamp);
+                        let frames = encoded.update(tick.frames_encoded as f64, tick.timestamp);
+                        let encode_ms = per_frame(time.delta, frames.delta);
+                        vec![
+                            layout.run().uuid.as_str().into(),
+                            tick.id.into(),
+                            tick.timestamp.into(),
+                            Value::float(tick.cpu_usage as f64, 2),
+                            tick.bytes_sent.into(),
+                            sent.delta.into(),
+                            Value::float(sent.kbps(), 1),
+                            Value::float(tick.current_round_trip_time, 1),
+                            Value::float(pipeline_ms, 2),
+                            Value::float(encode_ms, 2),
+                            system_monitor.annotate(tick.timestamp).into(),
+                        ]
+                    })
+                    .collect();
+                write_table(&mut sinks, &kind("ticks"), &TICKS_HEADER, &tick_rows);
+
+                write_table(
+                    &mut sinks,
+                    &kind("events"),
+                    &EVENTS_HEADER,
+                    &event_rows(&events),
+                );
+                let skipped = counters.skipped_stats.load(Ordering::Relaxed);
+                if skipped > 0 {
+                    log::warn!(
+                        "{}: {} samples skipped because the RTC stats were unavailable",
+                        track_name,
+                        skipped
+                    );
                 }
                 break;
             }
+            Ok(ScreenshareMessage::Event(event)) => events.push(event),
             Err(e) => match e {
                 mpsc::RecvTimeoutError::Timeout => {
+                    if !capturing.load(Ordering::Relaxed) || counters.source_lost.load(Ordering::Relaxed) {
+                        continue;
+                    }
                     let mut capturer = capturer.lock().unwrap();
+                    let callback_before = counters.callback_time.load(Ordering::Relaxed);
+                    let capture_start = Instant::now();
                     capturer.capture_frame();
+                    let capture_ns = capture_start.elapsed().as_nanos() as u64;
+                    /* The callback ran inside the call, it is the pipeline's time. */
+                    let callback_ns =
+                        counters.callback_time.load(Ordering::Relaxed) - callback_before;
+                    counters
+                        .capture_time
+                        .fetch_add(capture_ns.saturating_sub(callback_ns), Ordering::Relaxed);
                     frames += 1;
+
+                    /* Errors that don't stop mean the screen is gone, e.g. a display was unplugged. */
+                    let errors = counters.consecutive_errors.load(Ordering::Relaxed);
+                    if errors == 0 {
+                        if let Some(since) = lost_since.take() {
+                            let detail =
+                                format!("capturing again after {} ms", since.elapsed().as_millis());
+                            log::info!("{}: screen {}", track_name, detail);
+                            events.push(Event::new("source_restored", detail));
+                        }
+                    } else if errors >= SOURCE_LOST_ERRORS {
+                        let since = *lost_since.get_or_insert_with(|| {
+                            log::warn!(
+                                "{}: capture keeps failing, looking for the screen again",
+                                track_name
+                            );
+                            events.push(Event::new(
+                                "source_lost",
+                                format!("{} consecutive capture errors", errors),
+                            ));
+                            Instant::now()
+                        });
+                        if since.elapsed() >= SOURCE_RECOVERY_TIMEOUT {
+                            let detail = format!(
+                                "screen not found again within {} s",
+                                SOURCE_RECOVERY_TIMEOUT.as_secs()
+                            );
+                            log::error!("{}: {}, stopping", track_name, detail);
+                            events.push(Event::new("source_unrecoverable", detail));
+                            counters.source_lost.store(true, Ordering::Relaxed);
+                        } else if last_restart
+                            .is_none_or(|last| last.elapsed() >= SOURCE_RETRY_INTERVAL)
+                        {
+                            last_restart = Some(Instant::now());
+                            /* The error count only resets once the new capture delivers a frame. */
+                            match capturer.restart() {
+                                Ok(Some(index)) => {
+                                    log::info!(
+                                        "{}: capture restarted on source {}",
+                                        track_name,
+                                        index
+                                    )
+                                }
+                                Ok(None) => log::info!("{}: capture restarted", track_name),
+                                Err(e) => log::warn!("{}: {}", track_name, e),
+                            }
+                        }
+                    }
+
+                    let current_tick = tick.load(Ordering::Relaxed);
+                    if current_tick != last_tick {
+                        last_tick = current_tick;
+                        let cpu_usage = process_cpu_usage(&mut tick_system, pid);
+                        let read = StatsRead::Tick {
+                            id: current_tick,
+                            timestamp: std::time::SystemTime::now()
+                                .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                                .unwrap()
+                                .as_millis(),
+                            cpu_usage,
+                            frames_submitted: counters.frames.load(Ordering::Relaxed),
+                            pipeline_time_ns: counters.pipeline_time.load(Ordering::Relaxed),
+                        };
+                        reader.request(read, cpu_usage);
+                    }
+
                     if frames % 150 == 0 {
-                        system.refresh_processes_specifics(
-                            ProcessesToUpdate::All,
-                            true,
-                            ProcessRefreshKind::nothing().with_cpu(),
-                        );
-                        let mut cpu = 0.;
-                        if let Some(process) = system.process(Pid::from(pid)) {
-                            cpu = process.cpu_usage();
-                        } else {
-                            log::warn!("Process with PID {} not found", pid);
+                        let _span = tracing::debug_span!("stats", frame = frames).entered();
+                        /* Failing captures only lower the frame rate, they are recorded as they come and go. */
+                        let errors = counters.errors.load(Ordering::Relaxed);
+                        let error_percent = (errors - last_errors.1) as f64
+                            / (frames - last_errors.0).max(1) as f64
+                            * 100.;
+                        last_errors = (frames, errors);
+                        if (error_percent > CAPTURE_ERROR_WARNING_PERCENT) != failing {
+                            failing = !failing;
+                            let detail = format!(
+                                "{:.1}% of the last captures failed, {} errors, {} permanent",
+                                error_percent,
+                                errors,
+                                counters.permanent_errors.load(Ordering::Relaxed)
+                            );
+                            if failing {
+                                log::warn!("{}: capture errors, {}", track_name, detail);
+                                events.push(Event::new("capture_errors", detail));
+                            } else {
+                                log::info!("{}: capture errors cleared, {}", track_name, detail);
+                                events.push(Event::new("capture_errors_cleared", detail));
+                            }
                         }
+                        let cpu = process_cpu_usage(&mut system, pid);
 
-                        stats.push(pollster::block_on(get_rtc_stats(&room, cpu)));
+                        let was_throttled = thermal_monitor.throttled();
+                        let thermal = thermal_monitor.sample(&mut system);
+                        if thermal.throttled != was_throttled {
+                            let detail = format!(
+                                "frequency {} MHz (max {} MHz) temperature {:.1} C",
+                                thermal.frequency_mhz,
+                                thermal_monitor.max_frequency_mhz(),
+                                thermal.temperature
+                            );
+                            if thermal.throttled {
+                                log::warn!("CPU throttling started: {}", detail);
+                                events.push(Event::new("throttling_started", detail));
+                            } else {
+                                log::info!("CPU throttling stopped: {}", detail);
+                                events.push(Event::new("throttling_stopped", detail));
+                            }
+                        }
+                        let machine = system_events::snapshot(
+                            &mut system,
+                            &mut networks,
+                            pid,
+                            thermal.temperature as f64,
+                        );
+                        for event in system_monitor.update(machine) {
+                            log::info!("System event {}: {}", event.kind, event.detail);
+                            events.push(event);
+                        }
+                        /* The capture side as of now, the RTC stats it goes with are read meanwhile. */
+                        let snapshot = SampleSnapshot {
+                            frames,
+                            errors,
+                            frames_submitted: counters.frames.load(Ordering::Relaxed),
+                            backpressure_skipped: counters
+                                .backpressure_skipped
+                                .load(Ordering::Relaxed),
+                            permanent_errors: counters.permanent_errors.load(Ordering::Relaxed),
+                            thermal,
+                            pipeline_time_percent: pipeline_usage.sample(&counters.pipeline_time),
+                            capture_time_ns: counters.capture_time.load(Ordering::Relaxed),
+                            convert_time_ns: counters.convert_time.load(Ordering::Relaxed),
+                            scale_time_ns: counters.scale_time.load(Ordering::Relaxed),
+                            power_watts: power_sampler
+                                .as_mut()
+                                .and_then(|sampler| sampler.sample())
+                                .unwrap_or(f64::NAN),
+                            captured_pixels: counters.captured_pixels.load(Ordering::Relaxed),
+                            damaged_pixels: counters.damaged_pixels.load(Ordering::Relaxed),
+                            screen_pixels: counters.screen_pixels.load(Ordering::Relaxed),
+                            dropped_frames: counters.dropped_frames.load(Ordering::Relaxed),
+                        };
+                        reader.request(StatsRead::Sample(snapshot), cpu);
                     }
                 }
                 mpsc::RecvTimeoutError::Disconnected => {
@@ -276,30 +1878,503 @@ fn run_capture_frame(
     }
 }
 
+/*
+ * The capture side of a periodic stats sample and the state of the machine,
+ * read when the sample is requested like those of a tick, so they cover the
+ * same moment as the RTC stats read after them.
+ */
+struct SampleSnapshot {
+    /* Capture attempts and failed captures. */
+    frames: u64,
+    errors: u64,
+    frames_submitted: u64,
+    backpressure_skipped: u64,
+    permanent_errors: u64,
+    thermal: ThermalSample,
+    pipeline_time_percent: f64,
+    capture_time_ns: u64,
+    convert_time_ns: u64,
+    scale_time_ns: u64,
+    power_watts: f64,
+    captured_pixels: u64,
+    damaged_pixels: u64,
+    screen_pixels: u64,
+    dropped_frames: u64,
+}
+
+/* Turns the pipeline time counter into a share of the wall time of the last sample window. */
+struct PipelineUsage {
+    last_time: u64,
+    last_sample: Instant,
+}
+
+impl PipelineUsage {
+    fn new(pipeline_time: &AtomicU64) -> Self {
+        PipelineUsage {
+            last_time: pipeline_time.load(Ordering::Relaxed),
+            last_sample: Instant::now(),
+        }
+    }
+
+    fn sample(&mut self, pipeline_time: &AtomicU64) -> f64 {
+        let time = pipeline_time.load(Ordering::Relaxed);
+        let elapsed = self.last_sample.elapsed().as_nanos() as f64;
+        let usage = (time - self.last_time) as f64 / elapsed * 100.;
+        self.last_time = time;
+        self.last_sample = Instant::now();
+        usage
+    }
+}
+
+fn process_cpu_usage(system: &mut System, pid: usize) -> f32 {
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::All,
+        true,
+        ProcessRefreshKind::nothing().with_cpu(),
+    );
+    if let Some(process) = system.process(Pid::from(pid)) {
+        process.cpu_usage()
+    } else {
+        log::warn!("Process with PID {} not found", pid);
+        0.
+    }
+}
+
 struct Stats {
+    /* Unix ms when the stats were read. */
+    timestamp: u128,
+    /*
+     * RTP payload bytes including retransmissions of the largest layer, the
+     * whole track without simulcast, see `top_layer`.
+     */
     bytes_sent: u64,
+    /*
+     * Outbound RTP counters of the video track summed over the layers: RTP
+     * payload bytes including retransmissions, and the RTP header bytes.
+     */
+    layers_bytes_sent: u64,
+    header_bytes_sent: u64,
+    retransmitted_bytes_sent: u64,
+    /*
+     * Everything the transport of the track sent: RTP with headers, RTCP,
+     * the data channel and the audio on the same connection, plus the
+     * SRTP, DTLS and STUN overhead.
+     */
+    transport_bytes_sent: u64,
+    /*
+     * Loss recovery, summed over the layers: packets sent, the ones resent
+     * in answer to the NACKs received and the losses the receiver reported
+     * back in RTCP. libwebrtc doesn't report the FEC packets sent, the
+     * client records the ones received.
+     */
+    packets_sent: u64,
+    retransmitted_packets_sent: u64,
+    nack_count: u64,
+    remote_packets_lost: i64,
+    /* Frames encoded and the time spent encoding them in s, summed over the layers. */
+    frames_encoded: u64,
+    key_frames_encoded: u64,
+    total_encode_time: f64,
+    /* Encoder target summed over the layers and the send side bandwidth estimate, bps. */
+    target_bitrate: f64,
+    available_outgoing_bitrate: f64,
+    /* ICE round trip times of the nominated candidate pair, ms. */
+    current_round_trip_time: f64,
+    total_round_trip_time: f64,
+    cpu_usage: f32,
+    /* Share of the wall time spent converting, scaling and submitting frames, percent. */
+    pipeline_time_percent: f64,
+    /* Time spent so far in the capturer, the conversion and the scaling, ns. */
+    capture_time_ns: u64,
+    convert_time_ns: u64,
+    scale_time_ns: u64,
+    cpu_temperature: f32,
+    cpu_frequency_mhz: u64,
+    throttled: bool,
+    /* Package power averaged since the previous sample, NaN without a power source. */
+    power_watts: f64,
+    /* Captured and damaged pixels so far, and the current screen size in pixels. */
+    captured_pixels: u64,
+    damaged_pixels: u64,
+    screen_pixels: u64,
+    /* Frames dropped before the encoder so far, see `CaptureCounters`. */
+    dropped_frames: u64,
+    /*
+     * Frames the video source handed to WebRTC and its frame rate, NaN until
+     * reported, against the frames sent by the layer sending the most, so
+     * frames the capture never produced can be told from frames the encoder
+     * dropped.
+     */
+    source_frames: u64,
+    source_fps: f64,
+    frames_sent: u64,
+    /* Frames submitted to the video source and skipped while it was behind, so far. */
+    frames_submitted: u64,
+    backpressure_skipped: u64,
+    /* Captures asked for and the errors the capturer reported so far. */
+    capture_attempts: u64,
+    capture_errors: u64,
+    permanent_capture_errors: u64,
+    /* The counters of every RTP stream of the track, see `layers`. */
+    layers: Vec<LayerStats>,
+}
+
+/* The counters behind the per-sample deltas, the rows are built as the samples come in and again at the end. */
+#[derive(Default)]
+struct StatsRows {
+    bytes_sent: Counter,
+    media_bytes: Counter,
+    retransmitted_bytes: Counter,
+    retransmitted_packets: Counter,
+    nacks: Counter,
+    remote_lost: Counter,
+    captured_pixels: Counter,
+    damaged_pixels: Counter,
+    dropped_frames: Counter,
+    transport_bytes: Counter,
+    source_frames: Counter,
+    frames_sent: Counter,
+    capture_attempts: Counter,
+    capture_errors: Counter,
+    frames_encoded: Counter,
+    key_frames: Counter,
+    frame_sizes: FrameSizes,
+    capture_time: Counter,
+    convert_time: Counter,
+    scale_time: Counter,
+    submitted_frames: Counter,
+    skipped_frames: Counter,
+}
+
+impl StatsRows {
+    fn row(&mut self, i: usize, stat: &Stats) -> Vec<Value> {
+        let sent = self
+            .bytes_sent
+            .update(stat.bytes_sent as f64, stat.timestamp);
+        let media = self.media_bytes.update(
+            stat.layers_bytes_sent
+                .saturating_sub(stat.retransmitted_bytes_sent) as f64,
+            stat.timestamp,
+        );
+        let retransmitted = self
+            .retransmitted_bytes
+            .update(stat.retransmitted_bytes_sent as f64, stat.timestamp);
+        let captured = self
+            .captured_pixels
+            .update(stat.captured_pixels as f64, stat.timestamp);
+        let damaged = self
+            .damaged_pixels
+            .update(stat.damaged_pixels as f64, stat.timestamp);
+        /* Share of the captured pixels that changed, and of the screen per second. */
+        let damage_percent = if captured.delta > 0. {
+            damaged.delta / captured.delta * 100.
+        } else {
+            f64::NAN
+        };
+        let screen_changed_per_second = if stat.screen_pixels > 0 {
+            damaged.rate / stat.screen_pixels as f64 * 100.
+        } else {
+            f64::NAN
+        };
+        let attempts = self
+            .capture_attempts
+            .update(stat.capture_attempts as f64, stat.timestamp);
+        let errors = self
+            .capture_errors
+            .update(stat.capture_errors as f64, stat.timestamp);
+        let error_percent = if attempts.delta > 0. {
+            errors.delta / attempts.delta * 100.
+        } else {
+            f64::NAN
+        };
+        let key_frames = self
+            .key_frames
+            .update(stat.key_frames_encoded as f64, stat.timestamp);
+        let frame_size = self.frame_sizes.update(
+            media.delta,
+            self.frames_encoded
+                .update(stat.frames_encoded as f64, stat.timestamp)
+                .delta,
+            key_frames.delta,
+        );
+        /* ns per second to a share of the wall time, percent, like `pipeline_time_percent`. */
+        let time_percent = |counter: &mut Counter, time_ns: u64| {
+            counter.update(time_ns as f64, stat.timestamp).rate / 1e7
+        };
+        let capture_percent = time_percent(&mut self.capture_time, stat.capture_time_ns);
+        let convert_percent = time_percent(&mut self.convert_time, stat.convert_time_ns);
+        let scale_percent = time_percent(&mut self.scale_time, stat.scale_time_ns);
+        let source = self
+            .source_frames
+            .update(stat.source_frames as f64, stat.timestamp);
+        /* Frames submitted in the sample that the video source didn't take. */
+        let backpressure_frames = self
+            .submitted_frames
+            .update(stat.frames_submitted as f64, stat.timestamp)
+            .delta
+            - source.delta;
+        let watts_per_mbps = if sent.kbps() > 0. {
+            stat.power_watts / (sent.kbps() / 1000.)
+        } else {
+            f64::NAN
+        };
+        vec![
+            i.into(),
+            Value::float(stat.cpu_usage as f64, 2),
+            stat.bytes_sent.into(),
+            Value::float(stat.pipeline_time_percent, 2),
+            Value::float(stat.cpu_temperature as f64, 1),
+            stat.cpu_frequency_mhz.into(),
+            stat.throttled.into(),
+            stat.timestamp.into(),
+            sent.delta.into(),
+            Value::float(sent.kbps(), 1),
+            Value::float(stat.target_bitrate / 1000., 1),
+            Value::float(stat.available_outgoing_bitrate / 1000., 1),
+            Value::float(stat.current_round_trip_time, 1),
+            Value::float(stat.total_round_trip_time, 1),
+            Value::float(stat.power_watts, 2),
+            Value::float(watts_per_mbps, 3),
+            stat.header_bytes_sent.into(),
+            stat.retransmitted_bytes_sent.into(),
+            Value::float(media.kbps(), 1),
+            Value::float(retransmitted.kbps(), 1),
+            stat.packets_sent.into(),
+            stat.retransmitted_packets_sent.into(),
+            stat.nack_count.into(),
+            stat.remote_packets_lost.into(),
+            self.retransmitted_packets
+                .update(stat.retransmitted_packets_sent as f64, stat.timestamp)
+                .delta
+                .into(),
+            self.nacks
+                .update(stat.nack_count as f64, stat.timestamp)
+                .delta
+                .into(),
+            self.remote_lost
+                .update(stat.remote_packets_lost as f64, stat.timestamp)
+                .delta
+                .into(),
+            stat.damaged_pixels.into(),
+            Value::float(damage_percent, 2),
+            Value::float(screen_changed_per_second, 2),
+            stat.dropped_frames.into(),
+            self.dropped_frames
+                .update(stat.dropped_frames as f64, stat.timestamp)
+                .delta
+                .into(),
+            stat.transport_bytes_sent.into(),
+            Value::float(
+                self.transport_bytes
+                    .update(stat.transport_bytes_sent as f64, stat.timestamp)
+                    .kbps(),
+                1,
+            ),
+            stat.source_frames.into(),
+            source.delta.into(),
+            Value::float(stat.source_fps, 1),
+            stat.frames_sent.into(),
+            self.frames_sent
+                .update(stat.frames_sent as f64, stat.timestamp)
+                .delta
+                .into(),
+            stat.capture_errors.into(),
+            errors.delta.into(),
+            stat.permanent_capture_errors.into(),
+            Value::float(error_percent, 2),
+            stat.key_frames_encoded.into(),
+            key_frames.delta.into(),
+            Value::float(frame_size.average, 0),
+            Value::float(frame_size.delta_frame, 0),
+            Value::float(frame_size.key_frame, 0),
+            Value::float(capture_percent, 2),
+            Value::float(convert_percent, 2),
+            Value::float(scale_percent, 2),
+            backpressure_frames.into(),
+            stat.backpressure_skipped.into(),
+            self.skipped_frames
+                .update(stat.backpressure_skipped as f64, stat.timestamp)
+                .delta
+                .into(),
+            stat.layers_bytes_sent.into(),
+        ]
+    }
+}
+
+/* Publisher side sample taken when a tick requested by the client arrives. */
+struct TickSample {
+    id: u64,
+    timestamp: u128,
     cpu_usage: f32,
+    bytes_sent: u64,
+    current_round_trip_time: f64,
+    /* Cumulative frame counts and times for the per-frame costs between ticks. */
+    frames_submitted: u64,
+    pipeline_time_ns: u64,
+    frames_encoded: u64,
+    total_encode_time: f64,
 }
 
-async fn get_rtc_stats(room: &livekit::Room, cpu_usage: f32) -> Stats {
+/* Cumulative counters are followed by their per-sample `_delta` and rate. */
+const STATS_HEADER: [&str; 55] = [
+    "frame",
+    "cpu_usage",
+    "bytes_sent",
+    "pipeline_time_percent",
+    "cpu_temperature",
+    "cpu_frequency_mhz",
+    "throttled",
+    "timestamp",
+    "bytes_sent_delta",
+    "send_kbps",
+    "target_kbps",
+    "available_outgoing_kbps",
+    "current_round_trip_time",
+    "total_round_trip_time",
+    "power_watts",
+    "watts_per_mbps",
+    "header_bytes_sent",
+    "retransmitted_bytes_sent",
+    "media_kbps",
+    "retransmit_kbps",
+    "packets_sent",
+    "retransmitted_packets_sent",
+    "nack_count",
+    "remote_packets_lost",
+    "retransmitted_packets_delta",
+    "nack_count_delta",
+    "remote_packets_lost_delta",
+    "damaged_pixels",
+    "damage_percent",
+    "screen_changed_per_second",
+    "dropped_frames",
+    "dropped_frames_delta",
+    "transport_bytes_sent",
+    "transport_kbps",
+    "source_frames",
+    "source_frames_delta",
+    "source_fps",
+    "frames_sent",
+    "frames_sent_delta",
+    "capture_errors",
+    "capture_errors_delta",
+    "permanent_capture_errors",
+    "capture_error_percent",
+    "key_frames_encoded",
+    "key_frames_delta",
+    "avg_frame_bytes",
+    "delta_frame_bytes",
+    "key_frame_bytes",
+    "capture_time_percent",
+    "convert_time_percent",
+    "scale_time_percent",
+    "backpressure_frames_delta",
+    "backpressure_skipped",
+    "backpressure_skipped_delta",
+    "layers_bytes_sent",
+];
+const TICKS_HEADER: [&str; 11] = [
+    "run",
+    "id",
+    "timestamp",
+    "cpu_usage",
+    "bytes_sent",
+    "bytes_sent_delta",
+    "send_kbps",
+    "current_round_trip_time",
+    "pipeline_ms",
+    "encode_ms",
+    "system_events",
+];
+
+/* None when the stats of the track can't be read, the reason is logged. */
+async fn get_rtc_stats(
+    room: &livekit::Room,
+    track_name: &str,
+    cpu_usage: f32,
+) -> Option<Stats> {
     let mut ret_stats = Stats {
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis(),
         bytes_sent: 0,
+        layers_bytes_sent: 0,
+        header_bytes_sent: 0,
+        retransmitted_bytes_sent: 0,
+        transport_bytes_sent: 0,
+        packets_sent: 0,
+        retransmitted_packets_sent: 0,
+        nack_count: 0,
+        remote_packets_lost: 0,
+        frames_encoded: 0,
+        key_frames_encoded: 0,
+        total_encode_time: 0.,
+        target_bitrate: 0.,
+        available_outgoing_bitrate: f64::NAN,
+        current_round_trip_time: f64::NAN,
+        total_round_trip_time: f64::NAN,
         cpu_usage,
+        pipeline_time_percent: f64::NAN,
+        capture_time_ns: 0,
+        convert_time_ns: 0,
+        scale_time_ns: 0,
+        cpu_temperature: f32::NAN,
+        cpu_frequency_mhz: 0,
+        throttled: false,
+        power_watts: f64::NAN,
+        captured_pixels: 0,
+        damaged_pixels: 0,
+        screen_pixels: 0,
+        dropped_frames: 0,
+        source_frames: 0,
+        source_fps: f64::NAN,
+        frames_sent: 0,
+        frames_submitted: 0,
+        backpressure_skipped: 0,
+        capture_attempts: 0,
+        capture_errors: 0,
+        permanent_capture_errors: 0,
+        layers: vec![],
     };
     let local_participant = room.local_participant();
     for (_, publication) in local_participant.track_publications() {
+        if publication.name() != track_name {
+            continue;
+        }
         let track = publication.track();
         if track.is_none() {
             continue;
         }
         let track = track.unwrap();
         if let LocalTrack::Video(track) = track {
-            let stats = track.get_stats().await.unwrap();
+            let stats = match track.get_stats().await {
+                Ok(stats) => stats,
+                Err(e) => {
+                    log::warn!("Failed to get the RTC stats of {}: {:?}", track_name, e);
+                    return None;
+                }
+            };
             for stat in stats {
                 match stat {
+                    /* The nominated pair carries the transport wide bandwidth estimate and RTT. */
+                    livekit::webrtc::stats::RtcStats::CandidatePair(stats)
+                        if stats.candidate_pair.nominated =>
+                    {
+                        let pair = &stats.candidate_pair;
+                        ret_stats.available_outgoing_bitrate = pair.available_outgoing_bitrate;
+                        ret_stats.current_round_trip_time = pair.current_round_trip_time * 1000.;
+                        ret_stats.total_round_trip_time = pair.total_round_trip_time * 1000.;
+                    }
+                    livekit::webrtc::stats::RtcStats::Transport(stats) => {
+                        ret_stats.transport_bytes_sent += stats.transport.bytes_sent;
+                    }
+                    livekit::webrtc::stats::RtcStats::RemoteInboundRtp(stats) => {
+                        ret_stats.remote_packets_lost += stats.received.packets_lost;
+                    }
                     livekit::webrtc::stats::RtcStats::MediaSource(stats) => {
-                        let frames_sent = stats.video.frames;
-                        log::info!("Media Source Frames Sent: {}", frames_sent);
+                        ret_stats.source_frames += stats.video.frames as u64;
+                        ret_stats.source_fps = stats.video.frames_per_second;
                     }
                     livekit::webrtc::stats::RtcStats::OutboundRtp(stats) => {
                         let frames_sent = stats.outbound.frames_sent;
@@ -310,9 +2385,36 @@ async fn get_rtc_stats(room: &livekit::Room, cpu_usage: f32) -> Stats {
                         let target_bitrate = stats.outbound.target_bitrate;
                         let fps = stats.outbound.frames_per_second;
                         let total_encode_time = stats.outbound.total_encode_time;
-                        ret_stats.bytes_sent = stats.sent.bytes_sent;
+                        ret_stats.layers_bytes_sent += stats.sent.bytes_sent;
+                        ret_stats.header_bytes_sent += stats.outbound.header_bytes_sent;
+                        ret_stats.retransmitted_bytes_sent +=
+                            stats.outbound.retransmitted_bytes_sent;
+                        ret_stats.packets_sent += stats.sent.packets_sent;
+                        ret_stats.retransmitted_packets_sent +=
+                            stats.outbound.retransmitted_packets_sent;
+                        ret_stats.nack_count += stats.outbound.nack_count as u64;
+                        ret_stats.frames_sent = ret_stats.frames_sent.max(frames_sent as u64);
+                        ret_stats.frames_encoded += stats.outbound.frames_encoded as u64;
+                        ret_stats.key_frames_encoded += stats.outbound.key_frames_encoded as u64;
+                        ret_stats.total_encode_time += total_encode_time;
+                        ret_stats.target_bitrate += target_bitrate;
+                        ret_stats.layers.push(LayerStats {
+                            label: if stats.outbound.rid.is_empty() {
+                                format!("ssrc_{}", stats.stream.ssrc)
+                            } else {
+                                stats.outbound.rid.clone()
+                            },
+                            bytes_sent: stats.sent.bytes_sent,
+                            target_bitrate,
+                            frames_encoded: stats.outbound.frames_encoded as u64,
+                            key_frames_encoded: stats.outbound.key_frames_encoded as u64,
+                            total_encode_time,
+                            frame_width,
+                            frame_height,
+                            frames_per_second: fps,
+                        });
                         log::info!(
-                            "Outbound RTP Frames Sent: {}, Quality Limitation: {:?}, Quality Limitation Value: {:?}, Frame Size: {}x{}, Target Bitrate: {}, FPS: {}, Total Encode Time: {}, Total Bytes Sent: {}",
+                            "Outbound RTP Frames Sent: {}, Quality Limitation: {:?}, Quality Limitation Value: {:?}, Frame Size: {}x{}, Target Bitrate: {}, FPS: {}, Total Encode Time: {}, Bytes Sent: {}",
                             frames_sent,
                             quality_limitation,
                             quality_limitation_value,
@@ -321,7 +2423,7 @@ async fn get_rtc_stats(room: &livekit::Room, cpu_usage: f32) -> Stats {
                             target_bitrate,
                             fps,
                             total_encode_time,
-                            ret_stats.bytes_sent,
+                            stats.sent.bytes_sent,
                         );
                     }
                     _ => {}
@@ -330,25 +2432,170 @@ async fn get_rtc_stats(room: &livekit::Room, cpu_usage: f32) -> Stats {
         }
     }
 
+    ret_stats.bytes_sent = top_layer(&ret_stats.layers).map_or(0, |layer| layer.bytes_sent);
+
     log::info!("Stats: CPU Usage: {:.2}%", ret_stats.cpu_usage);
-    ret_stats
+    Some(ret_stats)
+}
+
+/*
+ * The layer with the largest frames, the one whose bytes are `bytes_sent`.
+ * Picked by size rather than by the order of the stats, which libwebrtc
+ * doesn't keep between reads, so the counter follows the same layer from
+ * one sample to the next. Equal sizes, e.g. before any frame was encoded,
+ * go to the layer that sent the most.
+ */
+fn top_layer(layers: &[LayerStats]) -> Option<&LayerStats> {
+    layers.iter().max_by_key(|layer| {
+        (
+            layer.frame_width as u64 * layer.frame_height as u64,
+            layer.bytes_sent,
+        )
+    })
+}
+
+/* Encoder counters of the track named `track_name`, None while it isn't published. */
+async fn encoder_snapshot(room: &livekit::Room, track_name: &str) -> Option<EncoderSnapshot> {
+    let publication = room
+        .local_participant()
+        .track_publications()
+        .into_values()
+        .find(|publication| publication.name() == track_name)?;
+    let Some(LocalTrack::Video(track)) = publication.track() else {
+        return None;
+    };
+    let stats = match track.get_stats().await {
+        Ok(stats) => stats,
+        Err(e) => {
+            log::warn!("Failed to get the encoder stats of {}: {:?}", track_name, e);
+            return None;
+        }
+    };
+    let mut snapshot = EncoderSnapshot::default();
+    for stat in stats {
+        if let livekit::webrtc::stats::RtcStats::OutboundRtp(stats) = stat {
+            snapshot.frames_encoded += stats.outbound.frames_encoded as u64;
+            snapshot.key_frames_encoded += stats.outbound.key_frames_encoded as u64;
+            snapshot.frame_width = snapshot.frame_width.max(stats.outbound.frame_width);
+            snapshot.frame_height = snapshot.frame_height.max(stats.outbound.frame_height);
+        }
+    }
+    Some(snapshot)
+}
+
+/* Marks the next `frames` frames, and tags the publisher sample with the tick id. */
+fn request_watermark(watermark_count: &Mutex<u32>, tick: &AtomicU64, id: Option<u64>, frames: u32) {
+    log::info!("Watermark received, marking {} frames", frames);
+    *watermark_count.lock().unwrap() = frames;
+    if let Some(id) = id {
+        tick.store(id, Ordering::Relaxed);
+    }
 }
 
 pub fn handle_room_events(
+    runtime: &AsyncRuntime,
     mut rx: tokio::sync::mpsc::UnboundedReceiver<RoomEvent>,
     watermark_count: Arc<Mutex<u32>>,
+    tick: Arc<AtomicU64>,
+    local_participant: LocalParticipant,
+    run_id: RunId,
+    control: tokio::sync::mpsc::UnboundedSender<ControlMessage>,
+    reconnects: tokio::sync::mpsc::UnboundedSender<Event>,
 ) {
-    tokio::spawn(async move {
+    runtime.handle().spawn(async move {
         while let Some(event) = rx.recv().await {
             match event {
-                RoomEvent::DataReceived { payload, .. } => {
+                RoomEvent::DataReceived {
+                    payload,
+                    topic,
+                    participant,
+                    ..
+                } => {
+                    /* Tell the client where to put its files for this run. */
+                    if topic.as_deref() == Some(RUN_ID_TOPIC) {
+                        let destination_identities = participant
+                            .map(|p| vec![p.identity()])
+                            .unwrap_or_default();
+                        let res = local_participant
+                            .publish_data(DataPacket {
+                                payload: run_id.encode().into_bytes(),
+                                topic: Some(RUN_ID_TOPIC.to_string()),
+                                reliable: true,
+                                destination_identities,
+                                ..Default::default()
+                            })
+                            .await;
+                        if let Err(e) = res {
+                            log::warn!("Failed to send run id: {:?}", e);
+                        }
+                        continue;
+                    }
+
+                    if topic.as_deref() == Some(CONTROL_TOPIC) {
+                        let received = now_us();
+                        match ControlMessage::decode_versioned(&payload) {
+                            /* Answered here, queueing behind the control loop would skew the offset. */
+                            Some((_, ControlMessage::Ping { id, sent })) => {
+                                let pong = ControlMessage::Pong {
+                                    id,
+                                    sent,
+                                    received,
+                                    replied: now_us(),
+                                };
+                                let destination_identities = participant
+                                    .map(|p| vec![p.identity()])
+                                    .unwrap_or_default();
+                                let res = local_participant
+                                    .publish_data(DataPacket {
+                                        payload: pong.encode(),
+                                        topic: Some(CONTROL_TOPIC.to_string()),
+                                        reliable: true,
+                                        destination_identities,
+                                        ..Default::default()
+                                    })
+                                    .await;
+                                if let Err(e) = res {
+                                    log::warn!("Failed to answer clock sync ping: {:?}", e);
+                                }
+                            }
+                            /* The frames right after the request are the ones timed. */
+                            Some((_, ControlMessage::Watermark { id, frames })) => {
+                                request_watermark(&watermark_count, &tick, Some(id), frames);
+                            }
+                            Some((version, ControlMessage::Unknown)) => log::debug!(
+                                "Ignoring control message of protocol version {} (ours {}): {}",
+                                version,
+                                PROTOCOL_VERSION,
+                                String::from_utf8_lossy(&payload)
+                            ),
+                            Some((_, message)) => {
+                                let _ = control.send(message);
+                            }
+                            None => log::warn!(
+                                "Invalid control message: {}",
+                                String::from_utf8_lossy(&payload)
+                            ),
+                        }
+                        continue;
+                    }
+
+                    /* Clients before the control protocol send `watermark` or `watermark:<tick id>`. */
                     let received_string = String::from_utf8_lossy(&payload);
-                    if received_string == "watermark" {
-                        log::info!("Watermark received, setting count to 10");
-                        let mut count = watermark_count.lock().unwrap();
-                        *count = 15;
+                    let mut parts = received_string.splitn(2, ':');
+                    if parts.next() == Some(LEGACY_WATERMARK) {
+                        let id = parts.next().and_then(|id| id.parse().ok());
+                        request_watermark(&watermark_count, &tick, id, WATERMARK_FRAMES);
                     }
                 }
+                /* The stream starts over after a reconnect, the run may not count. */
+                RoomEvent::Reconnecting => {
+                    log::warn!("Room reconnecting");
+                    let _ = reconnects.send(Event::new(RECONNECTING_EVENT, String::new()));
+                }
+                RoomEvent::Reconnected => {
+                    log::info!("Room reconnected");
+                    let _ = reconnects.send(Event::new(RECONNECTED_EVENT, String::new()));
+                }
                 _ => {}
             }
         }
@@ -1,10 +1,35 @@
-use clap::{value_parser, Arg, Command};
+use clap::parser::ValueSource;
+use clap::{value_parser, Arg, ArgMatches, Command};
 use livekit::options::{TrackPublishOptions, VideoCodec, VideoEncoding};
 use livekit::prelude::*;
 use livekit::track::{LocalTrack, LocalVideoTrack, TrackSource};
 use livekit::webrtc::prelude::RtcVideoSource;
-use screen_sharer::{handle_room_events, ScreenSharer};
+use screen_sharer::{
+    CaptureOptions, CodecSchedule, CodecSwitch, DEFAULT_PROFILE_FILE, DEFAULT_TRACK_NAME,
+    DisplayInfo, DisplaySpec, DropPolicy, FpsConversion, FpsMethod, FpsSchedule, MuteSchedule,
+    NO_WORKLOAD, OcclusionSchedule, OverlayConfig, OverlayKind, PixelFormat, QueueConfig,
+    ResizeSchedule, Rotation, RotationAngle, RotationMode, ScreenSharer, SentCodec, SourceSelector,
+    StaticSchedule, SwitchOutcome, VirtualDisplay, capability_json, capability_table,
+    default_workload, describe_fmtp, display_info, expand_profile, handle_room_events,
+    list_sources, parse_video_codec, probe_encoders, probe_source, retry_with_backoff,
+    room_options,
+};
+use common::control::{ControlMessage, PublisherConfig, CONTROL_TOPIC, PROTOCOL_VERSION};
+use common::events::Event;
+use common::logging::{init_tracing, RunLog};
+use common::metadata::{RunMetadata, METADATA_HEADER};
+use common::sink::{create_sinks, parse_sink_kinds, write_table, SinkConfig, SinkKind};
+use common::output::{OutputLayout, RunId, Side};
+use common::schemas::{SCHEMA_VERSION, SCHEMA_VERSION_KEY};
+use common::transport::{IceTransport, RoomFeatures};
+#[cfg(feature = "tui")]
+use common::tui::Dashboard;
+#[cfg(feature = "upload")]
+use common::upload::{upload_run, UploadTarget};
+use std::collections::HashMap;
 use std::env;
+use std::sync::Arc;
+use tracing::Instrument;
 
 #[derive(Debug, Clone)]
 enum Resolution {
@@ -23,6 +48,15 @@ impl Resolution {
             Resolution::UHD2160 => (4096, 2160),
         }
     }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Resolution::HD1080 => "1080p",
+            Resolution::QHD1440 => "1440p",
+            Resolution::HD720 => "720p",
+            Resolution::UHD2160 => "4K",
+        }
+    }
 }
 
 impl std::str::FromStr for Resolution {
@@ -39,145 +73,1700 @@ impl std::str::FromStr for Resolution {
     }
 }
 
-fn parse_video_codec(s: &str) -> Result<VideoCodec, String> {
-    match s.to_uppercase().as_str() {
-        "VP8" => Ok(VideoCodec::VP8),
-        "VP9" => Ok(VideoCodec::VP9),
-        "H264" => Ok(VideoCodec::H264),
-        "AV1" => Ok(VideoCodec::AV1),
-        "H265" => Ok(VideoCodec::H265),
-        _ => Err(format!("Invalid codec: {}. Use VP8, VP9, H264, H265, or AV1", s)),
+fn parse_sources(s: &str) -> Result<Vec<u32>, String> {
+    s.split(',')
+        .map(|source| {
+            source
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| format!("Invalid source index: {}", source))
+        })
+        .collect()
+}
+
+fn source_arg() -> Arg {
+    Arg::new("source_index")
+        .long("source")
+        .short('s')
+        .help("Screen source index, comma separated to share several screens")
+        .value_parser(parse_sources)
+        .default_value("0")
+}
+
+fn probe_timeout_arg() -> Arg {
+    Arg::new("probe_timeout")
+        .long("probe-timeout")
+        .help("Seconds to wait for the first frame of a screen")
+        .value_parser(value_parser!(u64))
+        .default_value("5")
+}
+
+/* Options of `share`, also taken without a subcommand, see `default_to_share`. */
+fn share_args() -> Vec<Arg> {
+    #[allow(unused_mut)]
+    let mut args = vec![
+        Arg::new("resolution")
+            .long("res")
+            .short('r')
+            .help("Screen resolution (1080p or 1440p)")
+            .value_parser(value_parser!(Resolution))
+            .default_value("1080p"),
+        Arg::new("duration")
+            .long("duration")
+            .short('d')
+            .help("Duration in seconds, 0 to run until the client sends finish")
+            .value_parser(value_parser!(u64))
+            .default_value("60"),
+        Arg::new("codec")
+            .long("codec")
+            .short('c')
+            .help("Video codec (VP8, VP9, H264, H265, AV1)")
+            .value_parser(parse_video_codec)
+            .default_value("VP9"),
+        Arg::new("bitrate")
+            .long("bitrate")
+            .short('b')
+            .help("Bitrate in kbps (will be multiplied by 1000)")
+            .value_parser(value_parser!(u64))
+            .default_value("4000"),
+        source_arg(),
+        Arg::new("check_permissions")
+            .long("check-permissions")
+            .help("Verify that the screens can be captured and exit")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("source_id")
+            .long("source-id")
+            .help("Platform ids of the screens to share instead of indices, comma separated (see list-sources)")
+            .value_parser(value_parser!(u64))
+            .value_delimiter(',')
+            .conflicts_with("source_index"),
+        Arg::new("source_name")
+            .long("source-name")
+            .help("Share the screen whose title contains this text, case insensitive, repeatable")
+            .value_parser(value_parser!(String))
+            .action(clap::ArgAction::Append)
+            .conflicts_with_all(["source_index", "source_id"]),
+        probe_timeout_arg(),
+        Arg::new("max_attempts")
+            .long("max-attempts")
+            .help("Attempts for connecting and publishing before giving up, with exponential backoff")
+            .value_parser(value_parser!(u32).range(1..))
+            .default_value("5"),
+        Arg::new("ice_transport")
+            .long("ice-transport")
+            .help("ICE candidates the connection may use: all, relay (TURN only) or nohost")
+            .value_parser(value_parser!(IceTransport))
+            .default_value("all"),
+        Arg::new("turn_url")
+            .long("turn-url")
+            .help("TURN server to use instead of those of the LiveKit server, e.g. turn:host:3478?transport=tcp or turns:host:443, repeatable")
+            .value_parser(value_parser!(String))
+            .action(clap::ArgAction::Append),
+        Arg::new("adaptive_stream")
+            .long("adaptive-stream")
+            .help("Enable adaptive stream for the tracks this side subscribes to")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("dynacast")
+            .long("dynacast")
+            .help("Pause the layers no subscriber receives")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("watchdog_timeout")
+            .long("watchdog-timeout")
+            .help("Seconds without a captured frame before the run is aborted, 0 to disable")
+            .value_parser(value_parser!(u64))
+            .default_value("30"),
+        Arg::new("fps")
+            .long("fps")
+            .short('f')
+            .help("Frames per second (default is 30)")
+            .value_parser(value_parser!(u32))
+            .default_value("30"),
+        Arg::new("name")
+            .long("name")
+            .short('n')
+            .help("Name for log file")
+            .value_parser(value_parser!(String))
+            .default_value("test"),
+        Arg::new("tag")
+            .long("tag")
+            .help("Free form label recorded in the run metadata, e.g. the machine or experiment")
+            .value_parser(value_parser!(String)),
+        Arg::new("track_name")
+            .long("track-name")
+            .help("Name of the published track, the screen index is appended when sharing several")
+            .value_parser(value_parser!(String))
+            .default_value(DEFAULT_TRACK_NAME),
+        Arg::new("stream_id")
+            .long("stream-id")
+            .help("Stream id the track is published under")
+            .value_parser(value_parser!(String)),
+        Arg::new("participant_metadata")
+            .long("participant-metadata")
+            .help("Metadata of the publishing participant, e.g. for clients to pick this publisher")
+            .value_parser(value_parser!(String)),
+        Arg::new("profile")
+            .long("profile")
+            .help("Take the options of this profile of the profiles file, options given here override them")
+            .value_parser(value_parser!(String)),
+        Arg::new("profile_file")
+            .long("profile-file")
+            .help("TOML file with the named profiles")
+            .value_parser(value_parser!(String))
+            .default_value(DEFAULT_PROFILE_FILE),
+        Arg::new("output_dir")
+            .long("output-dir")
+            .help("Root directory for the results")
+            .value_parser(value_parser!(String))
+            .default_value("results"),
+        Arg::new("sink")
+            .long("sink")
+            .help("Where to write the stats, comma separated (csv, sqlite, influx)")
+            .value_parser(parse_sink_kinds)
+            .default_value("csv"),
+        Arg::new("sqlite_path")
+            .long("sqlite-path")
+            .help("Database of the sqlite sink (default <output-dir>/results.sqlite)")
+            .value_parser(value_parser!(String)),
+        Arg::new("influx_url")
+            .long("influx-url")
+            .help("Write endpoint of the influx sink, the token is read from INFLUX_TOKEN")
+            .value_parser(value_parser!(String)),
+        Arg::new("simulcast")
+            .long("simulcast")
+            .help("Enable simulcast")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("wait_for_start")
+            .long("wait-for-start")
+            .help("Publish the track but only capture once a client sends a start control message")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("synthetic")
+            .long("synthetic")
+            .help("Publish a synthetic moving pattern instead of capturing the screen")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("virtual_display")
+            .long("virtual-display")
+            .help("Capture a virtual X display (Linux): <width>x<height> starts Xvfb, :<display> uses a running one")
+            .value_parser(value_parser!(DisplaySpec))
+            .conflicts_with("synthetic"),
+        Arg::new("display_workload")
+            .long("display-workload")
+            .help("Command rendering content on the virtual display, 'none' for an empty screen (default: an ffplay test pattern)")
+            .requires("virtual_display"),
+        Arg::new("allow_codec_fallback")
+            .long("allow-codec-fallback")
+            .help("Keep running when libwebrtc sends another codec than the requested one, instead of aborting")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("no_prescale")
+            .long("no-prescale")
+            .help("Feed frames at the native screen resolution and let the encoder downscale")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("scaling")
+            .long("scaling")
+            .help("Who scales the frames to the published size, `encoder` is --no-prescale")
+            .value_parser(["prescale", "encoder"])
+            .conflicts_with("no_prescale"),
+        Arg::new("capture_queue")
+            .long("capture-queue")
+            .help("Convert frames on their own thread behind a queue of this many frames, 0 converts in the capture callback")
+            .value_parser(value_parser!(usize))
+            .default_value("0"),
+        Arg::new("drop_policy")
+            .long("drop-policy")
+            .help("Frame dropped when the capture queue is full (drop-oldest or drop-newest)")
+            .value_parser(value_parser!(DropPolicy))
+            .default_value("drop-oldest"),
+        Arg::new("skip_when_behind")
+            .long("skip-when-behind")
+            .help("Skip frames while the video source takes them slower than they are submitted")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("no_damage_stats")
+            .long("no-damage-stats")
+            .help("Don't compare frames to measure how much of the screen changes")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("pixel_format")
+            .long("pixel-format")
+            .help("Byte order of the captured pixels (bgra or rgba)")
+            .value_parser(value_parser!(PixelFormat))
+            .default_value("bgra"),
+        Arg::new("rotation")
+            .long("rotation")
+            .help("Clockwise rotation of the published frames in degrees (0, 90, 180 or 270)")
+            .value_parser(value_parser!(RotationAngle))
+            .default_value("0"),
+        Arg::new("rotation_mode")
+            .long("rotation-mode")
+            .help("Signal the rotation in RTP (signal) or rotate the pixels before encoding (pre-encode)")
+            .value_parser(value_parser!(RotationMode))
+            .default_value("signal"),
+        Arg::new("resize_schedule")
+            .long("resize-schedule")
+            .help("Published resolution changes during the run, e.g. 20:1280x720,40:1920x1080 (seconds:widthxheight)")
+            .value_parser(value_parser!(ResizeSchedule)),
+        Arg::new("fps_schedule")
+            .long("fps-schedule")
+            .help("Frame rate changes during the run, e.g. 20:15,40:60 (seconds:fps), keeping the tracks")
+            .value_parser(value_parser!(FpsSchedule)),
+        Arg::new("capture_fps")
+            .long("capture-fps")
+            .help("Capture at this rate and convert to --fps before publishing, e.g. 60 for a 60 Hz capture published at 30 fps")
+            .value_parser(value_parser!(u32).range(1..=240))
+            .conflicts_with("fps_schedule"),
+        Arg::new("fps_method")
+            .long("fps-method")
+            .help("Frame rate conversion of --capture-fps (drop or blend)")
+            .value_parser(value_parser!(FpsMethod))
+            .default_value("drop"),
+        Arg::new("codec_schedule")
+            .long("codec-schedule")
+            .help("Codec switches during the run, e.g. 20:AV1,40:VP9 (seconds:codec), republishes the tracks")
+            .value_parser(value_parser!(CodecSchedule)),
+        Arg::new("occlusion_schedule")
+            .long("occlusion-schedule")
+            .help("Simulated screen locks during the run, e.g. 20:5,40:5:black (seconds:duration[:freeze|black])")
+            .value_parser(value_parser!(OcclusionSchedule)),
+        Arg::new("static_schedule")
+            .long("static-schedule")
+            .help("Static screen periods during the run, e.g. 20:10,60:30 (seconds:duration), the same frame is submitted throughout")
+            .value_parser(value_parser!(StaticSchedule)),
+        Arg::new("mute_schedule")
+            .long("mute-schedule")
+            .help("Track mutes during the run, e.g. 20:5,40:10 (seconds:duration), the time to resume is recorded in the events")
+            .value_parser(value_parser!(MuteSchedule)),
+        Arg::new("overlay")
+            .long("overlay")
+            .help("Composite a moving region onto the frames (box or text)")
+            .value_parser(value_parser!(OverlayKind)),
+        Arg::new("overlay_size")
+            .long("overlay-size")
+            .help("Overlay box side or text height in pixels")
+            .value_parser(value_parser!(u32))
+            .default_value("120"),
+        Arg::new("overlay_speed")
+            .long("overlay-speed")
+            .help("Overlay movement per frame in pixels")
+            .value_parser(value_parser!(u32))
+            .default_value("8"),
+        Arg::new("burn_in")
+            .long("burn-in")
+            .help("Draw the wall clock time and a frame counter onto every frame")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("fingerprint")
+            .long("fingerprint")
+            .help("Mark every frame with a hash of its content, for the client to detect corrupted frames")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("json_log")
+            .long("json-log")
+            .help("Also log as JSON lines with the spans of every record, next to the results of the run")
+            .action(clap::ArgAction::SetTrue),
+    ];
+    #[cfg(feature = "upload")]
+    args.push(
+        Arg::new("upload")
+            .long("upload")
+            .help("Upload the results to s3://, gs:// or an http(s):// endpoint after the run (default RESULTS_UPLOAD_URL)")
+            .value_parser(value_parser!(String)),
+    );
+    #[cfg(feature = "tui")]
+    args.push(
+        Arg::new("tui")
+            .long("tui")
+            .help("Show the progress of the run in a terminal dashboard, logging to <name>.log")
+            .action(clap::ArgAction::SetTrue),
+    );
+    args
+}
+
+/* With `--tui` the dashboard owns the terminal, the logs go to `<name>.log`. */
+#[cfg_attr(not(feature = "tui"), allow(unused_variables))]
+fn init_logging(matches: &ArgMatches) -> Option<RunLog> {
+    let share = matches.subcommand().map_or(matches, |(_, matches)| matches);
+    let json = matches!(share.try_get_one::<bool>("json_log"), Ok(Some(true)));
+    #[cfg(feature = "tui")]
+    if let Ok(Some(true)) = share.try_get_one::<bool>("tui") {
+        let path = format!("{}.log", share.get_one::<String>("name").unwrap());
+        match std::fs::File::create(&path) {
+            Ok(file) => return init_tracing(Some(file), json),
+            Err(e) => eprintln!("Failed to create {}, logging to stderr: {}", path, e),
+        }
     }
+    init_tracing(None, json)
+}
+
+/* Per second values of all screens for the dashboard. */
+#[cfg(feature = "tui")]
+#[derive(Default)]
+struct LiveProgress {
+    frames: u64,
+}
+
+#[cfg(feature = "tui")]
+impl LiveProgress {
+    fn record(&mut self, screen_sharers: &[ScreenSharer], dashboard: &Dashboard) {
+        let frames = screen_sharers
+            .iter()
+            .map(ScreenSharer::frames_captured)
+            .sum::<u64>();
+        let dropped_frames = screen_sharers
+            .iter()
+            .map(ScreenSharer::dropped_frames)
+            .sum::<u64>();
+        /* The CPU usage is the one of the process, the same for every screen. */
+        let (_, cpu_usage) = screen_sharers[0].live_stats();
+        let send_kbps = screen_sharers
+            .iter()
+            .map(|screen_sharer| screen_sharer.live_stats().0)
+            .sum::<f64>();
+        dashboard.record("fps", "fps", frames.saturating_sub(self.frames) as f64);
+        dashboard.record("send", "kbps", send_kbps);
+        dashboard.record("cpu", "%", cpu_usage);
+        let state = if screen_sharers[0].is_capturing() {
+            "capturing"
+        } else {
+            "paused"
+        };
+        dashboard.status(format!(
+            "{}, {} frames captured, {} dropped",
+            state, frames, dropped_frames
+        ));
+        self.frames = frames;
+    }
+}
+
+/* Destination of `--upload` or RESULTS_UPLOAD_URL, the run is refused when it's invalid. */
+#[cfg(feature = "upload")]
+fn upload_target(matches: &ArgMatches) -> Option<UploadTarget> {
+    let destination = matches
+        .get_one::<String>("upload")
+        .cloned()
+        .or_else(|| env::var("RESULTS_UPLOAD_URL").ok())?;
+    match destination.parse::<UploadTarget>() {
+        Ok(target) => Some(target.with_token(env::var("UPLOAD_TOKEN").ok())),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/* Subcommands of the command line, anything else before the options selects `share`. */
+const SUBCOMMANDS: [&str; 6] = [
+    "share",
+    "list-sources",
+    "probe",
+    "probe-encoders",
+    "sweep",
+    "help",
+];
+
+/*
+ * Without a subcommand the arguments are those of `share`, so existing
+ * scripts keep working. Inserting it keeps the options registered once.
+ */
+fn default_to_share(mut args: Vec<String>) -> Vec<String> {
+    let explicit = args.get(1).is_some_and(|arg| {
+        SUBCOMMANDS.contains(&arg.as_str())
+            || ["-h", "--help", "-V", "--version"].contains(&arg.as_str())
+    });
+    if !explicit {
+        args.insert(1.min(args.len()), "share".to_string());
+    }
+    args
 }
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
-
+    /* The options of `--profile` are added before parsing, so they are checked like any other. */
+    let args = match expand_profile(default_to_share(env::args().collect())) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
     let matches = Command::new("Screen Sharer")
         .version("1.0")
         .about("LiveKit screen sharing application")
-        .arg(
-            Arg::new("resolution")
-                .long("res")
-                .short('r')
-                .help("Screen resolution (1080p or 1440p)")
-                .value_parser(value_parser!(Resolution))
-                .default_value("1080p")
-        )
-        .arg(
-            Arg::new("duration")
-                .long("duration")
-                .short('d')
-                .help("Duration in seconds")
-                .value_parser(value_parser!(u64))
-                .default_value("60")
-        )
-        .arg(
-            Arg::new("codec")
-                .long("codec")
-                .short('c')
-                .help("Video codec (VP8, VP9, H264, H265, AV1)")
-                .value_parser(parse_video_codec)
-                .default_value("VP9")
-        )
-        .arg(
-            Arg::new("bitrate")
-                .long("bitrate")
-                .short('b')
-                .help("Bitrate in kbps (will be multiplied by 1000)")
-                .value_parser(value_parser!(u64))
-                .default_value("4000")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("share")
+                .about("Share screens in a LiveKit room and record the stats (the default)")
+                .args(share_args())
+                /* The command line overrides the profile. */
+                .args_override_self(true)
         )
-        .arg(
-            Arg::new("source_index")
-                .long("source")
-                .short('s')
-                .help("Screen source index")
-                .value_parser(value_parser!(u32))
-                .default_value("0")
+        .subcommand(
+            Command::new("list-sources")
+                .about("List the screens that can be shared")
         )
-        .arg(
-            Arg::new("fps")
-                .long("fps")
-                .short('f')
-                .help("Frames per second (default is 30)")
-                .value_parser(value_parser!(u32))
-                .default_value("30")
+        .subcommand(
+            Command::new("probe")
+                .about("Verify that the screens can be captured and exit")
+                .arg(source_arg())
+                .arg(probe_timeout_arg())
         )
-        .arg(
-            Arg::new("name")
-                .long("name")
-                .short('n')
-                .help("Name for log file")
-                .value_parser(value_parser!(String))
-                .default_value("test")
+        .subcommand(
+            Command::new("probe-encoders")
+                .about("Report the video encoders and decoders of the local webrtc build and exit")
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .help("Seconds to wait for frames to go through each codec")
+                        .value_parser(value_parser!(u64))
+                        .default_value("5")
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("JSON file the capabilities are saved to")
+                        .default_value("encoder_capabilities.json")
+                )
         )
-        .arg(
-            Arg::new("simulcast")
-                .long("simulcast")
-                .help("Enable simulcast")
-                .action(clap::ArgAction::SetTrue)
+        .subcommand(
+            Command::new("sweep")
+                .about("Run `share` once per combination of codecs, bitrates and resolutions")
+                .arg(
+                    Arg::new("codec")
+                        .long("codec")
+                        .short('c')
+                        .help("Video codecs, comma separated")
+                        .value_parser(parse_video_codec)
+                        .value_delimiter(',')
+                        .default_value("VP8,VP9,H264,AV1")
+                )
+                .arg(
+                    Arg::new("bitrate")
+                        .long("bitrate")
+                        .short('b')
+                        .help("Bitrates in kbps, comma separated")
+                        .value_parser(value_parser!(u64))
+                        .value_delimiter(',')
+                        .default_value("4000")
+                )
+                .arg(
+                    Arg::new("resolution")
+                        .long("res")
+                        .short('r')
+                        .help("Resolutions, comma separated")
+                        .value_parser(value_parser!(Resolution))
+                        .value_delimiter(',')
+                        .default_value("1080p")
+                )
+                .arg(
+                    Arg::new("pause")
+                        .long("pause")
+                        .help("Seconds to wait between runs")
+                        .value_parser(value_parser!(u64))
+                        .default_value("5")
+                )
+                .arg(
+                    Arg::new("share_args")
+                        .help("Arguments passed to every share run, after --")
+                        .num_args(0..)
+                        .allow_hyphen_values(true)
+                        .last(true)
+                )
         )
-        .get_matches();
+        .get_matches_from(args);
 
+    let run_log = init_logging(&matches);
+
+    match matches.subcommand() {
+        Some(("share", matches)) => share(matches, run_log).await,
+        Some(("list-sources", _)) => print_sources(),
+        Some(("probe", matches)) => probe(matches),
+        Some(("probe-encoders", matches)) => report_encoders(matches).await,
+        Some(("sweep", matches)) => sweep(matches),
+        _ => unreachable!("a subcommand is required"),
+    }
+}
+
+async fn share(matches: &ArgMatches, run_log: Option<RunLog>) {
     let resolution = matches.get_one::<Resolution>("resolution").unwrap();
     let duration = *matches.get_one::<u64>("duration").unwrap();
-    let codec = matches.get_one::<VideoCodec>("codec").unwrap().clone();
+    let mut codec = matches.get_one::<VideoCodec>("codec").unwrap().clone();
     let bitrate = *matches.get_one::<u64>("bitrate").unwrap();
-    let source_index = *matches.get_one::<u32>("source_index").unwrap();
+    let selectors: Vec<SourceSelector> = if let Some(ids) = matches.get_many::<u64>("source_id") {
+        ids.map(|id| SourceSelector::Id(*id)).collect()
+    } else if let Some(names) = matches.get_many::<String>("source_name") {
+        names.map(|name| SourceSelector::Name(name.clone())).collect()
+    } else {
+        matches
+            .get_one::<Vec<u32>>("source_index")
+            .unwrap()
+            .iter()
+            .map(|index| SourceSelector::Index(*index))
+            .collect()
+    };
+    let probe_timeout = *matches.get_one::<u64>("probe_timeout").unwrap();
+    let max_attempts = *matches.get_one::<u32>("max_attempts").unwrap();
+    let watchdog_timeout = *matches.get_one::<u64>("watchdog_timeout").unwrap();
     let fps = *matches.get_one::<u32>("fps").unwrap();
     let name = matches.get_one::<String>("name").unwrap();
+    let output_dir = matches.get_one::<String>("output_dir").unwrap();
+    let tag = matches.get_one::<String>("tag");
+    let track_name = matches.get_one::<String>("track_name").unwrap();
+    let stream_id = matches.get_one::<String>("stream_id").cloned();
+    let participant_metadata = matches.get_one::<String>("participant_metadata").cloned();
+    let sink_config = SinkConfig {
+        kinds: matches.get_one::<Vec<SinkKind>>("sink").unwrap().clone(),
+        sqlite_path: matches.get_one::<String>("sqlite_path").map(Into::into),
+        influx_url: matches.get_one::<String>("influx_url").cloned(),
+        influx_token: env::var("INFLUX_TOKEN").ok(),
+    };
     let simulcast = matches.get_flag("simulcast");
+    let wait_for_start = matches.get_flag("wait_for_start");
+    let synthetic = matches.get_flag("synthetic");
+
+    /* Opened before the screens are listed, so the capturer finds it. */
+    let display_workload = matches
+        .get_one::<DisplaySpec>("virtual_display")
+        .and_then(|spec| {
+            let (width, height) = match spec {
+                DisplaySpec::Start { width, height } => (*width, *height),
+                DisplaySpec::Attach(_) => resolution.dimensions(),
+            };
+            match matches.get_one::<String>("display_workload") {
+                Some(workload) if workload == NO_WORKLOAD => None,
+                Some(workload) => Some(workload.clone()),
+                None => Some(default_workload(width, height, fps)),
+            }
+        });
+    let virtual_display = match matches.get_one::<DisplaySpec>("virtual_display") {
+        Some(spec) => match VirtualDisplay::open(spec, display_workload.as_deref()) {
+            Ok(display) => {
+                display.make_current();
+                println!("Capturing virtual display {}", display.name());
+                Some(display)
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    /* Ids and names are resolved to indices, indices to what they point at for the metadata. */
+    let listed = if synthetic {
+        Vec::new()
+    } else {
+        list_sources().unwrap_or_else(|e| {
+            log::warn!("Failed to list the screens: {}", e);
+            Vec::new()
+        })
+    };
+    let mut sources = Vec::new();
+    let mut resolved = Vec::new();
+    for selector in &selectors {
+        match (selector, selector.resolve(&listed)) {
+            (_, Ok(source)) => {
+                println!("Screen {}: {} (id {})", source.index, source.title, source.id);
+                sources.push(source.index);
+                resolved.push(source.clone());
+            }
+            /* An index is used as is, e.g. on backends that don't enumerate. */
+            (SourceSelector::Index(index), Err(_)) => sources.push(*index),
+            (_, Err(e)) => {
+                eprintln!("Failed to select the screen: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if matches.get_flag("check_permissions") {
+        let ok = probe_sources(&sources, std::time::Duration::from_secs(probe_timeout));
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+    let no_prescale = matches.get_flag("no_prescale")
+        || matches.get_one::<String>("scaling").is_some_and(|scaling| scaling == "encoder");
+    let rotation = Rotation {
+        angle: *matches.get_one::<RotationAngle>("rotation").unwrap(),
+        mode: *matches.get_one::<RotationMode>("rotation_mode").unwrap(),
+    };
+    let resize_schedule = matches.get_one::<ResizeSchedule>("resize_schedule").cloned();
+    let fps_schedule = matches.get_one::<FpsSchedule>("fps_schedule").cloned();
+    /* The published maximum frame rate, high enough for every step so the tracks stay. */
+    let fps_ceiling = fps_schedule
+        .as_ref()
+        .and_then(FpsSchedule::max_fps)
+        .unwrap_or(0);
+    let codec_schedule = matches.get_one::<CodecSchedule>("codec_schedule").cloned();
+    let occlusion_schedule = matches
+        .get_one::<OcclusionSchedule>("occlusion_schedule")
+        .cloned();
+    let static_schedule = matches
+        .get_one::<StaticSchedule>("static_schedule")
+        .cloned();
+    let mute_schedule = matches.get_one::<MuteSchedule>("mute_schedule").cloned();
+    /* Nothing scales the native frames to --res: the SDK can't make the encoder downscale the top layer. */
+    if no_prescale && matches.value_source("resolution") == Some(ValueSource::CommandLine) {
+        eprintln!("--res needs prescaling, it can't be combined with --no-prescale");
+        std::process::exit(1);
+    }
+    if resize_schedule.is_some() && no_prescale {
+        eprintln!("--resize-schedule needs prescaling, it can't be combined with --no-prescale");
+        std::process::exit(1);
+    }
+    let fps_conversion = matches.get_one::<u32>("capture_fps").map(|capture_fps| FpsConversion {
+        capture_fps: *capture_fps,
+        publish_fps: fps,
+        method: *matches.get_one::<FpsMethod>("fps_method").unwrap(),
+    });
+    if fps_conversion.is_some_and(|conversion| conversion.capture_fps < fps) {
+        eprintln!("--capture-fps only lowers the frame rate, it can't be below --fps {}", fps);
+        std::process::exit(1);
+    }
+    let overlay = matches.get_one::<OverlayKind>("overlay").map(|kind| OverlayConfig {
+        kind: *kind,
+        size: *matches.get_one::<u32>("overlay_size").unwrap(),
+        speed: *matches.get_one::<u32>("overlay_speed").unwrap(),
+    });
 
     let (width, height) = resolution.dimensions();
 
-    let url = env::var("LIVEKIT_URL").expect("LIVEKIT_URL environment variable not set");
-    let token = env::var("LIVEKIT_TOKEN").expect("LIVEKIT_TOKEN environment variable not set");
+    let queue = match *matches.get_one::<usize>("capture_queue").unwrap() {
+        0 => None,
+        depth => Some(QueueConfig {
+            depth,
+            policy: *matches.get_one::<DropPolicy>("drop_policy").unwrap(),
+        }),
+    };
+    let fingerprint = matches.get_flag("fingerprint");
+    let capture_options = CaptureOptions {
+        overlay,
+        no_prescale,
+        pixel_format: *matches.get_one::<PixelFormat>("pixel_format").unwrap(),
+        probe_timeout: Some(std::time::Duration::from_secs(probe_timeout)),
+        rotation,
+        no_damage_stats: matches.get_flag("no_damage_stats"),
+        burn_in: matches.get_flag("burn_in"),
+        queue,
+        fingerprint,
+        skip_when_behind: matches.get_flag("skip_when_behind"),
+        fps_conversion,
+    };
+
+
+    let resolution_label = if no_prescale { "native" } else { resolution.label() };
+    let run_id = RunId::new(&format!("{:?}", codec), resolution_label, bitrate, name);
+    let layout = OutputLayout::new(output_dir, run_id.clone());
+    /* The records of the run carry its id, also in the threads started for it. */
+    let run_span = tracing::info_span!("run", run = %run_id.uuid, name = %run_id.name);
+    async {
+        if let Some(run_log) = &run_log {
+            let path = layout.file(Side::Publisher, "log").with_extension("jsonl");
+            if let Err(e) = run_log.open(&path) {
+                log::error!("Failed to create the JSON log {}: {}", path.display(), e);
+            }
+        }
+        #[cfg(feature = "upload")]
+        let upload = upload_target(matches);
+
+        let url = env::var("LIVEKIT_URL").expect("LIVEKIT_URL environment variable not set");
+        let token = env::var("LIVEKIT_TOKEN").expect("LIVEKIT_TOKEN environment variable not set");
+
+        let ice_transport = *matches.get_one::<IceTransport>("ice_transport").unwrap();
+        let turn_urls: Vec<String> = matches
+            .get_many::<String>("turn_url")
+            .map(|urls| urls.cloned().collect())
+            .unwrap_or_default();
+        let room_features = RoomFeatures {
+            adaptive_stream: matches.get_flag("adaptive_stream"),
+            dynacast: matches.get_flag("dynacast"),
+        };
+        let room_options = room_options(ice_transport, turn_urls, room_features);
+        let connection = retry_with_backoff("Connecting", max_attempts, || {
+            Room::connect(&url, &token, room_options.clone())
+        })
+        .await;
+        let (room, mut rx) = match connection {
+            Ok(connection) => connection,
+            Err(e) => {
+                eprintln!("Failed to connect to the room: {:?}", e);
+                std::process::exit(1);
+            }
+        };
+        println!("Connected to room: {}", room.name());
+        println!("Run: {}, results in {}", layout.run(), layout.run_dir().display());
+        println!("Configuration: {}x{} @ {} fps, {} codec, {} kbps, simulcast: {}",
+                 width, height, fps, format!("{:?}", codec), bitrate,
+                 if simulcast { "enabled" } else { "disabled" });
+
+        let mut screen_sharers = Vec::new();
+        for source_index in &sources {
+            let screen_sharer = if synthetic {
+                ScreenSharer::new_synthetic(width, height, capture_options.clone())
+            } else {
+                ScreenSharer::new(width, height, *source_index, capture_options.clone())
+            };
+            let mut screen_sharer = match screen_sharer {
+                Ok(screen_sharer) => screen_sharer,
+                Err(e) => {
+                    eprintln!("Failed to share screen {}: {}", source_index, e);
+                    std::process::exit(1);
+                }
+            };
+            screen_sharer.set_track_name(track_name, stream_id.as_deref());
+            if sources.len() > 1 {
+                screen_sharer.set_tag(&format!("source{}", source_index));
+            }
+            screen_sharers.push(screen_sharer);
+        }
+        /* Latency is measured on the first screen, only it reacts to watermark requests. */
+        let primary = &screen_sharers[0];
+
+        let mut metadata = RunMetadata::new();
+        if let Some(tag) = tag {
+            metadata.set("tag", tag);
+        }
+        metadata.set("track_name", primary.track_name());
+        if let Some(stream_id) = &stream_id {
+            metadata.set("stream_id", stream_id);
+        }
+        if let Some(participant_metadata) = &participant_metadata {
+            metadata.set("participant_metadata", participant_metadata);
+        }
+        if let Some(profile) = matches.get_one::<String>("profile") {
+            metadata.set("profile", profile);
+            metadata.set("profile_file", matches.get_one::<String>("profile_file").unwrap());
+        }
+        metadata.set(SCHEMA_VERSION_KEY, SCHEMA_VERSION);
+        metadata.set("git_commit", env!("BUILD_GIT_COMMIT"));
+        metadata.set("livekit_version", env!("BUILD_LIVEKIT_VERSION"));
+        metadata.set("libwebrtc_version", env!("BUILD_LIBWEBRTC_VERSION"));
+        metadata.set("pixel_format", primary.pixel_format().as_str());
+        metadata.set("rotation", rotation);
+        metadata.set("burn_in", matches.get_flag("burn_in"));
+        metadata.set("fingerprint", fingerprint);
+        if let Some(display) = &virtual_display {
+            metadata.set("virtual_display", display.name());
+            metadata.set("display_workload", display_workload.as_deref().unwrap_or(NO_WORKLOAD));
+        }
+        if let Some(queue) = &queue {
+            metadata.set("capture_queue", queue.depth);
+            metadata.set("drop_policy", queue.policy.as_str());
+        }
+        if let Some(schedule) = &occlusion_schedule {
+            metadata.set(
+                "occlusion_schedule",
+                schedule
+                    .0
+                    .iter()
+                    .map(|step| {
+                        let (at, duration) = (step.at.as_secs(), step.duration.as_secs());
+                        format!("{}:{}:{}", at, duration, step.mode.as_str())
+                    })
+                    .collect::<Vec<_>>()
+                    .join(","),
+            );
+        }
+        if let Some(conversion) = &fps_conversion {
+            metadata.set("capture_fps", conversion.capture_fps);
+            metadata.set("fps_method", conversion.method.as_str());
+        }
+        if let Some(schedule) = &fps_schedule {
+            metadata.set(
+                "fps_schedule",
+                schedule
+                    .0
+                    .iter()
+                    .map(|step| format!("{}:{}", step.at.as_secs(), step.fps))
+                    .collect::<Vec<_>>()
+                    .join(","),
+            );
+        }
+        if let Some(schedule) = &codec_schedule {
+            metadata.set("codec_schedule", schedule.label());
+        }
+        metadata.set("ice_transport", ice_transport.as_str());
+        room_features.record(&mut metadata);
+        if let Some(schedule) = &static_schedule {
+            metadata.set(
+                "static_schedule",
+                schedule
+                    .0
+                    .iter()
+                    .map(|step| format!("{}:{}", step.at.as_secs(), step.duration.as_secs()))
+                    .collect::<Vec<_>>()
+                    .join(","),
+            );
+        }
+        if let Some(schedule) = &mute_schedule {
+            metadata.set(
+                "mute_schedule",
+                schedule
+                    .0
+                    .iter()
+                    .map(|step| format!("{}:{}", step.at.as_secs(), step.duration.as_secs()))
+                    .collect::<Vec<_>>()
+                    .join(","),
+            );
+        }
+        metadata.set(
+            "sources",
+            sources.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(" "),
+        );
+        if !resolved.is_empty() {
+            metadata.set(
+                "source_ids",
+                resolved.iter().map(|s| s.id.to_string()).collect::<Vec<_>>().join(" "),
+            );
+            metadata.set(
+                "source_titles",
+                resolved.iter().map(|s| s.title.as_str()).collect::<Vec<_>>().join("; "),
+            );
+        }
+        /* A HiDPI screen hands the encoder downscaled content, one entry per shared screen. */
+        if !synthetic {
+            let displays: Vec<_> = sources
+                .iter()
+                .map(|index| display_info(resolved.iter().find(|s| s.index == *index)))
+                .collect();
+            for (index, display) in sources.iter().zip(&displays) {
+                match display {
+                    Some(display) => log::info!("Screen {}: {}", index, display.summary()),
+                    None => log::warn!("Screen {}: scale factor and refresh rate unknown", index),
+                }
+            }
+            if displays.iter().any(Option::is_some) {
+                let entries = |entry: &dyn Fn(&DisplayInfo) -> String| {
+                    displays
+                        .iter()
+                        .map(|display| display.as_ref().map_or("unknown".to_string(), entry))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                };
+                /* A desktop wide scale is recorded once rather than repeated as if per screen. */
+                match displays.iter().flatten().find(|d| d.scale_global) {
+                    Some(display) => metadata.set(
+                        "display_global_scale_factor",
+                        format!("{:.2}", display.scale_factor),
+                    ),
+                    None => metadata.set(
+                        "display_scale_factor",
+                        entries(&|d| format!("{:.2}", d.scale_factor)),
+                    ),
+                }
+                metadata.set(
+                    "display_physical_resolution",
+                    entries(&|d| format!("{}x{}", d.physical.0, d.physical.1)),
+                );
+                metadata.set(
+                    "display_logical_resolution",
+                    entries(&|d| format!("{}x{}", d.logical.0, d.logical.1)),
+                );
+                metadata.set("display_refresh_hz", entries(&|d| format!("{:.2}", d.refresh_hz)));
+            }
+        }
+
+        /* Tell the subscribers how the frames were oriented and marked. */
+        let attributes = HashMap::from([
+            ("rotation".to_string(), rotation.to_string()),
+            ("fingerprint".to_string(), fingerprint.to_string()),
+        ]);
+        if let Err(e) = room.local_participant().set_attributes(attributes).await {
+            log::warn!("Failed to set participant attributes: {:?}", e);
+        }
+        if let Some(participant_metadata) = &participant_metadata {
+            let result = room
+                .local_participant()
+                .set_metadata(participant_metadata.clone())
+                .await;
+            if let Err(e) = result {
+                log::warn!("Failed to set participant metadata: {:?}", e);
+            }
+        }
 
-    let (room, mut rx) = Room::connect(&url, &token, RoomOptions::default())
-        .await
-        .unwrap();
-    println!("Connected to room: {}", room.name());
-    println!("Configuration: {}x{} @ {} fps, {} codec, {} kbps, simulcast: {}",
-             width, height, fps, format!("{:?}", codec), bitrate,
-             if simulcast { "enabled" } else { "disabled" });
+        let mut config = PublisherConfig {
+            run: run_id.uuid.clone(),
+            codec: format!("{:?}", codec),
+            width: primary.stream_size().0,
+            height: primary.stream_size().1,
+            fps,
+            bitrate,
+            simulcast,
+            sources: sources.clone(),
+            scaling: if no_prescale { "encoder" } else { "prescale" }.to_string(),
+            rotation: rotation.to_string(),
+            sent_codec: String::new(),
+            duration,
+            protocol: PROTOCOL_VERSION,
+        };
+        let mut track_sids =
+            match publish_screens(&room, &screen_sharers, &codec, &config, fps_ceiling, max_attempts)
+                .await
+            {
+                Ok(track_sids) => track_sids,
+                Err(e) => {
+                    eprintln!("Failed to publish the screen tracks: {:?}", e);
+                    std::process::exit(1);
+                }
+            };
+        /* Clients already in the room record the config without asking for it. */
+        send_control(&room, &ControlMessage::Config(config.clone())).await;
 
-    let mut screen_sharer = ScreenSharer::new(width, height, source_index).unwrap();
+        let (control_tx, mut control_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (reconnect_tx, mut reconnect_rx) = tokio::sync::mpsc::unbounded_channel();
+        handle_room_events(
+            primary.runtime(),
+            rx,
+            primary.watermark_count(),
+            primary.tick(),
+            room.local_participant(),
+            run_id,
+            control_tx,
+            reconnect_tx,
+        );
 
-    let track = LocalVideoTrack::create_video_track(
-        "screen_share",
-        RtcVideoSource::Native(screen_sharer.buffer_source()),
+        let room = Arc::new(room);
+        for screen_sharer in &mut screen_sharers {
+            screen_sharer.set_capturing(!wait_for_start);
+            screen_sharer.start_capture(room.clone(), &layout, &sink_config);
+        }
+        #[cfg(feature = "tui")]
+        let dashboard = matches.get_flag("tui").then(|| {
+            let title = format!(
+                "{:?} {} {} kbps, {}",
+                codec,
+                resolution_label,
+                bitrate,
+                name
+            );
+            Dashboard::start(
+                &title,
+                (duration > 0).then(|| std::time::Duration::from_secs(duration)),
+            )
+        });
+        #[cfg(feature = "tui")]
+        let dashboard = match dashboard.transpose() {
+            Ok(dashboard) => dashboard,
+            Err(e) => {
+                log::error!("Failed to start the dashboard: {}", e);
+                None
+            }
+        };
+        #[cfg(feature = "tui")]
+        let mut live_progress = LiveProgress::default();
+
+        /* Without a duration the run lasts until the client sends `finish`. */
+        let deadline = async {
+            if duration > 0 {
+                tokio::time::sleep(std::time::Duration::from_secs(duration)).await;
+            } else {
+                std::future::pending::<()>().await;
+            }
+        };
+        tokio::pin!(deadline);
+        let mut heartbeat = tokio::time::interval(std::time::Duration::from_secs(1));
+        /* Frame count of every screen and when it last moved, for the watchdog. */
+        let mut progress: Vec<(u64, std::time::Instant)> = screen_sharers
+            .iter()
+            .map(|screen_sharer| (screen_sharer.frames_captured(), std::time::Instant::now()))
+            .collect();
+        /* Frame count of every screen, when it last moved and whether a stall was recorded. */
+        let mut stalls: Vec<(u64, std::time::Instant, bool)> = screen_sharers
+            .iter()
+            .map(|screen_sharer| {
+                (
+                    screen_sharer.frames_captured(),
+                    std::time::Instant::now(),
+                    false,
+                )
+            })
+            .collect();
+        let mut stalled = false;
+        let mut source_lost = false;
+        let mut finished_by_client = false;
+        let allow_codec_fallback = matches.get_flag("allow_codec_fallback");
+        /* Read back once the encoder runs, and again after every republish. */
+        let mut sent_codec: Option<SentCodec> = None;
+        let mut codec_fallback = false;
+        let start = std::time::Instant::now();
+        let mut resize_steps = resize_schedule
+            .map(|schedule| schedule.0)
+            .unwrap_or_default()
+            .into_iter()
+            .peekable();
+        let mut occlusion_steps = occlusion_schedule
+            .map(|schedule| schedule.0)
+            .unwrap_or_default()
+            .into_iter()
+            .peekable();
+        /* Time since the start at which the current occlusion ends. */
+        let mut occlusion_end = None;
+        let mut static_steps = static_schedule
+            .map(|schedule| schedule.0)
+            .unwrap_or_default()
+            .into_iter()
+            .peekable();
+        /* Time since the start at which the current static period ends. */
+        let mut static_end: Option<std::time::Duration> = None;
+        let mut fps_steps = fps_schedule
+            .map(|schedule| schedule.0)
+            .unwrap_or_default()
+            .into_iter()
+            .peekable();
+        let mut codec_steps = codec_schedule
+            .map(|schedule| schedule.0)
+            .unwrap_or_default()
+            .into_iter()
+            .peekable();
+        let mut codec_switch = codec_steps.peek().is_some().then(CodecSwitch::default);
+        /* The capture follows the frame rate from the start, so the steps compare. */
+        if fps_steps.peek().is_some() {
+            for screen_sharer in &screen_sharers {
+                screen_sharer.set_frame_rate(config.fps);
+            }
+        }
+        let mut mute_steps = mute_schedule
+            .map(|schedule| schedule.0)
+            .unwrap_or_default()
+            .into_iter()
+            .peekable();
+        /* When the current mute ends, and the encoder state of every screen before it. */
+        let mut mute_end: Option<std::time::Duration> = None;
+        let mut mute_baselines = vec![None; screen_sharers.len()];
+        loop {
+            tokio::select! {
+                _ = &mut deadline => break,
+                _ = heartbeat.tick() => {
+                    /* The capture thread gave up on a screen that disappeared. */
+                    if let Some(screen_sharer) = screen_sharers.iter().find(|s| s.source_lost()) {
+                        log::error!("{}: the screen is gone, stopping the run", screen_sharer.track_name());
+                        source_lost = true;
+                        break;
+                    }
+                    if watchdog_timeout > 0 {
+                        for (screen_sharer, (frames, since)) in screen_sharers.iter().zip(&mut progress) {
+                            let captured = screen_sharer.frames_captured();
+                            /* A paused or deliberately occluded capture doesn't count as stalled. */
+                            if captured != *frames
+                                || !screen_sharer.is_capturing()
+                                || screen_sharer.occlusion().is_some()
+                            {
+                                *frames = captured;
+                                *since = std::time::Instant::now();
+                            } else if since.elapsed().as_secs() >= watchdog_timeout {
+                                let detail = format!(
+                                    "no frame captured for {} s on {}, {} frames captured",
+                                    watchdog_timeout,
+                                    screen_sharer.track_name(),
+                                    captured
+                                );
+                                log::error!("Watchdog: {}", detail);
+                                screen_sharer.log_diagnostics(&room).await;
+                                screen_sharer.record_event(Event::new("watchdog", detail));
+                                stalled = true;
+                            }
+                        }
+                        if stalled {
+                            break;
+                        }
+                    }
+                    /*
+                     * Frames stopping while capturing, a locked or covered screen
+                     * or a simulated one, are recorded as they happen.
+                     */
+                    for (screen_sharer, (frames, moved, stall)) in
+                        screen_sharers.iter().zip(&mut stalls)
+                    {
+                        let captured = screen_sharer.frames_captured();
+                        if captured != *frames || !screen_sharer.is_capturing() {
+                            if *stall {
+                                let detail = format!(
+                                    "{} after about {} ms without frames",
+                                    screen_sharer.track_name(),
+                                    moved.elapsed().as_millis()
+                                );
+                                log::info!("Capture resumed: {}", detail);
+                                screen_sharer.record_event(Event::new("capture_resumed", detail));
+                            }
+                            *frames = captured;
+                            *moved = std::time::Instant::now();
+                            *stall = false;
+                        } else if !*stall && moved.elapsed() >= std::time::Duration::from_secs(1) {
+                            let detail =
+                                format!("{} delivered no frames for 1 s", screen_sharer.track_name());
+                            log::warn!("Capture stalled: {}", detail);
+                            screen_sharer.record_event(Event::new("capture_stalled", detail));
+                            *stall = true;
+                        }
+                    }
+                    if occlusion_end.is_some_and(|end| start.elapsed() >= end) {
+                        occlusion_end = None;
+                        log::info!("Occlusion ended");
+                        for screen_sharer in &screen_sharers {
+                            screen_sharer.set_occlusion(None);
+                            let detail = screen_sharer.track_name();
+                            screen_sharer.record_event(Event::new("occlusion_ended", detail));
+                        }
+                    }
+                    while let Some(step) = occlusion_steps.next_if(|step| start.elapsed() >= step.at) {
+                        let detail =
+                            format!("{} for {} s", step.mode.as_str(), step.duration.as_secs());
+                        log::info!("Occluding the screens: {}", detail);
+                        for screen_sharer in &screen_sharers {
+                            screen_sharer.set_occlusion(Some(step.mode));
+                            screen_sharer.record_event(Event::new("occlusion_started", detail.clone()));
+                        }
+                        occlusion_end = Some(step.at + step.duration);
+                    }
+                    if static_end.is_some_and(|end| start.elapsed() >= end) {
+                        static_end = None;
+                        log::info!("Static period ended, the screens move again");
+                        for screen_sharer in &screen_sharers {
+                            screen_sharer.set_static(false);
+                            let detail = screen_sharer.track_name();
+                            screen_sharer.record_event(Event::new("static_ended", detail));
+                        }
+                    }
+                    while let Some(step) = static_steps.next_if(|step| start.elapsed() >= step.at) {
+                        let detail = format!("for {} s", step.duration.as_secs());
+                        log::info!("Repeating the last frame of the screens {}", detail);
+                        for screen_sharer in &screen_sharers {
+                            screen_sharer.set_static(true);
+                            screen_sharer.record_event(Event::new("static_started", detail.clone()));
+                        }
+                        /* Overlapping periods extend the one in progress. */
+                        static_end = static_end.max(Some(step.at + step.duration));
+                    }
+                    if mute_end.is_some_and(|end| start.elapsed() >= end) {
+                        mute_end = None;
+                        for (screen_sharer, baseline) in screen_sharers.iter().zip(&mut mute_baselines) {
+                            let Some(baseline) = baseline.take() else {
+                                continue;
+                            };
+                            if let Err(e) = screen_sharer.unmute(room.clone(), baseline).await {
+                                log::warn!("Failed to unmute {}: {}", screen_sharer.track_name(), e);
+                            }
+                        }
+                    }
+                    while let Some(step) = mute_steps.next_if(|step| start.elapsed() >= step.at) {
+                        log::info!("Muting the screens for {} s", step.duration.as_secs());
+                        for (screen_sharer, baseline) in screen_sharers.iter().zip(&mut mute_baselines) {
+                            /* Overlapping mutes extend the one in progress. */
+                            if baseline.is_some() {
+                                continue;
+                            }
+                            match screen_sharer.mute(&room).await {
+                                Ok(snapshot) => *baseline = Some(snapshot),
+                                Err(e) => {
+                                    log::warn!("Failed to mute {}: {}", screen_sharer.track_name(), e)
+                                }
+                            }
+                        }
+                        mute_end = mute_end.max(Some(step.at + step.duration));
+                    }
+                    while let Some(step) = resize_steps.next_if(|step| start.elapsed() >= step.at) {
+                        for screen_sharer in &screen_sharers {
+                            if let Err(e) = screen_sharer.resize(step.width, step.height) {
+                                log::warn!("Failed to resize {}: {}", screen_sharer.track_name(), e);
+                            }
+                        }
+                        config.width = step.width;
+                        config.height = step.height;
+                        send_control(&room, &ControlMessage::Config(config.clone())).await;
+                    }
+                    /* Of steps due at the same time only the last rate counts. */
+                    let mut new_fps = None;
+                    while let Some(step) = fps_steps.next_if(|step| start.elapsed() >= step.at) {
+                        new_fps = Some(step.fps);
+                    }
+                    if let Some(fps) = new_fps.filter(|fps| *fps != config.fps) {
+                        log::info!("Changing the frame rate from {} to {} fps", config.fps, fps);
+                        for screen_sharer in &screen_sharers {
+                            screen_sharer.set_frame_rate(fps);
+                        }
+                        config.fps = fps;
+                        send_control(&room, &ControlMessage::Config(config.clone())).await;
+                    }
+                    /* Steps due at the same time only republish once, with the last codec. */
+                    let mut new_codec = None;
+                    while let Some(step) = codec_steps.next_if(|step| start.elapsed() >= step.at) {
+                        new_codec = Some(step.codec);
+                    }
+                    if let Some(switch) = &mut codec_switch {
+                        switch.sample();
+                        if let Some(next) = new_codec.filter(|next| *next != codec) {
+                            let detail = switch.start(codec.clone(), next.clone());
+                            log::info!("Switching codec: {}", detail);
+                            for screen_sharer in &screen_sharers {
+                                screen_sharer.record_event(Event::new("codec_switch_started", detail.clone()));
+                            }
+                            let mut next_config = config.clone();
+                            next_config.codec = format!("{:?}", next);
+                            let republished = republish_screens(
+                                &room,
+                                &screen_sharers,
+                                &next,
+                                &next_config,
+                                fps_ceiling,
+                                max_attempts,
+                                &mut track_sids,
+                            )
+                            .await;
+                            match republished {
+                                Ok(()) => {
+                                    codec = next;
+                                    config = next_config;
+                                    sent_codec = None;
+                                    config.sent_codec.clear();
+                                    send_control(&room, &ControlMessage::Config(config.clone())).await;
+                                }
+                                /* The previous tracks are still sent, the client keeps their config. */
+                                Err(e) => {
+                                    switch.cancel();
+                                    let detail = format!("{}, staying on {:?}: {:?}", detail, codec, e);
+                                    log::error!("Codec switch failed: {}", detail);
+                                    for screen_sharer in &screen_sharers {
+                                        screen_sharer.record_event(Event::new("codec_switch_failed", detail.clone()));
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    /* libwebrtc doesn't report a codec fallback, the stats tell once frames are sent. */
+                    if sent_codec.is_none() {
+                        sent_codec = screen_sharers[0].sent_codec(&room).await;
+                        if let Some(sent) = &sent_codec {
+                            config.sent_codec = sent.name.clone();
+                            send_control(&room, &ControlMessage::Config(config.clone())).await;
+                            if sent.matches(&codec) {
+                                log::info!("Sending {} with {}", sent.name, sent.implementation);
+                            } else if codec_switch.as_ref().is_some_and(CodecSwitch::in_progress) {
+                                /* The old track may still report its codec right after a switch. */
+                                sent_codec = None;
+                            } else {
+                                let detail = format!(
+                                    "requested {:?}, sending {} with {}",
+                                    codec, sent.name, sent.implementation
+                                );
+                                log::error!("Codec fallback: {}", detail);
+                                eprintln!("Codec fallback: {}", detail);
+                                for screen_sharer in &screen_sharers {
+                                    screen_sharer.record_event(Event::new("codec_fallback", detail.clone()));
+                                }
+                                if !allow_codec_fallback {
+                                    codec_fallback = true;
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    if let Some(switch) = &mut codec_switch {
+                        let sending = sent_codec.as_ref().is_some_and(|sent| sent.matches(&codec));
+                        if let Some((outcome, detail)) = switch.finish(sending) {
+                            let kind = match outcome {
+                                SwitchOutcome::Sending(_) => {
+                                    log::info!("Codec switched: {}", detail);
+                                    "codec_switched"
+                                }
+                                SwitchOutcome::TimedOut => {
+                                    log::warn!("Codec switch timed out: {}", detail);
+                                    "codec_switch_timeout"
+                                }
+                            };
+                            for screen_sharer in &screen_sharers {
+                                screen_sharer.record_event(Event::new(kind, detail.clone()));
+                            }
+                        }
+                    }
+                    let timestamp = std::time::SystemTime::now()
+                        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                        .as_millis() as u64;
+                    #[cfg(feature = "tui")]
+                    if let Some(dashboard) = &dashboard {
+                        live_progress.record(&screen_sharers, dashboard);
+                    }
+                    let capturing = screen_sharers[0].is_capturing();
+                    send_control(&room, &ControlMessage::Heartbeat { timestamp, capturing }).await;
+                }
+                Some(event) = reconnect_rx.recv() => {
+                    for screen_sharer in &screen_sharers {
+                        screen_sharer.record_event(event.clone());
+                    }
+                }
+                Some(message) = control_rx.recv() => {
+                    log::info!("Control message: {:?}", message);
+                    match message {
+                        ControlMessage::Start | ControlMessage::Stop => {
+                            let capturing = message == ControlMessage::Start;
+                            for screen_sharer in &screen_sharers {
+                                screen_sharer.set_capturing(capturing);
+                            }
+                        }
+                        ControlMessage::GetConfig => {
+                            send_control(&room, &ControlMessage::Config(config.clone())).await;
+                        }
+                        ControlMessage::Finish => {
+                            let detail = format!("by the client after {} s", start.elapsed().as_secs());
+                            log::info!("Run finished {}", detail);
+                            for screen_sharer in &screen_sharers {
+                                screen_sharer.record_event(Event::new("run_finished", detail.clone()));
+                            }
+                            finished_by_client = true;
+                            break;
+                        }
+                        ControlMessage::SetParameters { bitrate, fps } => {
+                            let mut next_config = config.clone();
+                            next_config.bitrate = bitrate.unwrap_or(config.bitrate);
+                            next_config.fps = fps.unwrap_or(config.fps);
+                            let republished = republish_screens(
+                                &room,
+                                &screen_sharers,
+                                &codec,
+                                &next_config,
+                                fps_ceiling,
+                                max_attempts,
+                                &mut track_sids,
+                            )
+                            .await;
+                            match republished {
+                                Ok(()) => {
+                                    config = next_config;
+                                    sent_codec = None;
+                                    config.sent_codec.clear();
+                                    send_control(&room, &ControlMessage::Config(config.clone())).await;
+                                }
+                                /* The previous tracks are still sent, the client keeps their config. */
+                                Err(e) => log::error!("Failed to republish the screen tracks: {:?}", e),
+                            }
+                        }
+                        ControlMessage::Config(_)
+                        | ControlMessage::Heartbeat { .. }
+                        | ControlMessage::Ping { .. }
+                        | ControlMessage::Pong { .. }
+                        | ControlMessage::Watermark { .. }
+                        | ControlMessage::Unknown => {}
+                    }
+                }
+            }
+        }
+        #[cfg(feature = "tui")]
+        if let Some(dashboard) = &dashboard {
+            dashboard.stop();
+        }
+        /* What the peers agreed on, which may not be what was asked for. */
+        if let Some(sent) = screen_sharers[0].sent_codec(&room).await.or(sent_codec) {
+            log::info!("Negotiated codec parameters: {}", sent.fmtp);
+            if let Some(negotiated) = describe_fmtp(&sent.fmtp) {
+                metadata.set("negotiated_profile", negotiated);
+            }
+            metadata.set("negotiated_fmtp", sent.fmtp);
+            metadata.set("encoder_implementation", sent.implementation);
+            config.sent_codec = sent.name;
+        }
+        match screen_sharers[0].candidate_pair(&room).await {
+            Some(pair) => {
+                log::info!("Sent over {}", pair);
+                pair.record(&mut metadata);
+            }
+            None => log::warn!("No selected candidate pair, the transport isn't recorded"),
+        }
+        for screen_sharer in &mut screen_sharers {
+            screen_sharer.stop_capture(&layout, &sink_config);
+        }
+
+        /* The config the run ended with, parameters may have been changed by the client. */
+        for (key, value) in config.entries() {
+            metadata.set(key, value);
+        }
+        metadata.set(
+            "skipped_stats_samples",
+            screen_sharers.iter().map(ScreenSharer::skipped_stats).sum::<u64>(),
+        );
+        metadata.set("finished_by_client", finished_by_client);
+        /* Against the bitrate the run ended with, of the screen the latency is measured on. */
+        match screen_sharers[0].bitrate_adherence(config.bitrate as f64) {
+            Some(adherence) => {
+                log::info!("Bitrate adherence: {}", adherence.summary());
+                adherence.record(&mut metadata);
+            }
+            None => log::warn!("No send rate samples, bitrate adherence unknown"),
+        }
+        metadata.set("skip_when_behind", matches.get_flag("skip_when_behind"));
+        metadata.set(
+            "backpressure_skipped",
+            screen_sharers.iter().map(ScreenSharer::backpressure_skipped).sum::<u64>(),
+        );
+        if fps_conversion.is_some() {
+            metadata.set(
+                "fps_converted",
+                screen_sharers.iter().map(ScreenSharer::fps_converted).sum::<u64>(),
+            );
+        }
+        if queue.is_some() {
+            metadata.set(
+                "dropped_frames",
+                screen_sharers.iter().map(ScreenSharer::dropped_frames).sum::<u64>(),
+            );
+        }
+        match create_sinks(&sink_config, &layout, Side::Publisher) {
+            Ok(mut sinks) => {
+                write_table(&mut sinks, "metadata", &METADATA_HEADER, &metadata.rows())
+            }
+            Err(e) => log::error!("Failed to create stats sinks: {}", e),
+        }
+        /* Wait for the logs to be written. */
+        std::thread::sleep(std::time::Duration::from_secs(5));
+        #[cfg(feature = "upload")]
+        if let Some(target) = &upload {
+            match upload_run(&layout, Side::Publisher, target) {
+                Ok(files) => log::info!("Uploaded {} result files", files),
+                Err(e) => log::error!("Failed to upload the results: {}", e),
+            }
+        }
+        if stalled {
+            eprintln!("Aborted by the watchdog, the results are incomplete");
+            std::process::exit(1);
+        }
+        if codec_fallback {
+            eprintln!(
+                "Aborted, the track was sent with {} instead of {:?}",
+                config.sent_codec, codec
+            );
+            std::process::exit(1);
+        }
+        if source_lost {
+            eprintln!("A shared screen disconnected, the results are incomplete");
+            std::process::exit(1);
+        }
+    }
+    .instrument(run_span)
+    .await;
+}
+
+fn print_sources() {
+    match list_sources() {
+        Ok(sources) if sources.is_empty() => {
+            println!("The capturer doesn't list screens, it picks the screen itself")
+        }
+        Ok(sources) => {
+            for source in sources {
+                println!("{}: {} (id {})", source.index, source.title, source.id);
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to list the screens: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/* Catch a missing permission before a long benchmark, not after it. */
+fn probe(matches: &ArgMatches) {
+    let sources = matches.get_one::<Vec<u32>>("source_index").unwrap();
+    let timeout = std::time::Duration::from_secs(*matches.get_one::<u64>("probe_timeout").unwrap());
+    let ok = probe_sources(sources, timeout);
+    std::process::exit(if ok { 0 } else { 1 });
+}
+
+/* Reports whether every screen delivers a frame, as `probe` and `share --check-permissions` do. */
+fn probe_sources(sources: &[u32], timeout: std::time::Duration) -> bool {
+    let mut ok = true;
+    for source_index in sources {
+        match probe_source(*source_index, timeout) {
+            Ok((width, height)) => {
+                println!("Screen {}: {}x{}, capture works", source_index, width, height)
+            }
+            Err(e) => {
+                println!("Screen {}: {}", source_index, e);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/* Tells which codecs can be compared on this machine, and with which encoders, before a benchmark. */
+async fn report_encoders(matches: &ArgMatches) {
+    let timeout = std::time::Duration::from_secs(*matches.get_one::<u64>("timeout").unwrap());
+    let output = matches.get_one::<String>("output").unwrap();
+    let capabilities = probe_encoders(timeout).await;
+    println!("{}", capability_table(&capabilities));
+
+    let system = sysinfo::System::new_with_specifics(
+        sysinfo::RefreshKind::nothing().with_cpu(sysinfo::CpuRefreshKind::nothing()),
     );
+    let machine = serde_json::json!({
+        "os": sysinfo::System::long_os_version().unwrap_or_default(),
+        "arch": std::env::consts::ARCH,
+        "cpu": system.cpus().first().map(|cpu| cpu.brand().to_string()).unwrap_or_default(),
+        "git_commit": env!("BUILD_GIT_COMMIT"),
+        "livekit_version": env!("BUILD_LIVEKIT_VERSION"),
+        "libwebrtc_version": env!("BUILD_LIBWEBRTC_VERSION"),
+    });
+    let json = capability_json(&capabilities, machine);
+    let written = serde_json::to_string_pretty(&json)
+        .map_err(|e| e.to_string())
+        .and_then(|json| std::fs::write(output, json + "\n").map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        eprintln!("Failed to write {}: {}", output, e);
+        std::process::exit(1);
+    }
+    println!("Saved to {}", output);
+}
 
-    let res = room
-        .local_participant()
-        .publish_track(
-            LocalTrack::Video(track),
-            TrackPublishOptions {
+/*
+ * Runs `share` in a child process per combination, one after the other, so
+ * every run starts from a fresh connection and encoder. A failed run is
+ * reported and the sweep goes on, the exit status tells whether all passed.
+ */
+fn sweep(matches: &ArgMatches) {
+    let codecs: Vec<&VideoCodec> = matches.get_many::<VideoCodec>("codec").unwrap().collect();
+    let bitrates: Vec<u64> = matches.get_many::<u64>("bitrate").unwrap().copied().collect();
+    let resolutions: Vec<&Resolution> =
+        matches.get_many::<Resolution>("resolution").unwrap().collect();
+    let pause = std::time::Duration::from_secs(*matches.get_one::<u64>("pause").unwrap());
+    let share_args: Vec<&String> = matches
+        .get_many::<String>("share_args")
+        .map(|args| args.collect())
+        .unwrap_or_default();
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprintln!("Failed to find the screen_sharer binary: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut failed = Vec::new();
+    let runs = codecs.len() * bitrates.len() * resolutions.len();
+    let mut run = 0;
+    for codec in &codecs {
+        for bitrate in &bitrates {
+            for resolution in &resolutions {
+                if run > 0 {
+                    std::thread::sleep(pause);
+                }
+                run += 1;
+                let label = format!("{:?} {} kbps {}", codec, bitrate, resolution.label());
+                println!("Run {}/{}: {}", run, runs, label);
+                let status = std::process::Command::new(&exe)
+                    .arg("share")
+                    .arg(format!("--codec={:?}", codec))
+                    .arg(format!("--bitrate={}", bitrate))
+                    .arg(format!("--res={}", resolution.label()))
+                    .args(&share_args)
+                    .status();
+                match status {
+                    Ok(status) if status.success() => {}
+                    Ok(status) => {
+                        eprintln!("Run {} failed: {}", label, status);
+                        failed.push(label);
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to start run {}: {}", label, e);
+                        failed.push(label);
+                    }
+                }
+            }
+        }
+    }
+    if !failed.is_empty() {
+        eprintln!("{} of {} runs failed: {}", failed.len(), runs, failed.join(", "));
+        std::process::exit(1);
+    }
+}
+
+/// Publishes a track per screen with the encoding of `config`, returns the
+/// track sids. The maximum frame rate is at least `fps_ceiling`.
+async fn publish_screens(
+    room: &Room,
+    screen_sharers: &[ScreenSharer],
+    codec: &VideoCodec,
+    config: &PublisherConfig,
+    fps_ceiling: u32,
+    max_attempts: u32,
+) -> RoomResult<Vec<TrackSid>> {
+    let mut track_sids = Vec::new();
+    for screen_sharer in screen_sharers {
+        let what = format!("Publishing {}", screen_sharer.track_name());
+        let span = tracing::info_span!("publish", track = %screen_sharer.track_name());
+        let publication = retry_with_backoff(&what, max_attempts, || {
+            let track = LocalVideoTrack::create_video_track(
+                &screen_sharer.track_name(),
+                RtcVideoSource::Native(screen_sharer.buffer_source()),
+            );
+            let options = TrackPublishOptions {
                 source: TrackSource::Screenshare,
-                video_codec: codec,
+                video_codec: codec.clone(),
                 video_encoding: Some(VideoEncoding {
-                    max_bitrate: bitrate * 1000,
-                    max_framerate: fps as f64,
+                    max_bitrate: config.bitrate * 1000,
+                    max_framerate: config.fps.max(fps_ceiling) as f64,
                 }),
-                simulcast,
+                simulcast: config.simulcast,
+                stream: screen_sharer.stream_id().unwrap_or_default().to_string(),
                 ..Default::default()
-            },
-        )
-        .await
-        .unwrap();
-
-    handle_room_events(rx, screen_sharer.watermark_count());
-
-    screen_sharer.start_capture(room);
-    std::thread::sleep(std::time::Duration::from_secs(duration));
-    screen_sharer.stop_capture(
-        &format!("{:?}", codec),
-        &format!("{}p", if height == 1080 { "1080" } else if height == 1440 { "1440" } else { "720" }),
-        bitrate,
-        &name,
-    );
-    /* Wait for the logs to be written. */
-    std::thread::sleep(std::time::Duration::from_secs(5));
+            };
+            let local_participant = room.local_participant();
+            async move {
+                local_participant
+                    .publish_track(LocalTrack::Video(track), options)
+                    .await
+            }
+        })
+        .instrument(span)
+        .await;
+        match publication {
+            Ok(publication) => track_sids.push(publication.sid()),
+            /* All or nothing, so a failed republish leaves only the previous tracks. */
+            Err(e) => {
+                unpublish_screens(room, &track_sids).await;
+                return Err(e);
+            }
+        }
+    }
+    Ok(track_sids)
+}
+
+async fn unpublish_screens(room: &Room, track_sids: &[TrackSid]) {
+    for sid in track_sids {
+        if let Err(e) = room.local_participant().unpublish_track(sid).await {
+            log::warn!("Failed to unpublish track {}: {:?}", sid, e);
+        }
+    }
+}
+
+/// Replaces the tracks in `track_sids` with ones with the encoding of
+/// `config`, which can't be changed on a published track. The new tracks
+/// are published first, when that fails the previous ones stay.
+async fn republish_screens(
+    room: &Room,
+    screen_sharers: &[ScreenSharer],
+    codec: &VideoCodec,
+    config: &PublisherConfig,
+    fps_ceiling: u32,
+    max_attempts: u32,
+    track_sids: &mut Vec<TrackSid>,
+) -> RoomResult<()> {
+    let published = publish_screens(
+        room,
+        screen_sharers,
+        codec,
+        config,
+        fps_ceiling,
+        max_attempts,
+    )
+    .await?;
+    unpublish_screens(room, track_sids).await;
+    *track_sids = published;
+    Ok(())
+}
+
+/// Broadcasts `message` on the control topic.
+async fn send_control(room: &Room, message: &ControlMessage) {
+    let res = room
+        .local_participant()
+        .publish_data(DataPacket {
+            payload: message.encode(),
+            topic: Some(CONTROL_TOPIC.to_string()),
+            reliable: true,
+            ..Default::default()
+        })
+        .await;
+    if let Err(e) = res {
+        log::warn!("Failed to send control message: {:?}", e);
+    }
 }
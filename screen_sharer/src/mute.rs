@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+/*
+ * Track mutes during a run. Muting the screen share and unmuting it again
+ * is an everyday operation, and encoders differ a lot in how quickly they
+ * are back to full quality afterwards: some send a key frame right away,
+ * others wait for one to be requested or restart from a low resolution.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MuteStep {
+    /// Time since the start of the run.
+    pub at: Duration,
+    pub duration: Duration,
+}
+
+/// Mutes parsed from `<seconds>:<duration>,...`, e.g. `20:5,40:10`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MuteSchedule(pub Vec<MuteStep>);
+
+impl std::str::FromStr for MuteSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut steps = s
+            .split(',')
+            .map(|entry| {
+                let (at, duration) = entry
+                    .split_once(':')
+                    .ok_or_else(|| format!("Invalid mute: {}. Use <seconds>:<duration>", entry))?;
+                let at = at
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid mute time: {}", at))?;
+                let duration = duration
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|duration| *duration > 0)
+                    .ok_or_else(|| format!("Invalid mute duration: {}", duration))?;
+                Ok(MuteStep {
+                    at: Duration::from_secs(at),
+                    duration: Duration::from_secs(duration),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        steps.sort_by_key(|step| step.at);
+        Ok(MuteSchedule(steps))
+    }
+}
+
+/// Encoder counters of a track, summed over its layers, with the size of
+/// the largest layer.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EncoderSnapshot {
+    pub frames_encoded: u64,
+    pub key_frames_encoded: u64,
+    pub frame_width: u32,
+    pub frame_height: u32,
+}
+
+/*
+ * Follows the encoder after an unmute until it is back to the frame size it
+ * had before the mute, with a key frame encoded since the unmute, which is
+ * when the receivers can show full quality video again.
+ */
+pub struct ResumeTracker {
+    baseline: EncoderSnapshot,
+    at_unmute: EncoderSnapshot,
+    first_frame: Option<Duration>,
+    key_frame: Option<Duration>,
+    full_size: Option<Duration>,
+}
+
+impl ResumeTracker {
+    /* `baseline` is read before the mute, `at_unmute` right before the unmute. */
+    pub fn new(baseline: EncoderSnapshot, at_unmute: EncoderSnapshot) -> Self {
+        ResumeTracker {
+            baseline,
+            at_unmute,
+            first_frame: None,
+            key_frame: None,
+            full_size: None,
+        }
+    }
+
+    /// Takes a snapshot read `elapsed` after the unmute, returns whether
+    /// the encoder is back to full quality.
+    pub fn update(&mut self, snapshot: &EncoderSnapshot, elapsed: Duration) -> bool {
+        if snapshot.frames_encoded > self.at_unmute.frames_encoded {
+            self.first_frame.get_or_insert(elapsed);
+        }
+        if snapshot.key_frames_encoded > self.at_unmute.key_frames_encoded {
+            self.key_frame.get_or_insert(elapsed);
+        }
+        if self.first_frame.is_some()
+            && snapshot.frame_width >= self.baseline.frame_width
+            && snapshot.frame_height >= self.baseline.frame_height
+        {
+            self.full_size.get_or_insert(elapsed);
+        }
+        self.key_frame.is_some() && self.full_size.is_some()
+    }
+
+    /// Time to full quality, the later of the key frame and the full size.
+    pub fn resumed(&self) -> Option<Duration> {
+        Some(self.key_frame?.max(self.full_size?))
+    }
+
+    /// What was reached so far, e.g. for the event detail.
+    pub fn describe(&self) -> String {
+        let ms = |at: Option<Duration>| match at {
+            Some(at) => format!("{} ms", at.as_millis()),
+            None => "never".to_string(),
+        };
+        format!(
+            "full quality after {}, first frame after {}, key frame after {}, {}x{} after {}",
+            ms(self.resumed()),
+            ms(self.first_frame),
+            ms(self.key_frame),
+            self.baseline.frame_width,
+            self.baseline.frame_height,
+            ms(self.full_size)
+        )
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+/*
+ * Simulated screen lock or occlusion during a run. Depending on the
+ * platform a locked or covered screen either stops delivering frames or
+ * delivers black ones, and pipelines differ in what the receiver sees then:
+ * a frozen last frame, black video, or a paused track.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OcclusionMode {
+    /// The capturer delivers no frames.
+    Freeze,
+    /// The capturer delivers black frames.
+    Black,
+}
+
+impl OcclusionMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OcclusionMode::Freeze => "freeze",
+            OcclusionMode::Black => "black",
+        }
+    }
+
+    /* The mode travels to the capture callback in an atomic, 0 when not occluded. */
+    pub fn encode(mode: Option<OcclusionMode>) -> u8 {
+        match mode {
+            None => 0,
+            Some(OcclusionMode::Freeze) => 1,
+            Some(OcclusionMode::Black) => 2,
+        }
+    }
+
+    pub fn decode(value: u8) -> Option<OcclusionMode> {
+        match value {
+            1 => Some(OcclusionMode::Freeze),
+            2 => Some(OcclusionMode::Black),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OcclusionStep {
+    /// Time since the start of the run.
+    pub at: Duration,
+    pub duration: Duration,
+    pub mode: OcclusionMode,
+}
+
+/// Occlusions parsed from `<seconds>:<duration>[:freeze|black],...`,
+/// e.g. `20:5,40:5:black`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OcclusionSchedule(pub Vec<OcclusionStep>);
+
+impl std::str::FromStr for OcclusionSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut steps = s
+            .split(',')
+            .map(|entry| {
+                let mut parts = entry.trim().split(':');
+                let (Some(at), Some(duration)) = (parts.next(), parts.next()) else {
+                    return Err(format!(
+                        "Invalid occlusion: {}. Use <seconds>:<duration>[:freeze|black]",
+                        entry
+                    ));
+                };
+                let at = at
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid occlusion time: {}", at))?;
+                let duration = duration
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|duration| *duration > 0)
+                    .ok_or_else(|| format!("Invalid occlusion duration: {}", duration))?;
+                let mode = match parts.next() {
+                    None | Some("freeze") => OcclusionMode::Freeze,
+                    Some("black") => OcclusionMode::Black,
+                    Some(mode) => {
+                        return Err(format!(
+                            "Invalid occlusion mode: {}. Use 'freeze' or 'black'",
+                            mode
+                        ));
+                    }
+                };
+                Ok(OcclusionStep {
+                    at: Duration::from_secs(at),
+                    duration: Duration::from_secs(duration),
+                    mode,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        steps.sort_by_key(|step| step.at);
+        Ok(OcclusionSchedule(steps))
+    }
+}
@@ -0,0 +1,197 @@
+use common::{fingerprint, font};
+use livekit::webrtc::prelude::{NV12Buffer, VideoBuffer};
+
+/* Text scrolled across the frame by the text overlay. */
+const OVERLAY_TEXT: &str = "THE QUICK BROWN FOX JUMPS OVER THE LAZY DOG 0123456789";
+/* Luma used for the overlay content. */
+const OVERLAY_LUMA: u8 = 235;
+/* Chroma of the bouncing box, a saturated red. */
+const BOX_U: u8 = 90;
+const BOX_V: u8 = 240;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverlayKind {
+    /// A filled box bouncing off the frame edges.
+    Box,
+    /// A line of text scrolling from right to left.
+    Text,
+}
+
+impl std::str::FromStr for OverlayKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "box" => Ok(OverlayKind::Box),
+            "text" => Ok(OverlayKind::Text),
+            _ => Err(format!("Invalid overlay: {}. Use 'box' or 'text'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OverlayConfig {
+    pub kind: OverlayKind,
+    /// Box side or text height in pixels.
+    pub size: u32,
+    /// Movement per frame in pixels.
+    pub speed: u32,
+}
+
+/*
+ * Synthetic moving region composited onto every frame before it is
+ * submitted, so all codecs see the same motion regardless of what is
+ * happening on the desktop.
+ */
+pub struct MotionOverlay {
+    config: OverlayConfig,
+    x: i64,
+    y: i64,
+    dx: i64,
+    dy: i64,
+}
+
+impl MotionOverlay {
+    pub fn new(config: OverlayConfig) -> Self {
+        let speed = config.speed as i64;
+        MotionOverlay {
+            config,
+            x: 0,
+            y: 0,
+            dx: speed,
+            dy: speed,
+        }
+    }
+
+    pub fn apply(&mut self, buffer: &mut NV12Buffer) {
+        match self.config.kind {
+            OverlayKind::Box => self.draw_box(buffer),
+            OverlayKind::Text => self.draw_text(buffer),
+        }
+    }
+
+    fn draw_box(&mut self, buffer: &mut NV12Buffer) {
+        let width = buffer.width() as i64;
+        let height = buffer.height() as i64;
+        let size = (self.config.size as i64).min(width).min(height) & !1;
+        if size <= 0 {
+            return;
+        }
+
+        self.x += self.dx;
+        self.y += self.dy;
+        if self.x < 0 || self.x + size > width {
+            self.dx = -self.dx;
+            self.x = self.x.clamp(0, width - size);
+        }
+        if self.y < 0 || self.y + size > height {
+            self.dy = -self.dy;
+            self.y = self.y.clamp(0, height - size);
+        }
+
+        let (s_y, s_uv) = buffer.strides();
+        let (dst_y, dst_uv) = buffer.data_mut();
+        let (x, y, size) = (self.x as usize, self.y as usize, size as usize);
+        for row in y..y + size {
+            let start = row * s_y as usize + x;
+            dst_y[start..start + size].fill(OVERLAY_LUMA);
+        }
+        for row in y / 2..(y + size) / 2 {
+            let start = row * s_uv as usize + (x / 2) * 2;
+            for pair in dst_uv[start..start + size].chunks_exact_mut(2) {
+                pair[0] = BOX_U;
+                pair[1] = BOX_V;
+            }
+        }
+    }
+
+    fn draw_text(&mut self, buffer: &mut NV12Buffer) {
+        let width = buffer.width() as usize;
+        let height = buffer.height() as usize;
+        let scale = (self.config.size as usize / font::GLYPH_HEIGHT).max(1);
+        let text_width = font::text_width(OVERLAY_TEXT, scale) as i64;
+
+        self.x -= self.dx;
+        if self.x < -text_width || self.x > width as i64 {
+            self.x = width as i64;
+        }
+        let y = (height.saturating_sub(font::GLYPH_HEIGHT * scale) / 2) as i64;
+
+        let (s_y, _) = buffer.strides();
+        let (dst_y, _) = buffer.data_mut();
+        let mut plane = font::Plane {
+            data: dst_y,
+            stride: s_y as usize,
+            width,
+            height,
+        };
+        font::draw_text(&mut plane, self.x, y, scale, OVERLAY_TEXT, OVERLAY_LUMA);
+    }
+}
+
+/* Dark box behind the burn-in text, so it stays readable on any screen. */
+const BURN_IN_BACKGROUND: u8 = 16;
+/* Text height of the burn-in at 1080p, scaled with the frame height. */
+const BURN_IN_LINES: usize = 270;
+
+/*
+ * Wall clock time (UTC) and frame counter drawn into the bottom left corner
+ * of every submitted frame, so client side dumps and screen recordings can
+ * be checked for latency and dropped frames by eye. Drawn after rotating,
+ * away from the watermark rows at the top, and above the fingerprint marker.
+ */
+pub struct BurnIn {
+    frames: u64,
+    above_fingerprint: bool,
+}
+
+impl BurnIn {
+    pub fn new(above_fingerprint: bool) -> Self {
+        BurnIn {
+            frames: 0,
+            above_fingerprint,
+        }
+    }
+
+    pub fn apply(&mut self, buffer: &mut NV12Buffer) {
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let text = format!(
+            "{:02}:{:02}:{:02}.{:03} F{}",
+            millis / 3_600_000 % 24,
+            millis / 60_000 % 60,
+            millis / 1000 % 60,
+            millis % 1000,
+            self.frames
+        );
+        self.frames += 1;
+
+        let width = buffer.width() as usize;
+        let mut height = buffer.height() as usize;
+        let scale = (height / BURN_IN_LINES).max(1);
+        if self.above_fingerprint {
+            height = height.saturating_sub(fingerprint::marker_rows(height));
+        }
+        let margin = font::GLYPH_HEIGHT * scale / 2;
+        let box_width = (font::text_width(&text, scale) + 2 * margin).min(width);
+        let box_height = (font::GLYPH_HEIGHT * scale + 2 * margin).min(height);
+
+        let (s_y, _) = buffer.strides();
+        let (dst_y, _) = buffer.data_mut();
+        let top = height - box_height;
+        for row in top..height {
+            let start = row * s_y as usize;
+            dst_y[start..start + box_width].fill(BURN_IN_BACKGROUND);
+        }
+        let mut plane = font::Plane {
+            data: dst_y,
+            stride: s_y as usize,
+            width,
+            height,
+        };
+        let (x, y) = (margin as i64, (top + margin) as i64);
+        font::draw_text(&mut plane, x, y, scale, &text, OVERLAY_LUMA);
+    }
+}
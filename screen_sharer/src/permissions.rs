@@ -0,0 +1,40 @@
+/*
+ * Screen recording permission checks. Without the permission macOS still
+ * delivers frames, but they only contain black (or the wallpaper), so a
+ * benchmark would silently encode an empty screen.
+ */
+
+#[cfg(target_os = "macos")]
+#[link(name = "CoreGraphics", kind = "framework")]
+unsafe extern "C" {
+    fn CGPreflightScreenCaptureAccess() -> bool;
+}
+
+/// Whether the process may record the screen, None where there is no such
+/// permission to check.
+pub fn screen_capture_allowed() -> Option<bool> {
+    #[cfg(target_os = "macos")]
+    {
+        Some(unsafe { CGPreflightScreenCaptureAccess() })
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        None
+    }
+}
+
+/* Black frames only mean a missing permission where the OS blanks them. */
+pub const BLACK_FRAMES_MEAN_DENIED: bool = cfg!(target_os = "macos");
+
+/// Checks a sparse grid of pixels of an ARGB frame for anything not black.
+pub fn is_black_frame(data: &[u8], stride: u32, width: i32, height: i32) -> bool {
+    let (stride, width, height) = (stride as usize, width as usize, height as usize);
+    let step_x = (width / 64).max(1);
+    let step_y = (height / 64).max(1);
+    (0..height).step_by(step_y).all(|y| {
+        (0..width).step_by(step_x).all(|x| {
+            let p = y * stride + x * 4;
+            data.get(p..p + 3).is_none_or(|bgr| bgr.iter().all(|c| *c == 0))
+        })
+    })
+}
@@ -0,0 +1,216 @@
+/*
+ * Power draw of the machine while it publishes. On laptops watts per
+ * megabit says more about an encoder than CPU percent, hardware encoders
+ * barely show up in the process CPU usage but do in the package power.
+ * Both sources measure the whole package (or SoC), not just this process,
+ * so idle the machine for comparable numbers.
+ */
+
+pub trait PowerSampler: Send {
+    /// Name of the source, for the logs.
+    fn name(&self) -> &'static str;
+
+    /// Average power in watts since the previous call, None while no
+    /// reading is available yet.
+    fn sample(&mut self) -> Option<f64>;
+}
+
+/// The sampler of this platform, None when it has no usable power source.
+pub fn power_sampler() -> Option<Box<dyn PowerSampler>> {
+    #[cfg(target_os = "linux")]
+    let sampler = rapl::RaplSampler::new().map(|s| Box::new(s) as Box<dyn PowerSampler>);
+    #[cfg(target_os = "macos")]
+    let sampler =
+        powermetrics::PowermetricsSampler::new().map(|s| Box::new(s) as Box<dyn PowerSampler>);
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    let sampler: Result<Box<dyn PowerSampler>, String> =
+        Err("no power source on this platform".to_string());
+
+    match sampler {
+        Ok(sampler) => {
+            log::info!("Sampling power with {}", sampler.name());
+            Some(sampler)
+        }
+        Err(e) => {
+            log::warn!("Power sampling unavailable: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod rapl {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::Instant;
+
+    const POWERCAP: &str = "/sys/class/powercap";
+
+    /* A package domain, intel-rapl:N, whose energy counter wraps at `max_uj`. */
+    struct Domain {
+        energy: PathBuf,
+        max_uj: u64,
+        last_uj: u64,
+    }
+
+    /// Package energy counters of the Intel/AMD RAPL interface. They are
+    /// root readable only on recent kernels.
+    pub struct RaplSampler {
+        domains: Vec<Domain>,
+        last: Instant,
+    }
+
+    fn read_u64(path: &std::path::Path) -> Result<u64, String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?
+            .trim()
+            .parse()
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    impl RaplSampler {
+        pub fn new() -> Result<Self, String> {
+            let entries = std::fs::read_dir(POWERCAP).map_err(|e| format!("{}: {}", POWERCAP, e))?;
+            let mut domains = Vec::new();
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                /* Subdomains (core, uncore, dram) are part of their package. */
+                if !name.starts_with("intel-rapl:") || name.matches(':').count() != 1 {
+                    continue;
+                }
+                let dir = entry.path();
+                let energy = dir.join("energy_uj");
+                domains.push(Domain {
+                    max_uj: read_u64(&dir.join("max_energy_range_uj"))?,
+                    last_uj: read_u64(&energy)?,
+                    energy,
+                });
+            }
+            if domains.is_empty() {
+                return Err(format!("no RAPL package domains in {}", POWERCAP));
+            }
+            Ok(RaplSampler {
+                domains,
+                last: Instant::now(),
+            })
+        }
+    }
+
+    impl PowerSampler for RaplSampler {
+        fn name(&self) -> &'static str {
+            "RAPL"
+        }
+
+        fn sample(&mut self) -> Option<f64> {
+            let elapsed = self.last.elapsed().as_secs_f64();
+            self.last = Instant::now();
+            let mut energy_uj = 0;
+            for domain in &mut self.domains {
+                let uj = match read_u64(&domain.energy) {
+                    Ok(uj) => uj,
+                    Err(e) => {
+                        log::warn!("Failed to read the package energy: {}", e);
+                        return None;
+                    }
+                };
+                energy_uj += if uj >= domain.last_uj {
+                    uj - domain.last_uj
+                } else {
+                    domain.max_uj - domain.last_uj + uj
+                };
+                domain.last_uj = uj;
+            }
+            (elapsed > 0.).then(|| energy_uj as f64 / 1e6 / elapsed)
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod powermetrics {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::{Arc, Mutex};
+
+    const INTERVAL_MS: u32 = 1000;
+
+    /// Streams `powermetrics`, which needs root, and averages the readings
+    /// that arrived between two samples.
+    pub struct PowermetricsSampler {
+        child: Child,
+        /* Sum of the readings in watts and their count. */
+        readings: Arc<Mutex<(f64, u32)>>,
+    }
+
+    /*
+     * Recent releases print "CPU Power: 1234 mW" followed by "Combined Power
+     * (CPU + GPU + ANE): 1500 mW", older ones only the first. The combined
+     * value wins, the GPU and media engines are part of encoding.
+     */
+    fn parse_milliwatts(line: &str) -> Option<(bool, f64)> {
+        let (label, value) = line.split_once(':')?;
+        let combined = label.starts_with("Combined Power");
+        if !combined && label != "CPU Power" {
+            return None;
+        }
+        let milliwatts = value.trim().strip_suffix("mW")?.trim().parse().ok()?;
+        Some((combined, milliwatts))
+    }
+
+    impl PowermetricsSampler {
+        pub fn new() -> Result<Self, String> {
+            let mut child = Command::new("powermetrics")
+                .args(["--samplers", "cpu_power", "-i"])
+                .arg(INTERVAL_MS.to_string())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|e| format!("failed to start powermetrics: {}", e))?;
+            let stdout = child.stdout.take().unwrap();
+            let readings = Arc::new(Mutex::new((0., 0)));
+            let reader_readings = readings.clone();
+            std::thread::spawn(move || {
+                /* Each interval starts with a header, its reading is kept until the next one. */
+                let mut interval: Option<(bool, f64)> = None;
+                for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                    if line.starts_with("*** Sampled system activity") {
+                        if let Some((_, milliwatts)) = interval.take() {
+                            let mut readings = reader_readings.lock().unwrap();
+                            readings.0 += milliwatts / 1000.;
+                            readings.1 += 1;
+                        }
+                    } else if let Some(reading) = parse_milliwatts(&line) {
+                        interval = match interval {
+                            Some((true, _)) => interval,
+                            _ => Some(reading),
+                        };
+                    }
+                }
+            });
+            Ok(PowermetricsSampler { child, readings })
+        }
+    }
+
+    impl PowerSampler for PowermetricsSampler {
+        fn name(&self) -> &'static str {
+            "powermetrics"
+        }
+
+        fn sample(&mut self) -> Option<f64> {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                log::warn!("powermetrics exited: {}", status);
+                return None;
+            }
+            let mut readings = self.readings.lock().unwrap();
+            let (sum, count) = std::mem::replace(&mut *readings, (0., 0));
+            (count > 0).then(|| sum / count as f64)
+        }
+    }
+
+    impl Drop for PowermetricsSampler {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use toml::Value;
+
+/*
+ * Named run configurations in a TOML file, so that a long command line can
+ * be reviewed and versioned next to the results it produced. Every profile
+ * is a table under `profiles` whose keys are `share` options without the
+ * leading dashes, e.g.
+ *
+ *   [profiles.slides-vp9]
+ *   codec = "VP9"
+ *   res = "1440p"
+ *   bitrate = 2500
+ *   simulcast = true
+ *   scaling = "encoder"
+ *   source-name = ["eDP-1", "HDMI-1"]
+ *
+ * The options of `--profile <name>` go in front of the command line, so
+ * options given there override the profile. Arrays repeat the option.
+ */
+
+/// Profiles file used unless `--profile-file` names another.
+pub const DEFAULT_PROFILE_FILE: &str = "benchmarks.toml";
+
+/// The options of profile `name` in `path` as command line arguments.
+pub fn profile_args(path: &str, name: &str) -> Result<Vec<String>, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let document: toml::Table = text
+        .parse()
+        .map_err(|e| format!("Invalid profiles file {}: {}", path, e))?;
+    let profiles = document
+        .get("profiles")
+        .and_then(Value::as_table)
+        .ok_or_else(|| format!("No [profiles] table in {}", path))?;
+    let profile = profiles
+        .get(name)
+        .and_then(Value::as_table)
+        .ok_or_else(|| {
+            let names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+            format!(
+                "No profile {} in {}, it has: {}",
+                name,
+                path,
+                names.join(", ")
+            )
+        })?;
+
+    let mut args = vec![];
+    for (key, value) in profile {
+        let option = format!("--{}", key.replace('_', "-"));
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::Boolean(true) => {
+                    args.push(option.clone());
+                    continue;
+                }
+                Value::Boolean(false) => continue,
+                Value::String(value) => value.clone(),
+                Value::Integer(value) => value.to_string(),
+                Value::Float(value) => value.to_string(),
+                _ => {
+                    return Err(format!(
+                        "Unsupported value of {} in profile {}: {}",
+                        key, name, value
+                    ));
+                }
+            };
+            args.extend([option.clone(), value]);
+        }
+    }
+    Ok(args)
+}
+
+/// Expands `--profile <name>` in the raw command line `args` into the
+/// options of the profile, read from `--profile-file` or
+/// `DEFAULT_PROFILE_FILE`. `args` is returned as is without a profile.
+pub fn expand_profile(mut args: Vec<String>) -> Result<Vec<String>, String> {
+    /* What follows `--` is passed on to the runs of `sweep`, which expand it themselves. */
+    let end = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    let value = |option: &str| -> Option<String> {
+        let prefix = format!("{}=", option);
+        args[..end].iter().enumerate().find_map(|(i, arg)| {
+            if arg == option {
+                args[..end].get(i + 1).cloned()
+            } else {
+                arg.strip_prefix(&prefix).map(str::to_string)
+            }
+        })
+    };
+    let Some(name) = value("--profile") else {
+        return Ok(args);
+    };
+    /* Right after the program, or after `share`, before any option of the command line. */
+    let at = match args.get(1).map(String::as_str) {
+        Some("share") => 2,
+        Some(arg) if !arg.starts_with('-') => {
+            return Err(format!("--profile only applies to share, not {}", arg));
+        }
+        _ => 1,
+    };
+    let path = value("--profile-file").unwrap_or_else(|| DEFAULT_PROFILE_FILE.to_string());
+    let profile = profile_args(&path, &name)?;
+    args.splice(at..at, profile);
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* A profiles file of its own per test, removed when dropped. */
+    struct ProfileFile(std::path::PathBuf);
+
+    impl ProfileFile {
+        fn new(test: &str, text: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "screen_sharer_{}_{}.toml",
+                test,
+                std::process::id()
+            ));
+            std::fs::write(&path, text).unwrap();
+            ProfileFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for ProfileFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    const PROFILES: &str = r#"
+[profiles.slides]
+codec = "VP9"
+res = "1440p"
+bitrate = 2500
+fps = 15
+simulcast = true
+scaling = "encoder"
+source-name = ["eDP-1", "HDMI-1"]
+"#;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /* The options with their values, e.g. `--fps 15`, in order. */
+    fn options(args: &[String]) -> Vec<String> {
+        let mut options: Vec<String> = Vec::new();
+        for arg in args {
+            match options.last_mut() {
+                Some(option) if !arg.starts_with("--") => *option = format!("{} {}", option, arg),
+                _ => options.push(arg.clone()),
+            }
+        }
+        options.sort();
+        options
+    }
+
+    #[test]
+    fn turns_every_key_into_options() {
+        let file = ProfileFile::new("keys", PROFILES);
+        let args = profile_args(file.path(), "slides").unwrap();
+        assert_eq!(
+            options(&args),
+            strings(&[
+                "--bitrate 2500",
+                "--codec VP9",
+                "--fps 15",
+                "--res 1440p",
+                "--scaling encoder",
+                "--simulcast",
+                "--source-name HDMI-1",
+                "--source-name eDP-1",
+            ])
+        );
+    }
+
+    #[test]
+    fn puts_the_profile_before_the_command_line() {
+        let file = ProfileFile::new("expand", PROFILES);
+        let args = expand_profile(strings(&[
+            "screen_sharer",
+            "share",
+            "--profile",
+            "slides",
+            "--profile-file",
+            file.path(),
+            "--scaling",
+            "prescale",
+        ]))
+        .unwrap();
+        assert_eq!(args[..2], strings(&["screen_sharer", "share"]));
+        /* The command line comes last, so its `--scaling` wins. */
+        assert_eq!(
+            args[args.len() - 6..args.len() - 2],
+            strings(&["--profile", "slides", "--profile-file", file.path()])
+        );
+        assert_eq!(args[args.len() - 2..], strings(&["--scaling", "prescale"]));
+        let profile = profile_args(file.path(), "slides").unwrap();
+        assert_eq!(args[2..2 + profile.len()], profile);
+    }
+
+    #[test]
+    fn names_the_profiles_of_the_file_when_one_is_missing() {
+        let file = ProfileFile::new("missing", PROFILES);
+        let error = profile_args(file.path(), "video").unwrap_err();
+        assert!(error.contains("it has: slides"), "{}", error);
+    }
+
+    #[test]
+    fn leaves_other_subcommands_alone() {
+        let args = strings(&["screen_sharer", "list-sources"]);
+        assert_eq!(expand_profile(args.clone()).unwrap(), args);
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+
+/*
+ * Bounded queue between the capturer and the conversion. Without it every
+ * frame is converted and scaled in the capture callback, and when that
+ * can't keep up (4K AV1 on a laptop) the capture loop falls behind while
+ * holding the capturer. With it the callback only copies the frame and a
+ * conversion thread works through the queue; a full queue drops a frame
+ * according to the policy, before it ever reaches the encoder.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DropPolicy {
+    /// Evict the oldest queued frame, the encoder always gets the latest screen.
+    DropOldest,
+    /// Reject the new frame, queued frames are encoded in capture order.
+    DropNewest,
+}
+
+impl std::str::FromStr for DropPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(DropPolicy::DropOldest),
+            "drop-newest" => Ok(DropPolicy::DropNewest),
+            _ => Err(format!(
+                "Invalid drop policy: {}. Use 'drop-oldest' or 'drop-newest'",
+                s
+            )),
+        }
+    }
+}
+
+impl DropPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropPolicy::DropOldest => "drop-oldest",
+            DropPolicy::DropNewest => "drop-newest",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueueConfig {
+    /// Frames waiting for conversion at most.
+    pub depth: usize,
+    pub policy: DropPolicy,
+}
+
+struct QueuedFrame {
+    data: Vec<u8>,
+    stride: u32,
+    width: i32,
+    height: i32,
+}
+
+struct State {
+    frames: VecDeque<QueuedFrame>,
+    /* Buffers of converted or dropped frames, reused for the next copies. */
+    spare: Vec<Vec<u8>>,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    ready: Condvar,
+}
+
+pub struct FrameQueue {
+    shared: Arc<Shared>,
+    config: QueueConfig,
+}
+
+impl FrameQueue {
+    /// Starts the conversion thread that hands the queued frames to `handler`.
+    pub fn new(
+        config: QueueConfig,
+        handler: impl Fn(&[u8], u32, i32, i32) + Send + 'static,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                frames: VecDeque::with_capacity(config.depth),
+                spare: vec![],
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        });
+        let worker = shared.clone();
+        std::thread::spawn(move || {
+            while let Some(frame) = worker.next() {
+                handler(&frame.data, frame.stride, frame.width, frame.height);
+                worker.state.lock().unwrap().spare.push(frame.data);
+            }
+        });
+        FrameQueue { shared, config }
+    }
+
+    /// Copies a captured frame into the queue, returns whether a frame was
+    /// dropped to make room or the new one was rejected.
+    pub fn push(&self, data: &[u8], stride: u32, width: i32, height: i32) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        let full = state.frames.len() >= self.config.depth;
+        if full && self.config.policy == DropPolicy::DropNewest {
+            return true;
+        }
+        let mut buffer = state.spare.pop().unwrap_or_default();
+        /* The copy of a large frame takes a while, the conversion thread keeps going meanwhile. */
+        drop(state);
+        buffer.clear();
+        buffer.extend_from_slice(data);
+
+        let mut state = self.shared.state.lock().unwrap();
+        let mut dropped = false;
+        while state.frames.len() >= self.config.depth {
+            let Some(oldest) = state.frames.pop_front() else {
+                break;
+            };
+            state.spare.push(oldest.data);
+            dropped = true;
+        }
+        state.frames.push_back(QueuedFrame {
+            data: buffer,
+            stride,
+            width,
+            height,
+        });
+        self.shared.ready.notify_one();
+        dropped
+    }
+}
+
+impl Shared {
+    /* Blocks until a frame is queued, None once the queue is closed. */
+    fn next(&self) -> Option<QueuedFrame> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return None;
+            }
+            if let Some(frame) = state.frames.pop_front() {
+                return Some(frame);
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+}
+
+/* Stops the conversion thread with the capturer, queued frames are discarded. */
+impl Drop for FrameQueue {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.ready.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::time::Duration;
+
+    /*
+     * A queue whose handler reports the first byte of every frame and then
+     * waits for the test to release it, so frames can be queued behind a
+     * busy conversion.
+     */
+    fn gated_queue(depth: usize, policy: DropPolicy) -> (FrameQueue, Receiver<u8>, Sender<()>) {
+        let (seen_tx, seen) = mpsc::channel();
+        let (release, gate) = mpsc::channel::<()>();
+        let gate = Mutex::new(gate);
+        let queue = FrameQueue::new(
+            QueueConfig { depth, policy },
+            move |data: &[u8], _, _, _| {
+                /* Both fail once the test is over and its ends are dropped. */
+                let _ = seen_tx.send(data[0]);
+                let _ = gate.lock().unwrap().recv();
+            },
+        );
+        (queue, seen, release)
+    }
+
+    fn next(seen: &Receiver<u8>) -> u8 {
+        seen.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    /* Queues frame 0 and waits until the conversion is busy with it. */
+    fn occupy(queue: &FrameQueue, seen: &Receiver<u8>) {
+        assert!(!queue.push(&[0], 4, 1, 1));
+        assert_eq!(next(seen), 0);
+    }
+
+    #[test]
+    fn delivers_frames_in_order() {
+        let (queue, seen, release) = gated_queue(4, DropPolicy::DropOldest);
+        occupy(&queue, &seen);
+        for frame in 1..=3 {
+            assert!(!queue.push(&[frame, 9], 8, 2, 1));
+        }
+        for frame in 1..=3 {
+            release.send(()).unwrap();
+            assert_eq!(next(&seen), frame);
+        }
+    }
+
+    #[test]
+    fn drop_oldest_evicts_the_queued_frame() {
+        let (queue, seen, release) = gated_queue(2, DropPolicy::DropOldest);
+        occupy(&queue, &seen);
+        assert!(!queue.push(&[1], 4, 1, 1));
+        assert!(!queue.push(&[2], 4, 1, 1));
+        assert!(queue.push(&[3], 4, 1, 1));
+        release.send(()).unwrap();
+        assert_eq!(next(&seen), 2);
+        release.send(()).unwrap();
+        assert_eq!(next(&seen), 3);
+    }
+
+    #[test]
+    fn drop_newest_rejects_the_new_frame() {
+        let (queue, seen, release) = gated_queue(2, DropPolicy::DropNewest);
+        occupy(&queue, &seen);
+        assert!(!queue.push(&[1], 4, 1, 1));
+        assert!(!queue.push(&[2], 4, 1, 1));
+        assert!(queue.push(&[3], 4, 1, 1));
+        release.send(()).unwrap();
+        assert_eq!(next(&seen), 1);
+        release.send(()).unwrap();
+        assert_eq!(next(&seen), 2);
+        release.send(()).unwrap();
+        assert!(seen.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn parses_policies() {
+        for policy in [DropPolicy::DropOldest, DropPolicy::DropNewest] {
+            assert_eq!(policy.as_str().parse::<DropPolicy>(), Ok(policy));
+        }
+        assert!("drop-random".parse::<DropPolicy>().is_err());
+    }
+}
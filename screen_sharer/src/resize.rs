@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+/*
+ * Published resolution changes during a run, simulating a display resize
+ * while sharing. Encoders differ a lot in how they handle the new frame
+ * size: some reconfigure in place, others restart with a key frame or go
+ * through a bitrate dip.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResizeStep {
+    /// Time since the start of the run.
+    pub at: Duration,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Resolution changes parsed from `<seconds>:<width>x<height>,...`,
+/// e.g. `20:1280x720,40:1920x1080`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResizeSchedule(pub Vec<ResizeStep>);
+
+impl std::str::FromStr for ResizeSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut steps = s
+            .split(',')
+            .map(|entry| {
+                let (at, size) = entry.split_once(':').ok_or_else(|| {
+                    format!("Invalid resize: {}. Use <seconds>:<width>x<height>", entry)
+                })?;
+                let at = at
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid resize time: {}", at))?;
+                let (width, height) = size
+                    .trim()
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+                    .filter(|(w, h)| *w >= 2 && *h >= 2)
+                    .ok_or_else(|| format!("Invalid resize resolution: {}", size))?;
+                Ok(ResizeStep {
+                    at: Duration::from_secs(at),
+                    width,
+                    height,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        steps.sort_by_key(|step| step.at);
+        Ok(ResizeSchedule(steps))
+    }
+}
+
+/* The stream size travels to the capture callback packed in one atomic. */
+pub fn pack_size(width: u32, height: u32) -> u64 {
+    ((width as u64) << 32) | height as u64
+}
+
+pub fn unpack_size(size: u64) -> (u32, u32) {
+    ((size >> 32) as u32, size as u32)
+}
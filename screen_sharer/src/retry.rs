@@ -0,0 +1,41 @@
+use std::future::Future;
+use std::time::Duration;
+
+/* Delay before the first retry, doubled after every failed attempt. */
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Runs `operation` up to `attempts` times, backing off exponentially
+/// between failures. Returns the last error once the attempts run out.
+pub async fn retry_with_backoff<T, E, F, Fut>(
+    what: &str,
+    attempts: u32,
+    mut operation: F,
+) -> Result<T, E>
+where
+    E: std::fmt::Debug,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < attempts => {
+                log::warn!(
+                    "{} failed (attempt {}/{}): {:?}, retrying in {:?}",
+                    what,
+                    attempt,
+                    attempts,
+                    e,
+                    backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
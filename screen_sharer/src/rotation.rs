@@ -0,0 +1,167 @@
+use livekit::webrtc::prelude::{NV12Buffer, VideoBuffer, VideoRotation};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RotationAngle {
+    #[default]
+    R0,
+    R90,
+    R180,
+    R270,
+}
+
+impl RotationAngle {
+    pub fn degrees(&self) -> u32 {
+        match self {
+            RotationAngle::R0 => 0,
+            RotationAngle::R90 => 90,
+            RotationAngle::R180 => 180,
+            RotationAngle::R270 => 270,
+        }
+    }
+
+    fn swaps_dimensions(&self) -> bool {
+        matches!(self, RotationAngle::R90 | RotationAngle::R270)
+    }
+}
+
+impl std::str::FromStr for RotationAngle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(RotationAngle::R0),
+            "90" => Ok(RotationAngle::R90),
+            "180" => Ok(RotationAngle::R180),
+            "270" => Ok(RotationAngle::R270),
+            _ => Err(format!("Invalid rotation: {}. Use 0, 90, 180 or 270", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RotationMode {
+    /// Frames keep their orientation and carry the rotation, which
+    /// libwebrtc sends in the video orientation (CVO) RTP extension.
+    #[default]
+    Signal,
+    /// The pixels are rotated before they reach the encoder.
+    PreEncode,
+}
+
+impl std::str::FromStr for RotationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "signal" => Ok(RotationMode::Signal),
+            "pre-encode" => Ok(RotationMode::PreEncode),
+            _ => Err(format!(
+                "Invalid rotation mode: {}. Use 'signal' or 'pre-encode'",
+                s
+            )),
+        }
+    }
+}
+
+/// Clockwise rotation of the published frames, see `--rotation`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Rotation {
+    pub angle: RotationAngle,
+    pub mode: RotationMode,
+}
+
+impl std::fmt::Display for Rotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mode = match self.mode {
+            RotationMode::Signal => "signal",
+            RotationMode::PreEncode => "pre-encode",
+        };
+        write!(f, "{}-{}", self.angle.degrees(), mode)
+    }
+}
+
+impl Rotation {
+    /// Whether the pixels have to be rotated before submission.
+    pub fn rotates_pixels(&self) -> bool {
+        self.mode == RotationMode::PreEncode && self.angle != RotationAngle::R0
+    }
+
+    /// Rotation carried by the submitted frames.
+    pub fn frame_rotation(&self) -> VideoRotation {
+        if self.mode == RotationMode::PreEncode {
+            return VideoRotation::VideoRotation0;
+        }
+        match self.angle {
+            RotationAngle::R0 => VideoRotation::VideoRotation0,
+            RotationAngle::R90 => VideoRotation::VideoRotation90,
+            RotationAngle::R180 => VideoRotation::VideoRotation180,
+            RotationAngle::R270 => VideoRotation::VideoRotation270,
+        }
+    }
+
+    /// Dimensions of the submitted frames for a `width`x`height` stream.
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        if self.rotates_pixels() && self.angle.swaps_dimensions() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+}
+
+/* Rotates a plane of `bpp` byte elements clockwise. */
+#[allow(clippy::too_many_arguments)]
+fn rotate_plane(
+    src: &[u8],
+    src_stride: usize,
+    width: usize,
+    height: usize,
+    dst: &mut [u8],
+    dst_stride: usize,
+    bpp: usize,
+    angle: RotationAngle,
+) {
+    for y in 0..height {
+        for x in 0..width {
+            let (dst_x, dst_y) = match angle {
+                RotationAngle::R0 => (x, y),
+                RotationAngle::R90 => (height - 1 - y, x),
+                RotationAngle::R180 => (width - 1 - x, height - 1 - y),
+                RotationAngle::R270 => (y, width - 1 - x),
+            };
+            let s = y * src_stride + x * bpp;
+            let d = dst_y * dst_stride + dst_x * bpp;
+            dst[d..d + bpp].copy_from_slice(&src[s..s + bpp]);
+        }
+    }
+}
+
+/// Rotates `src` clockwise by `angle` into `dst`, which must have the
+/// rotated dimensions.
+pub fn rotate_nv12(src: &NV12Buffer, dst: &mut NV12Buffer, angle: RotationAngle) {
+    let (width, height) = (src.width() as usize, src.height() as usize);
+    let (src_stride_y, src_stride_uv) = src.strides();
+    let (src_y, src_uv) = src.data();
+    let (dst_stride_y, dst_stride_uv) = dst.strides();
+    let (dst_y, dst_uv) = dst.data_mut();
+    rotate_plane(
+        src_y,
+        src_stride_y as usize,
+        width,
+        height,
+        dst_y,
+        dst_stride_y as usize,
+        1,
+        angle,
+    );
+    rotate_plane(
+        src_uv,
+        src_stride_uv as usize,
+        width.div_ceil(2),
+        height.div_ceil(2),
+        dst_uv,
+        dst_stride_uv as usize,
+        2,
+        angle,
+    );
+}
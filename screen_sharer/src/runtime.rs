@@ -0,0 +1,146 @@
+use crate::{SampleSnapshot, Stats, get_rtc_stats};
+use std::sync::{Arc, mpsc};
+use std::time::{Duration, Instant};
+use tokio::runtime::{Handle, Runtime};
+
+/*
+ * Where the async work of the library runs. The LiveKit SDK drives the
+ * room on the tokio runtime it was connected from, and its futures (stats
+ * reads, publications) expect to be polled on one. The capture pacing
+ * keeps a thread of its own so nothing async delays a frame: it hands the
+ * stats reads to a task on the runtime through a channel and picks the
+ * results up between captures, see `StatsReader`.
+ */
+
+/* A runtime the library started, shut down without waiting when the last copy goes. */
+struct OwnedRuntime(Option<Runtime>);
+
+impl Drop for OwnedRuntime {
+    fn drop(&mut self) {
+        /* A plain drop blocks, which panics when the last copy goes on a runtime thread. */
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// The tokio runtime the library spawns its tasks on, the caller's or one
+/// it started itself.
+#[derive(Clone)]
+pub struct AsyncRuntime {
+    handle: Handle,
+    _owned: Option<Arc<OwnedRuntime>>,
+}
+
+impl AsyncRuntime {
+    /// Runs the async work on the caller's runtime, which should be the
+    /// one the room was connected from.
+    pub fn from_handle(handle: Handle) -> Self {
+        AsyncRuntime {
+            handle,
+            _owned: None,
+        }
+    }
+
+    /// The runtime the caller is running on, or else a runtime of its own
+    /// with a single worker, for callers outside of tokio.
+    pub fn current() -> Result<Self, String> {
+        if let Ok(handle) = Handle::try_current() {
+            return Ok(Self::from_handle(handle));
+        }
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("screen_sharer-runtime")
+            .enable_all()
+            .build()
+            .map_err(|e| format!("failed to start a tokio runtime: {}", e))?;
+        Ok(AsyncRuntime {
+            handle: runtime.handle().clone(),
+            _owned: Some(Arc::new(OwnedRuntime(Some(runtime)))),
+        })
+    }
+
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+}
+
+/* What a stats read is for, handed back with its result. */
+pub(crate) enum StatsRead {
+    /* A latency tick of the client, with the counters when it was seen. */
+    Tick {
+        id: u64,
+        timestamp: u128,
+        cpu_usage: f32,
+        frames_submitted: u64,
+        pipeline_time_ns: u64,
+    },
+    /* A periodic stats sample, with the capture side when it was requested. */
+    Sample(SampleSnapshot),
+}
+
+/*
+ * Stats reads on the runtime for the capture thread. Requests are read in
+ * order by a single task, so the results come back in the order asked.
+ */
+pub(crate) struct StatsReader {
+    requests: tokio::sync::mpsc::UnboundedSender<(StatsRead, f32)>,
+    results: mpsc::Receiver<(StatsRead, Option<Stats>)>,
+    pending: usize,
+}
+
+impl StatsReader {
+    pub fn start(runtime: &AsyncRuntime, room: Arc<livekit::Room>, track_name: String) -> Self {
+        let (requests, mut rx) = tokio::sync::mpsc::unbounded_channel::<(StatsRead, f32)>();
+        let (tx, results) = mpsc::channel();
+        runtime.handle().spawn(async move {
+            while let Some((read, cpu_usage)) = rx.recv().await {
+                let stats = get_rtc_stats(&room, &track_name, cpu_usage).await;
+                if tx.send((read, stats)).is_err() {
+                    break;
+                }
+            }
+        });
+        StatsReader {
+            requests,
+            results,
+            pending: 0,
+        }
+    }
+
+    /* Reads the stats for `read`, `cpu_usage` goes into them. */
+    pub fn request(&mut self, read: StatsRead, cpu_usage: f32) {
+        if self.requests.send((read, cpu_usage)).is_ok() {
+            self.pending += 1;
+        } else {
+            log::error!("The stats task is gone, the runtime was shut down");
+        }
+    }
+
+    /* The results that came in since the last call, without waiting. */
+    pub fn ready(&mut self) -> Vec<(StatsRead, Option<Stats>)> {
+        let results: Vec<_> = self.results.try_iter().collect();
+        self.pending -= results.len();
+        results
+    }
+
+    /* At the end of the run, the reads still running, given up on after `timeout`. */
+    pub fn finish(&mut self, timeout: Duration) -> Vec<(StatsRead, Option<Stats>)> {
+        let deadline = Instant::now() + timeout;
+        let mut results = vec![];
+        while self.pending > 0 {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.results.recv_timeout(left) {
+                Ok(result) => {
+                    self.pending -= 1;
+                    results.push(result);
+                }
+                Err(_) => {
+                    log::warn!("{} stats reads still running, left out", self.pending);
+                    break;
+                }
+            }
+        }
+        results
+    }
+}
@@ -0,0 +1,162 @@
+use livekit::webrtc::prelude::{NV12Buffer, VideoBuffer};
+
+/// Rounds a frame size down to even dimensions, at least 2x2. NV12
+/// subsamples chroma by two in both directions and encoders reject odd
+/// sizes, while screens like 1366x768 scaled to 1080p or the 1728x1117 of a
+/// scaled MacBook display give odd ones.
+pub fn even_size(width: u32, height: u32) -> (u32, u32) {
+    ((width & !1).max(2), (height & !1).max(2))
+}
+
+/// Whether a captured ARGB frame holds `height` rows of `width` pixels
+/// `stride` bytes apart. Rows may be padded beyond the width, the last one
+/// may end right after its pixels.
+pub fn frame_fits(len: usize, stride: u32, width: i32, height: i32) -> bool {
+    if width <= 0 || height <= 0 {
+        return false;
+    }
+    let (stride, width, height) = (stride as usize, width as usize, height as usize);
+    stride >= width * 4 && len >= stride * (height - 1) + width * 4
+}
+
+/// Scales `src` into `dst`, whose dimensions are the target size, with
+/// libyuv through `NV12Buffer::scale` as the SDK's own capturers do, so the
+/// publisher CPU usage compares with other apps. libyuv writes into a new
+/// buffer, which is copied into the reused target.
+pub fn scale_nv12(src: &mut NV12Buffer, dst: &mut NV12Buffer) {
+    let mut scaled = src.scale(dst.width() as i32, dst.height() as i32);
+    let (data_y, data_uv) = scaled.data_mut();
+    let (dst_y, dst_uv) = dst.data_mut();
+    dst_y.copy_from_slice(data_y);
+    dst_uv.copy_from_slice(data_uv);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aspect_fit;
+
+    /* Screens the pipeline has to cope with, as (capture size, fit into 1080p). */
+    const SCREENS: [((u32, u32), (u32, u32)); 5] = [
+        /* Common laptop panel, 16:9 only approximately. */
+        ((1366, 768), (1920, 1078)),
+        /* Ultrawide. */
+        ((3440, 1440), (1920, 802)),
+        /* Scaled MacBook Pro 16" ("more space"), odd height. */
+        ((1728, 1117), (1920, 1240)),
+        /* Retina framebuffer of a 14" MacBook Pro. */
+        ((3024, 1964), (1920, 1246)),
+        /* Portrait monitor. */
+        ((1080, 1920), (1080, 1920)),
+    ];
+
+    #[test]
+    fn fitted_sizes_are_even() {
+        for ((width, height), expected) in SCREENS {
+            let fitted = aspect_fit(width, height, 1920, 1080);
+            assert_eq!(fitted, expected, "{}x{}", width, height);
+            assert_eq!((fitted.0 % 2, fitted.1 % 2), (0, 0));
+        }
+    }
+
+    #[test]
+    fn even_size_rounds_down() {
+        assert_eq!(even_size(1728, 1117), (1728, 1116));
+        assert_eq!(even_size(1367, 769), (1366, 768));
+        assert_eq!(even_size(1, 1), (2, 2));
+    }
+
+    #[test]
+    fn frame_fits_respects_stride() {
+        let (width, height): (i32, i32) = (1366, 768);
+        let stride: u32 = 1408 * 4;
+        let padded = stride as usize * height as usize;
+        assert!(frame_fits(padded, stride, width, height));
+        /* The last row doesn't need its padding. */
+        let tight = padded - (stride as usize - width as usize * 4);
+        assert!(frame_fits(tight, stride, width, height));
+        assert!(!frame_fits(tight - 1, stride, width, height));
+        /* A stride shorter than a row can't be right. */
+        assert!(!frame_fits(usize::MAX, width as u32 * 4 - 4, width, height));
+        assert!(!frame_fits(0, 0, 0, 0));
+    }
+
+    const DARK: u8 = 16;
+    const BRIGHT: u8 = 235;
+    const CHROMA: (u8, u8) = (90, 170);
+
+    /* A frame dark on the left half and bright on the right, of one color. */
+    fn split_frame(width: u32, height: u32) -> NV12Buffer {
+        let mut frame = NV12Buffer::new(width, height);
+        let (stride_y, stride_uv) = frame.strides();
+        let (y, uv) = frame.data_mut();
+        for row in y.chunks_mut(stride_y as usize) {
+            for (column, luma) in row.iter_mut().enumerate() {
+                *luma = if (column as u32) < width / 2 {
+                    DARK
+                } else {
+                    BRIGHT
+                };
+            }
+        }
+        for row in uv.chunks_mut(stride_uv as usize) {
+            for pair in row.chunks_mut(2) {
+                pair[0] = CHROMA.0;
+                if let Some(v) = pair.get_mut(1) {
+                    *v = CHROMA.1;
+                }
+            }
+        }
+        frame
+    }
+
+    /* Filters may round a flat area by a level. */
+    fn near(value: u8, expected: u8) -> bool {
+        value.abs_diff(expected) <= 1
+    }
+
+    /* Asserts the scaled frame kept the size it was given and the split content. */
+    fn assert_scaled(frame: &NV12Buffer, width: u32, height: u32, source: (u32, u32)) {
+        assert_eq!(
+            (frame.width(), frame.height()),
+            (width, height),
+            "from {:?}",
+            source
+        );
+        let (stride_y, stride_uv) = frame.strides();
+        let (y, uv) = frame.data();
+        /* Away from the edge in the middle, which the filter blends. */
+        let (left, right) = (width as usize / 4, width as usize * 3 / 4);
+        for row in [0, height as usize / 2, height as usize - 1] {
+            let line = &y[row * stride_y as usize..];
+            assert!(near(line[left], DARK), "row {} from {:?}", row, source);
+            assert!(near(line[right], BRIGHT), "row {} from {:?}", row, source);
+        }
+        for row in [0, (height as usize).div_ceil(2) - 1] {
+            let line = &uv[row * stride_uv as usize..];
+            for column in [0, (width as usize).div_ceil(2) - 1] {
+                let (u, v) = (line[column * 2], line[column * 2 + 1]);
+                assert!(
+                    near(u, CHROMA.0) && near(v, CHROMA.1),
+                    "chroma row {} from {:?}",
+                    row,
+                    source
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn scales_odd_sources() {
+        for ((width, height), (fit_width, fit_height)) in SCREENS {
+            let source = (width | 1, height | 1);
+            let mut src = split_frame(source.0, source.1);
+            let mut dst = NV12Buffer::new(fit_width, fit_height);
+            scale_nv12(&mut src, &mut dst);
+            assert_scaled(&dst, fit_width, fit_height, source);
+            let mut odd = NV12Buffer::new(fit_width + 1, fit_height + 1);
+            scale_nv12(&mut src, &mut odd);
+            assert_scaled(&odd, fit_width + 1, fit_height + 1, source);
+        }
+    }
+}
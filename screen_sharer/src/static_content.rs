@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+/*
+ * Static screen content during a run: the capture callback submits the
+ * frame captured when the period started over and over, at the capture
+ * rate, as a screen nobody touches would. Encoders differ in how far their
+ * bitrate drops on an unchanged screen, whether they send refresh key
+ * frames and how quickly they are back to full quality once it moves again.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StaticStep {
+    /// Time since the start of the run.
+    pub at: Duration,
+    pub duration: Duration,
+}
+
+/// Static periods parsed from `<seconds>:<duration>,...`, e.g. `20:10,60:30`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaticSchedule(pub Vec<StaticStep>);
+
+impl std::str::FromStr for StaticSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut steps = s
+            .split(',')
+            .map(|entry| {
+                let (at, duration) = entry.split_once(':').ok_or_else(|| {
+                    format!("Invalid static period: {}. Use <seconds>:<duration>", entry)
+                })?;
+                let at = at
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid static period time: {}", at))?;
+                let duration = duration
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|duration| *duration > 0)
+                    .ok_or_else(|| format!("Invalid static period duration: {}", duration))?;
+                Ok(StaticStep {
+                    at: Duration::from_secs(at),
+                    duration: Duration::from_secs(duration),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        steps.sort_by_key(|step| step.at);
+        Ok(StaticSchedule(steps))
+    }
+}
+
+/* The frame repeated during a static period, as the capturer delivered it. */
+pub struct StillFrame {
+    pub data: Vec<u8>,
+    pub stride: u32,
+    pub width: i32,
+    pub height: i32,
+}
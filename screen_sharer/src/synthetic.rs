@@ -0,0 +1,71 @@
+/*
+ * Synthetic frame source used when no real screen is available
+ * (integration tests, headless machines). It renders a gradient
+ * background with a moving vertical bar so the encoder always has
+ * some motion to work with.
+ */
+pub struct SyntheticSource {
+    width: u32,
+    height: u32,
+    frame: u64,
+    data: Vec<u8>,
+    handler: Box<dyn Fn(&[u8], u32, i32, i32) + Send + Sync>,
+}
+
+/* Width of the moving bar in pixels. */
+const BAR_WIDTH: u32 = 64;
+/* Horizontal distance the bar moves per frame. */
+const BAR_SPEED: u32 = 8;
+
+impl SyntheticSource {
+    pub fn new(
+        width: u32,
+        height: u32,
+        handler: Box<dyn Fn(&[u8], u32, i32, i32) + Send + Sync>,
+    ) -> Self {
+        SyntheticSource {
+            width,
+            height,
+            frame: 0,
+            data: vec![0; (width * height * 4) as usize],
+            handler,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn capture_frame(&mut self) {
+        self.render();
+        (self.handler)(
+            &self.data,
+            self.width * 4,
+            self.width as i32,
+            self.height as i32,
+        );
+        self.frame += 1;
+    }
+
+    fn render(&mut self) {
+        let bar_start = ((self.frame * BAR_SPEED as u64) % self.width as u64) as u32;
+        let stride = (self.width * 4) as usize;
+        for (y, row) in self.data.chunks_exact_mut(stride).enumerate() {
+            let shade = (y as u32 * 255 / self.height.max(1)) as u8;
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let x = x as u32;
+                let in_bar = x >= bar_start && x < bar_start + BAR_WIDTH;
+                /* libyuv ARGB is stored as B, G, R, A in memory. */
+                if in_bar {
+                    pixel.copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+                } else {
+                    pixel.copy_from_slice(&[shade, 0x80, 0xff - shade, 0xff]);
+                }
+            }
+        }
+    }
+}
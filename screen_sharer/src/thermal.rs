@@ -0,0 +1,111 @@
+use sysinfo::{Components, System};
+
+/*
+ * The CPU is considered throttled when its average frequency drops below
+ * this fraction of the maximum frequency of the current cpufreq governor
+ * policy, or where that isn't known, of the highest average frequency seen
+ * during the run.
+ */
+const THROTTLE_RATIO: f64 = 0.85;
+
+/* Component labels that identify the CPU package sensor, most specific first. */
+const CPU_SENSOR_LABELS: [&str; 4] = ["package", "tctl", "tdie", "cpu"];
+
+pub struct ThermalSample {
+    /// CPU package temperature in celsius, NaN when no sensor is available.
+    pub temperature: f32,
+    /// Average CPU frequency in MHz.
+    pub frequency_mhz: u64,
+    pub throttled: bool,
+}
+
+pub struct ThermalMonitor {
+    components: Components,
+    peak_frequency_mhz: u64,
+    /* What the frequency of the last sample was compared with, MHz. */
+    max_frequency_mhz: u64,
+    throttled: bool,
+}
+
+impl ThermalMonitor {
+    pub fn new() -> Self {
+        ThermalMonitor {
+            components: Components::new_with_refreshed_list(),
+            peak_frequency_mhz: 0,
+            max_frequency_mhz: 0,
+            throttled: false,
+        }
+    }
+
+    pub fn throttled(&self) -> bool {
+        self.throttled
+    }
+
+    pub fn sample(&mut self, system: &mut System) -> ThermalSample {
+        self.components.refresh(false);
+        system.refresh_cpu_frequency();
+
+        let cpus = system.cpus();
+        let frequency_mhz = if cpus.is_empty() {
+            0
+        } else {
+            cpus.iter().map(|cpu| cpu.frequency()).sum::<u64>() / cpus.len() as u64
+        };
+        self.peak_frequency_mhz = self.peak_frequency_mhz.max(frequency_mhz);
+        /* Read every time, the governor and its limits can change during the run. */
+        self.max_frequency_mhz =
+            governor_max_frequency_mhz(cpus.len()).unwrap_or(self.peak_frequency_mhz);
+        self.throttled = (frequency_mhz as f64) < (self.max_frequency_mhz as f64) * THROTTLE_RATIO;
+
+        ThermalSample {
+            temperature: self.cpu_temperature().unwrap_or(f32::NAN),
+            frequency_mhz,
+            throttled: self.throttled,
+        }
+    }
+
+    /// The frequency the last sample was compared with, in MHz.
+    pub fn max_frequency_mhz(&self) -> u64 {
+        self.max_frequency_mhz
+    }
+
+    fn cpu_temperature(&self) -> Option<f32> {
+        CPU_SENSOR_LABELS
+            .iter()
+            .find_map(|label| self.max_temperature(Some(*label)))
+            .or_else(|| self.max_temperature(None))
+    }
+
+    /* Highest temperature among the sensors whose label contains `label`. */
+    fn max_temperature(&self, label: Option<&str>) -> Option<f32> {
+        self.components
+            .list()
+            .iter()
+            .filter(|component| {
+                label.is_none_or(|label| component.label().to_lowercase().contains(label))
+            })
+            .filter_map(|component| component.temperature())
+            .filter(|temperature| !temperature.is_nan())
+            .reduce(f32::max)
+    }
+}
+
+/*
+ * Average of the highest frequency the cpufreq policies of the first `cpus`
+ * CPUs allow right now, in MHz. None without cpufreq, e.g. off Linux.
+ */
+fn governor_max_frequency_mhz(cpus: usize) -> Option<u64> {
+    let khz: Vec<u64> = (0..cpus)
+        .filter_map(|cpu| {
+            let path = format!(
+                "/sys/devices/system/cpu/cpu{}/cpufreq/scaling_max_freq",
+                cpu
+            );
+            std::fs::read_to_string(path).ok()?.trim().parse().ok()
+        })
+        .collect();
+    if khz.is_empty() {
+        return None;
+    }
+    Some(khz.iter().sum::<u64>() / khz.len() as u64 / 1000)
+}
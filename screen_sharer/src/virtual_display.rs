@@ -0,0 +1,212 @@
+use std::io;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/*
+ * A virtual X display for machines without a screen, e.g. CI containers.
+ * Unlike the synthetic source, which hands frames straight to the
+ * converter, the capturer records the display like any other screen, so
+ * the whole capture and encode path runs. An Xvfb server is started on a
+ * free display number, or an already running display is used, and a
+ * workload renders moving content on it, by default an ffplay test
+ * pattern. Both processes are stopped when the screen_sharer exits, also
+ * when it exits early, by a watcher that outlives it.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisplaySpec {
+    /// Start an Xvfb server with a screen of this size.
+    Start { width: u32, height: u32 },
+    /// Use a running display, e.g. `:99`.
+    Attach(String),
+}
+
+impl std::str::FromStr for DisplaySpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with(':') {
+            return Ok(DisplaySpec::Attach(s.to_string()));
+        }
+        let invalid = || {
+            format!(
+                "Invalid virtual display: {}. Use <width>x<height> or :<display>",
+                s
+            )
+        };
+        let (width, height) = s.split_once('x').ok_or_else(invalid)?;
+        let width = width.parse::<u32>().map_err(|_| invalid())?;
+        let height = height.parse::<u32>().map_err(|_| invalid())?;
+        if width == 0 || height == 0 {
+            return Err(invalid());
+        }
+        Ok(DisplaySpec::Start { width, height })
+    }
+}
+
+const SERVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The `--display-workload` value that disables the workload.
+pub const NO_WORKLOAD: &str = "none";
+
+/// Moving test pattern covering a screen of `width`x`height`.
+pub fn default_workload(width: u32, height: u32, fps: u32) -> String {
+    format!(
+        "ffplay -loglevel error -fs -noborder -f lavfi -i testsrc2=size={}x{}:rate={}",
+        width, height, fps
+    )
+}
+
+fn socket(number: u32) -> String {
+    format!("/tmp/.X11-unix/X{}", number)
+}
+
+/* In a process group of its own, so stopping it stops what it started too. */
+fn spawn_group(command: &mut Command) -> io::Result<Child> {
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(command, 0);
+    command.stdin(Stdio::null()).spawn()
+}
+
+/*
+ * SIGTERM to the group lets Xvfb remove its lock file and socket, SIGKILL
+ * only if it hangs. procps `kill` needs the `--` before a group.
+ */
+fn terminate(child: &mut Child) {
+    let group = format!("-{}", child.id());
+    let _ = Command::new("kill").args(["-TERM", "--", &group]).status();
+    let started = Instant::now();
+    while let Ok(None) = child.try_wait() {
+        if started.elapsed() >= Duration::from_secs(2) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/* Away from the display numbers desktops use. */
+fn free_display_number() -> Option<u32> {
+    (99..200).find(|number| {
+        !Path::new(&socket(*number)).exists()
+            && !Path::new(&format!("/tmp/.X{}-lock", number)).exists()
+    })
+}
+
+pub struct VirtualDisplay {
+    name: String,
+    server: Option<Child>,
+    workload: Option<Child>,
+}
+
+impl VirtualDisplay {
+    /// Starts or attaches to the display and runs `workload` on it with
+    /// `sh -c`.
+    pub fn open(spec: &DisplaySpec, workload: Option<&str>) -> Result<Self, String> {
+        if !cfg!(target_os = "linux") {
+            return Err("Virtual displays need Xvfb and are only supported on Linux".to_string());
+        }
+        let mut display = match spec {
+            DisplaySpec::Attach(name) => VirtualDisplay {
+                name: name.clone(),
+                server: None,
+                workload: None,
+            },
+            DisplaySpec::Start { width, height } => Self::start_server(*width, *height)?,
+        };
+        if let Some(workload) = workload {
+            let child = spawn_group(
+                Command::new("sh")
+                    .arg("-c")
+                    .arg(workload)
+                    .env("DISPLAY", &display.name),
+            )
+            .map_err(|e| format!("Failed to start the display workload: {}", e))?;
+            log::info!("Display workload on {}: {}", display.name, workload);
+            display.workload = Some(child);
+        }
+        display.watch();
+        Ok(display)
+    }
+
+    fn start_server(width: u32, height: u32) -> Result<Self, String> {
+        let number = free_display_number().ok_or("No free X display number")?;
+        let name = format!(":{}", number);
+        let mut server = spawn_group(
+            Command::new("Xvfb")
+                .arg(&name)
+                .args(["-screen", "0", &format!("{}x{}x24", width, height)])
+                .args(["-nolisten", "tcp"]),
+        )
+        .map_err(|e| format!("Failed to start Xvfb, is it installed? {}", e))?;
+        let started = Instant::now();
+        while !Path::new(&socket(number)).exists() {
+            if let Ok(Some(status)) = server.try_wait() {
+                return Err(format!("Xvfb exited with {}", status));
+            }
+            if started.elapsed() >= SERVER_TIMEOUT {
+                terminate(&mut server);
+                return Err(format!("Xvfb didn't open display {}", name));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        log::info!(
+            "Started Xvfb on {} with a {}x{} screen",
+            name,
+            width,
+            height
+        );
+        Ok(VirtualDisplay {
+            name,
+            server: Some(server),
+            workload: None,
+        })
+    }
+
+    /* Kills our processes once the screen_sharer is gone, however it exited. */
+    fn watch(&self) {
+        let groups: Vec<String> = [&self.workload, &self.server]
+            .into_iter()
+            .flatten()
+            .map(|child| format!("-{}", child.id()))
+            .collect();
+        if groups.is_empty() {
+            return;
+        }
+        let script = format!(
+            "while kill -0 {} 2>/dev/null; do sleep 1; done; kill -TERM {} 2>/dev/null",
+            std::process::id(),
+            groups.join(" ")
+        );
+        let watcher = Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if let Err(e) = watcher {
+            log::warn!("Failed to watch the virtual display processes: {}", e);
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Points the capturer at the display. Call it before the screens are
+    /// listed or captured, no other thread may read the environment then.
+    pub fn make_current(&self) {
+        /* Only the runtime workers exist yet, and they are idle. */
+        unsafe { std::env::set_var("DISPLAY", &self.name) };
+    }
+}
+
+impl Drop for VirtualDisplay {
+    fn drop(&mut self) {
+        for child in [&mut self.workload, &mut self.server].into_iter().flatten() {
+            terminate(child);
+        }
+    }
+}